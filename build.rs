@@ -0,0 +1,15 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc binary");
+        std::env::set_var("PROTOC", protoc);
+
+        tonic_build::configure()
+            .build_client(true)
+            // The generated connect helper needs the 2021 prelude, clients
+            // connect a tonic Endpoint instead
+            .build_transport(false)
+            .compile_protos(&["proto/scheduler.proto"], &["proto"])
+            .expect("gRPC service definition");
+    }
+}
@@ -0,0 +1,36 @@
+// Scheduler running Rust functions, for pipelines like:
+// {"expression": "*/5 * * * *", "stages": ["main"], "jobs": [{"id": "report", "stage": "main", "function": "report"}]}
+//
+// cargo run --example embedded -- ./pipelines
+
+use rusty_scheduler::function::{self, Context};
+use rusty_scheduler::scheduler;
+use rusty_scheduler::security::Security;
+use std::env;
+use std::time::Duration;
+
+fn report(context: &Context) -> Result<(), String> {
+    let target = context.param("TARGET").unwrap_or("production");
+
+    context.print(&format!("Building report for {}", target));
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    let pipelines_path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("./pipelines"));
+
+    function::register("report", report);
+
+    scheduler::run(
+        &pipelines_path,
+        Duration::from_secs(60),
+        None,
+        None,
+        &Security::default(),
+    );
+}
@@ -0,0 +1,238 @@
+use super::pipeline::Pipeline;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+
+// A pipeline running every minute would make the analysis slow
+pub const MAX_RUNS: usize = 1000;
+
+// Runs without a known duration still take a slot
+const MIN_DURATION: i64 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub pipeline: String,
+
+    pub start: DateTime<Utc>,
+
+    pub end: DateTime<Utc>,
+}
+
+// Period where at least the minimum number of runs are active at once
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlap {
+    pub start: DateTime<Utc>,
+
+    pub end: DateTime<Utc>,
+
+    // Most runs active at the same time
+    pub peak: usize,
+
+    // Runs active during the period, by start
+    pub runs: Vec<Run>,
+
+    // Minutes to delay the start of pipelines, so fewer runs overlap
+    pub offsets: Vec<(String, i64)>,
+
+    // Same overlap at the same time of other days
+    pub count: usize,
+}
+
+// Runs of the enabled pipelines in the period, lasting for their duration
+pub fn runs<F>(
+    pipelines: &[Pipeline],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    duration: F,
+) -> Vec<Run>
+where
+    F: Fn(&Pipeline) -> f64,
+{
+    let mut runs = Vec::new();
+
+    for pipeline in pipelines.iter().filter(|pipeline| pipeline.enabled) {
+        let duration = Duration::seconds((duration(pipeline) as i64).max(MIN_DURATION));
+
+        for start in pipeline.interval.between(from, to, MAX_RUNS) {
+            runs.push(Run {
+                pipeline: pipeline.id.to_string(),
+                start,
+                end: start + duration,
+            });
+        }
+    }
+
+    runs
+}
+
+// Overlaps of at least `min_runs` runs, the ones repeating at the same time
+// of day are counted once
+pub fn overlaps(runs: &[Run], min_runs: usize) -> Vec<Overlap> {
+    let mut grouped: Vec<Overlap> = Vec::new();
+
+    for overlap in find(runs, min_runs.max(2)) {
+        let same = grouped.iter_mut().find(|other| {
+            other.start.time() == overlap.start.time()
+                && other.end - other.start == overlap.end - overlap.start
+                && pipelines(&other.runs) == pipelines(&overlap.runs)
+        });
+
+        match same {
+            Some(other) => other.count += 1,
+            None => grouped.push(overlap),
+        }
+    }
+
+    grouped.sort_by(|a, b| b.peak.cmp(&a.peak).then(a.start.cmp(&b.start)));
+
+    grouped
+}
+
+fn find(runs: &[Run], min_runs: usize) -> Vec<Overlap> {
+    // Ends go before starts at the same time, a run ending when another
+    // starts doesn't overlap it
+    let mut events: Vec<(DateTime<Utc>, bool, usize)> = runs
+        .iter()
+        .enumerate()
+        .flat_map(|(index, run)| vec![(run.start, true, index), (run.end, false, index)])
+        .collect();
+
+    events.sort();
+
+    let mut overlaps = Vec::new();
+    let mut active: Vec<usize> = Vec::new();
+    let mut current: Option<(DateTime<Utc>, usize, Vec<usize>)> = None;
+
+    for (time, starts, index) in events {
+        if starts {
+            active.push(index);
+
+            if let Some((_, peak, members)) = current.as_mut() {
+                *peak = (*peak).max(active.len());
+                members.push(index);
+            } else if active.len() >= min_runs {
+                current = Some((time, active.len(), active.clone()));
+            }
+        } else {
+            active.retain(|active| *active != index);
+
+            if active.len() < min_runs {
+                if let Some((start, peak, members)) = current.take() {
+                    let mut members: Vec<Run> = members
+                        .into_iter()
+                        .map(|index| runs[index].clone())
+                        .collect();
+
+                    members.sort_by(|a, b| {
+                        (a.start, b.end, &a.pipeline).cmp(&(b.start, a.end, &b.pipeline))
+                    });
+
+                    overlaps.push(Overlap {
+                        start,
+                        end: time,
+                        peak,
+                        offsets: offsets(&members, min_runs),
+                        runs: members,
+                        count: 1,
+                    });
+                }
+            }
+        }
+    }
+
+    overlaps
+}
+
+// Runs start in order, each one waiting until fewer than `min_runs - 1`
+// earlier runs are still active
+fn offsets(runs: &[Run], min_runs: usize) -> Vec<(String, i64)> {
+    let mut offsets = Vec::new();
+    let mut ends: Vec<DateTime<Utc>> = Vec::new();
+
+    for run in runs {
+        let mut active: Vec<DateTime<Utc>> = ends
+            .iter()
+            .filter(|end| **end > run.start)
+            .cloned()
+            .collect();
+
+        active.sort();
+
+        let start = if active.len() < min_runs - 1 {
+            run.start
+        } else {
+            active[active.len() - (min_runs - 1)]
+        };
+
+        let minutes = ((start - run.start).num_seconds() + 59) / 60;
+
+        if minutes > 0 {
+            offsets.push((run.pipeline.to_string(), minutes));
+        }
+
+        ends.push(run.end + Duration::minutes(minutes));
+    }
+
+    offsets
+}
+
+fn pipelines(runs: &[Run]) -> Vec<(&str, NaiveTime)> {
+    runs.iter()
+        .map(|run| (run.pipeline.as_str(), run.start.time()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::interval::Interval;
+    use super::*;
+    use chrono::TimeZone;
+
+    fn pipeline(id: &str, expression: &str) -> Pipeline {
+        let mut pipeline: Pipeline =
+            serde_json::from_str(&format!(r#"{{"id": "{}"}}"#, id)).unwrap();
+
+        pipeline.expression = expression.to_string();
+        pipeline.interval = Interval::new(expression).unwrap();
+
+        pipeline
+    }
+
+    #[test]
+    fn find_daily_overlaps() {
+        let pipelines = vec![
+            pipeline("loader", "0 0 * * *"),
+            pipeline("report", "0 0 * * *"),
+            pipeline("cleanup", "5 0 * * *"),
+            pipeline("backup", "0 12 * * *"),
+        ];
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 1, 0, 0).unwrap();
+        let to = from + Duration::days(3);
+
+        let runs = runs(&pipelines, from, to, |pipeline| {
+            match pipeline.id.as_str() {
+                "loader" => 1200.0,
+                _ => 600.0,
+            }
+        });
+
+        let overlaps = overlaps(&runs, 3);
+
+        assert_eq!(overlaps.len(), 1);
+
+        let overlap = &overlaps[0];
+
+        assert_eq!(
+            overlap.start,
+            Utc.with_ymd_and_hms(2019, 7, 2, 0, 5, 0).unwrap()
+        );
+        assert_eq!(
+            overlap.end,
+            Utc.with_ymd_and_hms(2019, 7, 2, 0, 10, 0).unwrap()
+        );
+        assert_eq!(overlap.peak, 3);
+        assert_eq!(overlap.count, 3);
+        assert_eq!(overlap.offsets, vec![(String::from("cleanup"), 5)]);
+
+        assert!(super::overlaps(&runs, 4).is_empty());
+    }
+}
@@ -0,0 +1,917 @@
+use super::control::{self, Request, Response};
+use super::error::{Error, ErrorKind};
+use super::health;
+use super::history::History;
+use super::overview::Overview;
+use super::pipeline::Pipeline;
+use super::runtime::SharedRuntime;
+use super::scheduler;
+use super::security::{Role, Security, Stream};
+use super::state::State;
+use super::websocket;
+use chrono::{DateTime, Utc};
+use failure::ResultExt;
+use log::{error, info, trace};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+// Request bodies are small JSON documents
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+
+// Limits of the request line and headers, so a client can't hold a thread
+// or fill the memory before the request is handled
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+// How often the output of an active run is checked for new lines
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Dashboard,
+    Health,
+    Ready,
+    Role,
+    Status,
+    Pipelines,
+    Queue,
+    Pipeline(String),
+    State(String),
+    History(String),
+    Output(String),
+    OutputStream(String),
+    Trigger(String),
+    Cancel(String),
+    Pause(String),
+    Resume(String),
+    Dequeue(String),
+    Drain,
+}
+
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+
+    pub path: String,
+
+    // Percent decoded
+    pub query: BTreeMap<String, String>,
+
+    // Lowercase names
+    pub headers: BTreeMap<String, String>,
+
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TriggerBody {
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub skip_stages: Vec<String>,
+
+    #[serde(default)]
+    pub skip_jobs: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DequeueBody {
+    // All the queued runs of the pipeline when missing
+    #[serde(default)]
+    pub scheduled: Option<DateTime<Utc>>,
+}
+
+impl Route {
+    pub fn parse(method: &str, path: &str) -> Option<Route> {
+        let segments: Vec<&str> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        let route = match (method, segments.as_slice()) {
+            ("GET", []) => Route::Dashboard,
+            ("GET", ["healthz"]) => Route::Health,
+            ("GET", ["readyz"]) => Route::Ready,
+            ("GET", ["role"]) => Route::Role,
+            ("GET", ["status"]) => Route::Status,
+            ("GET", ["pipelines"]) => Route::Pipelines,
+            ("GET", ["queue"]) => Route::Queue,
+            ("GET", ["pipelines", id]) => Route::Pipeline(id.to_string()),
+            ("GET", ["pipelines", id, "state"]) => Route::State(id.to_string()),
+            ("GET", ["pipelines", id, "history"]) => Route::History(id.to_string()),
+            ("GET", ["pipelines", id, "output"]) => Route::Output(id.to_string()),
+            ("GET", ["pipelines", id, "output", "stream"]) => Route::OutputStream(id.to_string()),
+            ("POST", ["pipelines", id, "trigger"]) => Route::Trigger(id.to_string()),
+            ("POST", ["pipelines", id, "cancel"]) => Route::Cancel(id.to_string()),
+            ("POST", ["pipelines", id, "pause"]) => Route::Pause(id.to_string()),
+            ("POST", ["pipelines", id, "resume"]) => Route::Resume(id.to_string()),
+            ("POST", ["pipelines", id, "dequeue"]) => Route::Dequeue(id.to_string()),
+            ("POST", ["drain"]) => Route::Drain,
+            _ => return None,
+        };
+
+        Some(route)
+    }
+
+    fn pipeline(&self) -> Option<&str> {
+        match self {
+            Route::Dashboard
+            | Route::Health
+            | Route::Ready
+            | Route::Role
+            | Route::Status
+            | Route::Pipelines
+            | Route::Queue
+            | Route::Drain => None,
+            Route::Pipeline(id)
+            | Route::State(id)
+            | Route::History(id)
+            | Route::Output(id)
+            | Route::OutputStream(id)
+            | Route::Trigger(id)
+            | Route::Cancel(id)
+            | Route::Pause(id)
+            | Route::Resume(id)
+            | Route::Dequeue(id) => Some(id),
+        }
+    }
+
+    // Least role allowed, None for the probes and the dashboard page (its requests carry the token)
+    fn role(&self) -> Option<Role> {
+        match self {
+            Route::Dashboard | Route::Health | Route::Ready => None,
+            Route::Role
+            | Route::Status
+            | Route::Pipelines
+            | Route::Queue
+            | Route::Pipeline(_)
+            | Route::State(_)
+            | Route::History(_)
+            | Route::Output(_)
+            | Route::OutputStream(_) => Some(Role::Viewer),
+            Route::Trigger(_) | Route::Cancel(_) | Route::Dequeue(_) => Some(Role::Operator),
+            Route::Pause(_) | Route::Resume(_) | Route::Drain => Some(Role::Admin),
+        }
+    }
+}
+
+pub fn serve(
+    address: &str,
+    pipelines_path: &str,
+    runtime: SharedRuntime,
+    security: &Security,
+) -> Result<(), Error> {
+    let tls = security.server_config()?;
+
+    let listener =
+        TcpListener::bind(address).context(ErrorKind::ApiListenFailed(address.to_string()))?;
+
+    info!(
+        "API listening: {} ({})",
+        address,
+        if tls.is_some() { "https" } else { "http" }
+    );
+
+    let security = security.clone();
+
+    let pipelines_path = pipelines_path.to_string();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let pipelines_path = pipelines_path.to_string();
+                    let runtime = runtime.clone();
+                    let security = security.clone();
+                    let tls = tls.clone();
+
+                    thread::spawn(move || {
+                        let mut stream = match Stream::accept(stream, tls.as_ref()) {
+                            Ok(stream) => stream,
+                            Err(err) => return error!("{}", err),
+                        };
+
+                        if let Err(err) = handle(&mut stream, &pipelines_path, &runtime, &security)
+                        {
+                            error!("{}", err);
+                        }
+
+                        stream.close();
+                    });
+                }
+                Err(err) => {
+                    error!("{}", err);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(
+    stream: &mut Stream,
+    pipelines_path: &str,
+    runtime: &SharedRuntime,
+    security: &Security,
+) -> Result<(), Error> {
+    let request = stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .context(ErrorKind::ApiRequestFailed(String::from("timeout")))
+        .map_err(Error::from)
+        .and_then(|_| read_request(stream));
+
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => {
+            let (status, body) = error(400, err.to_string());
+
+            return write_response(stream, status, "application/json", &body.to_string());
+        }
+    };
+
+    // Output streams stay open as long as the run
+    stream
+        .set_read_timeout(None)
+        .context(ErrorKind::ApiRequestFailed(String::from("timeout")))?;
+
+    trace!("API request: {} {}", request.method, request.path);
+
+    let route = Route::parse(&request.method, &request.path);
+
+    let role = authorize(&request, security);
+
+    // Unknown routes need a token too, so they don't tell which ones exist
+    match (route.as_ref().map_or(Some(Role::Viewer), Route::role), role) {
+        (Some(_), None) => {
+            let (status, body) = error(401, String::from("Unauthorized"));
+
+            return write_response(stream, status, "application/json", &body.to_string());
+        }
+        (Some(required), Some(role)) if role < required => {
+            let (status, body) = error(403, format!("Forbidden: requires the {} role", required));
+
+            return write_response(stream, status, "application/json", &body.to_string());
+        }
+        _ => {}
+    }
+
+    // Without tokens, any web page could make the browser of someone who can
+    // reach the API trigger runs or read their output, so changes and streams
+    // can't come from other origins. WebSockets are not bound by CORS
+    let checks_origin = request.method == "POST" || matches!(route, Some(Route::OutputStream(_)));
+
+    if security.tokens.is_empty() && checks_origin && !is_same_origin(&request) {
+        let (status, body) = error(403, String::from("Forbidden: cross-origin request"));
+
+        return write_response(stream, status, "application/json", &body.to_string());
+    }
+
+    match route {
+        Some(Route::Dashboard) => {
+            return write_response(stream, 200, "text/html; charset=utf-8", DASHBOARD);
+        }
+        Some(Route::Role) => {
+            let body = json!({ "role": role });
+
+            return write_response(stream, 200, "application/json", &body.to_string());
+        }
+        Some(Route::OutputStream(id)) => {
+            return stream_output(stream, &request, &id, pipelines_path, runtime)
+        }
+        _ => {}
+    }
+
+    let actor = match (stream.peer_addr(), role) {
+        (Ok(address), Some(role)) => format!("http {} ({})", address, role),
+        (Ok(address), None) => format!("http {}", address),
+        (Err(_), _) => String::from("http"),
+    };
+
+    let (status, body) = respond(&request, pipelines_path, runtime, &actor);
+
+    write_response(stream, status, "application/json", &body.to_string())
+}
+
+// Requests without an Origin don't come from a browser page, or come from
+// the same origin in older browsers
+fn is_same_origin(request: &HttpRequest) -> bool {
+    let origin = match request.headers.get("origin") {
+        Some(origin) => origin,
+        None => return true,
+    };
+
+    let origin_host = origin
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+
+    request
+        .headers
+        .get("host")
+        .is_some_and(|host| host.eq_ignore_ascii_case(origin_host))
+}
+
+// Browsers can't send headers on WebSockets, so streams also take the token from the query
+fn authorize(request: &HttpRequest, security: &Security) -> Option<Role> {
+    let header = request.headers.get("authorization").map(String::as_str);
+
+    let query = match Route::parse(&request.method, &request.path) {
+        Some(Route::OutputStream(_)) => request
+            .query
+            .get("access_token")
+            .map(|token| format!("Bearer {}", token)),
+        _ => None,
+    };
+
+    security
+        .authorize(header)
+        .max(query.and_then(|query| security.authorize(Some(&query))))
+}
+
+// Sends the output lines of the active run as WebSocket text messages,
+// until the run finishes or the client goes away
+fn stream_output(
+    stream: &mut Stream,
+    request: &HttpRequest,
+    id: &str,
+    pipelines_path: &str,
+    runtime: &SharedRuntime,
+) -> Result<(), Error> {
+    let key = match request.headers.get("sec-websocket-key") {
+        Some(key)
+            if request
+                .headers
+                .get("upgrade")
+                .map(|upgrade| upgrade.to_lowercase())
+                == Some(String::from("websocket")) =>
+        {
+            key
+        }
+        _ => {
+            let (status, body) = error(400, String::from("WebSocket upgrade required"));
+
+            return write_response(stream, status, "application/json", &body.to_string());
+        }
+    };
+
+    let pipelines = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path));
+
+    if !pipelines.iter().any(|pipeline| pipeline.id == id) {
+        let (status, body) = error(404, ErrorKind::PipelineNotFound(id.to_string()).to_string());
+
+        return write_response(stream, status, "application/json", &body.to_string());
+    }
+
+    let run = match runtime.lock().unwrap().find_run(id) {
+        Some(run) => run.clone(),
+        None => {
+            let (status, body) = error(
+                404,
+                ErrorKind::PipelineNotRunning(id.to_string()).to_string(),
+            );
+
+            return write_response(stream, status, "application/json", &body.to_string());
+        }
+    };
+
+    let handshake = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key)
+    );
+
+    stream
+        .write_all(handshake.as_bytes())
+        .and_then(|_| stream.flush())
+        .context(ErrorKind::ApiRequestFailed(String::from("handshake")))?;
+
+    trace!("Streaming output: {}", run.run_id);
+
+    let mut cursor = 0;
+
+    loop {
+        // Read the finished flag first, so lines pushed before the end are still sent
+        let finished = !runtime.lock().unwrap().is_running(&run.run_id);
+
+        let (lines, next_cursor) = run.output.lines_since(cursor);
+
+        cursor = next_cursor;
+
+        for line in lines {
+            let frame = websocket::encode_frame(websocket::OPCODE_TEXT, line.as_bytes(), None);
+
+            if stream
+                .write_all(&frame)
+                .and_then(|_| stream.flush())
+                .is_err()
+            {
+                trace!("Output stream closed: {}", run.run_id);
+
+                return Ok(());
+            }
+        }
+
+        if finished {
+            let frame = websocket::encode_frame(websocket::OPCODE_CLOSE, &[], None);
+            let _ = stream.write_all(&frame).and_then(|_| stream.flush());
+
+            return Ok(());
+        }
+
+        thread::sleep(OUTPUT_POLL_INTERVAL);
+    }
+}
+
+fn respond(
+    request: &HttpRequest,
+    pipelines_path: &str,
+    runtime: &SharedRuntime,
+    actor: &str,
+) -> (u16, Value) {
+    let route = match Route::parse(&request.method, &request.path) {
+        Some(route) => route,
+        None => return error(404, format!("Not found: {}", request.path)),
+    };
+
+    match route {
+        Route::Health => {
+            let report = health::liveness(&runtime.lock().unwrap().health, Utc::now());
+
+            return (report.status_code(), json!(report));
+        }
+        Route::Ready => {
+            let health = runtime.lock().unwrap().health.clone();
+
+            let report = health::readiness(&health, pipelines_path, Utc::now());
+
+            return (report.status_code(), json!(report));
+        }
+        _ => {}
+    }
+
+    let pipelines = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path));
+
+    let pipeline = match route.pipeline() {
+        Some(id) => match pipelines.iter().find(|pipeline| pipeline.id == id) {
+            Some(pipeline) => Some(pipeline),
+            None => return error(404, ErrorKind::PipelineNotFound(id.to_string()).to_string()),
+        },
+        None => None,
+    };
+
+    let request = match route {
+        Route::Dashboard | Route::Health | Route::Ready | Route::Role | Route::OutputStream(_) => {
+            return error(404, format!("Not found: {}", request.path))
+        }
+        Route::Status => Request::Status,
+        Route::Pipelines => {
+            let overviews: Vec<Overview> = pipelines.iter().map(Overview::from_pipeline).collect();
+
+            return (200, json!(overviews));
+        }
+        Route::Pipeline(_) => {
+            return (200, json!(Overview::from_pipeline(pipeline.unwrap())));
+        }
+        Route::State(_) => {
+            return match State::read_file(&State::path_from_pipeline(pipeline.unwrap())) {
+                Ok(state) => (200, json!(state)),
+                Err(err) => error(404, err.to_string()),
+            };
+        }
+        Route::History(_) => {
+            return match History::read_file(&History::path_from_pipeline(pipeline.unwrap())) {
+                Ok(history) => (200, json!(history)),
+                Err(err) => error(404, err.to_string()),
+            };
+        }
+        Route::Output(id) => {
+            return match runtime.lock().unwrap().find_run(&id) {
+                Some(run) => (
+                    200,
+                    json!({ "run_id": run.run_id, "lines": run.output.lines_since(0).0 }),
+                ),
+                None => error(404, ErrorKind::PipelineNotRunning(id).to_string()),
+            };
+        }
+        Route::Trigger(pipeline) => {
+            let body: TriggerBody = if request.body.is_empty() {
+                TriggerBody::default()
+            } else {
+                match serde_json::from_slice(&request.body) {
+                    Ok(body) => body,
+                    Err(err) => return error(400, err.to_string()),
+                }
+            };
+
+            Request::Trigger {
+                pipeline,
+                params: body.params,
+                labels: body.labels,
+                skip_stages: body.skip_stages,
+                skip_jobs: body.skip_jobs,
+            }
+        }
+        Route::Cancel(pipeline) => Request::Cancel { pipeline },
+        Route::Pause(pipeline) => Request::Pause {
+            pipeline: Some(pipeline),
+        },
+        Route::Resume(pipeline) => Request::Resume {
+            pipeline: Some(pipeline),
+        },
+        Route::Queue => Request::Queue,
+        Route::Dequeue(pipeline) => {
+            let body: DequeueBody = if request.body.is_empty() {
+                DequeueBody::default()
+            } else {
+                match serde_json::from_slice(&request.body) {
+                    Ok(body) => body,
+                    Err(err) => return error(400, err.to_string()),
+                }
+            };
+
+            Request::Dequeue {
+                pipeline,
+                scheduled: body.scheduled,
+            }
+        }
+        Route::Drain => Request::Drain,
+    };
+
+    match control::respond(request, pipelines_path, runtime, actor) {
+        Response::Status(status) => (200, json!(status)),
+        // A run already active or not active
+        Response::Error { message } => error(409, message),
+        response => (200, json!(response)),
+    }
+}
+
+fn error(status: u16, message: String) -> (u16, Value) {
+    (status, json!({ "result": "error", "message": message }))
+}
+
+pub fn read_request<R: Read>(stream: &mut R) -> Result<HttpRequest, Error> {
+    let mut reader = BufReader::new(stream);
+
+    let line = read_line(&mut reader, "request line")?;
+
+    let mut parts = line.split_whitespace();
+
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(ErrorKind::ApiRequestFailed(line.trim().to_string()).into()),
+    };
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_query(query)),
+        None => (target, BTreeMap::new()),
+    };
+
+    let mut headers = BTreeMap::new();
+    let mut content_length = 0;
+
+    for count in 0.. {
+        let header = read_line(&mut reader, "headers")?;
+
+        let header = header.trim();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if count >= MAX_HEADERS {
+            return Err(ErrorKind::ApiRequestFailed(String::from("too many headers")).into());
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ErrorKind::ApiRequestFailed(header.to_string()))?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_LENGTH {
+        return Err(ErrorKind::ApiRequestFailed(String::from("body too large")).into());
+    }
+
+    let mut body = vec![0; content_length];
+
+    reader
+        .read_exact(&mut body)
+        .context(ErrorKind::ApiRequestFailed(String::from("body")))?;
+
+    Ok(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+fn read_line<R: BufRead>(reader: &mut R, part: &str) -> Result<String, Error> {
+    let mut line = String::new();
+
+    reader
+        .take(MAX_LINE_LENGTH as u64)
+        .read_line(&mut line)
+        .context(ErrorKind::ApiRequestFailed(part.to_string()))?;
+
+    if line.len() >= MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(ErrorKind::ApiRequestFailed(format!("{} too long", part)).into());
+    }
+
+    Ok(line)
+}
+
+fn write_response(
+    stream: &mut Stream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> Result<(), Error> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+
+    let authenticate = if status == 401 {
+        "WWW-Authenticate: Bearer\r\n"
+    } else {
+        ""
+    };
+
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        authenticate,
+        body.len(),
+        body
+    );
+
+    stream
+        .write_all(response.as_bytes())
+        .and_then(|_| stream.flush())
+        .context(ErrorKind::ApiRequestFailed(String::from("response")))?;
+
+    Ok(())
+}
+
+pub fn parse_query(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (percent_decode(name), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::runtime::Runtime;
+    use super::*;
+    use std::net::TcpStream;
+
+    #[test]
+    fn parse_routes() {
+        assert_eq!(Route::parse("GET", "/"), Some(Route::Dashboard));
+        assert_eq!(Route::parse("GET", "/healthz"), Some(Route::Health));
+        assert_eq!(Route::parse("GET", "/readyz"), Some(Route::Ready));
+        assert_eq!(Route::parse("GET", "/status"), Some(Route::Status));
+        assert_eq!(Route::parse("GET", "/pipelines/"), Some(Route::Pipelines));
+        assert_eq!(
+            Route::parse("GET", "/pipelines/loader/history"),
+            Some(Route::History(String::from("loader")))
+        );
+        assert_eq!(
+            Route::parse("GET", "/pipelines/loader/output"),
+            Some(Route::Output(String::from("loader")))
+        );
+        assert_eq!(
+            Route::parse("GET", "/pipelines/loader/output/stream"),
+            Some(Route::OutputStream(String::from("loader")))
+        );
+        assert_eq!(
+            Route::parse("POST", "/pipelines/loader/trigger"),
+            Some(Route::Trigger(String::from("loader")))
+        );
+    }
+
+    #[test]
+    fn parse_queries() {
+        let query = parse_query("access_token=a%2Bb%3D&empty&name=two+words");
+
+        assert_eq!(query["access_token"], "a+b=");
+        assert_eq!(query["empty"], "");
+        assert_eq!(query["name"], "two words");
+        assert!(parse_query("").is_empty());
+    }
+
+    #[test]
+    fn authorize_streams_with_query_token() {
+        let security = Security {
+            tokens: vec![(Role::Viewer, String::from("s3cret"))],
+            tls: None,
+        };
+
+        let request = |method: &str, path: &str, query: &str| HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: parse_query(query),
+            headers: BTreeMap::new(),
+            body: Vec::new(),
+        };
+
+        assert_eq!(
+            authorize(
+                &request(
+                    "GET",
+                    "/pipelines/loader/output/stream",
+                    "access_token=s3cret"
+                ),
+                &security
+            ),
+            Some(Role::Viewer)
+        );
+        assert_eq!(
+            authorize(
+                &request("POST", "/pipelines/loader/trigger", "access_token=s3cret"),
+                &security
+            ),
+            None
+        );
+        assert_eq!(authorize(&request("GET", "/status", ""), &security), None);
+    }
+
+    #[test]
+    fn limit_request_lines_and_headers() {
+        let request = b"POST /drain HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec();
+
+        assert_eq!(read_request(&mut &request[..]).unwrap().path, "/drain");
+
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+
+        assert!(read_request(&mut long_line.as_bytes()).is_err());
+
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Header: value\r\n".repeat(MAX_HEADERS + 1)
+        );
+
+        assert!(read_request(&mut many_headers.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reject_cross_origin_requests() {
+        let request = |origin: Option<&str>| HttpRequest {
+            method: String::from("POST"),
+            path: String::from("/drain"),
+            query: BTreeMap::new(),
+            headers: vec![("host", Some("10.0.0.5:8080")), ("origin", origin)]
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    value.map(|value| (name.to_string(), value.to_string()))
+                })
+                .collect(),
+            body: Vec::new(),
+        };
+
+        assert!(is_same_origin(&request(None)));
+        assert!(is_same_origin(&request(Some("http://10.0.0.5:8080"))));
+        assert!(!is_same_origin(&request(Some("https://evil.example.com"))));
+        assert!(!is_same_origin(&request(Some("null"))));
+    }
+
+    #[test]
+    fn reject_cross_origin_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut client = TcpStream::connect(address).unwrap();
+            let mut response = String::new();
+
+            client
+                .write_all(
+                    format!(
+                        "GET /pipelines/loader/output/stream HTTP/1.1\r\nHost: {}\r\n\
+                         Origin: https://evil.example.com\r\nUpgrade: websocket\r\n\
+                         Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                         Sec-WebSocket-Version: 13\r\n\r\n",
+                        address
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+            client.read_to_string(&mut response).unwrap();
+
+            response
+        });
+
+        let (tcp, _) = listener.accept().unwrap();
+        let mut stream = Stream::Plain(tcp);
+        let security = Security {
+            tokens: Vec::new(),
+            tls: None,
+        };
+
+        handle(&mut stream, "", &Runtime::new_shared(60), &security).unwrap();
+        drop(stream);
+
+        let response = client.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(response.contains("cross-origin"));
+    }
+
+    #[test]
+    fn route_roles() {
+        let role = |method: &str, path: &str| Route::parse(method, path).unwrap().role();
+
+        assert_eq!(role("GET", "/"), None);
+        assert_eq!(role("GET", "/healthz"), None);
+        assert_eq!(role("GET", "/status"), Some(Role::Viewer));
+        assert_eq!(
+            role("GET", "/pipelines/loader/output/stream"),
+            Some(Role::Viewer)
+        );
+        assert_eq!(
+            role("POST", "/pipelines/loader/trigger"),
+            Some(Role::Operator)
+        );
+        assert_eq!(
+            role("POST", "/pipelines/loader/cancel"),
+            Some(Role::Operator)
+        );
+        assert_eq!(role("POST", "/pipelines/loader/pause"), Some(Role::Admin));
+        assert_eq!(role("GET", "/queue"), Some(Role::Viewer));
+        assert_eq!(
+            role("POST", "/pipelines/loader/dequeue"),
+            Some(Role::Operator)
+        );
+        assert_eq!(role("POST", "/drain"), Some(Role::Admin));
+    }
+
+    #[test]
+    fn parse_unknown_routes() {
+        assert_eq!(Route::parse("GET", "/pipelines/loader/trigger"), None);
+        assert_eq!(Route::parse("DELETE", "/pipelines/loader"), None);
+        assert_eq!(Route::parse("POST", "/"), None);
+        assert_eq!(Route::parse("GET", "/drain"), None);
+    }
+}
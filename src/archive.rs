@@ -0,0 +1,300 @@
+use super::crypto;
+use super::error::{Error, ErrorKind};
+use super::history::History;
+use super::pipeline::Pipeline;
+use super::state::State;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use failure::ResultExt;
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Archive {
+    #[serde(default = "Utc::now")]
+    pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub pipelines: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    #[serde(default)]
+    pub id: String,
+
+    #[serde(default)]
+    pub state: Option<State>,
+
+    #[serde(default)]
+    pub history: Option<History>,
+
+    // State and history of pipelines with encrypt_state, encrypted together
+    // with the state key and base64 encoded, never archived as plain text
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted: Option<String>,
+}
+
+impl Archive {
+    pub fn from_pipelines(pipelines: &[Pipeline]) -> Result<Archive, Error> {
+        let pipelines = pipelines
+            .iter()
+            .map(Entry::from_pipeline)
+            .collect::<Result<Vec<Entry>, Error>>()?;
+
+        Ok(Archive {
+            timestamp: Utc::now(),
+            pipelines,
+        })
+    }
+
+    pub fn restore(self, pipelines: &[Pipeline]) -> Result<usize, Error> {
+        let mut restored = 0;
+
+        for entry in self.pipelines {
+            let pipeline = match pipelines.iter().find(|pipeline| pipeline.id == entry.id) {
+                Some(pipeline) => pipeline,
+                None => {
+                    warn!("Pipeline not found, skipping: {}", entry.id);
+
+                    continue;
+                }
+            };
+
+            let entry = entry.decrypt()?;
+
+            if let Some(mut state) = entry.state {
+                state.path = State::path_from_pipeline(pipeline);
+                state.encrypted = pipeline.encrypt_state;
+
+                state.write_file()?;
+            }
+
+            if let Some(mut history) = entry.history {
+                history.path = History::path_from_pipeline(pipeline);
+                history.encrypted = pipeline.encrypt_state;
+
+                history.write_file()?;
+            }
+
+            trace!("Pipeline restored: {}", entry.id);
+
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+
+    pub fn read_file(archive_path: &str) -> Result<Archive, Error> {
+        let archive_data = fs::read_to_string(archive_path)
+            .context(ErrorKind::InvalidArchiveFile(archive_path.to_string()))?;
+
+        let archive: Archive = serde_json::from_str(&archive_data)
+            .context(ErrorKind::InvalidArchiveFile(archive_path.to_string()))?;
+
+        Ok(archive)
+    }
+
+    pub fn to_json(&self, archive_path: &str) -> Result<String, Error> {
+        let archive_data = serde_json::to_string_pretty(&self)
+            .context(ErrorKind::InvalidArchiveFile(archive_path.to_string()))?;
+
+        Ok(archive_data)
+    }
+
+    pub fn write_file(&self, archive_path: &str) -> Result<(), Error> {
+        let archive_data = self.to_json(archive_path)?;
+
+        fs::write(archive_path, archive_data)
+            .context(ErrorKind::InvalidArchiveFile(archive_path.to_string()))?;
+
+        Ok(())
+    }
+}
+
+impl Entry {
+    pub fn from_pipeline(pipeline: &Pipeline) -> Result<Entry, Error> {
+        let entry = Entry {
+            id: pipeline.id.to_string(),
+            state: State::read_file(&State::path_from_pipeline(pipeline)).ok(),
+            history: History::read_file(&History::path_from_pipeline(pipeline)).ok(),
+            encrypted: None,
+        };
+
+        if !pipeline.encrypt_state {
+            return Ok(entry);
+        }
+
+        let entry_data = serde_json::to_vec(&entry)
+            .context(ErrorKind::EncryptionFailed(pipeline.id.to_string()))?;
+
+        let entry_data = crypto::encrypt(&entry_data, &pipeline.id)?;
+
+        Ok(Entry {
+            id: pipeline.id.to_string(),
+            state: None,
+            history: None,
+            encrypted: Some(STANDARD.encode(entry_data)),
+        })
+    }
+
+    pub fn decrypt(self) -> Result<Entry, Error> {
+        let encrypted = match &self.encrypted {
+            Some(encrypted) => encrypted,
+            None => return Ok(self),
+        };
+
+        let entry_data = STANDARD
+            .decode(encrypted)
+            .context(ErrorKind::DecryptionFailed(self.id.to_string()))?;
+
+        let entry_data = crypto::decrypt(&entry_data, &self.id)?;
+
+        let entry: Entry = serde_json::from_slice(&entry_data)
+            .context(ErrorKind::DecryptionFailed(self.id.to_string()))?;
+
+        Ok(Entry {
+            id: self.id,
+            encrypted: None,
+            ..entry
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::history::{Run, RunStatus, SkipReason};
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use std::env;
+
+    #[test]
+    fn archive_encrypted_pipelines() {
+        env::set_var(crypto::KEY_VARIABLE, "secret");
+
+        let dir = env::temp_dir().join("rusty-scheduler-archive");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "private", "encrypt_state": true, "stages": ["main"], "jobs": []}"#,
+        )
+        .unwrap();
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+
+        let mut state = State::read_from_pipeline(&pipeline);
+
+        state.checksum = String::from("plain-checksum");
+        state.write_file().unwrap();
+
+        let archive = Archive::from_pipelines(&[pipeline.clone()]).unwrap();
+
+        assert!(!archive.to_json("-").unwrap().contains("plain-checksum"));
+
+        fs::remove_file(State::path_from_pipeline(&pipeline)).unwrap();
+
+        assert_eq!(archive.restore(&[pipeline.clone()]).unwrap(), 1);
+
+        let state = State::read_file(&State::path_from_pipeline(&pipeline)).unwrap();
+
+        assert_eq!(state.checksum, "plain-checksum");
+    }
+
+    #[test]
+    fn restore_files_byte_for_byte() {
+        env::set_var(crypto::KEY_VARIABLE, "secret");
+
+        let dir = env::temp_dir().join("rusty-scheduler-round-trip");
+        let start = Utc.with_ymd_and_hms(2019, 7, 1, 8, 0, 0).unwrap();
+
+        let pipelines: Vec<Pipeline> = [("plain", false), ("private", true)]
+            .iter()
+            .map(|(id, encrypt_state)| {
+                let mut pipeline: Pipeline = serde_json::from_str(&format!(
+                    r#"{{"id": "{}", "encrypt_state": {}, "stages": ["main"], "jobs": []}}"#,
+                    id, encrypt_state
+                ))
+                .unwrap();
+
+                fs::create_dir_all(dir.join(id)).unwrap();
+                pipeline.path = dir
+                    .join(id)
+                    .join("pipeline.json")
+                    .to_string_lossy()
+                    .to_string();
+
+                let mut state = State::read_from_pipeline(&pipeline);
+
+                state.timestamp = start;
+                state.next_timestamp = Some(start + Duration::days(1));
+                state.consecutive_failures = 2;
+                state.checksum = String::from("checksum");
+                state
+                    .outputs
+                    .insert(String::from("VERSION"), String::from("1.2.3"));
+                state.dropped.push(start);
+                state.write_file().unwrap();
+
+                let mut history = History::read_from_pipeline(&pipeline);
+
+                for day in 0..3 {
+                    let mut run = Run::new(
+                        RunStatus::Succeeded,
+                        start + Duration::days(day),
+                        start + Duration::days(day) + Duration::seconds(90),
+                    );
+
+                    run.labels
+                        .insert(String::from("reason"), String::from("hotfix"));
+                    history.runs.push(run);
+                }
+
+                history.runs.push(Run::skipped(
+                    SkipReason::Paused,
+                    Some(start),
+                    start + Duration::days(3),
+                ));
+                history.compact(start + Duration::days(1), 0);
+                history.write_file().unwrap();
+
+                pipeline
+            })
+            .collect();
+
+        let files: Vec<String> = pipelines
+            .iter()
+            .flat_map(|pipeline| {
+                vec![
+                    State::path_from_pipeline(pipeline),
+                    History::path_from_pipeline(pipeline),
+                ]
+            })
+            .collect();
+
+        // Encrypted files get a new nonce on each write, so their content is compared
+        let read = |file: &String| crypto::decrypt(&fs::read(file).unwrap(), file).unwrap();
+
+        let dumped: Vec<Vec<u8>> = files.iter().map(read).collect();
+
+        let archive = Archive::from_pipelines(&pipelines).unwrap();
+        let archive: Archive = serde_json::from_str(&archive.to_json("-").unwrap()).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        for pipeline in &pipelines {
+            fs::create_dir_all(dir.join(&pipeline.id)).unwrap();
+        }
+
+        assert_eq!(archive.restore(&pipelines).unwrap(), 2);
+
+        for (file, dumped) in files.iter().zip(dumped) {
+            assert_eq!(read(file), dumped, "{}", file);
+        }
+
+        assert!(crypto::is_encrypted(&fs::read(&files[2]).unwrap()));
+        assert!(!crypto::is_encrypted(&fs::read(&files[0]).unwrap()));
+    }
+}
@@ -0,0 +1,174 @@
+use super::pipeline::Pipeline;
+use super::storage;
+use chrono::{DateTime, Utc};
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Utc>,
+
+    // Who requested the action, e.g. "uid=1000(felipe)" or "http 127.0.0.1:51234"
+    pub actor: String,
+
+    pub action: Action,
+
+    pub pipeline: String,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Trigger,
+    Cancel,
+    Pause,
+    Resume,
+    Dequeue,
+    DefinitionChanged,
+}
+
+impl Entry {
+    pub fn new(actor: &str, action: Action, pipeline: &str) -> Entry {
+        Entry {
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            action,
+            pipeline: pipeline.to_string(),
+            details: BTreeMap::new(),
+        }
+    }
+
+    pub fn detail(mut self, key: &str, value: &str) -> Entry {
+        self.details.insert(key.to_string(), value.to_string());
+
+        self
+    }
+}
+
+pub fn path(pipelines_path: &str) -> String {
+    let mut audit_path = PathBuf::from(pipelines_path);
+    audit_path.push("audit.log");
+
+    audit_path.to_string_lossy().to_string()
+}
+
+pub fn path_from_pipeline(pipeline: &Pipeline) -> String {
+    let mut audit_path = PathBuf::from(&pipeline.path);
+    audit_path.pop();
+    audit_path.pop();
+    audit_path.push("audit.log");
+
+    audit_path.to_string_lossy().to_string()
+}
+
+// One JSON entry per line, existing lines are never changed
+pub fn record(audit_path: &str, entry: &Entry) {
+    let mut line = match serde_json::to_string(entry) {
+        Ok(line) => line,
+        Err(err) => {
+            warn!("Error recording audit entry: {}", err);
+
+            return;
+        }
+    };
+
+    line.push('\n');
+
+    trace!("Audit entry: {}", line.trim());
+
+    if let Err(err) = storage::append(audit_path, line.as_bytes()) {
+        warn!("Error recording audit entry: {}: {}", audit_path, err);
+    }
+}
+
+// User of the process on the other side of the control socket
+pub fn socket_actor(stream: &UnixStream) -> String {
+    let mut credentials: libc::ucred = unsafe { mem::zeroed() };
+    let mut length = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+
+    if result != 0 {
+        return String::from("unknown");
+    }
+
+    match user_name(credentials.uid) {
+        Some(name) => format!("uid={}({})", credentials.uid, name),
+        None => format!("uid={}", credentials.uid),
+    }
+}
+
+fn user_name(uid: libc::uid_t) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if status != 0 || result.is_null() {
+        return None;
+    }
+
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+
+    Some(name.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn serialize_entry() {
+        let entry = Entry {
+            timestamp: Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap(),
+            ..Entry::new("uid=1000(felipe)", Action::Trigger, "loader")
+                .detail("run_id", "loader-20190701T000000000")
+        };
+
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"timestamp":"2019-07-01T00:00:00Z","actor":"uid=1000(felipe)","action":"trigger","pipeline":"loader","details":{"run_id":"loader-20190701T000000000"}}"#
+        );
+    }
+
+    #[test]
+    fn serialize_entry_without_details() {
+        let entry = Entry {
+            timestamp: Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap(),
+            ..Entry::new("scheduler", Action::DefinitionChanged, "loader")
+        };
+
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"timestamp":"2019-07-01T00:00:00Z","actor":"scheduler","action":"definition_changed","pipeline":"loader"}"#
+        );
+    }
+}
@@ -0,0 +1,548 @@
+use super::error::{Error, ErrorKind};
+use super::executor;
+use super::interval::{Compat, Interval};
+use super::notification::Sink;
+use super::pipeline::{Concurrency, FirstRun, Job, OutputFormat, Pipeline, MAX_RETENTION_DAYS};
+use super::plugin;
+use super::services::Service;
+use super::sla::Sla;
+use super::stuck::StuckAfter;
+use super::timezone::Timezone;
+use super::window::{OutsideWindow, Window};
+use glob::Pattern;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+// Pipelines defined in code, checked when built like doctor checks pipeline files
+pub struct PipelineBuilder {
+    pipeline: Pipeline,
+}
+
+pub struct JobBuilder {
+    job: Job,
+}
+
+impl PipelineBuilder {
+    pub fn new(id: &str) -> PipelineBuilder {
+        // Same defaults as a pipeline file without the optional fields
+        let mut pipeline: Pipeline = serde_json::from_value(Value::Object(Map::new())).unwrap();
+        pipeline.id = id.to_string();
+
+        PipelineBuilder { pipeline }
+    }
+
+    pub fn expression(mut self, expression: &str) -> PipelineBuilder {
+        self.pipeline.expression = expression.to_string();
+        self
+    }
+
+    pub fn compat(mut self, compat: Compat) -> PipelineBuilder {
+        self.pipeline.compat = compat;
+        self
+    }
+
+    // Evaluates the expression in a timezone instead of UTC
+    pub fn timezone(mut self, timezone: Timezone) -> PipelineBuilder {
+        self.pipeline.timezone = Some(timezone);
+        self
+    }
+
+    // Folder of the state, logs and scripts, like the folder of a pipeline file
+    pub fn folder(mut self, folder: &str) -> PipelineBuilder {
+        let mut path = PathBuf::from(folder);
+        path.push("pipeline.json");
+
+        self.pipeline.path = path.to_string_lossy().to_string();
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> PipelineBuilder {
+        self.pipeline.enabled = enabled;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> PipelineBuilder {
+        self.pipeline.dry_run = dry_run;
+        self
+    }
+
+    // Runs again when its scripts change, in a scheduler started with run()
+    pub fn watch(mut self, watch: bool) -> PipelineBuilder {
+        self.pipeline.watch = watch;
+        self
+    }
+
+    // Runs once when a scheduler started with run() starts
+    pub fn run_on_startup(mut self, run_on_startup: bool) -> PipelineBuilder {
+        self.pipeline.run_on_startup = run_on_startup;
+        self
+    }
+
+    pub fn first_run(mut self, first_run: FirstRun) -> PipelineBuilder {
+        self.pipeline.first_run = first_run;
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: Concurrency) -> PipelineBuilder {
+        self.pipeline.concurrency = concurrency;
+        self
+    }
+
+    pub fn max_queue_depth(mut self, max_depth: u32) -> PipelineBuilder {
+        self.pipeline.max_queue_depth = Some(max_depth);
+        self
+    }
+
+    // Like "bash -euo pipefail", instead of the global interpreter
+    pub fn interpreter(mut self, interpreter: &str) -> PipelineBuilder {
+        self.pipeline.interpreter = Some(interpreter.to_string());
+        self
+    }
+
+    pub fn history_days(mut self, days: i64) -> PipelineBuilder {
+        self.pipeline.history_days = days;
+        self
+    }
+
+    pub fn log_days(mut self, days: i64) -> PipelineBuilder {
+        self.pipeline.log_days = days;
+        self
+    }
+
+    pub fn critical(mut self, critical: bool) -> PipelineBuilder {
+        self.pipeline.critical = critical;
+        self
+    }
+
+    pub fn notification(mut self, sink: Sink) -> PipelineBuilder {
+        self.pipeline.notifications.push(sink);
+        self
+    }
+
+    // Failures in a row after which the pipeline is paused
+    pub fn disable_after_failures(mut self, failures: u32) -> PipelineBuilder {
+        self.pipeline.disable_after_failures = Some(failures);
+        self
+    }
+
+    pub fn sla(mut self, sla: Sla) -> PipelineBuilder {
+        self.pipeline.sla = Some(sla);
+        self
+    }
+
+    // Seconds, triggers in the window of a triggered run are collapsed into it
+    pub fn dedup_window(mut self, seconds: u64) -> PipelineBuilder {
+        self.pipeline.dedup_window = Some(seconds);
+        self
+    }
+
+    // Tag the scheduler needs to run the pipeline
+    pub fn runs_on(mut self, tag: &str) -> PipelineBuilder {
+        self.pipeline.runs_on.push(tag.to_string());
+        self
+    }
+
+    // Share of the runs under a global limit, alone or with the pipelines of a
+    // fair share tag
+    pub fn weight(mut self, weight: u32, fair_share_tag: Option<&str>) -> PipelineBuilder {
+        self.pipeline.weight = weight;
+        self.pipeline.fair_share_tag = fair_share_tag.map(str::to_string);
+        self
+    }
+
+    // Service checked before a run starts, waited for up to `timeout` seconds
+    pub fn depends_on_service(mut self, service: Service, timeout: u64) -> PipelineBuilder {
+        self.pipeline.depends_on_services.push(service);
+        self.pipeline.services_timeout = timeout;
+        self
+    }
+
+    pub fn stuck_after(mut self, stuck_after: StuckAfter) -> PipelineBuilder {
+        self.pipeline.stuck_after = Some(stuck_after);
+        self
+    }
+
+    pub fn param(mut self, name: &str, value: &str) -> PipelineBuilder {
+        self.pipeline
+            .params
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // Stages run in the order they are added
+    pub fn stage(mut self, stage: &str) -> PipelineBuilder {
+        self.pipeline.stages.push(stage.to_string());
+        self
+    }
+
+    pub fn job(mut self, job: JobBuilder) -> PipelineBuilder {
+        self.pipeline.jobs.push(job.job);
+        self
+    }
+
+    pub fn build(self) -> Result<Pipeline, Error> {
+        let mut pipeline = self.pipeline;
+
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+
+        if pipeline.id.is_empty() || !pipeline.id.chars().all(valid) {
+            return Err(ErrorKind::InvalidPipelineId(pipeline.id).into());
+        }
+
+        pipeline.interval = Interval::with_compat(&pipeline.expression, pipeline.compat)?;
+        pipeline.interval.timezone = pipeline.timezone.unwrap_or_default();
+        pipeline.link_jobs();
+
+        if pipeline.max_queue_depth == Some(0) {
+            let message = format!("{}: max_queue_depth must be at least 1", pipeline.id);
+
+            return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+        }
+
+        if pipeline.disable_after_failures == Some(0) {
+            let message = format!("{}: disable_after_failures must be at least 1", pipeline.id);
+
+            return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+        }
+
+        if pipeline.weight == 0 {
+            let message = format!("{}: weight must be at least 1", pipeline.id);
+
+            return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+        }
+
+        if !(0..=MAX_RETENTION_DAYS).contains(&pipeline.history_days) {
+            let message = format!(
+                "{}: history_days must be between 0 and {}",
+                pipeline.id, MAX_RETENTION_DAYS
+            );
+
+            return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+        }
+
+        if !(0..=MAX_RETENTION_DAYS).contains(&pipeline.log_days) {
+            let message = format!(
+                "{}: log_days must be between 0 and {}",
+                pipeline.id, MAX_RETENTION_DAYS
+            );
+
+            return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+        }
+
+        for service in &pipeline.depends_on_services {
+            if let Err(err) = service.validate() {
+                let message = format!("{}: {}", pipeline.id, err);
+
+                return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+            }
+        }
+
+        if pipeline
+            .interpreter
+            .as_ref()
+            .is_some_and(|interpreter| interpreter.trim().is_empty())
+        {
+            let message = format!("{}: interpreter is empty", pipeline.id);
+
+            return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+        }
+
+        let mut stages = HashSet::new();
+
+        for stage in &pipeline.stages {
+            if !stages.insert(stage) {
+                let message = format!("{}/{}: stage is listed twice", pipeline.id, stage);
+
+                return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+            }
+        }
+
+        let mut breadcrumbs = HashSet::new();
+
+        for job in &pipeline.jobs {
+            if let Err(err) = validate_job(job, &stages) {
+                let message = format!("{}: {}", job.breadcrumb, err);
+
+                return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+            }
+
+            if !breadcrumbs.insert(&job.breadcrumb) {
+                let message = format!("{}: job is defined twice", job.breadcrumb);
+
+                return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+            }
+        }
+
+        for stage in &pipeline.stages {
+            if let Err(err) = pipeline.job_groups(stage) {
+                let message = format!("{}/{}: {}", pipeline.id, stage, err);
+
+                return Err(ErrorKind::InvalidPipelineDefinition(message).into());
+            }
+        }
+
+        // Changes of the definition are detected like changes of a pipeline file
+        let definition = serde_json::to_string(&pipeline).unwrap_or_default();
+        pipeline.checksum = format!("{:x}", Sha256::digest(definition.as_bytes()));
+
+        Ok(pipeline)
+    }
+}
+
+impl JobBuilder {
+    pub fn new(id: &str, stage: &str) -> JobBuilder {
+        JobBuilder {
+            job: Job {
+                id: id.to_string(),
+                breadcrumb: String::new(),
+                stage: stage.to_string(),
+                script: String::new(),
+                args: Vec::new(),
+                env: BTreeMap::new(),
+                pass_env: None,
+                produces: Vec::new(),
+                consumes: Vec::new(),
+                hosts: Vec::new(),
+                allowed_window: None,
+                outside_window: OutsideWindow::Wait,
+                output_format: None,
+                function: None,
+                kind: None,
+                config: Map::new(),
+                path: String::new(),
+                folder: String::new(),
+            },
+        }
+    }
+
+    // Relative to the folder of the pipeline
+    pub fn script(mut self, script: &str) -> JobBuilder {
+        self.job.script = script.to_string();
+        self
+    }
+
+    // Like {{ jobs.build.outputs.id }}, resolved when the job starts
+    pub fn arg(mut self, arg: &str) -> JobBuilder {
+        self.job.args.push(arg.to_string());
+        self
+    }
+
+    pub fn env(mut self, name: &str, value: &str) -> JobBuilder {
+        self.job.env.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // Host variables the script inherits, like "AWS_*", instead of all of them
+    pub fn pass_env(mut self, glob: &str) -> JobBuilder {
+        self.job
+            .pass_env
+            .get_or_insert_with(Vec::new)
+            .push(glob.to_string());
+        self
+    }
+
+    // Relative to the folder of the pipeline, jobs of the stage consuming
+    // the file start after this one
+    pub fn produces(mut self, file: &str) -> JobBuilder {
+        self.job.produces.push(file.to_string());
+        self
+    }
+
+    pub fn consumes(mut self, file: &str) -> JobBuilder {
+        self.job.consumes.push(file.to_string());
+        self
+    }
+
+    // SSH host the script runs on, once per host in parallel
+    pub fn host(mut self, host: &str) -> JobBuilder {
+        self.job.hosts.push(host.to_string());
+        self
+    }
+
+    // The job waits for the window to open, or is skipped
+    pub fn allowed_window(mut self, window: Window, outside: OutsideWindow) -> JobBuilder {
+        self.job.allowed_window = Some(window);
+        self.job.outside_window = outside;
+        self
+    }
+
+    pub fn output_format(mut self, output_format: OutputFormat) -> JobBuilder {
+        self.job.output_format = Some(output_format);
+        self
+    }
+
+    pub fn function(mut self, name: &str) -> JobBuilder {
+        self.job.function = Some(name.to_string());
+        self
+    }
+
+    pub fn job_type(mut self, name: &str) -> JobBuilder {
+        self.job.kind = Some(name.to_string());
+        self
+    }
+
+    // A field of the job type config
+    pub fn config(mut self, name: &str, value: Value) -> JobBuilder {
+        self.job.config.insert(name.to_string(), value);
+        self
+    }
+}
+
+fn validate_job(job: &Job, stages: &HashSet<&String>) -> Result<(), String> {
+    if job.id.is_empty() {
+        return Err(String::from("job has no id"));
+    }
+
+    if !stages.contains(&job.stage) {
+        return Err(String::from("job stage is not listed"));
+    }
+
+    let runners = [
+        !job.script.is_empty(),
+        job.function.is_some(),
+        job.kind.is_some(),
+    ];
+
+    if runners.iter().filter(|runner| **runner).count() != 1 {
+        return Err(String::from(
+            "job needs exactly one of a script, a function or a type",
+        ));
+    }
+
+    for glob in job.pass_env.iter().flatten() {
+        Pattern::new(glob).map_err(|_| format!("invalid pass_env pattern {}", glob))?;
+    }
+
+    if !job.hosts.is_empty() && job.script.is_empty() {
+        return Err(String::from("only jobs with a script run on hosts"));
+    }
+
+    if let Some(host) = job.hosts.iter().find(|host| !executor::is_host(host)) {
+        return Err(format!("invalid host {}", host));
+    }
+
+    if job.output_format.is_some() && (job.script.is_empty() || !job.hosts.is_empty()) {
+        return Err(String::from(
+            "only jobs with a script on the scheduler host have an output format",
+        ));
+    }
+
+    if let Some(kind) = &job.kind {
+        let job_type = plugin::find(kind).ok_or(format!("unknown job type {}", kind))?;
+
+        job_type.validate(&job.config)?;
+    } else if !job.config.is_empty() {
+        return Err(String::from("only jobs with a type have a config"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::executor::Options;
+    use super::super::function;
+    use super::*;
+    use serde_json::json;
+
+    fn builder() -> PipelineBuilder {
+        PipelineBuilder::new("built")
+            .expression("0 * * * *")
+            .stage("main")
+    }
+
+    #[test]
+    fn build_runnable_pipelines() {
+        function::register("test-built", |context| {
+            context.print(context.param("TARGET").unwrap_or("?"));
+
+            Ok(())
+        });
+
+        let pipeline = builder()
+            .folder("/srv/pipelines/built")
+            .param("TARGET", "staging")
+            .job(JobBuilder::new("report", "main").function("test-built"))
+            .job(JobBuilder::new("load", "main").script("load.sh"))
+            .build()
+            .unwrap();
+
+        assert_eq!(pipeline.jobs[0].breadcrumb, "built/main/report");
+        assert_eq!(pipeline.jobs[1].path, "/srv/pipelines/built/load.sh");
+        assert!(!pipeline.checksum.is_empty());
+
+        let pipeline = builder()
+            .param("TARGET", "staging")
+            .job(JobBuilder::new("report", "main").function("test-built"))
+            .build()
+            .unwrap();
+
+        let options = Options::default();
+
+        assert!(executor::execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[built/main/report] staging")]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_definitions() {
+        let invalid = vec![
+            PipelineBuilder::new("no spaces").expression("0 * * * *"),
+            PipelineBuilder::new("built").expression("every hour"),
+            builder().stage("main"),
+            builder().job(JobBuilder::new("job", "other").script("job.sh")),
+            builder().job(JobBuilder::new("job", "main")),
+            builder().job(JobBuilder::new("job", "main").script("a.sh").function("a")),
+            builder()
+                .job(JobBuilder::new("job", "main").script("a.sh"))
+                .job(JobBuilder::new("job", "main").script("b.sh")),
+            builder().job(JobBuilder::new("job", "main").job_type("test-unknown")),
+            builder().job(JobBuilder::new("job", "main").job_type("http_request")),
+            builder().max_queue_depth(0),
+            builder().disable_after_failures(0),
+            builder().weight(0, None),
+            builder().history_days(-1),
+            builder().history_days(MAX_RETENTION_DAYS + 1),
+            builder().log_days(-1),
+            builder().log_days(i64::MAX),
+            builder().job(JobBuilder::new("job", "main").script("a.sh").pass_env("[")),
+            builder().job(JobBuilder::new("job", "main").function("a").host("web1")),
+            builder().job(
+                JobBuilder::new("job", "main")
+                    .script("a.sh")
+                    .host("-oProxyCommand=x"),
+            ),
+            builder()
+                .job(
+                    JobBuilder::new("a", "main")
+                        .script("a.sh")
+                        .produces("a.csv")
+                        .consumes("b.csv"),
+                )
+                .job(
+                    JobBuilder::new("b", "main")
+                        .script("b.sh")
+                        .produces("b.csv")
+                        .consumes("a.csv"),
+                ),
+        ];
+
+        for builder in invalid {
+            assert!(builder.build().is_err());
+        }
+
+        let pipeline = builder()
+            .job(
+                JobBuilder::new("ping", "main")
+                    .job_type("http_request")
+                    .config("url", json!("https://example.com/hook")),
+            )
+            .build();
+
+        assert!(pipeline.is_ok());
+    }
+}
@@ -0,0 +1,244 @@
+use super::error::Error;
+use super::pipeline::Pipeline;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Next occurrence and definition checksum of each pipeline by state path, so
+// pipelines that aren't due don't read their state on every tick
+static NEXT_TIMES: Mutex<BTreeMap<String, (String, DateTime<Utc>)>> = Mutex::new(BTreeMap::new());
+
+// Parsed pipelines of the scheduler loop, files are parsed again only when they change
+#[derive(Default)]
+pub struct PipelineCache {
+    entries: HashMap<String, Entry>,
+}
+
+struct Entry {
+    metadata: Metadata,
+
+    pipeline: Pipeline,
+}
+
+// Modification time and size of a file
+type Metadata = (SystemTime, u64);
+
+impl PipelineCache {
+    pub fn new() -> PipelineCache {
+        PipelineCache::default()
+    }
+
+    // Same as Pipeline::read_dir
+    pub fn read_dir(
+        &mut self,
+        pipelines_path: &str,
+    ) -> Result<Vec<Result<Pipeline, Error>>, Error> {
+        let files = Pipeline::find_files(pipelines_path)?;
+
+        // Removed pipelines are forgotten
+        let found: HashSet<&String> = files.iter().collect();
+        self.entries.retain(|path, _| found.contains(path));
+
+        let metadata: Vec<Option<Metadata>> = files.iter().map(|file| metadata(file)).collect();
+
+        // Invalid files are read again on every tick, so fixing them is noticed
+        let stale: Vec<String> = files
+            .iter()
+            .zip(&metadata)
+            .filter(|(file, metadata)| !self.is_fresh(file, metadata))
+            .map(|(file, _)| file.to_string())
+            .collect();
+
+        let mut parsed: HashMap<String, Result<Pipeline, Error>> = stale
+            .iter()
+            .cloned()
+            .zip(Pipeline::read_files(&stale))
+            .collect();
+
+        Ok(files
+            .iter()
+            .zip(metadata)
+            .map(|(file, metadata)| match parsed.remove(file) {
+                Some(pipeline) => self.store(file, metadata, pipeline),
+                None => Ok(self.entries[file].pipeline.clone()),
+            })
+            .collect())
+    }
+
+    fn is_fresh(&self, pipeline_path: &str, metadata: &Option<Metadata>) -> bool {
+        match (self.entries.get(pipeline_path), metadata) {
+            (Some(entry), Some(metadata)) => entry.metadata == *metadata,
+            _ => false,
+        }
+    }
+
+    fn store(
+        &mut self,
+        pipeline_path: &str,
+        metadata: Option<Metadata>,
+        pipeline: Result<Pipeline, Error>,
+    ) -> Result<Pipeline, Error> {
+        match (&pipeline, metadata) {
+            (Ok(pipeline), Some(metadata)) => {
+                let entry = Entry {
+                    metadata,
+                    pipeline: pipeline.clone(),
+                };
+
+                self.entries.insert(pipeline_path.to_string(), entry);
+            }
+            _ => {
+                self.entries.remove(pipeline_path);
+            }
+        }
+
+        pipeline
+    }
+}
+
+fn metadata(pipeline_path: &str) -> Option<Metadata> {
+    let metadata = fs::metadata(pipeline_path).ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+// Unknown when the definition changed since it was saved
+pub fn next_time(state_path: &str, checksum: &str) -> Option<DateTime<Utc>> {
+    NEXT_TIMES
+        .lock()
+        .unwrap()
+        .get(state_path)
+        .filter(|(saved, _)| saved == checksum)
+        .map(|(_, next)| *next)
+}
+
+pub fn set_next_time(state_path: &str, checksum: &str, next: DateTime<Utc>) {
+    NEXT_TIMES
+        .lock()
+        .unwrap()
+        .insert(state_path.to_string(), (checksum.to_string(), next));
+}
+
+// Called when the state changes, runs and pauses move the next occurrence
+pub fn invalidate_next_time(state_path: &str) {
+    NEXT_TIMES.lock().unwrap().remove(state_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::time::Duration;
+
+    fn write(path: &str, id: &str, modified: SystemTime) {
+        let pipeline = format!(r#"{{"id": "{}", "expression": "0 * * * *"}}"#, id);
+
+        fs::write(path, pipeline).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn parse_only_changed_files() {
+        let folder = env::temp_dir().join("rusty-scheduler-cache");
+        let pipeline_dir = folder.join("cached");
+
+        fs::create_dir_all(&pipeline_dir).unwrap();
+
+        let folder = folder.to_string_lossy().to_string();
+        let path = pipeline_dir.join("pipeline.json");
+        let path = path.to_string_lossy().to_string();
+
+        let modified = SystemTime::now() - Duration::from_secs(60);
+
+        write(&path, "first", modified);
+
+        let mut cache = PipelineCache::new();
+
+        let ids = |cache: &mut PipelineCache| -> Vec<String> {
+            cache
+                .read_dir(&folder)
+                .unwrap()
+                .into_iter()
+                .map(|pipeline| pipeline.unwrap().id)
+                .collect()
+        };
+
+        assert_eq!(ids(&mut cache), vec!["first"]);
+
+        // Same size and time, the cached pipeline is kept
+        write(&path, "other", modified);
+
+        assert_eq!(ids(&mut cache), vec!["first"]);
+
+        write(&path, "other", SystemTime::now());
+
+        assert_eq!(ids(&mut cache), vec!["other"]);
+
+        fs::remove_dir_all(&pipeline_dir).unwrap();
+
+        assert!(ids(&mut cache).is_empty());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn read_many_pipelines_in_order() {
+        let folder = env::temp_dir().join("rusty-scheduler-many");
+
+        let _ = fs::remove_dir_all(&folder);
+
+        for index in 0..200 {
+            let pipeline_dir = folder.join(format!("p{:03}", index));
+
+            fs::create_dir_all(&pipeline_dir).unwrap();
+
+            let path = pipeline_dir.join("pipeline.json");
+
+            write(
+                &path.to_string_lossy(),
+                &format!("p{:03}", index),
+                SystemTime::now(),
+            );
+        }
+
+        let folder = folder.to_string_lossy().to_string();
+
+        let files = Pipeline::find_files(&folder).unwrap();
+
+        let ids: Vec<String> = PipelineCache::new()
+            .read_dir(&folder)
+            .unwrap()
+            .into_iter()
+            .map(|pipeline| pipeline.unwrap().id)
+            .collect();
+
+        assert_eq!(ids.len(), 200);
+
+        for (file, id) in files.iter().zip(ids) {
+            assert!(file.contains(&format!("/{}/", id)));
+        }
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn forget_next_times_of_changed_pipelines() {
+        let next = Utc::now();
+
+        set_next_time("/tmp/next/state.json", "a", next);
+
+        assert_eq!(next_time("/tmp/next/state.json", "a"), Some(next));
+        assert_eq!(next_time("/tmp/next/state.json", "b"), None);
+
+        invalidate_next_time("/tmp/next/state.json");
+
+        assert_eq!(next_time("/tmp/next/state.json", "a"), None);
+    }
+}
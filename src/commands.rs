@@ -0,0 +1,757 @@
+use super::analyze;
+use super::archive::Archive;
+use super::console::{self, Color};
+use super::control::{self, Request, Response};
+use super::crontab;
+use super::digest::{self, Digest};
+use super::doctor::{self, Level};
+use super::error::{Error, ErrorKind};
+use super::executor;
+use super::explain;
+use super::export;
+use super::graph;
+use super::history::Report;
+use super::lock::Lock;
+use super::logs;
+use super::overview::Overview;
+use super::pipeline::Pipeline;
+use super::scaffold;
+use super::scheduler;
+use super::security::Security;
+use super::state::State;
+use super::timezone;
+use super::websocket;
+use chrono::{DateTime, Utc};
+use failure::ResultExt;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+
+pub fn load_pipelines(pipelines_path: &str) -> Result<Vec<Pipeline>, Error> {
+    let pipelines = Pipeline::read_dir(pipelines_path)?;
+
+    Ok(scheduler::unwrap_pipelines(Ok(pipelines)))
+}
+
+pub fn find_pipeline<'a>(pipelines: &'a [Pipeline], id: &str) -> Result<&'a Pipeline, Error> {
+    let pipeline = pipelines
+        .iter()
+        .find(|pipeline| pipeline.id == id)
+        .ok_or_else(|| ErrorKind::PipelineNotFound(id.to_string()))?;
+
+    Ok(pipeline)
+}
+
+pub fn init(pipelines_path: &str, id: &str) -> Result<(), Error> {
+    let pipeline_dir = scaffold::create(pipelines_path, id)?;
+
+    println!("Pipeline created: {}", pipeline_dir);
+
+    Ok(())
+}
+
+pub fn list(pipelines_path: &str, json: bool) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let overviews: Vec<Overview> = pipelines.iter().map(Overview::from_pipeline).collect();
+
+    if json {
+        let data = serde_json::to_string_pretty(&overviews)
+            .context(ErrorKind::InvalidPipelineFolder(pipelines_path.to_string()))?;
+
+        println!("{}", data);
+
+        return Ok(());
+    }
+
+    let rows = overviews
+        .iter()
+        .map(|overview| {
+            vec![
+                overview.id.to_string(),
+                overview.schedule.to_string(),
+                console::status(overview.status()),
+                match overview.last_result {
+                    Some(status) => format!(
+                        "{} ({})",
+                        console::status(&status.to_string()),
+                        format_time(overview.last_run)
+                    ),
+                    None => console::status("never"),
+                },
+                format_time(Some(overview.next_run)),
+            ]
+        })
+        .collect();
+
+    print_table(&["ID", "SCHEDULE", "STATUS", "LAST RUN", "NEXT RUN"], rows);
+
+    Ok(())
+}
+
+pub fn run(pipelines_path: &str, id: &str, stages: &[&str], jobs: &[&str]) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let pipeline = find_pipeline(&pipelines, id)?;
+
+    let selected = pipeline.select(stages, jobs)?;
+
+    let lock = Lock::acquire_from_pipeline(pipeline)?
+        .ok_or_else(|| ErrorKind::PipelineAlreadyRunning(id.to_string()))?;
+
+    let options = executor::Options {
+        stream: true,
+        progress: true,
+        ..executor::Options::default()
+    };
+
+    let status = executor::execute(&selected, &options);
+
+    lock.release()?;
+
+    println!(
+        "{} {}",
+        console::paint("Run summary:", Color::Bold),
+        Report::new(&selected, &options.output, 0)
+    );
+
+    status?;
+
+    println!(
+        "{} {}",
+        console::paint("Pipeline completed:", Color::Green),
+        id
+    );
+
+    Ok(())
+}
+
+pub fn next(pipelines_path: &str, id: Option<&str>, count: usize) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let pipelines: Vec<&Pipeline> = match id {
+        Some(id) => vec![find_pipeline(&pipelines, id)?],
+        None => pipelines.iter().collect(),
+    };
+
+    let now = Utc::now();
+
+    let mut occurrences: Vec<(DateTime<Utc>, &str)> = pipelines
+        .iter()
+        .flat_map(|pipeline| {
+            pipeline
+                .interval
+                .preview(now, count)
+                .into_iter()
+                .map(move |time| (time, pipeline.id.as_str()))
+        })
+        .collect();
+
+    occurrences.sort();
+    occurrences.truncate(count);
+
+    let rows = occurrences
+        .into_iter()
+        .map(|(time, id)| vec![format_time(Some(time)), id.to_string()])
+        .collect();
+
+    print_table(&["TIME", "PIPELINE"], rows);
+
+    Ok(())
+}
+
+pub fn explain(pipelines_path: &str, id: &str) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let pipeline = find_pipeline(&pipelines, id)?;
+
+    let state = State::read_file(&State::path_from_pipeline(pipeline)).ok();
+
+    // Active runs of the running scheduler, or the lock held by the run
+    // command when no scheduler is running
+    let running = match control::send(pipelines_path, &Request::Status) {
+        Ok(Response::Status(status)) => status.active.iter().any(|run| run.id == pipeline.id),
+        _ => Lock::is_held_from_pipeline(pipeline)?,
+    };
+
+    let explanation = explain::explain(pipeline, state.as_ref(), running, Utc::now());
+
+    let width = explanation
+        .facts
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+
+    for (name, value) in &explanation.facts {
+        println!(
+            "{:<width$}  {}",
+            format!("{}:", name),
+            value,
+            width = width + 1
+        );
+    }
+
+    println!();
+
+    if explanation.will_run {
+        println!(
+            "{} {}",
+            console::paint("Runs on the next tick:", Color::Green),
+            explanation.reason
+        );
+    } else {
+        println!(
+            "{} {}",
+            console::paint("Does not run on the next tick:", Color::Yellow),
+            explanation.reason
+        );
+    }
+
+    Ok(())
+}
+
+pub fn graph(pipelines_path: &str, id: &str, dot: bool) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let pipeline = find_pipeline(&pipelines, id)?;
+
+    if dot {
+        println!("{}", graph::dot(pipeline));
+    } else {
+        println!("{}", graph::ascii(pipeline));
+    }
+
+    Ok(())
+}
+
+pub fn doctor(pipelines_path: &str) -> Result<(), Error> {
+    let checks = doctor::run(pipelines_path);
+
+    for check in &checks {
+        let level = console::status(&check.level.to_string());
+
+        println!("[{}] {}", console::pad(&level, 4), check.message);
+
+        if let Some(hint) = &check.hint {
+            println!("       {}", hint);
+        }
+    }
+
+    let failures = checks
+        .iter()
+        .filter(|check| check.level == Level::Failure)
+        .count();
+
+    if failures > 0 {
+        return Err(ErrorKind::DoctorFailed(failures).into());
+    }
+
+    Ok(())
+}
+
+pub fn validate(pipelines_path: &str) -> Result<(), Error> {
+    let mut invalid = 0;
+
+    for pipeline in Pipeline::read_dir(pipelines_path)? {
+        let pipeline = match pipeline {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                println!("[{}] {}", console::status("fail"), err);
+
+                invalid += 1;
+
+                continue;
+            }
+        };
+
+        let mut warnings = pipeline.interval.lint();
+
+        warnings.extend(
+            pipeline
+                .empty_stages()
+                .iter()
+                .map(|stage| format!("Stage has no jobs: {}", stage)),
+        );
+
+        if warnings.is_empty() {
+            println!(
+                "[{}] {} ({})",
+                console::pad(&console::status("ok"), 4),
+                pipeline.id,
+                pipeline.expression
+            );
+        }
+
+        for warning in warnings {
+            println!(
+                "[{}] {} ({}): {}",
+                console::status("warn"),
+                pipeline.id,
+                pipeline.expression,
+                warning
+            );
+        }
+    }
+
+    if invalid > 0 {
+        return Err(ErrorKind::InvalidPipelines(invalid).into());
+    }
+
+    Ok(())
+}
+
+pub fn status(pipelines_path: &str) -> Result<(), Error> {
+    let status = match control::send(pipelines_path, &Request::Status)? {
+        Response::Status(status) => status,
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("status")).into()),
+    };
+
+    println!(
+        "Scheduler running since {}",
+        format_time(Some(status.started))
+    );
+    println!();
+
+    if status.active.is_empty() {
+        println!("No active runs");
+    } else {
+        let rows = status
+            .active
+            .iter()
+            .map(|run| {
+                vec![
+                    run.id.to_string(),
+                    run.run_id.to_string(),
+                    format_time(run.scheduled),
+                    format_time(Some(run.start)),
+                ]
+            })
+            .collect();
+
+        print_table(&["ACTIVE", "RUN", "SCHEDULED", "STARTED"], rows);
+    }
+
+    println!();
+
+    let rows = status
+        .pipelines
+        .iter()
+        .map(|overview| {
+            vec![
+                overview.id.to_string(),
+                console::status(overview.status()),
+                match overview.last_result {
+                    Some(status) => format!(
+                        "{} ({})",
+                        console::status(&status.to_string()),
+                        format_time(overview.last_run)
+                    ),
+                    None => console::status("never"),
+                },
+                format_time(Some(overview.next_run)),
+            ]
+        })
+        .collect();
+
+    print_table(&["PIPELINE", "STATUS", "LAST RUN", "NEXT RUN"], rows);
+
+    Ok(())
+}
+
+pub fn pause(pipelines_path: &str, id: Option<&str>, paused: bool) -> Result<(), Error> {
+    let pipeline = id.map(|id| id.to_string());
+
+    let request = if paused {
+        Request::Pause { pipeline }
+    } else {
+        Request::Resume { pipeline }
+    };
+
+    let pipelines = match control::send(pipelines_path, &request)? {
+        Response::Updated { pipelines } => pipelines,
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("pause")).into()),
+    };
+
+    for id in pipelines {
+        if paused {
+            println!("Pipeline paused: {}", id);
+        } else {
+            println!("Pipeline resumed: {}", id);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn trigger(
+    pipelines_path: &str,
+    id: &str,
+    params: Vec<&str>,
+    skip_stages: Vec<&str>,
+    skip_jobs: Vec<&str>,
+    labels: Vec<&str>,
+) -> Result<(), Error> {
+    let params = params
+        .into_iter()
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(ErrorKind::InvalidPipelineParameter(param.to_string())),
+        })
+        .collect::<Result<BTreeMap<String, String>, ErrorKind>>()?;
+
+    let labels = labels
+        .into_iter()
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if executor::is_variable_name(key) => {
+                Ok((key.to_string(), value.to_string()))
+            }
+            _ => Err(ErrorKind::InvalidRunLabel(label.to_string())),
+        })
+        .collect::<Result<BTreeMap<String, String>, ErrorKind>>()?;
+
+    let request = Request::Trigger {
+        pipeline: id.to_string(),
+        params,
+        labels,
+        skip_stages: skip_stages.into_iter().map(String::from).collect(),
+        skip_jobs: skip_jobs.into_iter().map(String::from).collect(),
+    };
+
+    match control::send(pipelines_path, &request)? {
+        Response::Triggered { run_id } => println!("{}", run_id),
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("trigger")).into()),
+    };
+
+    Ok(())
+}
+
+pub fn rerun(pipelines_path: &str, run_id: &str) -> Result<(), Error> {
+    let request = Request::Rerun {
+        run_id: run_id.to_string(),
+    };
+
+    match control::send(pipelines_path, &request)? {
+        Response::Triggered { run_id } => println!("{}", run_id),
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("rerun")).into()),
+    };
+
+    Ok(())
+}
+
+pub fn export(pipelines_path: &str, format: &str, days: i64) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    match format {
+        "crontab" => {
+            // Cron runs with a different working directory, so paths are absolute
+            let executable = env::current_exe()
+                .context(ErrorKind::InvalidPipelineFolder(pipelines_path.to_string()))?;
+            let pipelines_dir = fs::canonicalize(pipelines_path)
+                .context(ErrorKind::InvalidPipelineFolder(pipelines_path.to_string()))?;
+
+            let command = format!(
+                "{} --pipelines {}",
+                executable.to_string_lossy(),
+                pipelines_dir.to_string_lossy()
+            );
+
+            println!("{}", export::crontab(&pipelines, &command));
+        }
+        "ics" => {
+            let now = Utc::now();
+
+            let ics = export::ics(
+                &pipelines,
+                now,
+                now + chrono::Duration::days(days),
+                average_duration,
+            );
+
+            print!("{}", ics);
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+// Prints the times of the next days when at least `min_runs` runs are active
+// at once, with start offsets spreading them
+pub fn analyze(pipelines_path: &str, days: i64, min_runs: usize) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let now = Utc::now();
+
+    let runs = analyze::runs(
+        &pipelines,
+        now,
+        now + chrono::Duration::days(days),
+        average_duration,
+    );
+
+    let overlaps = analyze::overlaps(&runs, min_runs);
+
+    if overlaps.is_empty() {
+        println!(
+            "No overlap of {} runs or more in the next {} days",
+            min_runs, days
+        );
+
+        return Ok(());
+    }
+
+    for overlap in overlaps {
+        println!(
+            "{} {} runs at once for {}m, {} times: {}",
+            console::paint(&format_time(Some(overlap.start)), Color::Bold),
+            overlap.peak,
+            ((overlap.end - overlap.start).num_seconds() + 59) / 60,
+            overlap.count,
+            overlap
+                .runs
+                .iter()
+                .map(|run| run.pipeline.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+
+        let offsets: Vec<String> = overlap
+            .offsets
+            .iter()
+            .map(|(pipeline, minutes)| format!("{} +{}m", pipeline, minutes))
+            .collect();
+
+        println!("  Suggested start offsets: {}", offsets.join(", "));
+    }
+
+    Ok(())
+}
+
+// Of the runs of the pipeline, zero without a state
+fn average_duration(pipeline: &Pipeline) -> f64 {
+    match State::read_file(&State::path_from_pipeline(pipeline)) {
+        Ok(state) => state.statistics.average_duration,
+        Err(_) => 0.0,
+    }
+}
+
+// Prints the digest of the last days, or emails it with the digest settings
+pub fn digest(pipelines_path: &str, days: i64, send: bool) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let now = Utc::now();
+
+    let digest = Digest::read(&pipelines, now - chrono::Duration::days(days), now);
+
+    if !send {
+        print!("{}", digest.to_html());
+
+        return Ok(());
+    }
+
+    let mailer =
+        digest::mailer().ok_or_else(|| ErrorKind::MissingSetting(String::from("digest_smtp")))?;
+
+    mailer.send(&digest.subject(), &digest.to_html())?;
+
+    println!("Digest sent: {}", mailer.to.join(", "));
+
+    Ok(())
+}
+
+pub fn import(pipelines_path: &str, crontab_path: &str, system: bool) -> Result<(), Error> {
+    let data = fs::read_to_string(crontab_path)
+        .context(ErrorKind::InvalidCrontabFile(crontab_path.to_string()))?;
+
+    let system = system || crontab::is_system(crontab_path);
+
+    for entry in crontab::parse(&data, system) {
+        let created = entry.and_then(|entry| {
+            let id = format!("cron-{}", entry.line);
+
+            crontab::create_pipeline(pipelines_path, &id, &entry)
+        });
+
+        match created {
+            Ok(pipeline_dir) => println!("Pipeline created: {}", pipeline_dir),
+            Err(err) => println!("Skipped: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn logs(pipelines_path: &str, id: &str) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let pipeline = find_pipeline(&pipelines, id)?;
+
+    match logs::latest(pipeline)? {
+        Some(log_path) => print!("{}", logs::read(&log_path)?),
+        None => println!("No run log: {}", id),
+    }
+
+    Ok(())
+}
+
+// Address of the HTTP API of a local or remote scheduler
+pub fn follow_logs(address: &str, id: &str, security: &Security) -> Result<(), Error> {
+    let mut client = websocket::Client::connect(
+        address,
+        &format!("/pipelines/{}/output/stream", id),
+        security,
+    )?;
+
+    while let Some(line) = client.read_text()? {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+pub fn cancel(pipelines_path: &str, id: &str) -> Result<(), Error> {
+    let request = Request::Cancel {
+        pipeline: id.to_string(),
+    };
+
+    match control::send(pipelines_path, &request)? {
+        Response::Updated { .. } => println!("Pipeline cancelled: {}", id),
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("cancel")).into()),
+    };
+
+    Ok(())
+}
+
+pub fn queue(pipelines_path: &str) -> Result<(), Error> {
+    let runs = match control::send(pipelines_path, &Request::Queue)? {
+        Response::Queue { runs } => runs,
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("queue")).into()),
+    };
+
+    if runs.is_empty() {
+        println!("No queued runs");
+
+        return Ok(());
+    }
+
+    let rows = runs
+        .iter()
+        .map(|run| {
+            vec![
+                run.pipeline.to_string(),
+                format_time(Some(run.scheduled)),
+                run.reason.to_string(),
+            ]
+        })
+        .collect();
+
+    print_table(&["PIPELINE", "SCHEDULED", "REASON"], rows);
+
+    Ok(())
+}
+
+pub fn dequeue(
+    pipelines_path: &str,
+    id: &str,
+    scheduled: Option<DateTime<Utc>>,
+) -> Result<(), Error> {
+    let request = Request::Dequeue {
+        pipeline: id.to_string(),
+        scheduled,
+    };
+
+    match control::send(pipelines_path, &request)? {
+        Response::Queue { runs } => {
+            for run in runs {
+                println!(
+                    "Queued run removed: {} ({})",
+                    id,
+                    format_time(Some(run.scheduled))
+                );
+            }
+        }
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("dequeue")).into()),
+    };
+
+    Ok(())
+}
+
+// The scheduler stops starting runs and exits once the active ones complete
+pub fn drain(pipelines_path: &str) -> Result<(), Error> {
+    match control::send(pipelines_path, &Request::Drain)? {
+        Response::Draining { active } if active.is_empty() => {
+            println!("Scheduler draining, no active runs")
+        }
+        Response::Draining { active } => println!(
+            "Scheduler draining, waiting for {} active run(s): {}",
+            active.len(),
+            active.join(", ")
+        ),
+        _ => return Err(ErrorKind::ControlRequestFailed(String::from("drain")).into()),
+    };
+
+    Ok(())
+}
+
+pub fn dump(pipelines_path: &str, archive_path: Option<&str>) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let archive = Archive::from_pipelines(&pipelines)?;
+
+    match archive_path {
+        Some(archive_path) => archive.write_file(archive_path)?,
+        None => println!("{}", archive.to_json("-")?),
+    }
+
+    Ok(())
+}
+
+pub fn restore(pipelines_path: &str, archive_path: &str) -> Result<(), Error> {
+    let pipelines = load_pipelines(pipelines_path)?;
+
+    let archive = Archive::read_file(archive_path)?;
+
+    let total = archive.pipelines.len();
+    let restored = archive.restore(&pipelines)?;
+
+    println!("Restored {} of {} pipelines", restored, total);
+
+    if restored < total {
+        Err(ErrorKind::PartialRestore(archive_path.to_string()))?
+    } else {
+        Ok(())
+    }
+}
+
+pub fn format_time(time: Option<DateTime<Utc>>) -> String {
+    match time {
+        Some(time) => timezone::format(time, "%Y-%m-%d %H:%M"),
+        None => String::from("-"),
+    }
+}
+
+pub fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(console::width(cell));
+        }
+    }
+
+    let headers: Vec<String> = headers
+        .iter()
+        .map(|header| console::paint(header, Color::Bold))
+        .collect();
+
+    for row in std::iter::once(&headers).chain(rows.iter()) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| console::pad(cell, *width))
+            .collect();
+
+        println!("{}", line.join("  ").trim_end());
+    }
+}
@@ -0,0 +1,117 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Green,
+    Red,
+    Yellow,
+    Cyan,
+    Dim,
+    Bold,
+}
+
+pub fn init(no_color: bool) {
+    // Colors are only used on a terminal, see https://no-color.org
+    let terminal = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+
+    let color = !no_color && env::var_os("NO_COLOR").is_none() && terminal;
+
+    COLOR.store(color, Ordering::SeqCst);
+}
+
+pub fn paint(text: &str, color: Color) -> String {
+    if !COLOR.load(Ordering::SeqCst) {
+        return text.to_string();
+    }
+
+    let code = match color {
+        Color::Green => "32",
+        Color::Red => "31",
+        Color::Yellow => "33",
+        Color::Cyan => "36",
+        Color::Dim => "2",
+        Color::Bold => "1",
+    };
+
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+pub fn status(text: &str) -> String {
+    let color = match text {
+        "succeeded" | "enabled" | "ok" => Color::Green,
+        "failed" | "fail" => Color::Red,
+        "skipped" | "cancelled" | "paused" | "warn" => Color::Yellow,
+        "disabled" | "never" => Color::Dim,
+        _ => return text.to_string(),
+    };
+
+    paint(text, color)
+}
+
+// Number of characters shown, without the color codes
+pub fn width(text: &str) -> usize {
+    let mut width = 0;
+    let mut escape = false;
+
+    for c in text.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => {}
+            _ => width += 1,
+        }
+    }
+
+    width
+}
+
+pub fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(self::width(text));
+
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+pub fn stage_started(pipeline: &str, stage: &str, index: usize, total: usize) {
+    println!(
+        "{} {}",
+        paint(&format!("==> [{}/{}]", index, total), Color::Cyan),
+        paint(&format!("{}/{}", pipeline, stage), Color::Bold)
+    );
+}
+
+pub fn stage_completed(stage: &str, duration: Duration, succeeded: bool) {
+    let (status, color) = if succeeded {
+        ("completed", Color::Green)
+    } else {
+        ("failed", Color::Red)
+    };
+
+    println!(
+        "{} {} ({:.1}s)",
+        paint(&format!("==> Stage {}:", status), color),
+        stage,
+        duration.as_secs_f64()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn width_without_color_codes() {
+        assert_eq!(width("failed"), 6);
+        assert_eq!(width("\x1b[31mfailed\x1b[0m"), 6);
+        assert_eq!(width("\x1b[32msucceeded\x1b[0m (2019-07-01)"), 22);
+    }
+
+    #[test]
+    fn pad_colored_text() {
+        assert_eq!(pad("\x1b[2mnever\x1b[0m", 7), "\x1b[2mnever\x1b[0m  ");
+        assert_eq!(pad("never", 3), "never");
+    }
+}
@@ -0,0 +1,747 @@
+use super::audit::{self, Action, Entry};
+use super::error::{Error, ErrorKind};
+use super::executor;
+use super::history::{History, RunStatus};
+use super::lock::Lock;
+use super::overview::Overview;
+use super::pipeline::Pipeline;
+use super::queue::{self, Pending};
+use super::runtime::{ActiveRun, SharedRuntime};
+use super::scheduler::{self, Overrides};
+use super::shutdown;
+use super::state::State;
+use chrono::{DateTime, Utc};
+use failure::ResultExt;
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    Pause {
+        pipeline: Option<String>,
+    },
+    Resume {
+        pipeline: Option<String>,
+    },
+    Trigger {
+        pipeline: String,
+        #[serde(default)]
+        params: BTreeMap<String, String>,
+        #[serde(default)]
+        labels: BTreeMap<String, String>,
+        #[serde(default)]
+        skip_stages: Vec<String>,
+        #[serde(default)]
+        skip_jobs: Vec<String>,
+    },
+    Rerun {
+        run_id: String,
+    },
+    Cancel {
+        pipeline: String,
+    },
+    Queue,
+    Dequeue {
+        pipeline: String,
+        #[serde(default)]
+        scheduled: Option<DateTime<Utc>>,
+    },
+    Drain,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Status(Status),
+    Updated { pipelines: Vec<String> },
+    Triggered { run_id: String },
+    Queue { runs: Vec<Pending> },
+    Draining { active: Vec<String> },
+    Error { message: String },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Status {
+    pub started: DateTime<Utc>,
+
+    #[serde(default)]
+    pub active: Vec<ActiveRun>,
+
+    #[serde(default)]
+    pub pipelines: Vec<Overview>,
+}
+
+// Set once the socket is removed, after that the path may belong to a new scheduler
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+pub fn socket_path(pipelines_path: &str) -> String {
+    let mut socket_path = PathBuf::from(pipelines_path);
+    socket_path.push("scheduler.sock");
+
+    socket_path.to_string_lossy().to_string()
+}
+
+// Removes the socket, so a new scheduler can take over while the runs complete
+pub fn release(pipelines_path: &str) {
+    if !RELEASED.swap(true, Ordering::SeqCst) {
+        let _ = fs::remove_file(socket_path(pipelines_path));
+    }
+}
+
+pub fn serve(pipelines_path: &str, runtime: SharedRuntime) -> Result<(), Error> {
+    let socket_path = socket_path(pipelines_path);
+
+    // A socket left behind by a crashed scheduler refuses connections
+    if UnixStream::connect(&socket_path).is_ok() {
+        RELEASED.store(true, Ordering::SeqCst);
+
+        return Err(ErrorKind::ControlSocketInUse(socket_path).into());
+    }
+
+    let _ = fs::remove_file(&socket_path);
+
+    let listener = UnixListener::bind(&socket_path)
+        .context(ErrorKind::ControlSocketFailed(socket_path.to_string()))?;
+
+    info!("Control socket listening: {}", socket_path);
+
+    let pipelines_path = pipelines_path.to_string();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = handle(stream, &pipelines_path, &runtime) {
+                        error!("{}", err);
+                    }
+                }
+                Err(err) => {
+                    error!("{}", err);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(stream: UnixStream, pipelines_path: &str, runtime: &SharedRuntime) -> Result<(), Error> {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+
+    reader
+        .read_line(&mut line)
+        .context(ErrorKind::ControlRequestFailed(line.to_string()))?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            trace!("Control request: {:?}", request);
+
+            respond(
+                request,
+                pipelines_path,
+                runtime,
+                &audit::socket_actor(&stream),
+            )
+        }
+        Err(err) => Response::Error {
+            message: err.to_string(),
+        },
+    };
+
+    let mut data = serde_json::to_string(&response)
+        .context(ErrorKind::ControlRequestFailed(line.to_string()))?;
+    data.push('\n');
+
+    (&stream)
+        .write_all(data.as_bytes())
+        .context(ErrorKind::ControlRequestFailed(line.to_string()))?;
+
+    Ok(())
+}
+
+pub fn respond(
+    request: Request,
+    pipelines_path: &str,
+    runtime: &SharedRuntime,
+    actor: &str,
+) -> Response {
+    let audit_path = audit::path(pipelines_path);
+
+    match request {
+        Request::Status => {
+            let pipelines = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path));
+
+            let runtime = runtime.lock().unwrap();
+
+            Response::Status(Status {
+                started: runtime.started,
+                active: runtime.active.clone(),
+                pipelines: pipelines.iter().map(Overview::from_pipeline).collect(),
+            })
+        }
+        Request::Pause { pipeline } => {
+            let response = set_paused(pipelines_path, pipeline, true);
+
+            if let Response::Updated { pipelines } = &response {
+                for id in pipelines {
+                    audit::record(&audit_path, &Entry::new(actor, Action::Pause, id));
+                }
+            }
+
+            response
+        }
+        Request::Resume { pipeline } => {
+            let response = set_paused(pipelines_path, pipeline, false);
+
+            if let Response::Updated { pipelines } = &response {
+                for id in pipelines {
+                    audit::record(&audit_path, &Entry::new(actor, Action::Resume, id));
+                }
+            }
+
+            response
+        }
+        Request::Trigger {
+            pipeline,
+            params,
+            labels,
+            skip_stages,
+            skip_jobs,
+        } => {
+            let mut entry = Entry::new(actor, Action::Trigger, &pipeline);
+
+            for (key, value) in &params {
+                entry = entry.detail(&format!("param.{}", key), value);
+            }
+
+            for (key, value) in &labels {
+                entry = entry.detail(&format!("label.{}", key), value);
+            }
+
+            if !skip_stages.is_empty() {
+                entry = entry.detail("skip_stages", &skip_stages.join(","));
+            }
+
+            if !skip_jobs.is_empty() {
+                entry = entry.detail("skip_jobs", &skip_jobs.join(","));
+            }
+
+            let overrides = Overrides {
+                params,
+                labels,
+                skip_stages,
+                skip_jobs,
+                ..Overrides::default()
+            };
+
+            match trigger(pipelines_path, &pipeline, overrides, runtime) {
+                Ok(run_id) => {
+                    audit::record(&audit_path, &entry.detail("run_id", &run_id));
+
+                    Response::Triggered { run_id }
+                }
+                Err(err) => Response::Error {
+                    message: err.to_string(),
+                },
+            }
+        }
+        Request::Rerun { run_id } => match rerun(pipelines_path, &run_id, runtime) {
+            Ok((pipeline, rerun_id)) => {
+                let entry = Entry::new(actor, Action::Trigger, &pipeline)
+                    .detail("rerun_of", &run_id)
+                    .detail("run_id", &rerun_id);
+
+                audit::record(&audit_path, &entry);
+
+                Response::Triggered { run_id: rerun_id }
+            }
+            Err(err) => Response::Error {
+                message: err.to_string(),
+            },
+        },
+        Request::Cancel { pipeline } => {
+            let runtime = runtime.lock().unwrap();
+
+            if runtime.cancel_run(&pipeline) {
+                info!("Pipeline cancel requested: {}", pipeline);
+
+                let run_id = runtime
+                    .find_run(&pipeline)
+                    .map(|run| run.run_id.to_string())
+                    .unwrap_or_default();
+
+                audit::record(
+                    &audit_path,
+                    &Entry::new(actor, Action::Cancel, &pipeline).detail("run_id", &run_id),
+                );
+
+                Response::Updated {
+                    pipelines: vec![pipeline],
+                }
+            } else {
+                Response::Error {
+                    message: ErrorKind::PipelineNotRunning(pipeline).to_string(),
+                }
+            }
+        }
+        Request::Queue => {
+            let pipelines = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path));
+
+            let runtime = runtime.lock().unwrap();
+
+            let now = Utc::now();
+
+            Response::Queue {
+                runs: pipelines
+                    .iter()
+                    .flat_map(|pipeline| {
+                        let state = State::read_from_pipeline(pipeline);
+
+                        queue::pending(pipeline, &state, runtime.find_run(&pipeline.id), now)
+                    })
+                    .collect(),
+            }
+        }
+        Request::Dequeue {
+            pipeline,
+            scheduled,
+        } => match dequeue(pipelines_path, &pipeline, scheduled, runtime) {
+            Ok(runs) => {
+                for run in &runs {
+                    info!(
+                        "Queued run removed ({}): {}",
+                        run.scheduled.to_rfc3339(),
+                        pipeline
+                    );
+
+                    audit::record(
+                        &audit_path,
+                        &Entry::new(actor, Action::Dequeue, &pipeline)
+                            .detail("scheduled", &run.scheduled.to_rfc3339()),
+                    );
+                }
+
+                Response::Queue { runs }
+            }
+            Err(err) => Response::Error {
+                message: err.to_string(),
+            },
+        },
+        Request::Drain => {
+            if !shutdown::is_requested() {
+                info!("Scheduler draining, requested by {}", actor);
+            }
+
+            shutdown::drain();
+
+            release(pipelines_path);
+
+            let runtime = runtime.lock().unwrap();
+
+            Response::Draining {
+                active: runtime
+                    .active
+                    .iter()
+                    .map(|run| run.id.to_string())
+                    .collect(),
+            }
+        }
+    }
+}
+
+fn trigger(
+    pipelines_path: &str,
+    id: &str,
+    overrides: Overrides,
+    runtime: &SharedRuntime,
+) -> Result<String, Error> {
+    if shutdown::is_requested() {
+        return Err(ErrorKind::SchedulerStopping(id.to_string()).into());
+    }
+
+    let pipeline = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path))
+        .into_iter()
+        .find(|pipeline| pipeline.id == id)
+        .ok_or_else(|| ErrorKind::PipelineNotFound(id.to_string()))?;
+
+    if let Some(key) = overrides
+        .labels
+        .keys()
+        .find(|key| !executor::is_variable_name(key))
+    {
+        return Err(ErrorKind::InvalidRunLabel(key.to_string()).into());
+    }
+
+    // Only the declared parameters can be overridden, so a trigger can't set
+    // variables like LD_PRELOAD or PATH, or replace a secret
+    if let Some(key) = overrides
+        .params
+        .keys()
+        .find(|key| !executor::is_variable_name(key) || !pipeline.params.contains_key(*key))
+    {
+        return Err(ErrorKind::InvalidPipelineParameter(key.to_string()).into());
+    }
+
+    // The variables of a rerun are read from the history, so they are held to
+    // the parameters and the variables the scheduler sets
+    if let Some(key) = overrides.env.keys().find(|key| {
+        !executor::is_variable_name(key)
+            || !(pipeline.params.contains_key(*key) || key.starts_with(executor::VARIABLE_PREFIX))
+    }) {
+        return Err(ErrorKind::InvalidPipelineParameter(key.to_string()).into());
+    }
+
+    let stages: Vec<&str> = overrides.skip_stages.iter().map(String::as_str).collect();
+    let jobs: Vec<&str> = overrides.skip_jobs.iter().map(String::as_str).collect();
+
+    let pipeline = pipeline.skip(&stages, &jobs)?;
+
+    if scheduler::is_dry_run(&pipeline) {
+        return Err(ErrorKind::PipelineDryRun(id.to_string()).into());
+    }
+
+    if !scheduler::runs_here(&pipeline) {
+        return Err(
+            ErrorKind::PipelineRunsElsewhere(id.to_string(), pipeline.runs_on.join(",")).into(),
+        );
+    }
+
+    // Bursts of triggers start a single run, whatever their parameters. A
+    // rerun is not collapsed, it would not reproduce anything
+    if let Some(window) = pipeline
+        .dedup_window
+        .filter(|_| overrides.rerun_of.is_none())
+    {
+        let collapsed = runtime.lock().unwrap().collapse_trigger(
+            id,
+            chrono::Duration::seconds(window as i64),
+            Utc::now(),
+        );
+
+        if let Some((run_id, recorded)) = collapsed {
+            info!("Pipeline trigger collapsed into {}: {}", run_id, id);
+
+            if recorded {
+                scheduler::record_collapsed_trigger(&pipeline, &run_id);
+            }
+
+            return Ok(run_id);
+        }
+    }
+
+    let lock = Lock::acquire_from_pipeline(&pipeline)?
+        .ok_or_else(|| ErrorKind::PipelineAlreadyRunning(id.to_string()))?;
+
+    info!("Pipeline triggered: {}", id);
+
+    let overrides = Overrides {
+        dedup: pipeline.dedup_window.is_some(),
+        ..overrides
+    };
+
+    Ok(scheduler::spawn_run(
+        pipeline, lock, runtime, None, overrides,
+    ))
+}
+
+// Triggers a past run again, with its parameters, labels, skipped stages and
+// jobs, logical date and the variables of its snapshot. Secrets are fetched
+// again, and the scripts are the current ones
+fn rerun(
+    pipelines_path: &str,
+    run_id: &str,
+    runtime: &SharedRuntime,
+) -> Result<(String, String), Error> {
+    let (pipeline, run) = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path))
+        .into_iter()
+        .find_map(|pipeline| {
+            History::read_from_pipeline(&pipeline)
+                .runs
+                .into_iter()
+                .find(|run| run.id == run_id && run.status != RunStatus::Skipped)
+                .map(|run| (pipeline, run))
+        })
+        .ok_or_else(|| ErrorKind::RunNotFound(run_id.to_string()))?;
+
+    let snapshot = run.snapshot.unwrap_or_default();
+
+    if !snapshot.version.is_empty() && snapshot.version != executor::VERSION {
+        warn!(
+            "Run {} was made by version {}, rerun by {}: {}",
+            run_id,
+            snapshot.version,
+            executor::VERSION,
+            pipeline.id
+        );
+    }
+
+    for (breadcrumb, job) in &snapshot.jobs {
+        let script = pipeline
+            .jobs
+            .iter()
+            .find(|other| &other.breadcrumb == breadcrumb)
+            .and_then(|other| fs::read(&other.path).ok())
+            .map(|script| format!("{:x}", Sha256::digest(&script)));
+
+        if script.as_ref() != Some(&job.script_sha256) {
+            warn!("Script changed since run {}: {}", run_id, breadcrumb);
+        }
+    }
+
+    let mut env = snapshot.env;
+
+    let logical_date = env
+        .remove(executor::LOGICAL_DATE_VARIABLE)
+        .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+        .map(|date| date.with_timezone(&Utc))
+        .or(run.scheduled)
+        .unwrap_or(run.start);
+
+    let overrides = Overrides {
+        params: run.params,
+        labels: run.labels,
+        skip_stages: run.skipped_stages,
+        skip_jobs: run.skipped_jobs,
+        rerun_of: Some(run_id.to_string()),
+        env,
+        logical_date: Some(logical_date),
+        ..Overrides::default()
+    };
+
+    info!("Pipeline rerun of {}: {}", run_id, pipeline.id);
+
+    let rerun_id = trigger(pipelines_path, &pipeline.id, overrides, runtime)?;
+
+    Ok((pipeline.id, rerun_id))
+}
+
+fn dequeue(
+    pipelines_path: &str,
+    id: &str,
+    scheduled: Option<DateTime<Utc>>,
+    runtime: &SharedRuntime,
+) -> Result<Vec<Pending>, Error> {
+    let pipeline = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path))
+        .into_iter()
+        .find(|pipeline| pipeline.id == id)
+        .ok_or_else(|| ErrorKind::PipelineNotFound(id.to_string()))?;
+
+    let runtime = runtime.lock().unwrap();
+
+    queue::dequeue(&pipeline, runtime.find_run(id), scheduled)
+}
+
+fn set_paused(pipelines_path: &str, id: Option<String>, paused: bool) -> Response {
+    let pipelines = scheduler::unwrap_pipelines(Pipeline::read_dir(pipelines_path));
+
+    let targets: Vec<&Pipeline> = pipelines
+        .iter()
+        .filter(|pipeline| id.as_ref().is_none_or(|id| &pipeline.id == id))
+        .collect();
+
+    if targets.is_empty() {
+        return Response::Error {
+            message: ErrorKind::PipelineNotFound(id.unwrap_or_default()).to_string(),
+        };
+    }
+
+    for pipeline in &targets {
+        scheduler::set_paused(pipeline, paused);
+    }
+
+    Response::Updated {
+        pipelines: targets
+            .iter()
+            .map(|pipeline| pipeline.id.to_string())
+            .collect(),
+    }
+}
+
+pub fn send(pipelines_path: &str, request: &Request) -> Result<Response, Error> {
+    let socket_path = socket_path(pipelines_path);
+
+    let stream = UnixStream::connect(&socket_path)
+        .context(ErrorKind::SchedulerNotRunning(socket_path.to_string()))?;
+
+    let mut data = serde_json::to_string(request)
+        .context(ErrorKind::ControlRequestFailed(socket_path.to_string()))?;
+    data.push('\n');
+
+    (&stream)
+        .write_all(data.as_bytes())
+        .context(ErrorKind::ControlRequestFailed(socket_path.to_string()))?;
+
+    let mut line = String::new();
+
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .context(ErrorKind::ControlRequestFailed(socket_path.to_string()))?;
+
+    let response: Response = serde_json::from_str(&line)
+        .context(ErrorKind::ControlRequestFailed(socket_path.to_string()))?;
+
+    match response {
+        Response::Error { message } => Err(ErrorKind::ControlRequestFailed(message).into()),
+        response => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::function;
+    use super::super::history::{Run, Snapshot};
+    use super::super::runtime::Runtime;
+    use super::*;
+    use chrono::TimeZone;
+    use std::env;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reject_undeclared_trigger_params() {
+        let dir = env::temp_dir().join("rusty-scheduler-trigger");
+
+        fs::create_dir_all(dir.join("params")).unwrap();
+        fs::write(
+            dir.join("params").join("pipeline.json"),
+            r#"{"id": "params", "expression": "0 0 * * *", "params": {"TARGET": "production"},
+                "secrets": {"TOKEN": {"provider": "vault", "path": "secret/data/ci", "key": "token"}},
+                "stages": ["main"], "jobs": []}"#,
+        )
+        .unwrap();
+
+        let runtime = Runtime::new_shared(60);
+
+        for key in &["LD_PRELOAD", "PATH", "TOKEN", "TARGET-1"] {
+            let mut params = BTreeMap::new();
+
+            params.insert(key.to_string(), String::from("value"));
+
+            let err = trigger(
+                &dir.to_string_lossy(),
+                "params",
+                Overrides {
+                    params,
+                    ..Overrides::default()
+                },
+                &runtime,
+            )
+            .unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid pipeline parameter: {}", key)
+            );
+        }
+
+        assert!(runtime.lock().unwrap().active.is_empty());
+    }
+
+    #[test]
+    fn rerun_with_the_logical_date_and_skipped_jobs() {
+        static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        function::register("test-rerun", |context| {
+            CALLS.lock().unwrap().push(format!(
+                "{} {}",
+                context
+                    .param(executor::LOGICAL_DATE_VARIABLE)
+                    .unwrap_or("?"),
+                context.param("TARGET").unwrap_or("?")
+            ));
+
+            Ok(())
+        });
+        function::register("test-rerun-other", |_| {
+            CALLS.lock().unwrap().push(String::from("other"));
+
+            Ok(())
+        });
+
+        let dir = env::temp_dir().join("rusty-scheduler-rerun");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("rerun")).unwrap();
+        fs::write(
+            dir.join("rerun").join("pipeline.json"),
+            r#"{"id": "rerun", "expression": "@yearly", "params": {"TARGET": "production"}, "stages": ["main"],
+                "jobs": [{"id": "job", "stage": "main", "function": "test-rerun"},
+                         {"id": "other", "stage": "main", "function": "test-rerun-other"}]}"#,
+        )
+        .unwrap();
+
+        let pipeline =
+            Pipeline::read_file(&dir.join("rerun").join("pipeline.json").to_string_lossy())
+                .unwrap();
+
+        let start = Utc.with_ymd_and_hms(2019, 7, 2, 9, 30, 0).unwrap();
+        let mut history = History::read_from_pipeline(&pipeline);
+        let mut run = Run::new(RunStatus::Failed, start, start);
+        let mut snapshot = Snapshot::default();
+
+        snapshot.env.insert(
+            executor::LOGICAL_DATE_VARIABLE.to_string(),
+            String::from("2019-07-01T08:00:00Z"),
+        );
+        snapshot
+            .env
+            .insert(String::from("TARGET"), String::from("staging"));
+
+        run.id = String::from("original");
+        run.params
+            .insert(String::from("TARGET"), String::from("staging"));
+        run.skipped_jobs = vec![String::from("other")];
+        run.snapshot = Some(snapshot.clone());
+        history.runs.push(run.clone());
+
+        snapshot
+            .env
+            .insert(String::from("LD_PRELOAD"), String::from("/tmp/evil.so"));
+
+        run.id = String::from("tampered");
+        run.snapshot = Some(snapshot);
+        history.runs.push(run);
+        history.write_file().unwrap();
+
+        let runtime = Runtime::new_shared(60);
+        let path = dir.to_string_lossy();
+
+        let err = rerun(&path, "tampered", &runtime).unwrap_err();
+
+        assert_eq!(err.to_string(), "Invalid pipeline parameter: LD_PRELOAD");
+
+        let (id, rerun_id) = rerun(&path, "original", &runtime).unwrap();
+
+        assert_eq!(id, "rerun");
+
+        let started = Instant::now();
+
+        while !runtime.lock().unwrap().active.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let history = History::read_from_pipeline(&pipeline);
+        let rerun = history.runs.iter().find(|run| run.id == rerun_id).unwrap();
+
+        assert_eq!(rerun.rerun_of.as_deref(), Some("original"));
+        assert_eq!(rerun.skipped_jobs, vec![String::from("other")]);
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            vec![String::from("2019-07-01T08:00:00Z staging")]
+        );
+    }
+}
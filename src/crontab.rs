@@ -0,0 +1,308 @@
+use super::error::{Error, ErrorKind};
+use super::executor::shell_quote;
+use super::interval;
+use super::scaffold;
+
+// The identifier and expression never need escaping
+const PIPELINE_TEMPLATE: &str = r#"{
+  "id": "{id}",
+  "expression": "{expression}",
+  "first_run": "next",
+  "stages": ["main"],
+  "jobs": [
+    {
+      "id": "command",
+      "stage": "main",
+      "script": "command.sh"
+    }
+  ]
+}
+"#;
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub line: usize,
+
+    pub expression: String,
+
+    // Only in system crontabs (/etc/crontab and /etc/cron.d)
+    pub user: Option<String>,
+
+    pub command: String,
+
+    // Variables assigned before the entry
+    pub variables: Vec<(String, String)>,
+}
+
+pub fn is_system(crontab_path: &str) -> bool {
+    crontab_path == "/etc/crontab" || crontab_path.starts_with("/etc/cron.d/")
+}
+
+pub fn parse(data: &str, system: bool) -> Vec<Result<Entry, Error>> {
+    let mut entries = Vec::new();
+    let mut variables = Vec::new();
+
+    for (index, line) in data.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(variable) = parse_variable(line) {
+            variables.push(variable);
+
+            continue;
+        }
+
+        let entry = parse_entry(line, system).map(|(expression, user, command)| Entry {
+            line: line_number,
+            expression,
+            user,
+            command,
+            variables: variables.clone(),
+        });
+
+        entries.push(entry.map_err(|reason| {
+            ErrorKind::InvalidCrontabLine(format!("line {}: {}", line_number, reason)).into()
+        }));
+    }
+
+    entries
+}
+
+pub fn create_pipeline(pipelines_path: &str, id: &str, entry: &Entry) -> Result<String, Error> {
+    let pipeline_dir = scaffold::create_dir(pipelines_path, id)?;
+
+    let mut script = vec![
+        String::from("#!/bin/sh"),
+        format!("# Imported from crontab line {}", entry.line),
+    ];
+
+    if let Some(user) = &entry.user {
+        script.push(format!("# Originally run as user: {}", user));
+    }
+
+    script.push(String::new());
+
+    for (name, value) in &entry.variables {
+        script.push(format!("export {}={}", name, shell_quote(value)));
+    }
+
+    script.push(entry.command.to_string());
+    script.push(String::new());
+
+    let pipeline_data = PIPELINE_TEMPLATE
+        .replace("{id}", id)
+        .replace("{expression}", &entry.expression);
+
+    scaffold::write(&pipeline_dir, "pipeline.json", &pipeline_data, 0o644)?;
+    scaffold::write(&pipeline_dir, "command.sh", &script.join("\n"), 0o755)?;
+
+    Ok(pipeline_dir)
+}
+
+fn parse_variable(line: &str) -> Option<(String, String)> {
+    let (name, value) = line.split_once('=')?;
+    let name = name.trim();
+
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    if name.is_empty() || !name.chars().all(valid) {
+        return None;
+    }
+
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .or_else(|| {
+            value
+                .strip_prefix('\'')
+                .and_then(|value| value.strip_suffix('\''))
+        })
+        .unwrap_or(value);
+
+    Some((name.to_string(), value.to_string()))
+}
+
+fn parse_entry(line: &str, system: bool) -> Result<(String, Option<String>, String), String> {
+    let (expression, rest) = if line.starts_with('@') {
+        let (name, rest) = split_field(line);
+
+        let expression = interval::expand_macro(name)
+            .ok_or_else(|| format!("unsupported schedule: {}", name))?;
+
+        (expression.to_string(), rest)
+    } else {
+        let mut fields = Vec::new();
+        let mut rest = line;
+
+        for _ in 0..5 {
+            let (field, next) = split_field(rest);
+
+            fields.push(field);
+            rest = next;
+        }
+
+        (convert_expression(&fields)?, rest)
+    };
+
+    let (user, command) = if system {
+        let (user, command) = split_field(rest);
+
+        (Some(user.to_string()), command)
+    } else {
+        (None, rest)
+    };
+
+    if command.is_empty() {
+        return Err(String::from("missing command"));
+    }
+
+    // A '%' sends the rest of the command to its standard input
+    if command.replace("\\%", "").contains('%') {
+        return Err(String::from("'%' in commands is not supported"));
+    }
+
+    Ok((expression, user, command.replace("\\%", "%")))
+}
+
+fn split_field(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim_start()),
+        None => (line, ""),
+    }
+}
+
+pub fn convert_expression(fields: &[&str]) -> Result<String, String> {
+    if fields.len() != 5 || fields.iter().any(|field| field.is_empty()) {
+        return Err(String::from("expected 5 schedule fields"));
+    }
+
+    let minutes = interval::expand_field(fields[0], 0, 59, &[], 0)?;
+    let hours = interval::expand_field(fields[1], 0, 23, &[], 0)?;
+    let days = interval::expand_field(fields[2], 1, 31, &[], 0)?;
+    let months = interval::expand_field(fields[3], 1, 12, &interval::CRON_MONTH_NAMES, 1)?;
+    let weekdays = interval::expand_field(fields[4], 0, 7, &interval::CRON_WEEKDAY_NAMES, 0)?;
+
+    // Sunday is 7 here, it can be 0 or 7 in cron
+    let weekdays = weekdays.map(|weekdays| {
+        let mut weekdays: Vec<u32> = weekdays
+            .into_iter()
+            .map(|weekday| if weekday == 0 { 7 } else { weekday })
+            .collect();
+
+        weekdays.sort();
+        weekdays.dedup();
+
+        weekdays
+    });
+
+    let weekdays = weekdays.filter(|weekdays| weekdays.len() < 7);
+
+    // Cron runs when either the day or the weekday matches
+    if days.is_some() && weekdays.is_some() {
+        return Err(String::from("day and weekday together are not supported"));
+    }
+
+    let fields: Vec<String> = [minutes, hours, days, months, weekdays]
+        .iter()
+        .map(|numbers| match numbers {
+            None => String::from("*"),
+            Some(numbers) => {
+                let numbers: Vec<String> =
+                    numbers.iter().map(|number| number.to_string()).collect();
+
+                numbers.join(",")
+            }
+        })
+        .collect();
+
+    Ok(fields.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(expression: &str) -> Result<String, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+
+        convert_expression(&fields)
+    }
+
+    #[test]
+    fn convert_lists_and_wildcards() {
+        assert_eq!(convert("30 0,4 * * *").unwrap(), "30 0,4 * * *");
+        assert_eq!(convert("0 0 1 1 *").unwrap(), "0 0 1 1 *");
+    }
+
+    #[test]
+    fn convert_ranges_and_steps() {
+        assert_eq!(
+            convert("*/15 9-11 * * *").unwrap(),
+            "0,15,30,45 9,10,11 * * *"
+        );
+        assert_eq!(convert("5/20 * * * *").unwrap(), "5,25,45 * * * *");
+        assert_eq!(convert("0 0-6/3 * * *").unwrap(), "0 0,3,6 * * *");
+    }
+
+    #[test]
+    fn convert_names_and_sunday() {
+        assert_eq!(convert("0 0 * jan,dec sun").unwrap(), "0 0 * 1,12 7");
+        assert_eq!(convert("0 0 * * 0,7").unwrap(), "0 0 * * 7");
+        assert_eq!(convert("0 0 * * mon-fri").unwrap(), "0 0 * * 1,2,3,4,5");
+        assert_eq!(convert("0 0 * * 0-6").unwrap(), "0 0 * * *");
+    }
+
+    #[test]
+    fn convert_invalid_expressions() {
+        assert!(convert("60 * * * *").is_err());
+        assert!(convert("*/0 * * * *").is_err());
+        assert!(convert("0 0 1 * 1").is_err());
+        assert!(convert("0 0 * *").is_err());
+    }
+
+    #[test]
+    fn parse_user_crontab() {
+        let data = "# comment\nMAILTO=ops\nPATH=\"/usr/bin:/bin\"\n\n@daily /opt/backup.sh --full\n*/5 * * * * echo \\% done\n";
+
+        let entries: Vec<Entry> = parse(data, false).into_iter().map(Result::unwrap).collect();
+
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].line, 5);
+        assert_eq!(entries[0].expression, "0 0 * * *");
+        assert_eq!(entries[0].command, "/opt/backup.sh --full");
+        assert_eq!(
+            entries[0].variables,
+            vec![
+                (String::from("MAILTO"), String::from("ops")),
+                (String::from("PATH"), String::from("/usr/bin:/bin")),
+            ]
+        );
+
+        assert_eq!(entries[1].command, "echo % done");
+    }
+
+    #[test]
+    fn parse_system_crontab() {
+        let entries = parse("17 * * * * root cd / && run-parts /etc/cron.hourly", true);
+
+        let entry = entries.into_iter().next().unwrap().unwrap();
+
+        assert_eq!(entry.user, Some(String::from("root")));
+        assert_eq!(entry.command, "cd / && run-parts /etc/cron.hourly");
+    }
+
+    #[test]
+    fn parse_unsupported_entries() {
+        let entries = parse("@reboot /opt/start.sh\n0 0 * * * cat %input", false);
+
+        assert!(entries.iter().all(|entry| entry.is_err()));
+    }
+}
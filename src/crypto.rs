@@ -0,0 +1,242 @@
+use super::error::{Error, ErrorKind};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+
+pub const KEY_VARIABLE: &str = "RUSTY_SCHEDULER_STATE_KEY";
+
+// Key derived with PBKDF2-HMAC-SHA256, the iterations and the salt follow
+const MAGIC: &[u8] = b"RSENC2";
+
+// Key hashed once with SHA-256, still read
+const MAGIC_V1: &[u8] = b"RSENC1";
+
+const ITERATIONS: u32 = 600_000;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+// Deriving a key takes a while, so a process writes with a single salt and
+// keeps the keys it derived, by secret, salt and iterations
+static SALT: Mutex<Option<[u8; SALT_LENGTH]>> = Mutex::new(None);
+
+type KeyId = (String, Vec<u8>, u32);
+
+static KEYS: Mutex<Option<HashMap<KeyId, [u8; 32]>>> = Mutex::new(None);
+
+pub fn encrypt(data: &[u8], path: &str) -> Result<Vec<u8>, Error> {
+    let salt = *SALT.lock().unwrap().get_or_insert_with(|| {
+        let mut salt = [0; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        salt
+    });
+
+    let cipher = cipher(&salt, ITERATIONS)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let encrypted = cipher
+        .encrypt(&nonce, data)
+        .map_err(|_| ErrorKind::EncryptionFailed(path.to_string()))?;
+
+    let mut output =
+        Vec::with_capacity(MAGIC.len() + 4 + SALT_LENGTH + NONCE_LENGTH + encrypted.len());
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&ITERATIONS.to_be_bytes());
+    output.extend_from_slice(&salt);
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&encrypted);
+
+    Ok(output)
+}
+
+pub fn decrypt(data: &[u8], path: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || ErrorKind::DecryptionFailed(path.to_string());
+
+    // Files written before encryption was enabled are read as they are
+    let (cipher, data) = if data.starts_with(MAGIC) {
+        let data = &data[MAGIC.len()..];
+
+        if data.len() < 4 + SALT_LENGTH + NONCE_LENGTH {
+            return Err(invalid().into());
+        }
+
+        let (iterations, data) = data.split_at(4);
+        let (salt, data) = data.split_at(SALT_LENGTH);
+
+        let iterations =
+            u32::from_be_bytes([iterations[0], iterations[1], iterations[2], iterations[3]]);
+
+        if iterations == 0 {
+            return Err(invalid().into());
+        }
+
+        (cipher(salt, iterations)?, data)
+    } else if data.starts_with(MAGIC_V1) {
+        (cipher_v1()?, &data[MAGIC_V1.len()..])
+    } else {
+        return Ok(data.to_vec());
+    };
+
+    if data.len() < NONCE_LENGTH {
+        return Err(invalid().into());
+    }
+
+    let (nonce, encrypted) = data.split_at(NONCE_LENGTH);
+
+    let decrypted = cipher
+        .decrypt(Nonce::from_slice(nonce), encrypted)
+        .map_err(|_| invalid())?;
+
+    Ok(decrypted)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) || data.starts_with(MAGIC_V1)
+}
+
+fn secret() -> Result<String, Error> {
+    let secret = env::var(KEY_VARIABLE)
+        .map_err(|_| ErrorKind::InvalidEncryptionKey(KEY_VARIABLE.to_string()))?;
+
+    if secret.is_empty() {
+        return Err(ErrorKind::InvalidEncryptionKey(KEY_VARIABLE.to_string()).into());
+    }
+
+    Ok(secret)
+}
+
+fn cipher(salt: &[u8], iterations: u32) -> Result<ChaCha20Poly1305, Error> {
+    let secret = secret()?;
+
+    // Held while deriving, so concurrent reads wait for the key instead of
+    // deriving it again
+    let mut keys = KEYS.lock().unwrap();
+
+    let key = keys
+        .get_or_insert_with(HashMap::new)
+        .entry((secret, salt.to_vec(), iterations))
+        .or_insert_with_key(|(secret, salt, iterations)| {
+            pbkdf2(secret.as_bytes(), salt, *iterations)
+        });
+
+    Ok(ChaCha20Poly1305::new(Key::from_slice(key)))
+}
+
+fn cipher_v1() -> Result<ChaCha20Poly1305, Error> {
+    let key = Sha256::digest(secret()?.as_bytes());
+
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+// PBKDF2-HMAC-SHA256 with a single block, the length of the key, see RFC 8018
+fn pbkdf2(secret: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+
+    let mut block = mac.clone();
+    block.update(salt);
+    block.update(&1u32.to_be_bytes());
+
+    let mut previous: [u8; 32] = block.finalize().into_bytes().into();
+    let mut key = previous;
+
+    for _ in 1..iterations {
+        let mut block = mac.clone();
+        block.update(&previous);
+
+        previous = block.finalize().into_bytes().into();
+
+        for (byte, other) in key.iter_mut().zip(previous.iter()) {
+            *byte ^= other;
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt() {
+        env::set_var(KEY_VARIABLE, "secret");
+
+        let encrypted = encrypt(b"{\"id\":\"test\"}", "state.json").expect("encrypt failed");
+
+        assert!(is_encrypted(&encrypted));
+        assert!(encrypted.starts_with(MAGIC));
+
+        let decrypted = decrypt(&encrypted, "state.json").expect("decrypt failed");
+
+        assert_eq!(decrypted, b"{\"id\":\"test\"}");
+    }
+
+    #[test]
+    fn decrypt_plain() {
+        let decrypted = decrypt(b"{\"id\":\"test\"}", "state.json").expect("decrypt failed");
+
+        assert_eq!(decrypted, b"{\"id\":\"test\"}");
+    }
+
+    #[test]
+    fn decrypt_previous_format() {
+        env::set_var(KEY_VARIABLE, "secret");
+
+        // Written by the first format, with a SHA-256 key and a nonce of sevens
+        let encrypted: Vec<u8> = [
+            &b"RSENC1"[..],
+            &[7; NONCE_LENGTH],
+            &[
+                0xfe, 0x79, 0xae, 0x62, 0x61, 0xd3, 0x2a, 0xb6, 0x7a, 0x84, 0x68, 0x82, 0x98, 0xc4,
+                0x02, 0x9f, 0xf5, 0x62, 0x8b, 0x7d, 0xd3, 0xf9, 0xa5, 0x5f, 0x84, 0x1a, 0xc1, 0xd9,
+                0x31,
+            ],
+        ]
+        .concat();
+
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(
+            decrypt(&encrypted, "state.json").unwrap(),
+            b"{\"id\":\"test\"}"
+        );
+    }
+
+    #[test]
+    fn read_iterations_and_salt() {
+        env::set_var(KEY_VARIABLE, "secret");
+
+        let salt = [3; SALT_LENGTH];
+        let nonce = [7; NONCE_LENGTH];
+        let encrypted = cipher(&salt, 2)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), &b"{\"id\":\"test\"}"[..])
+            .unwrap();
+
+        let data = [MAGIC, &2u32.to_be_bytes(), &salt, &nonce, &encrypted].concat();
+
+        assert_eq!(decrypt(&data, "state.json").unwrap(), b"{\"id\":\"test\"}");
+
+        let data = [MAGIC, &0u32.to_be_bytes(), &salt, &nonce, &encrypted].concat();
+
+        assert!(decrypt(&data, "state.json").is_err());
+        assert!(decrypt(&data[..MAGIC.len() + 8], "state.json").is_err());
+    }
+
+    #[test]
+    fn derive_keys() {
+        // Test vectors of RFC 7914, section 11
+        assert_eq!(
+            pbkdf2(b"passwd", b"salt", 1)[..],
+            [
+                0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f, 0xec, 0x16, 0x91, 0xc2, 0x25, 0x44,
+                0xb6, 0x05, 0xf9, 0x41, 0x85, 0x21, 0x6d, 0xde, 0x04, 0x65, 0xe6, 0x8b, 0x9d, 0x57,
+                0xc2, 0x0d, 0xac, 0xbc
+            ]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use super::error::{Error, ErrorKind};
+use failure::ResultExt;
+use log::info;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct PidFile {
+    pub path: String,
+
+    // The pid file is locked for as long as the scheduler is running
+    file: File,
+}
+
+pub fn pidfile_path(pipelines_path: &str, pidfile: Option<&str>) -> String {
+    match pidfile {
+        Some(pidfile) => pidfile.to_string(),
+        None => {
+            let mut pidfile_path = PathBuf::from(pipelines_path);
+            pidfile_path.push("scheduler.pid");
+
+            pidfile_path.to_string_lossy().to_string()
+        }
+    }
+}
+
+pub fn daemonize(pidfile_path: &str) -> Result<PidFile, Error> {
+    // The lock is taken before forking so a second daemon fails in the
+    // foreground, the child inherits it
+    let mut pidfile = PidFile::acquire(pidfile_path)?;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error())
+            .context(ErrorKind::DaemonFailed(pidfile_path.to_string()))?,
+        0 => {}
+        pid => {
+            println!("Scheduler started: {}", pid);
+
+            process::exit(0);
+        }
+    }
+
+    if unsafe { libc::setsid() } == -1 {
+        Err(io::Error::last_os_error())
+            .context(ErrorKind::DaemonFailed(pidfile_path.to_string()))?;
+    }
+
+    // Standard error is kept so logs can still be redirected to a file
+    let null =
+        File::open("/dev/null").context(ErrorKind::DaemonFailed(pidfile_path.to_string()))?;
+
+    unsafe {
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO);
+    }
+
+    pidfile.write(process::id())?;
+
+    info!("Daemon started: {}", process::id());
+
+    Ok(pidfile)
+}
+
+pub fn stop(pidfile_path: &str) -> Result<u32, Error> {
+    let pid = PidFile::read(pidfile_path)?;
+
+    // A pid file that can be locked was left behind by a dead scheduler
+    if PidFile::acquire(pidfile_path).is_ok() {
+        let _ = fs::remove_file(pidfile_path);
+
+        return Err(ErrorKind::SchedulerNotRunning(pidfile_path.to_string()).into());
+    }
+
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == -1 {
+        Err(io::Error::last_os_error()).context(ErrorKind::StopFailed(pid.to_string()))?;
+    }
+
+    let started = Instant::now();
+
+    while started.elapsed() < STOP_TIMEOUT {
+        if PidFile::acquire(pidfile_path).is_ok() {
+            let _ = fs::remove_file(pidfile_path);
+
+            return Ok(pid);
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Err(ErrorKind::StopFailed(pid.to_string()))?
+}
+
+impl PidFile {
+    pub fn acquire(pidfile_path: &str) -> Result<PidFile, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(pidfile_path)
+            .context(ErrorKind::InvalidPidFile(pidfile_path.to_string()))?;
+
+        match file.try_lock() {
+            Ok(_) => Ok(PidFile {
+                path: pidfile_path.to_string(),
+                file,
+            }),
+            Err(TryLockError::WouldBlock) => {
+                Err(ErrorKind::SchedulerAlreadyRunning(pidfile_path.to_string()))?
+            }
+            Err(TryLockError::Error(err)) => {
+                Err(err).context(ErrorKind::InvalidPidFile(pidfile_path.to_string()))?
+            }
+        }
+    }
+
+    pub fn read(pidfile_path: &str) -> Result<u32, Error> {
+        let data = fs::read_to_string(pidfile_path)
+            .context(ErrorKind::SchedulerNotRunning(pidfile_path.to_string()))?;
+
+        let pid = data
+            .trim()
+            .parse::<u32>()
+            .context(ErrorKind::InvalidPidFile(pidfile_path.to_string()))?;
+
+        Ok(pid)
+    }
+
+    pub fn write(&mut self, pid: u32) -> Result<(), Error> {
+        self.file
+            .set_len(0)
+            .context(ErrorKind::InvalidPidFile(self.path.to_string()))?;
+
+        writeln!(self.file, "{}", pid).context(ErrorKind::InvalidPidFile(self.path.to_string()))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use super::control::{self, Request};
+use super::crypto;
+use super::error::ErrorKind;
+use super::executor;
+use super::function;
+use super::history::RunStatus;
+use super::notification::{Notification, Sink};
+use super::pipeline::Pipeline;
+use super::plugin;
+use super::state::State;
+use super::timezone;
+use chrono::{TimeZone, Utc};
+use std::collections::HashSet;
+use std::env;
+use std::ffi::CString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Ok,
+    Warning,
+    Failure,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub level: Level,
+
+    pub message: String,
+
+    // What to do to fix the problem
+    pub hint: Option<String>,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self {
+            Level::Ok => "ok",
+            Level::Warning => "warn",
+            Level::Failure => "fail",
+        };
+
+        formatter.pad(level)
+    }
+}
+
+impl Check {
+    fn ok(message: String) -> Check {
+        Check {
+            level: Level::Ok,
+            message,
+            hint: None,
+        }
+    }
+
+    fn warning(message: String, hint: &str) -> Check {
+        Check {
+            level: Level::Warning,
+            message,
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn failure(message: String, hint: &str) -> Check {
+        Check {
+            level: Level::Failure,
+            message,
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+pub fn run(pipelines_path: &str) -> Vec<Check> {
+    let mut checks = vec![
+        check_shell(),
+        check_clock(),
+        check_directory(pipelines_path),
+    ];
+
+    match Pipeline::read_dir(pipelines_path) {
+        Ok(pipelines) => {
+            if pipelines.is_empty() {
+                checks.push(Check::warning(
+                    format!("No pipeline found in {}", pipelines_path),
+                    "Create a sub-directory with a pipeline.json file for each pipeline",
+                ));
+            }
+
+            for pipeline in pipelines {
+                match pipeline {
+                    Ok(pipeline) => checks.extend(check_pipeline(&pipeline)),
+                    // Jobs of unlisted stages, the message names the job
+                    Err(err) if matches!(err.kind(), ErrorKind::InvalidPipelineDefinition(_)) => {
+                        checks.push(Check::failure(
+                            err.to_string(),
+                            "Add the stage to the stages list",
+                        ))
+                    }
+                    Err(err) => checks.push(Check::failure(
+                        err.to_string(),
+                        "Fix the JSON syntax and the CRON expression of the file",
+                    )),
+                }
+            }
+        }
+        Err(err) => checks.push(Check::failure(
+            err.to_string(),
+            "Check the --pipelines setting",
+        )),
+    }
+
+    checks.push(check_scheduler(pipelines_path));
+
+    checks
+}
+
+fn check_shell() -> Check {
+    let interpreter = executor::interpreter();
+
+    let program = interpreter.split_whitespace().next().unwrap_or_default();
+
+    // Interpreters that are not shells may reject -c, starting is enough
+    let status = Command::new(program)
+        .arg("-c")
+        .arg("true")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    match status {
+        Ok(_) => Check::ok(format!("Shell is available: {}", interpreter)),
+        Err(_) => Check::failure(
+            format!("Shell is not available: {}", interpreter),
+            "Install a POSIX shell or change the interpreter setting, every job script is run with it",
+        ),
+    }
+}
+
+fn check_clock() -> Check {
+    let now = Utc::now();
+
+    // Any date before the project existed means the clock was never set
+    if now < Utc.with_ymd_and_hms(2019, 1, 1, 0, 0, 0).unwrap() {
+        Check::failure(
+            format!("System clock looks wrong: {}", now),
+            "Synchronize the clock (e.g. with NTP), schedules depend on it",
+        )
+    } else {
+        Check::ok(format!(
+            "System clock: {}",
+            timezone::format(now, "%Y-%m-%d %H:%M")
+        ))
+    }
+}
+
+fn check_directory(path: &str) -> Check {
+    if !Path::new(path).is_dir() {
+        return Check::failure(
+            format!("Directory not found: {}", path),
+            "Create the directory or fix the path",
+        );
+    }
+
+    if !is_writable(path) {
+        return Check::failure(
+            format!("Directory is not writable: {}", path),
+            "Give the scheduler user write permission, it creates state and lock files",
+        );
+    }
+
+    Check::ok(format!("Directory is writable: {}", path))
+}
+
+fn check_pipeline(pipeline: &Pipeline) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let mut pipeline_dir = PathBuf::from(&pipeline.path);
+    pipeline_dir.pop();
+
+    let pipeline_dir = pipeline_dir.to_string_lossy().to_string();
+
+    if !is_writable(&pipeline_dir) {
+        checks.push(Check::failure(
+            format!("Pipeline directory is not writable: {}", pipeline_dir),
+            "Give the scheduler user write permission, state.json is saved there",
+        ));
+    }
+
+    for warning in pipeline.interval.lint() {
+        checks.push(Check::warning(
+            format!("Suspicious CRON expression: {} ({})", pipeline.id, warning),
+            "Change the expression, cron and this scheduler may run it at different times",
+        ));
+    }
+
+    if pipeline.stages.is_empty() {
+        checks.push(Check::warning(
+            format!("Pipeline has no stages: {}", pipeline.id),
+            "Add the stages in execution order, nothing runs without them",
+        ));
+    }
+
+    for stage in pipeline.empty_stages() {
+        checks.push(Check::warning(
+            format!("Stage has no jobs: {}/{}", pipeline.id, stage),
+            "Add jobs to the stage or remove it",
+        ));
+    }
+
+    let mut breadcrumbs = HashSet::new();
+
+    for job in &pipeline.jobs {
+        if !breadcrumbs.insert(&job.breadcrumb) {
+            checks.push(Check::warning(
+                format!("Job is defined twice: {}", job.breadcrumb),
+                "Give every job of a stage a unique id",
+            ));
+        }
+
+        match (&job.function, &job.kind) {
+            (Some(function), _) if !function::is_registered(function) => {
+                checks.push(Check::failure(
+                    format!("Job function not registered: {} ({})", job.breadcrumb, function),
+                    "Register the function before starting the scheduler, the command line scheduler has none",
+                ));
+            }
+            (Some(_), _) => {}
+            (None, Some(kind)) => match plugin::find(kind) {
+                Some(job_type) => {
+                    if let Err(err) = job_type.validate(&job.config) {
+                        checks.push(Check::failure(
+                            format!("Invalid job config: {} ({})", job.breadcrumb, err),
+                            &format!("Fix the fields of the {} job", kind),
+                        ));
+                    }
+                }
+                None => checks.push(Check::failure(
+                    format!("Unknown job type: {} ({})", job.breadcrumb, kind),
+                    &format!("Use one of the job types: {}", plugin::names().join(", ")),
+                )),
+            },
+            (None, None) if !Path::new(&job.path).is_file() => {
+                checks.push(Check::failure(
+                    format!("Job script not found: {} ({})", job.breadcrumb, job.path),
+                    "Create the script or fix the script path, it is relative to the pipeline folder",
+                ));
+            }
+            (None, None) => {}
+        }
+    }
+
+    if pipeline.encrypt_state && env::var(crypto::KEY_VARIABLE).is_err() {
+        checks.push(Check::failure(
+            format!("Encryption key is not set: {}", pipeline.id),
+            &format!("Set {} or disable encrypt_state", crypto::KEY_VARIABLE),
+        ));
+    }
+
+    if !pipeline.critical && pipeline.notifications.iter().any(Sink::is_alert) {
+        checks.push(Check::warning(
+            format!(
+                "Alerting sink on a pipeline that is not critical: {}",
+                pipeline.id
+            ),
+            "Set critical to true, incidents are only opened for critical pipelines",
+        ));
+    }
+
+    if pipeline.sla.is_some() && pipeline.notifications.is_empty() {
+        checks.push(Check::warning(
+            format!("SLA without notifications: {}", pipeline.id),
+            "Add a notification sink, missed SLAs are only logged until then",
+        ));
+    }
+
+    if pipeline.stuck_after.is_some() && pipeline.notifications.is_empty() {
+        checks.push(Check::warning(
+            format!("stuck_after without notifications: {}", pipeline.id),
+            "Add a notification sink, stuck runs are only logged until then",
+        ));
+    }
+
+    if let Some(template) = &pipeline.notification_template {
+        let notification = Notification {
+            pipeline: pipeline.id.to_string(),
+            run_id: String::new(),
+            status: RunStatus::Failed,
+            duration: 0.0,
+            previous_failures: 0,
+            consecutive_failures: 1,
+            failed_jobs: Vec::new(),
+            output: Vec::new(),
+            dashboard_url: None,
+            sla: None,
+            stuck: None,
+            paused: false,
+        };
+
+        if let Err(err) = notification.render(template) {
+            checks.push(Check::failure(
+                err.to_string(),
+                "Fix the template syntax, the default message is sent until then",
+            ));
+        }
+    }
+
+    // A previous run in the future means the clock went backwards
+    if let Ok(state) = State::read_file(&State::path_from_pipeline(pipeline)) {
+        if state.timestamp > Utc::now() {
+            checks.push(Check::warning(
+                format!("Previous run is in the future: {}", pipeline.id),
+                "Check the system clock, the pipeline waits until that date",
+            ));
+        }
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::ok(format!("Pipeline is valid: {}", pipeline.id)));
+    }
+
+    checks
+}
+
+fn check_scheduler(pipelines_path: &str) -> Check {
+    match control::send(pipelines_path, &Request::Status) {
+        Ok(_) => Check::ok(String::from("Scheduler is running")),
+        Err(_) => Check::warning(
+            String::from("Scheduler is not running"),
+            "Start the scheduler, the status, pause, resume and trigger commands need it",
+        ),
+    }
+}
+
+fn is_writable(path: &str) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => false,
+    }
+}
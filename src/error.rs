@@ -1,4 +1,7 @@
+#![allow(non_local_definitions)]
+
 use failure::{Context, Fail};
+use std::fmt;
 
 #[derive(Debug, Display)]
 pub struct Error {
@@ -7,6 +10,13 @@ pub struct Error {
 
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
+    #[fail(display = "Invalid config file: {}", _0)]
+    InvalidConfigFile(String),
+    #[fail(display = "Invalid setting: {}", _0)]
+    InvalidSetting(String),
+    #[fail(display = "Missing setting: {}", _0)]
+    MissingSetting(String),
+
     #[fail(display = "Invalid pipeline folder: {}", _0)]
     InvalidPipelineFolder(String),
     #[fail(display = "Invalid pipeline file: {}", _0)]
@@ -15,6 +25,97 @@ pub enum ErrorKind {
     #[fail(display = "Invalid state file: {}", _0)]
     InvalidStateFile(String),
 
+    #[fail(display = "Invalid history file: {}", _0)]
+    InvalidHistoryFile(String),
+
+    #[fail(display = "Invalid log file: {}", _0)]
+    InvalidLogFile(String),
+
+    #[fail(display = "Invalid lock file: {}", _0)]
+    InvalidLockFile(String),
+
+    #[fail(display = "Invalid archive file: {}", _0)]
+    InvalidArchiveFile(String),
+    #[fail(display = "Some pipelines were not restored from archive: {}", _0)]
+    PartialRestore(String),
+
+    #[fail(display = "Control socket already in use: {}", _0)]
+    ControlSocketInUse(String),
+    #[fail(display = "Error opening control socket: {}", _0)]
+    ControlSocketFailed(String),
+    #[fail(display = "Error watching pipeline scripts")]
+    WatchFailed,
+    #[fail(display = "Error handling control request: {}", _0)]
+    ControlRequestFailed(String),
+    #[fail(display = "Scheduler is not running: {}", _0)]
+    SchedulerNotRunning(String),
+
+    #[fail(display = "Error listening for API requests: {}", _0)]
+    ApiListenFailed(String),
+    #[fail(display = "Error handling API request: {}", _0)]
+    ApiRequestFailed(String),
+    #[fail(display = "Invalid TLS file: {}", _0)]
+    InvalidTlsFile(String),
+    #[fail(display = "WebSocket error: {}", _0)]
+    WebSocketFailed(String),
+    #[fail(display = "Error listening for gRPC requests: {}", _0)]
+    GrpcListenFailed(String),
+    #[fail(display = "Invalid StatsD address: {}", _0)]
+    InvalidStatsdAddress(String),
+
+    #[fail(display = "Invalid pid file: {}", _0)]
+    InvalidPidFile(String),
+    #[fail(display = "Scheduler is already running: {}", _0)]
+    SchedulerAlreadyRunning(String),
+    #[fail(display = "Error starting daemon: {}", _0)]
+    DaemonFailed(String),
+    #[fail(display = "Error stopping scheduler: {}", _0)]
+    StopFailed(String),
+
+    #[fail(display = "Invalid encryption key: {}", _0)]
+    InvalidEncryptionKey(String),
+    #[fail(display = "Error encrypting file: {}", _0)]
+    EncryptionFailed(String),
+    #[fail(display = "Error decrypting file: {}", _0)]
+    DecryptionFailed(String),
+
+    #[fail(display = "Invalid pipeline identifier: {}", _0)]
+    InvalidPipelineId(String),
+    #[fail(display = "Pipeline already exists: {}", _0)]
+    PipelineAlreadyExists(String),
+    #[fail(display = "Pipeline not found: {}", _0)]
+    PipelineNotFound(String),
+    #[fail(display = "Run not found: {}", _0)]
+    RunNotFound(String),
+    #[fail(display = "Pipeline is already running: {}", _0)]
+    PipelineAlreadyRunning(String),
+    #[fail(display = "Scheduler is stopping, run not started: {}", _0)]
+    SchedulerStopping(String),
+    #[fail(display = "Pipeline is in dry run, run not started: {}", _0)]
+    PipelineDryRun(String),
+    #[fail(
+        display = "Pipeline runs on schedulers tagged {}, run not started: {}",
+        _1, _0
+    )]
+    PipelineRunsElsewhere(String, String),
+    #[fail(display = "Invalid pipeline parameter: {}", _0)]
+    InvalidPipelineParameter(String),
+    #[fail(display = "Invalid run label: {}", _0)]
+    InvalidRunLabel(String),
+    #[fail(display = "Invalid pipeline definition: {}", _0)]
+    InvalidPipelineDefinition(String),
+    #[fail(display = "Stage not found: {}", _0)]
+    StageNotFound(String),
+    #[fail(display = "Job not found: {}", _0)]
+    JobNotFound(String),
+
+    #[fail(display = "Run is not queued: {}", _0)]
+    RunNotQueued(String),
+    #[fail(display = "Pipeline is not running: {}", _0)]
+    PipelineNotRunning(String),
+    #[fail(display = "Pipeline cancelled: {}", _0)]
+    PipelineCancelled(String),
+
     #[fail(display = "Error executing pipeline: {}", _0)]
     PipelineExecutionFailed(String),
 
@@ -27,11 +128,221 @@ pub enum ErrorKind {
     JobWaitFailed(String),
     #[fail(display = "Error executing job: {}\nError:\n{}", _0, _1)]
     JobExecutionFailed(String, String),
+    #[fail(display = "Consumed file was never produced ({}): {}", _0, _1)]
+    MissingJobInput(String, String),
+    #[fail(display = "Job function is not registered: {}", _0)]
+    FunctionNotRegistered(String),
+    #[fail(display = "Unknown job type ({}): {}", _0, _1)]
+    UnknownJobType(String, String),
+    #[fail(display = "Unresolved reference ({}): {}", _0, _1)]
+    UnresolvedReference(String, String),
+
+    #[fail(display = "Error sending notification ({}): {}", _0, _1)]
+    NotificationFailed(String, String),
+    #[fail(display = "Invalid notification template ({}): {}", _0, _1)]
+    InvalidNotificationTemplate(String, String),
+
+    #[fail(display = "Error publishing MQTT event ({}): {}", _0, _1)]
+    MqttFailed(String, String),
+
+    #[fail(display = "Error sending digest ({}): {}", _0, _1)]
+    DigestFailed(String, String),
+
+    #[fail(display = "Error uploading to S3 ({}): {}", _0, _1)]
+    UploadFailed(String, String),
+    #[fail(display = "Invalid artifact path: {}", _0)]
+    InvalidArtifact(String),
+    #[fail(display = "Invalid input pattern: {}", _0)]
+    InvalidInputPattern(String),
+
+    #[fail(display = "Error fetching secret ({}): {}", _0, _1)]
+    SecretFetchFailed(String, String),
+
+    #[fail(display = "Services unavailable ({}): {}", _0, _1)]
+    ServicesUnavailable(String, String),
+
+    #[fail(display = "Environment check failed: {} problem(s)", _0)]
+    DoctorFailed(usize),
+    #[fail(display = "Pipeline validation failed: {} invalid pipeline(s)", _0)]
+    InvalidPipelines(usize),
+
+    #[fail(display = "Invalid crontab file: {}", _0)]
+    InvalidCrontabFile(String),
+    #[fail(display = "Invalid crontab entry: {}", _0)]
+    InvalidCrontabLine(String),
 
     #[fail(display = "Invalid interval expression: {}", _0)]
     InvalidIntervalExpression(String),
 }
 
+// Groups of errors with their own process exit code, for wrapper scripts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    // The scheduler or a command failed
+    Runtime,
+    // Settings, pipeline files or command arguments are invalid
+    Config,
+    // Some items succeeded and others didn't
+    PartialFailure,
+    // A pipeline run failed or was cancelled
+    PipelineFailure,
+}
+
+impl Category {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Category::Runtime => 1,
+            Category::Config => 2,
+            Category::PartialFailure => 3,
+            Category::PipelineFailure => 4,
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let category = match self {
+            Category::Runtime => "runtime",
+            Category::Config => "config",
+            Category::PartialFailure => "partial_failure",
+            Category::PipelineFailure => "pipeline_failure",
+        };
+
+        formatter.pad(category)
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> &ErrorKind {
+        self.inner.get_context()
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.kind().category().exit_code()
+    }
+}
+
+impl ErrorKind {
+    // Stable identifier of the error, messages may change but codes don't
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidConfigFile(_) => "invalid_config_file",
+            ErrorKind::InvalidSetting(_) => "invalid_setting",
+            ErrorKind::MissingSetting(_) => "missing_setting",
+            ErrorKind::InvalidPipelineFolder(_) => "invalid_pipeline_folder",
+            ErrorKind::InvalidPipelineFile(_) => "invalid_pipeline_file",
+            ErrorKind::InvalidStateFile(_) => "invalid_state_file",
+            ErrorKind::InvalidHistoryFile(_) => "invalid_history_file",
+            ErrorKind::InvalidLogFile(_) => "invalid_log_file",
+            ErrorKind::InvalidLockFile(_) => "invalid_lock_file",
+            ErrorKind::InvalidArchiveFile(_) => "invalid_archive_file",
+            ErrorKind::PartialRestore(_) => "partial_restore",
+            ErrorKind::ControlSocketInUse(_) => "control_socket_in_use",
+            ErrorKind::ControlSocketFailed(_) => "control_socket_failed",
+            ErrorKind::WatchFailed => "watch_failed",
+            ErrorKind::ControlRequestFailed(_) => "control_request_failed",
+            ErrorKind::SchedulerNotRunning(_) => "scheduler_not_running",
+            ErrorKind::ApiListenFailed(_) => "api_listen_failed",
+            ErrorKind::ApiRequestFailed(_) => "api_request_failed",
+            ErrorKind::InvalidTlsFile(_) => "invalid_tls_file",
+            ErrorKind::WebSocketFailed(_) => "websocket_failed",
+            ErrorKind::GrpcListenFailed(_) => "grpc_listen_failed",
+            ErrorKind::InvalidStatsdAddress(_) => "invalid_statsd_address",
+            ErrorKind::InvalidPidFile(_) => "invalid_pid_file",
+            ErrorKind::SchedulerAlreadyRunning(_) => "scheduler_already_running",
+            ErrorKind::DaemonFailed(_) => "daemon_failed",
+            ErrorKind::StopFailed(_) => "stop_failed",
+            ErrorKind::InvalidEncryptionKey(_) => "invalid_encryption_key",
+            ErrorKind::EncryptionFailed(_) => "encryption_failed",
+            ErrorKind::DecryptionFailed(_) => "decryption_failed",
+            ErrorKind::InvalidPipelineId(_) => "invalid_pipeline_id",
+            ErrorKind::PipelineAlreadyExists(_) => "pipeline_already_exists",
+            ErrorKind::PipelineNotFound(_) => "pipeline_not_found",
+            ErrorKind::RunNotFound(_) => "run_not_found",
+            ErrorKind::PipelineAlreadyRunning(_) => "pipeline_already_running",
+            ErrorKind::SchedulerStopping(_) => "scheduler_stopping",
+            ErrorKind::PipelineDryRun(_) => "pipeline_dry_run",
+            ErrorKind::PipelineRunsElsewhere(_, _) => "pipeline_runs_elsewhere",
+            ErrorKind::InvalidPipelineParameter(_) => "invalid_pipeline_parameter",
+            ErrorKind::InvalidRunLabel(_) => "invalid_run_label",
+            ErrorKind::InvalidPipelineDefinition(_) => "invalid_pipeline_definition",
+            ErrorKind::StageNotFound(_) => "stage_not_found",
+            ErrorKind::JobNotFound(_) => "job_not_found",
+            ErrorKind::RunNotQueued(_) => "run_not_queued",
+            ErrorKind::PipelineNotRunning(_) => "pipeline_not_running",
+            ErrorKind::PipelineCancelled(_) => "pipeline_cancelled",
+            ErrorKind::PipelineExecutionFailed(_) => "pipeline_execution_failed",
+            ErrorKind::StageExecutionFailed(_) => "stage_execution_failed",
+            ErrorKind::JobStartFailed(_) => "job_start_failed",
+            ErrorKind::JobWaitFailed(_) => "job_wait_failed",
+            ErrorKind::JobExecutionFailed(_, _) => "job_execution_failed",
+            ErrorKind::MissingJobInput(_, _) => "missing_job_input",
+            ErrorKind::FunctionNotRegistered(_) => "function_not_registered",
+            ErrorKind::UnknownJobType(_, _) => "unknown_job_type",
+            ErrorKind::UnresolvedReference(_, _) => "unresolved_reference",
+            ErrorKind::NotificationFailed(_, _) => "notification_failed",
+            ErrorKind::InvalidNotificationTemplate(_, _) => "invalid_notification_template",
+            ErrorKind::MqttFailed(_, _) => "mqtt_failed",
+            ErrorKind::DigestFailed(_, _) => "digest_failed",
+            ErrorKind::UploadFailed(_, _) => "upload_failed",
+            ErrorKind::InvalidArtifact(_) => "invalid_artifact",
+            ErrorKind::InvalidInputPattern(_) => "invalid_input_pattern",
+            ErrorKind::SecretFetchFailed(_, _) => "secret_fetch_failed",
+            ErrorKind::ServicesUnavailable(_, _) => "services_unavailable",
+            ErrorKind::DoctorFailed(_) => "doctor_failed",
+            ErrorKind::InvalidPipelines(_) => "invalid_pipelines",
+            ErrorKind::InvalidCrontabFile(_) => "invalid_crontab_file",
+            ErrorKind::InvalidCrontabLine(_) => "invalid_crontab_line",
+            ErrorKind::InvalidIntervalExpression(_) => "invalid_interval_expression",
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        match self {
+            ErrorKind::InvalidConfigFile(_)
+            | ErrorKind::InvalidSetting(_)
+            | ErrorKind::MissingSetting(_)
+            | ErrorKind::InvalidPipelineFolder(_)
+            | ErrorKind::InvalidPipelineFile(_)
+            | ErrorKind::InvalidTlsFile(_)
+            | ErrorKind::InvalidStatsdAddress(_)
+            | ErrorKind::InvalidEncryptionKey(_)
+            | ErrorKind::InvalidPipelineId(_)
+            | ErrorKind::PipelineNotFound(_)
+            | ErrorKind::RunNotFound(_)
+            | ErrorKind::InvalidPipelineParameter(_)
+            | ErrorKind::InvalidRunLabel(_)
+            | ErrorKind::InvalidPipelineDefinition(_)
+            | ErrorKind::StageNotFound(_)
+            | ErrorKind::JobNotFound(_)
+            | ErrorKind::FunctionNotRegistered(_)
+            | ErrorKind::UnknownJobType(_, _)
+            | ErrorKind::UnresolvedReference(_, _)
+            | ErrorKind::InvalidNotificationTemplate(_, _)
+            | ErrorKind::InvalidArtifact(_)
+            | ErrorKind::InvalidInputPattern(_)
+            | ErrorKind::InvalidCrontabFile(_)
+            | ErrorKind::InvalidCrontabLine(_)
+            | ErrorKind::InvalidIntervalExpression(_)
+            | ErrorKind::InvalidPipelines(_) => Category::Config,
+
+            ErrorKind::PartialRestore(_) | ErrorKind::DoctorFailed(_) => Category::PartialFailure,
+
+            ErrorKind::PipelineCancelled(_)
+            | ErrorKind::PipelineExecutionFailed(_)
+            | ErrorKind::StageExecutionFailed(_)
+            | ErrorKind::JobExecutionFailed(_, _)
+            | ErrorKind::MissingJobInput(_, _) => Category::PipelineFailure,
+
+            _ => Category::Runtime,
+        }
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
         Error {
@@ -42,6 +353,36 @@ impl From<ErrorKind> for Error {
 
 impl From<Context<ErrorKind>> for Error {
     fn from(inner: Context<ErrorKind>) -> Error {
-        Error { inner: inner }
+        Error { inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_errors_to_exit_codes() {
+        let errors: Vec<Error> = vec![
+            ErrorKind::StopFailed(String::from("1")).into(),
+            ErrorKind::MissingSetting(String::from("listen")).into(),
+            ErrorKind::DoctorFailed(2).into(),
+            ErrorKind::PipelineExecutionFailed(String::from("loader")).into(),
+        ];
+
+        let codes: Vec<(&str, i32)> = errors
+            .iter()
+            .map(|err| (err.code(), err.exit_code()))
+            .collect();
+
+        assert_eq!(
+            codes,
+            vec![
+                ("stop_failed", 1),
+                ("missing_setting", 2),
+                ("doctor_failed", 3),
+                ("pipeline_execution_failed", 4)
+            ]
+        );
     }
 }
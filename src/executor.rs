@@ -1,26 +1,227 @@
+use super::console;
 use super::error::{Error, ErrorKind};
-use super::pipeline::{Job, Pipeline};
+use super::function::{self, Context, Function};
+use super::history::{JobSnapshot, Snapshot};
+use super::pipeline::{Job, OutputFormat, Pipeline};
+use super::plugin;
+use super::runtime::{Cancellation, Output};
+use super::secrets;
+use super::services;
+use super::window::OutsideWindow;
+use chrono::{DateTime, SecondsFormat, Utc};
 use failure::ResultExt;
-use log::{error, trace};
+use glob::Pattern;
+use log::{error, trace, warn};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::process::{Child, Command, Stdio};
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-pub struct JobProcess<'a>(&'a Job, Child);
+// Variables set by the scheduler itself, like the labels and the last outputs
+pub const VARIABLE_PREFIX: &str = "RUSTY_SCHEDULER_";
+
+// Path of the outputs file of a script
+pub const OUTPUTS_VARIABLE: &str = "RUSTY_SCHEDULER_OUTPUT";
+
+pub const LOGICAL_DATE_VARIABLE: &str = "RUSTY_SCHEDULER_LOGICAL_DATE";
+
+// Folder of the pipelines directory with shared helper scripts, prepended to
+// the PATH of every job
+pub const SCRIPTS_LIB: &str = "scripts_lib";
+
+// Recorded in the run snapshots
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Scripts run with "sh <script>" unless an interpreter is set
+const DEFAULT_INTERPRETER: &str = "sh";
+
+static INTERPRETER: Mutex<Option<String>> = Mutex::new(None);
+
+// Jobs with hosts run "ssh <host> <interpreter> -s" with the script as input
+const DEFAULT_SSH_COMMAND: &str = "ssh -o BatchMode=yes";
+
+static SSH_COMMAND: Mutex<Option<String>> = Mutex::new(None);
+
+// Outputs files of the jobs running in this process
+static OUTPUTS_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// Jobs waiting for their allowed window check it this often
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Lines of the standard error kept for the message of a failed job, the
+// whole output is in the run log
+const ERROR_TAIL_LINES: usize = 100;
+
+// Host, its ssh process and the threads reading its output
+pub type HostProcess = (String, Child, Vec<JoinHandle<String>>);
+
+pub struct JobProcess<'a>(&'a Job, Task, Cancellation);
+
+pub enum Task {
+    // Script process, the threads reading its output and its outputs file
+    Process(Child, Vec<JoinHandle<String>>, OutputsFile),
+
+    // Registered function running on its own thread
+    Function(JoinHandle<Result<(), String>>),
+
+    // SSH process of each host of the job
+    Hosts(Vec<HostProcess>),
+}
+
+// File where a script writes KEY=value lines, its outputs
+pub struct OutputsFile {
+    path: PathBuf,
+
+    output: Output,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    // Job output goes to the scheduler output instead of being captured
+    pub stream: bool,
+
+    // Stage progress is printed for interactive runs
+    pub progress: bool,
+
+    // Parameter overrides, passed to the jobs as environment variables
+    pub params: BTreeMap<String, String>,
+
+    // Labels of a triggered run, passed as RUSTY_SCHEDULER_LABEL_<KEY> variables
+    pub labels: BTreeMap<String, String>,
+
+    // Outputs of the last successful run, passed as RUSTY_SCHEDULER_LAST_<KEY> variables
+    pub last_outputs: BTreeMap<String, String>,
+
+    // Occurrence the run stands for, the start of the run when not set
+    pub logical_date: Option<DateTime<Utc>>,
+
+    // Interpreter of the pipeline, the global one when not set
+    pub interpreter: Option<String>,
+
+    // Shared scripts folder of the pipelines directory, when it exists
+    pub scripts_lib: Option<PathBuf>,
+
+    pub cancellation: Cancellation,
+
+    // Captured job output, line by line
+    pub output: Output,
+}
+
+pub fn execute<'a>(pipeline: &'a Pipeline, options: &Options) -> Result<&'a Pipeline, Error> {
+    // Runs wait for their services, instead of failing in the first job
+    if !pipeline.depends_on_services.is_empty() {
+        if let Err(err) = services::wait(pipeline, &options.cancellation, &options.output) {
+            error!("{}", err);
+
+            return Err(err);
+        }
+    }
+
+    let mut params = pipeline.params.clone();
+
+    // Secrets are fetched for each run, and override the parameters, the
+    // ones of a triggered run included
+    let secrets = match secrets::resolve(pipeline) {
+        Ok(secrets) => secrets,
+        Err(err) => {
+            error!("{}", err);
+
+            return Err(err);
+        }
+    };
+
+    let secret_names: Vec<String> = secrets.keys().cloned().collect();
+
+    params.extend(options.params.clone());
+
+    params.extend(secrets);
+
+    params.extend(
+        options
+            .labels
+            .iter()
+            .map(|(key, value)| (label_variable(key), value.to_string())),
+    );
+
+    params.extend(
+        options
+            .last_outputs
+            .iter()
+            .map(|(key, value)| (last_output_variable(key), value.to_string())),
+    );
+
+    let logical_date = options.logical_date.unwrap_or_else(Utc::now);
+
+    params.insert(
+        LOGICAL_DATE_VARIABLE.to_string(),
+        logical_date.to_rfc3339_opts(SecondsFormat::Secs, true),
+    );
+
+    // Secrets are left out, a rerun fetches them again
+    options.output.set_snapshot(Snapshot {
+        version: VERSION.to_string(),
+        env: params
+            .iter()
+            .filter(|(name, _)| !secret_names.contains(name))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        jobs: BTreeMap::new(),
+    });
+
+    let options = &Options {
+        params,
+        interpreter: pipeline.interpreter.clone(),
+        scripts_lib: scripts_lib(pipeline),
+        ..options.clone()
+    };
+
+    for (index, stage) in pipeline.stages.iter().enumerate() {
+        if options.cancellation.is_cancelled() {
+            Err(ErrorKind::PipelineCancelled(pipeline.id.to_string()))?;
+        }
 
-pub fn execute(pipeline: &Pipeline) -> Result<&Pipeline, Error> {
-    for stage in &pipeline.stages {
         trace!("Running stage: {}/{}", pipeline.id, stage);
 
-        let status = execute_stage(&pipeline, &stage);
+        if options.progress {
+            console::stage_started(&pipeline.id, stage, index + 1, pipeline.stages.len());
+        }
+
+        let started = Instant::now();
+
+        let status = execute_stage(pipeline, stage, options);
+
+        options
+            .output
+            .push_stage(stage, started.elapsed().as_secs_f64());
+
+        if options.progress {
+            console::stage_completed(stage, started.elapsed(), status.is_ok());
+        }
 
         match status {
             Ok(_) => {
                 trace!("Stage completed: {}/{}", pipeline.id, stage);
             }
+            Err(_) if options.cancellation.is_cancelled() => {
+                Err(ErrorKind::PipelineCancelled(pipeline.id.to_string()))?;
+            }
             Err(err) => {
                 error!("{}", err);
 
-                return Err(ErrorKind::PipelineExecutionFailed(pipeline.id.to_string()))?;
+                Err(ErrorKind::PipelineExecutionFailed(pipeline.id.to_string()))?;
             }
         }
     }
@@ -28,34 +229,230 @@ pub fn execute(pipeline: &Pipeline) -> Result<&Pipeline, Error> {
     Ok(pipeline)
 }
 
-pub fn execute_stage(pipeline: &Pipeline, stage: &str) -> Result<String, Error> {
-    let jobs: Vec<&Job> = pipeline
-        .jobs
+pub fn set_interpreter(interpreter: &str) -> Result<(), Error> {
+    if interpreter.trim().is_empty() {
+        return Err(ErrorKind::InvalidSetting(format!("interpreter={}", interpreter)).into());
+    }
+
+    *INTERPRETER.lock().unwrap() = Some(interpreter.to_string());
+
+    Ok(())
+}
+
+// Global interpreter, like "bash -euo pipefail"
+pub fn interpreter() -> String {
+    INTERPRETER
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_INTERPRETER.to_string())
+}
+
+pub fn set_ssh_command(ssh_command: &str) -> Result<(), Error> {
+    if ssh_command.trim().is_empty() {
+        return Err(ErrorKind::InvalidSetting(format!("ssh_command={}", ssh_command)).into());
+    }
+
+    *SSH_COMMAND.lock().unwrap() = Some(ssh_command.to_string());
+
+    Ok(())
+}
+
+// Command connecting to the hosts of a job, like "ssh -i deploy.key"
+pub fn ssh_command() -> String {
+    SSH_COMMAND
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SSH_COMMAND.to_string())
+}
+
+// Like "web1" or "deploy@web1:2222", never an option of the ssh command
+pub fn is_host(host: &str) -> bool {
+    !host.is_empty() && !host.starts_with('-') && !host.contains(char::is_whitespace)
+}
+
+fn inherited_env(globs: &[String]) -> Vec<(OsString, OsString)> {
+    let patterns: Vec<Pattern> = globs
         .iter()
-        .filter(|job| job.stage == stage)
+        .filter_map(|glob| Pattern::new(glob).ok())
         .collect();
 
-    let jobs_count = jobs.len();
+    env::vars_os()
+        .filter(|(name, _)| {
+            name.to_str()
+                .is_some_and(|name| patterns.iter().any(|pattern| pattern.matches(name)))
+        })
+        .collect()
+}
 
-    let started = start_jobs(jobs);
+// Command line, variables and script hash of a script job, the hosts of a
+// job run the same command
+fn job_snapshot(job: &Job, interpreter: &str, args: &[String], options: &Options) -> JobSnapshot {
+    let mut command: Vec<String> = interpreter.split_whitespace().map(String::from).collect();
 
-    let completed = wait_jobs(started);
+    command.push(job.path.to_string());
+    command.extend(args.iter().cloned());
 
-    let successful_count = completed
-        .into_iter()
-        .filter_map(|process| process.ok())
-        .count();
+    let env = job
+        .env
+        .keys()
+        .filter_map(|name| {
+            options
+                .params
+                .get(name)
+                .map(|value| (name.to_string(), value.to_string()))
+        })
+        .collect();
+
+    let script_sha256 = fs::read(&job.path)
+        .map(|script| format!("{:x}", Sha256::digest(&script)))
+        .unwrap_or_default();
+
+    JobSnapshot {
+        command,
+        env,
+        script_sha256,
+    }
+}
+
+// The scripts_lib folder next to the pipeline folders
+fn scripts_lib(pipeline: &Pipeline) -> Option<PathBuf> {
+    let folder = Path::new(&pipeline.path).parent()?;
+
+    let scripts_lib = folder.parent()?.join(SCRIPTS_LIB);
 
-    if successful_count == jobs_count {
-        Ok(stage.to_string())
+    if scripts_lib.is_dir() {
+        // Scripts run in the folder of the scheduler, relative paths would
+        // break in the ones changing folder
+        scripts_lib.canonicalize().ok()
     } else {
-        Err(ErrorKind::StageExecutionFailed(stage.to_string()))?
+        None
+    }
+}
+
+// The shared scripts folder first, then the PATH the job gets otherwise
+fn library_path(scripts_lib: &Path, job: &Job, options: &Options) -> OsString {
+    let path = match (options.params.get("PATH"), &job.pass_env) {
+        (Some(path), _) => Some(OsString::from(path)),
+        (None, None) => env::var_os("PATH"),
+        (None, Some(pass_env)) => inherited_env(pass_env)
+            .into_iter()
+            .find(|(name, _)| name == "PATH")
+            .map(|(_, path)| path),
+    };
+
+    let mut folders = vec![scripts_lib.to_path_buf()];
+
+    folders.extend(path.iter().flat_map(env::split_paths));
+
+    env::join_paths(folders).unwrap_or_else(|_| scripts_lib.as_os_str().to_os_string())
+}
+
+// Labels and outputs become environment variables, so their keys are limited
+// to letters, digits and underscores
+pub fn is_variable_name(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn label_variable(key: &str) -> String {
+    format!("RUSTY_SCHEDULER_LABEL_{}", key.to_uppercase())
+}
+
+pub fn last_output_variable(key: &str) -> String {
+    format!("RUSTY_SCHEDULER_LAST_{}", key.to_uppercase())
+}
+
+pub fn execute_stage(pipeline: &Pipeline, stage: &str, options: &Options) -> Result<String, Error> {
+    let groups = pipeline.job_groups(stage).map_err(|err| {
+        ErrorKind::InvalidPipelineDefinition(format!("{}/{}: {}", pipeline.id, stage, err))
+    })?;
+
+    // Groups run one after the other, the first failing one stops the stage
+    for jobs in groups {
+        let now = Utc::now();
+
+        // Jobs outside their allowed window are skipped or start once it opens
+        let (jobs, skipped): (Vec<&Job>, Vec<&Job>) = jobs.into_iter().partition(|job| {
+            job.outside_window == OutsideWindow::Wait
+                || job.allowed_window.is_none_or(|window| window.contains(now))
+        });
+
+        for job in skipped {
+            let window = job.allowed_window.map(|window| window.to_string());
+
+            warn!("Job skipped outside its allowed window: {}", job.breadcrumb);
+
+            options.output.push(format!(
+                "[{}] Skipped outside its allowed window {}",
+                job.breadcrumb,
+                window.unwrap_or_default()
+            ));
+        }
+
+        let jobs_count = jobs.len();
+
+        let breadcrumbs: Vec<String> = jobs.iter().map(|job| job.breadcrumb.to_string()).collect();
+
+        let (ready, mut waiting): (Vec<&Job>, Vec<&Job>) = jobs
+            .into_iter()
+            .partition(|job| job.allowed_window.is_none_or(|window| window.contains(now)));
+
+        let mut started = start_jobs(ready, options);
+
+        for job in &waiting {
+            options.output.push(format!(
+                "[{}] Waiting for its allowed window {}",
+                job.breadcrumb,
+                job.allowed_window
+                    .map(|window| window.to_string())
+                    .unwrap_or_default()
+            ));
+        }
+
+        // A cancelled run doesn't start the waiting jobs, they fail
+        while !waiting.is_empty() && !options.cancellation.is_cancelled() {
+            thread::sleep(WINDOW_CHECK_INTERVAL);
+
+            let now = Utc::now();
+
+            let (open, closed): (Vec<&Job>, Vec<&Job>) = waiting
+                .into_iter()
+                .partition(|job| job.allowed_window.is_none_or(|window| window.contains(now)));
+
+            started.extend(start_jobs(open, options));
+
+            waiting = closed;
+        }
+
+        let completed = wait_jobs(started);
+
+        let successful: Vec<&Job> = completed
+            .into_iter()
+            .filter_map(|process| process.ok())
+            .collect();
+
+        successful
+            .iter()
+            .for_each(|job| options.output.push_passed_job(&job.breadcrumb));
+
+        if successful.len() != jobs_count {
+            // Jobs that failed to start or exited with an error
+            breadcrumbs
+                .iter()
+                .filter(|breadcrumb| !successful.iter().any(|job| &&job.breadcrumb == breadcrumb))
+                .for_each(|breadcrumb| options.output.push_failed_job(breadcrumb));
+
+            Err(ErrorKind::StageExecutionFailed(stage.to_string()))?
+        }
     }
+
+    Ok(stage.to_string())
 }
 
-pub fn start_jobs(jobs: Vec<&Job>) -> Vec<Result<JobProcess, Error>> {
-    let started_jobs: Vec<Result<JobProcess, Error>> =
-        jobs.iter().map(|job| start_job(job)).collect();
+pub fn start_jobs<'a>(jobs: Vec<&'a Job>, options: &Options) -> Vec<Result<JobProcess<'a>, Error>> {
+    let started_jobs: Vec<Result<JobProcess<'_>, Error>> =
+        jobs.iter().map(|job| start_job(job, options)).collect();
 
     started_jobs
         .iter()
@@ -65,12 +462,12 @@ pub fn start_jobs(jobs: Vec<&Job>) -> Vec<Result<JobProcess, Error>> {
     started_jobs
         .iter()
         .filter_map(|process| process.as_ref().ok())
-        .for_each(|JobProcess(job, _)| trace!("Running job: {}", job.breadcrumb));
+        .for_each(|JobProcess(job, _, _)| trace!("Running job: {}", job.breadcrumb));
 
     started_jobs
 }
 
-pub fn wait_jobs(jobs: Vec<Result<JobProcess, Error>>) -> Vec<Result<&Job, Error>> {
+pub fn wait_jobs(jobs: Vec<Result<JobProcess<'_>, Error>>) -> Vec<Result<&Job, Error>> {
     let completed_jobs: Vec<Result<&Job, Error>> = jobs
         .into_iter()
         .filter_map(|process| process.ok())
@@ -90,33 +487,1136 @@ pub fn wait_jobs(jobs: Vec<Result<JobProcess, Error>>) -> Vec<Result<&Job, Error
     completed_jobs
 }
 
-pub fn start_job(job: &Job) -> Result<JobProcess, Error> {
-    let child = Command::new("sh")
+pub fn start_job<'a>(job: &'a Job, options: &Options) -> Result<JobProcess<'a>, Error> {
+    let unresolved =
+        |reference| ErrorKind::UnresolvedReference(job.breadcrumb.to_string(), reference);
+
+    if let Some(file) = job
+        .consumes
+        .iter()
+        .find(|file| !PathBuf::from(&job.folder).join(file).exists())
+    {
+        return Err(
+            ErrorKind::MissingJobInput(job.breadcrumb.to_string(), file.to_string()).into(),
+        );
+    }
+
+    let mut params = options.params.clone();
+
+    for (name, value) in &job.env {
+        let value = interpolate(value, &options.output).map_err(unresolved)?;
+
+        params.insert(name.to_string(), value);
+    }
+
+    let options = &Options {
+        params,
+        ..options.clone()
+    };
+
+    if let Some(name) = &job.function {
+        let function = function::find(name)
+            .ok_or_else(|| ErrorKind::FunctionNotRegistered(job.breadcrumb.to_string()))?;
+
+        return start_function(job, function, options);
+    }
+
+    if let Some(kind) = &job.kind {
+        let job_type = plugin::find(kind).ok_or_else(|| {
+            ErrorKind::UnknownJobType(job.breadcrumb.to_string(), kind.to_string())
+        })?;
+
+        let config = interpolate_config(&job.config, &options.output).map_err(unresolved)?;
+
+        let function: Function = Arc::new(move |context| job_type.run(&config, context));
+
+        return start_function(job, function, options);
+    }
+
+    let output = || {
+        if options.stream {
+            Stdio::inherit()
+        } else {
+            Stdio::piped()
+        }
+    };
+
+    let args = job
+        .args
+        .iter()
+        .map(|arg| interpolate(arg, &options.output))
+        .collect::<Result<Vec<String>, String>>()
+        .map_err(unresolved)?;
+
+    // Options of the interpreter go before the script, like "bash -eu <script>"
+    let interpreter = options.interpreter.clone().unwrap_or_else(interpreter);
+    let mut words = interpreter.split_whitespace();
+
+    options.output.push_job_snapshot(
+        &job.breadcrumb,
+        job_snapshot(job, &interpreter, &args, options),
+    );
+
+    if !job.hosts.is_empty() {
+        return start_hosts(job, &args, options);
+    }
+
+    let outputs = OutputsFile::create(job, &options.output)?;
+
+    let mut command = Command::new(words.next().unwrap_or(DEFAULT_INTERPRETER));
+
+    // Only the listed host variables reach the script, so secrets of the
+    // scheduler environment don't leak into it
+    if let Some(pass_env) = &job.pass_env {
+        command.env_clear().envs(inherited_env(pass_env));
+    }
+
+    command
+        .args(words)
         .arg(&job.path)
+        .args(&args)
+        .envs(&options.params)
+        .env(OUTPUTS_VARIABLE, &outputs.path)
         .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+        .stdout(output())
+        .stderr(output());
+
+    if let Some(scripts_lib) = &options.scripts_lib {
+        command.env("PATH", library_path(scripts_lib, job, options));
+    }
+
+    // Background jobs get their own process group, so cancelling a run also
+    // stops the processes started by the script
+    if !options.stream {
+        command.process_group(0);
+    }
+
+    let mut child = command
         .spawn()
         .context(ErrorKind::JobStartFailed(job.breadcrumb.to_string()))?;
 
-    Ok(JobProcess(job, child))
+    options.cancellation.register(child.id());
+
+    let mut readers = Vec::new();
+
+    // A JSON result may span more lines than the end kept for errors
+    let stdout_lines = match job.output_format {
+        Some(OutputFormat::Json) => usize::MAX,
+        None => ERROR_TAIL_LINES,
+    };
+
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(read_output(
+            &job.breadcrumb,
+            stdout,
+            &options.output,
+            stdout_lines,
+        ));
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(read_output(
+            &job.breadcrumb,
+            stderr,
+            &options.output,
+            ERROR_TAIL_LINES,
+        ));
+    }
+
+    Ok(JobProcess(
+        job,
+        Task::Process(child, readers, outputs),
+        options.cancellation.clone(),
+    ))
+}
+
+// One ssh process per host, reading the script from its standard input.
+// Parameters are exported by a prelude written before the script, so they
+// never show up in the process list, the remote environment doesn't get
+// the variables of the scheduler, and outputs are not collected
+fn start_hosts<'a>(
+    job: &'a Job,
+    args: &[String],
+    options: &Options,
+) -> Result<JobProcess<'a>, Error> {
+    let interpreter = options.interpreter.clone().unwrap_or_else(interpreter);
+
+    let mut remote: Vec<String> = interpreter.split_whitespace().map(str::to_string).collect();
+
+    remote.push(String::from("-s"));
+    remote.push(String::from("--"));
+    remote.extend(args.iter().map(|arg| shell_quote(arg)));
+
+    let mut script: Vec<u8> = options
+        .params
+        .iter()
+        .map(|(name, value)| format!("export {}\n", shell_quote(&format!("{}={}", name, value))))
+        .collect::<String>()
+        .into_bytes();
+
+    File::open(&job.path)
+        .and_then(|mut file| file.read_to_end(&mut script))
+        .context(ErrorKind::JobStartFailed(job.breadcrumb.to_string()))?;
+
+    let script = Arc::new(script);
+
+    let ssh_command = ssh_command();
+    let mut processes: Vec<HostProcess> = Vec::new();
+
+    for host in &job.hosts {
+        let breadcrumb = format!("{}@{}", job.breadcrumb, host);
+
+        let output = || {
+            if options.stream {
+                Stdio::inherit()
+            } else {
+                Stdio::piped()
+            }
+        };
+
+        let mut words = ssh_command.split_whitespace();
+
+        let mut command = Command::new(words.next().unwrap_or("ssh"));
+
+        command
+            .args(words)
+            .arg(host)
+            .arg(remote.join(" "))
+            .stdin(Stdio::piped())
+            .stdout(output())
+            .stderr(output());
+
+        if !options.stream {
+            command.process_group(0);
+        }
+
+        // Hosts already started are stopped, the job didn't start
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                for (_, mut child, _) in processes {
+                    options.cancellation.unregister(child.id());
+
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+
+                return Err(err)
+                    .context(ErrorKind::JobStartFailed(breadcrumb))
+                    .map_err(Error::from);
+            }
+        };
+
+        options.cancellation.register(child.id());
+
+        // Written from a thread, ssh may not read its input before the
+        // remote command starts producing output
+        if let Some(mut stdin) = child.stdin.take() {
+            let script = Arc::clone(&script);
+
+            thread::spawn(move || {
+                let _ = stdin.write_all(&script);
+            });
+        }
+
+        let mut readers = Vec::new();
+
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(read_output(
+                &breadcrumb,
+                stdout,
+                &options.output,
+                ERROR_TAIL_LINES,
+            ));
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(read_output(
+                &breadcrumb,
+                stderr,
+                &options.output,
+                ERROR_TAIL_LINES,
+            ));
+        }
+
+        processes.push((host.to_string(), child, readers));
+    }
+
+    Ok(JobProcess(
+        job,
+        Task::Hosts(processes),
+        options.cancellation.clone(),
+    ))
+}
+
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub fn start_function<'a>(
+    job: &'a Job,
+    function: Function,
+    options: &Options,
+) -> Result<JobProcess<'a>, Error> {
+    let context = Context {
+        job: job.id.to_string(),
+        breadcrumb: job.breadcrumb.to_string(),
+        folder: job.folder.to_string(),
+        params: options.params.clone(),
+        stream: options.stream,
+        output: options.output.clone(),
+        cancellation: options.cancellation.clone(),
+    };
+
+    let handle = thread::Builder::new()
+        .name(job.breadcrumb.to_string())
+        .spawn(move || function(&context))
+        .context(ErrorKind::JobStartFailed(job.breadcrumb.to_string()))?;
+
+    Ok(JobProcess(
+        job,
+        Task::Function(handle),
+        options.cancellation.clone(),
+    ))
+}
+
+// Replaces references to outputs of jobs of earlier stages, like
+// {{ jobs.build.outputs.id }}. Other {{ }} blocks are left as they are
+pub fn interpolate(value: &str, output: &Output) -> Result<String, String> {
+    let mut interpolated = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        interpolated.push_str(&rest[..start]);
+
+        let reference = rest[start + 2..end].trim();
+
+        match reference.split('.').collect::<Vec<&str>>().as_slice() {
+            ["jobs", job, "outputs", key] => {
+                let value = output
+                    .job_value(job, key)
+                    .ok_or_else(|| reference.to_string())?;
+
+                interpolated.push_str(&value);
+            }
+            _ => interpolated.push_str(&rest[start..end + 2]),
+        }
+
+        rest = &rest[end + 2..];
+    }
+
+    interpolated.push_str(rest);
+
+    Ok(interpolated)
+}
+
+// Strings of a job type config, at any depth
+fn interpolate_config(
+    config: &Map<String, Value>,
+    output: &Output,
+) -> Result<Map<String, Value>, String> {
+    config
+        .iter()
+        .map(|(name, value)| Ok((name.to_string(), interpolate_value(value, output)?)))
+        .collect()
+}
+
+fn interpolate_value(value: &Value, output: &Output) -> Result<Value, String> {
+    match value {
+        Value::String(value) => Ok(Value::String(interpolate(value, output)?)),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| interpolate_value(value, output))
+            .collect(),
+        Value::Object(config) => Ok(Value::Object(interpolate_config(config, output)?)),
+        value => Ok(value.clone()),
+    }
+}
+
+// Copies each line to the run output (and its log file) as it is read, and
+// returns the last lines for the error message of a failed job or its result
+fn read_output<R>(breadcrumb: &str, stream: R, output: &Output, lines: usize) -> JoinHandle<String>
+where
+    R: Read + Send + 'static,
+{
+    let breadcrumb = breadcrumb.to_string();
+    let output = output.clone();
+
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut tail = VecDeque::with_capacity(lines.min(ERROR_TAIL_LINES));
+        let mut buffer = Vec::new();
+
+        // Invalid UTF-8 must not stop the reader, a full pipe would block the job
+        while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+            if read == 0 {
+                break;
+            }
+
+            let line = String::from_utf8_lossy(&buffer)
+                .trim_end_matches(&['\n', '\r'][..])
+                .to_string();
+
+            buffer.clear();
+
+            output.push(format!("[{}] {}", breadcrumb, line));
+
+            if tail.len() == lines {
+                tail.pop_front();
+            }
+
+            tail.push_back(line);
+        }
+
+        tail.into_iter().fold(String::new(), |mut content, line| {
+            content.push_str(&line);
+            content.push('\n');
+            content
+        })
+    })
 }
 
-pub fn wait_job(process: JobProcess) -> Result<&Job, Error> {
-    let JobProcess(job, child) = process;
+impl OutputsFile {
+    // The temporary folder is shared, so the file must be a new one that
+    // only the scheduler user can read and write, never a planted symlink
+    fn create(job: &Job, output: &Output) -> Result<OutputsFile, Error> {
+        loop {
+            let count = OUTPUTS_FILES.fetch_add(1, Ordering::SeqCst);
 
-    let output = child
-        .wait_with_output()
-        .context(ErrorKind::JobWaitFailed(job.breadcrumb.to_string()))?;
+            let path = env::temp_dir().join(format!(
+                "rusty-scheduler-{}-{}.outputs",
+                process::id(),
+                count
+            ));
+
+            let created = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path);
+
+            match created {
+                Ok(_) => {
+                    return Ok(OutputsFile {
+                        path,
+                        output: output.clone(),
+                    })
+                }
+                // Taken by another file, maybe on purpose, the next name is tried
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(err)
+                        .context(ErrorKind::JobStartFailed(job.breadcrumb.to_string()))
+                        .map_err(Error::from)
+                }
+            }
+        }
+    }
+
+    // Later lines win, invalid ones are logged and ignored
+    fn collect(self, job: &Job) {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => {
+                for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                    match line.split_once('=') {
+                        Some((key, value)) if is_variable_name(key) => {
+                            self.output.set_value(&job.id, key, value)
+                        }
+                        _ => warn!("Invalid job output ({}): {}", job.breadcrumb, line),
+                    }
+                }
+            }
+            Err(err) => warn!("Error reading job outputs ({}): {}", job.breadcrumb, err),
+        }
+
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn wait_job(process: JobProcess<'_>) -> Result<&Job, Error> {
+    let (job, mut child, cancellation, readers, outputs) = match process {
+        JobProcess(job, Task::Process(child, readers, outputs), cancellation) => {
+            (job, child, cancellation, readers, outputs)
+        }
+        JobProcess(job, Task::Function(handle), _) => return wait_function(job, handle),
+        JobProcess(job, Task::Hosts(processes), cancellation) => {
+            return wait_hosts(job, processes, cancellation)
+        }
+    };
+
+    let pid = child.id();
+
+    let status = child.wait();
+
+    cancellation.unregister(pid);
+
+    let output = outputs.output.clone();
+
+    outputs.collect(job);
+
+    // Readers of the standard output and error, when output is captured
+    let mut tails: Vec<String> = readers
+        .into_iter()
+        .map(|reader| reader.join().unwrap_or_default())
+        .collect();
+
+    let stderr = tails.pop().unwrap_or_default();
+
+    if let (Some(OutputFormat::Json), Some(stdout)) = (job.output_format, tails.first()) {
+        match parse_json(stdout) {
+            Some(result) => output.set_result(&job.id, result),
+            None => warn!("Invalid JSON output ({})", job.breadcrumb),
+        }
+    }
 
-    if output.status.success() {
+    let status = status.context(ErrorKind::JobWaitFailed(job.breadcrumb.to_string()))?;
+
+    if status.success() {
         Ok(job)
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(ErrorKind::JobExecutionFailed(
+            job.breadcrumb.to_string(),
+            stderr,
+        ))?
+    }
+}
+
+// Waits for every host, the error lists the hosts that failed and the end
+// of their standard error
+fn wait_hosts(
+    job: &Job,
+    processes: Vec<HostProcess>,
+    cancellation: Cancellation,
+) -> Result<&Job, Error> {
+    let mut failures = Vec::new();
+
+    for (host, mut child, readers) in processes {
+        let pid = child.id();
+
+        let status = child.wait();
+
+        cancellation.unregister(pid);
+
+        let stderr = readers
+            .into_iter()
+            .map(|reader| reader.join().unwrap_or_default())
+            .last()
+            .unwrap_or_default();
+
+        match status {
+            Ok(status) if status.success() => trace!("Job completed: {}@{}", job.breadcrumb, host),
+            Ok(status) => failures.push(format!("{} ({}):\n{}", host, status, stderr)),
+            Err(err) => failures.push(format!("{} ({})", host, err)),
+        }
+    }
 
+    if failures.is_empty() {
+        Ok(job)
+    } else {
         Err(ErrorKind::JobExecutionFailed(
             job.breadcrumb.to_string(),
-            stderr.to_string(),
+            format!(
+                "failed on {} of {} hosts: {}",
+                failures.len(),
+                job.hosts.len(),
+                failures.join("\n")
+            ),
         ))?
     }
 }
+
+// The last line, or the whole end of the output for indented JSON
+fn parse_json(stdout: &str) -> Option<Value> {
+    let last = stdout.lines().rev().find(|line| !line.trim().is_empty())?;
+
+    serde_json::from_str(last)
+        .or_else(|_| serde_json::from_str(stdout))
+        .ok()
+}
+
+fn wait_function(job: &Job, handle: JoinHandle<Result<(), String>>) -> Result<&Job, Error> {
+    // A panic fails the job like a script exiting with an error
+    let result = handle
+        .join()
+        .unwrap_or_else(|_| Err(String::from("function panicked")));
+
+    match result {
+        Ok(_) => Ok(job),
+        Err(message) => Err(ErrorKind::JobExecutionFailed(
+            job.breadcrumb.to_string(),
+            message,
+        ))?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn keep_the_end_of_large_outputs() {
+        let script = env::temp_dir().join("rusty-scheduler-output.sh");
+
+        fs::write(
+            &script,
+            "seq 1 20000; seq 1 5000 >&2; printf 'bad \\377 byte\\n' >&2; exit 1",
+        )
+        .unwrap();
+
+        let pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "output", "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "path": "{}"}}]}}"#,
+            script.to_string_lossy()
+        ))
+        .unwrap();
+
+        let options = Options::default();
+
+        let job = start_job(&pipeline.jobs[0], &options).unwrap();
+
+        let message = match wait_job(job) {
+            Err(err) => match err.kind() {
+                ErrorKind::JobExecutionFailed(_, stderr) => stderr.to_string(),
+                kind => panic!("unexpected error: {}", kind),
+            },
+            Ok(_) => panic!("job should fail"),
+        };
+
+        let lines: Vec<&str> = message.lines().collect();
+
+        assert_eq!(lines.len(), ERROR_TAIL_LINES);
+        assert_eq!(lines[0], "4902");
+        assert_eq!(lines[ERROR_TAIL_LINES - 1], "bad \u{fffd} byte");
+        assert_eq!(options.output.lines_since(0).1, 25001);
+    }
+
+    #[test]
+    fn pass_labels_to_jobs() {
+        function::register("test-labels", |context| {
+            let reason = context.param("RUSTY_SCHEDULER_LABEL_REASON").unwrap_or("?");
+            let target = context.param("TARGET").unwrap_or("?");
+
+            context.print(&format!("{} {}", reason, target));
+
+            Ok(())
+        });
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "labels", "params": {"TARGET": "production"}, "stages": ["main"], "jobs": [{"id": "job", "stage": "main", "function": "test-labels"}]}"#,
+        )
+        .unwrap();
+
+        pipeline.link_jobs();
+
+        let options = Options {
+            labels: vec![(String::from("reason"), String::from("hotfix"))]
+                .into_iter()
+                .collect(),
+            ..Options::default()
+        };
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[labels/main/job] hotfix production")]
+        );
+
+        assert!(is_variable_name("requested_by"));
+        assert!(!is_variable_name("requested-by"));
+        assert!(!is_variable_name(""));
+    }
+
+    #[test]
+    fn run_jobs_after_their_producers() {
+        let folder = env::temp_dir().join("rusty-scheduler-produces");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+
+        fs::write(
+            folder.join("extract.sh"),
+            "sleep 0.2; echo 42 > \"$(dirname \"$0\")/raw.txt\"\n",
+        )
+        .unwrap();
+        fs::write(
+            folder.join("load.sh"),
+            "echo \"loaded $(cat \"$(dirname \"$0\")/raw.txt\")\"\n",
+        )
+        .unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "produces", "stages": ["main"], "jobs": [
+                {"id": "load", "stage": "main", "script": "load.sh", "consumes": ["raw.txt"]},
+                {"id": "extract", "stage": "main", "script": "extract.sh", "produces": ["raw.txt"]}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = folder.join("pipeline.json").to_string_lossy().to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[produces/main/load] loaded 42")]
+        );
+
+        fs::remove_file(folder.join("raw.txt")).unwrap();
+        pipeline.jobs[1].produces.clear();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_err());
+        assert_eq!(
+            options.output.failed_jobs(),
+            vec![String::from("produces/main/load")]
+        );
+    }
+
+    #[test]
+    fn pass_logical_date_to_jobs() {
+        function::register("test-logical-date", |context| {
+            context.print(context.param(LOGICAL_DATE_VARIABLE).unwrap_or("?"));
+
+            Ok(())
+        });
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "logical", "stages": ["main"], "jobs": [{"id": "job", "stage": "main", "function": "test-logical-date"}]}"#,
+        )
+        .unwrap();
+
+        pipeline.link_jobs();
+
+        let options = Options {
+            logical_date: Some("2019-07-02T06:00:00Z".parse().unwrap()),
+            ..Options::default()
+        };
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[logical/main/job] 2019-07-02T06:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn collect_job_outputs() {
+        let script = env::temp_dir().join("rusty-scheduler-outputs.sh");
+
+        fs::write(
+            &script,
+            "echo \"last=$RUSTY_SCHEDULER_LAST_CURSOR\"\n\
+             echo cursor=41 >> \"$RUSTY_SCHEDULER_OUTPUT\"\n\
+             echo 'not an output' >> \"$RUSTY_SCHEDULER_OUTPUT\"\n\
+             echo cursor=42 >> \"$RUSTY_SCHEDULER_OUTPUT\"\n",
+        )
+        .unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "outputs", "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "script": "{}"}}]}}"#,
+            script.file_name().unwrap().to_string_lossy()
+        ))
+        .unwrap();
+
+        pipeline.path = script
+            .with_file_name("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+        pipeline.link_jobs();
+
+        let options = Options {
+            last_outputs: vec![(String::from("cursor"), String::from("40"))]
+                .into_iter()
+                .collect(),
+            ..Options::default()
+        };
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[outputs/main/job] last=40")]
+        );
+        assert_eq!(
+            options.output.values(),
+            vec![(String::from("cursor"), String::from("42"))]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn capture_json_results() {
+        let dir = env::temp_dir().join("rusty-scheduler-json");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("line.sh"), "echo loading\necho '{\"rows\": 3}'\n").unwrap();
+        fs::write(
+            dir.join("indented.sh"),
+            "printf '{\\n  \"ok\": true\\n}\\n'\n",
+        )
+        .unwrap();
+        fs::write(dir.join("text.sh"), "echo done\n").unwrap();
+        fs::write(
+            dir.join("long.sh"),
+            "echo '['\nseq 1 199 | sed 's/$/,/'\nprintf '200\\n]\\n'\n",
+        )
+        .unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "json", "stages": ["main"], "jobs": [
+                {"id": "line", "stage": "main", "script": "line.sh", "output_format": "json"},
+                {"id": "indented", "stage": "main", "script": "indented.sh", "output_format": "json"},
+                {"id": "text", "stage": "main", "script": "text.sh", "output_format": "json"},
+                {"id": "long", "stage": "main", "script": "long.sh", "output_format": "json"}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+
+        let results = options.output.results();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results["line"], serde_json::json!({"rows": 3}));
+        assert_eq!(results["indented"], serde_json::json!({"ok": true}));
+        assert_eq!(results["long"], serde_json::json!((1..=200).collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn hold_jobs_outside_their_window() {
+        let dir = env::temp_dir().join("rusty-scheduler-window");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("job.sh"), "echo ran\n").unwrap();
+
+        // Opens in an hour, closed for the whole test
+        let start = Utc::now() + chrono::Duration::hours(1);
+        let window = format!(
+            "{}-{}",
+            start.format("%H:%M"),
+            (start + chrono::Duration::hours(1)).format("%H:%M")
+        );
+
+        let mut pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "window", "stages": ["main", "report"], "jobs": [
+                {{"id": "wait", "stage": "main", "script": "job.sh", "allowed_window": "{0}"}},
+                {{"id": "skip", "stage": "report", "script": "job.sh", "allowed_window": "{0}", "outside_window": "skip"}},
+                {{"id": "open", "stage": "report", "script": "job.sh"}}
+            ]}}"#,
+            window
+        ))
+        .unwrap();
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute_stage(&pipeline, "report", &options).is_ok());
+        assert_eq!(options.output.passed_jobs(), vec!["window/report/open"]);
+
+        // Cancelled while waiting, the job never starts
+        let options = Options::default();
+        let cancellation = options.cancellation.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancellation.cancel();
+        });
+
+        assert!(execute_stage(&pipeline, "main", &options).is_err());
+        assert_eq!(options.output.failed_jobs(), vec!["window/main/wait"]);
+        assert!(!options
+            .output
+            .lines_since(0)
+            .0
+            .contains(&String::from("[window/main/wait] ran")));
+    }
+
+    #[test]
+    fn pass_listed_host_variables() {
+        let script = env::temp_dir().join("rusty-scheduler-pass-env.sh");
+
+        fs::write(
+            &script,
+            "echo \"${RUSTY_SCHEDULER_TEST_PASSED}-${RUSTY_SCHEDULER_TEST_HIDDEN}-${TARGET}\"\n",
+        )
+        .unwrap();
+
+        env::set_var("RUSTY_SCHEDULER_TEST_PASSED", "passed");
+        env::set_var("RUSTY_SCHEDULER_TEST_HIDDEN", "hidden");
+
+        let mut pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "pass-env", "params": {{"TARGET": "staging"}}, "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "script": "{}", "pass_env": ["PATH", "RUSTY_SCHEDULER_TEST_P*"]}}]}}"#,
+            script.file_name().unwrap().to_string_lossy()
+        ))
+        .unwrap();
+
+        pipeline.path = script
+            .with_file_name("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[pass-env/main/job] passed--staging")]
+        );
+    }
+
+    #[test]
+    fn run_helpers_of_the_scripts_lib() {
+        let dir = env::temp_dir().join("rusty-scheduler-scripts-lib");
+
+        fs::create_dir_all(dir.join(SCRIPTS_LIB)).unwrap();
+        fs::create_dir_all(dir.join("helpers")).unwrap();
+
+        let helper = dir.join(SCRIPTS_LIB).join("greet");
+
+        fs::write(&helper, "#!/bin/sh\necho \"hello $1\"\n").unwrap();
+        fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+
+        fs::write(dir.join("helpers").join("job.sh"), "greet world\n").unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "helpers", "stages": ["main"], "jobs": [
+                {"id": "job", "stage": "main", "script": "job.sh"}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = dir
+            .join("helpers")
+            .join("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[helpers/main/job] hello world")]
+        );
+    }
+
+    #[test]
+    fn record_run_snapshots() {
+        let script = env::temp_dir().join("rusty-scheduler-snapshot.sh");
+
+        fs::write(&script, "echo \"$1\"\n").unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "snapshot", "params": {{"TARGET": "staging"}}, "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "script": "{}", "args": ["--full"], "env": {{"MODE": "fast"}}}}]}}"#,
+            script.file_name().unwrap().to_string_lossy()
+        ))
+        .unwrap();
+
+        pipeline.path = script
+            .with_file_name("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+
+        let snapshot = options.output.snapshot().unwrap();
+
+        assert_eq!(snapshot.version, VERSION);
+        assert_eq!(snapshot.env["TARGET"], "staging");
+        assert!(snapshot.env.contains_key(LOGICAL_DATE_VARIABLE));
+
+        let job = &snapshot.jobs["snapshot/main/job"];
+
+        assert_eq!(job.command, vec!["sh", &job.command[1], "--full"]);
+        assert!(job.command[1].ends_with("rusty-scheduler-snapshot.sh"));
+        assert_eq!(job.env["MODE"], "fast");
+        assert_eq!(
+            job.script_sha256,
+            format!("{:x}", Sha256::digest(b"echo \"$1\"\n"))
+        );
+    }
+
+    #[test]
+    fn interpolate_job_outputs() {
+        let output = Output::default();
+        output.set_value("build", "id", "1234");
+
+        assert_eq!(
+            interpolate("--build-id {{ jobs.build.outputs.id }}", &output).unwrap(),
+            "--build-id 1234"
+        );
+        assert_eq!(
+            interpolate("{{jobs.build.outputs.id}}-{{ name }}-{{", &output).unwrap(),
+            "1234-{{ name }}-{{"
+        );
+        assert_eq!(
+            interpolate("{{ jobs.build.outputs.tag }}", &output).unwrap_err(),
+            "jobs.build.outputs.tag"
+        );
+        assert_eq!(
+            interpolate_value(
+                &serde_json::json!({"ids": ["{{ jobs.build.outputs.id }}", 1]}),
+                &output
+            )
+            .unwrap(),
+            serde_json::json!({"ids": ["1234", 1]})
+        );
+    }
+
+    #[test]
+    fn pass_outputs_to_later_stages() {
+        let build = env::temp_dir().join("rusty-scheduler-build.sh");
+        let deploy = env::temp_dir().join("rusty-scheduler-deploy.sh");
+
+        fs::write(&build, "echo id=1234 >> \"$RUSTY_SCHEDULER_OUTPUT\"\n").unwrap();
+        fs::write(&deploy, "echo \"$1 $2 $BUILD_ID\"\n").unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "chain", "stages": ["build", "deploy"], "jobs": [
+                {"id": "build", "stage": "build", "script": "rusty-scheduler-build.sh"},
+                {"id": "deploy", "stage": "deploy", "script": "rusty-scheduler-deploy.sh",
+                 "args": ["--build-id", "{{ jobs.build.outputs.id }}"],
+                 "env": {"BUILD_ID": "build-{{ jobs.build.outputs.id }}"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = build
+            .with_file_name("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from(
+                "[chain/deploy/deploy] --build-id 1234 build-1234"
+            )]
+        );
+
+        // Jobs of the same stage run at the same time, so their outputs can't be used
+        pipeline.jobs[1].stage = String::from("build");
+        pipeline.stages.pop();
+        pipeline.link_jobs();
+
+        assert!(execute(&pipeline, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn run_scripts_with_the_pipeline_interpreter() {
+        let script = env::temp_dir().join("rusty-scheduler-interpreter.sh");
+
+        fs::write(&script, "false\necho \"after $1\"\n").unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "strict", "stages": ["main"], "jobs": [
+                {"id": "job", "stage": "main", "script": "rusty-scheduler-interpreter.sh", "args": ["false"]}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = script
+            .with_file_name("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[strict/main/job] after false")]
+        );
+
+        // Options of the interpreter go before the script and its arguments
+        pipeline.interpreter = Some(String::from("sh  -e"));
+
+        assert!(execute(&pipeline, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn run_scripts_on_hosts() {
+        let dir = env::temp_dir().join("rusty-scheduler-hosts");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        // Runs the remote command locally, failing on "web2"
+        let ssh = dir.join("ssh.sh");
+
+        fs::write(
+            &ssh,
+            "[ \"$1\" = web2 ] && exit 3\necho \"$2\" > \"$(dirname \"$0\")/remote.txt\"\nexec sh -c \"$2\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("job.sh"), "echo \"$NAME $1 $2\"\n").unwrap();
+
+        set_ssh_command(&format!("sh {}", ssh.to_string_lossy())).unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "hosts", "stages": ["main"], "jobs": [
+                {"id": "job", "stage": "main", "script": "job.sh", "args": ["it's", "a b"],
+                 "env": {"NAME": "deploy"}, "hosts": ["web1", "web3"]}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+        pipeline.link_jobs();
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_ok());
+
+        let mut lines = options.output.lines_since(0).0;
+
+        lines.sort();
+
+        assert_eq!(
+            lines,
+            vec![
+                String::from("[hosts/main/job@web1] deploy it's a b"),
+                String::from("[hosts/main/job@web3] deploy it's a b"),
+            ]
+        );
+
+        // Parameters are not part of the remote command
+        let remote = fs::read_to_string(dir.join("remote.txt")).unwrap();
+
+        assert!(!remote.contains("deploy"));
+
+        pipeline.jobs[0].hosts.push(String::from("web2"));
+
+        let options = Options::default();
+
+        assert!(execute(&pipeline, &options).is_err());
+        assert_eq!(
+            options.output.failed_jobs(),
+            vec![String::from("hosts/main/job")]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use super::pipeline::Pipeline;
+use super::state::State;
+use super::timezone;
+use chrono::{DateTime, TimeZone, Utc};
+
+#[derive(Debug)]
+pub struct Explanation {
+    pub facts: Vec<(&'static str, String)>,
+
+    pub will_run: bool,
+
+    pub reason: String,
+}
+
+// Follows the checks done by the scheduler on every tick
+pub fn explain(
+    pipeline: &Pipeline,
+    state: Option<&State>,
+    running: bool,
+    now: DateTime<Utc>,
+) -> Explanation {
+    let previous = match state {
+        Some(state) => state.timestamp,
+        None => State::first_timestamp(pipeline.first_run),
+    };
+
+    let next = pipeline.interval.next_time(previous);
+
+    let paused = state.is_some_and(|state| state.paused);
+    let failures = state.map_or(0, |state| state.consecutive_failures);
+
+    let mut facts = vec![
+        ("Pipeline", pipeline.id.to_string()),
+        (
+            "Schedule",
+            format!("{} ({})", pipeline.interval.describe(), pipeline.expression),
+        ),
+        (
+            "Status",
+            String::from(if !pipeline.enabled {
+                "disabled"
+            } else if paused {
+                "paused"
+            } else {
+                "enabled"
+            }),
+        ),
+    ];
+
+    let previous_fact = if state.is_none() {
+        format!(
+            "never, no state file (first_run is {:?})",
+            pipeline.first_run
+        )
+        .to_lowercase()
+    } else if previous == Utc.timestamp_opt(0, 0).unwrap() {
+        String::from("never")
+    } else {
+        format_time(previous)
+    };
+
+    facts.push(("Previous run", previous_fact));
+    facts.push(("Next run", format_time(next)));
+    facts.push(("Now", format_time(now)));
+    facts.push(("Failures", format!("{} in a row", failures)));
+    facts.push(("Running", String::from(if running { "yes" } else { "no" })));
+
+    let (will_run, reason) = if !pipeline.enabled {
+        (
+            false,
+            String::from("The pipeline is disabled, set `enabled` to true in pipeline.json."),
+        )
+    } else if next > now {
+        let minutes = (next - now).num_minutes();
+
+        (
+            false,
+            format!(
+                "The next run time is not reached yet, it runs in {} minute(s).",
+                minutes
+            ),
+        )
+    } else if paused {
+        (
+            false,
+            String::from(
+                "The pipeline is paused, the due run is recorded as skipped. Use the `resume` command.",
+            ),
+        )
+    } else if running {
+        (
+            false,
+            String::from(
+                "A previous run is still active, the latest due run starts after it completes.",
+            ),
+        )
+    } else if failures > 0 {
+        (
+            true,
+            String::from(
+                "The next run time has passed. Failed runs keep the previous run date, so the pipeline is retried on every tick until it succeeds.",
+            ),
+        )
+    } else {
+        (
+            true,
+            String::from("The next run time has passed since the previous run."),
+        )
+    };
+
+    Explanation {
+        facts,
+        will_run,
+        reason,
+    }
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    timezone::format(time, "%Y-%m-%d %H:%M:%S")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interval::Interval;
+
+    fn pipeline(enabled: bool) -> Pipeline {
+        let mut pipeline: Pipeline =
+            serde_json::from_str(&format!(r#"{{"id": "loader", "enabled": {}}}"#, enabled))
+                .unwrap();
+
+        pipeline.expression = String::from("0 8 * * *");
+        pipeline.interval = Interval::new(&pipeline.expression).unwrap();
+
+        pipeline
+    }
+
+    fn state(timestamp: &str, paused: bool, failures: u32) -> State {
+        serde_json::from_str(&format!(
+            r#"{{"timestamp": "{}", "paused": {}, "consecutive_failures": {}}}"#,
+            timestamp, paused, failures
+        ))
+        .unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2019, 7, 2, 9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn explain_due_run() {
+        let state = state("2019-07-01T08:00:00Z", false, 0);
+
+        let explanation = explain(&pipeline(true), Some(&state), false, now());
+
+        assert!(explanation.will_run);
+        assert!(explanation
+            .facts
+            .contains(&("Next run", String::from("2019-07-02 08:00:00 UTC"))));
+    }
+
+    #[test]
+    fn explain_not_due_run() {
+        let state = state("2019-07-02T08:00:00Z", false, 0);
+
+        let explanation = explain(&pipeline(true), Some(&state), false, now());
+
+        assert!(!explanation.will_run);
+        assert!(explanation.reason.contains("1380 minute(s)"));
+    }
+
+    #[test]
+    fn explain_blocked_runs() {
+        let paused = state("2019-07-01T08:00:00Z", true, 0);
+        let active = state("2019-07-01T08:00:00Z", false, 0);
+
+        assert!(!explain(&pipeline(false), Some(&active), false, now()).will_run);
+        assert!(!explain(&pipeline(true), Some(&paused), false, now()).will_run);
+        assert!(!explain(&pipeline(true), Some(&active), true, now()).will_run);
+    }
+
+    #[test]
+    fn explain_new_pipeline() {
+        let explanation = explain(&pipeline(true), None, false, now());
+
+        assert!(explanation.will_run);
+        assert!(explanation.facts.contains(&(
+            "Previous run",
+            String::from("never, no state file (first_run is immediately)")
+        )));
+    }
+}
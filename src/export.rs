@@ -0,0 +1,288 @@
+use super::interval::{expand_macro, Compat};
+use super::pipeline::Pipeline;
+use super::timezone::Timezone;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+
+// A pipeline running every minute would flood the calendar
+pub const ICS_MAX_EVENTS: usize = 1000;
+
+const ICS_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+pub fn crontab(pipelines: &[Pipeline], command: &str) -> String {
+    let mut lines = vec![
+        String::from("# Generated by rusty-scheduler"),
+        String::from("# Schedules are evaluated in UTC unless CRON_TZ sets another timezone"),
+        String::from("CRON_TZ=UTC"),
+        String::new(),
+    ];
+
+    let mut timezone = Timezone::Utc;
+
+    for pipeline in pipelines {
+        let run = format!("{} run {}", command, pipeline.id);
+
+        // CRON_TZ applies to the lines after it, the host timezone has no name
+        if pipeline.interval.timezone == Timezone::Local {
+            lines.push(format!(
+                "# {}: local timezone cannot be converted: {}",
+                pipeline.id, pipeline.expression
+            ));
+
+            continue;
+        }
+
+        if pipeline.interval.timezone != timezone {
+            timezone = pipeline.interval.timezone;
+
+            lines.push(format!("CRON_TZ={}", timezone));
+        }
+
+        match crontab_expression(pipeline) {
+            Some(expression) if pipeline.enabled => {
+                lines.push(format!("{} {}", expression, run));
+            }
+            Some(expression) => {
+                lines.push(format!("# {}: disabled", pipeline.id));
+                lines.push(format!("# {} {}", expression, run));
+            }
+            None => {
+                lines.push(format!(
+                    "# {}: expression cannot be converted: {}",
+                    pipeline.id, pipeline.expression
+                ));
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+pub fn ics<F>(pipelines: &[Pipeline], from: DateTime<Utc>, to: DateTime<Utc>, duration: F) -> String
+where
+    F: Fn(&Pipeline) -> f64,
+{
+    let stamp = from.format(ICS_TIME_FORMAT);
+
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        String::from("PRODID:-//rusty-scheduler//EN"),
+        String::from("CALSCALE:GREGORIAN"),
+    ];
+
+    for pipeline in pipelines.iter().filter(|pipeline| pipeline.enabled) {
+        let times = pipeline.interval.between(from, to, ICS_MAX_EVENTS);
+
+        if times.len() == ICS_MAX_EVENTS {
+            warn!(
+                "Only the first {} runs are exported: {}",
+                ICS_MAX_EVENTS, pipeline.id
+            );
+        }
+
+        // Events last for the average run duration, at least a minute
+        let duration = Duration::seconds(duration(pipeline).max(60.0) as i64);
+
+        for time in times {
+            let start = time.format(ICS_TIME_FORMAT);
+
+            lines.push(String::from("BEGIN:VEVENT"));
+            lines.push(format!("UID:{}-{}@rusty-scheduler", pipeline.id, start));
+            lines.push(format!("DTSTAMP:{}", stamp));
+            lines.push(format!("DTSTART:{}", start));
+            lines.push(format!(
+                "DTEND:{}",
+                (time + duration).format(ICS_TIME_FORMAT)
+            ));
+            lines.push(format!("SUMMARY:{}", ics_escape(&pipeline.id)));
+            lines.push(format!(
+                "DESCRIPTION:{}",
+                ics_escape(&pipeline.interval.describe())
+            ));
+            lines.push(String::from("END:VEVENT"));
+        }
+    }
+
+    lines.push(String::from("END:VCALENDAR"));
+
+    // Lines end with CRLF and are folded at 75 octets
+    lines
+        .iter()
+        .map(|line| ics_fold(line))
+        .collect::<Vec<String>>()
+        .join("\r\n")
+        + "\r\n"
+}
+
+fn ics_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn ics_fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+
+        folded.push(c);
+        length += c.len_utf8();
+    }
+
+    folded
+}
+
+pub fn crontab_expression(pipeline: &Pipeline) -> Option<String> {
+    let interval = &pipeline.interval;
+
+    // Already read like cron
+    if interval.compat == Compat::Vixie {
+        let expression = interval.expression.trim();
+
+        return Some(expand_macro(expression).unwrap_or(expression).to_string());
+    }
+
+    // Cron runs when either the day or the weekday matches, here both
+    // are ignored when given together
+    if !interval.days.is_empty() && !interval.weekdays.is_empty() {
+        return None;
+    }
+
+    // Cron has no last weekday of the month
+    if interval.last_weekday {
+        return None;
+    }
+
+    let fields: Vec<String> = [
+        &interval.minutes,
+        &interval.hours,
+        &interval.days,
+        &interval.months,
+        &interval.weekdays,
+    ]
+    .iter()
+    .map(|numbers| {
+        if numbers.is_empty() {
+            String::from("*")
+        } else {
+            let numbers: Vec<String> = numbers.iter().map(|number| number.to_string()).collect();
+
+            numbers.join(",")
+        }
+    })
+    .collect();
+
+    Some(fields.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interval::Interval;
+
+    fn pipeline(id: &str, expression: &str, enabled: bool) -> Pipeline {
+        let mut pipeline: Pipeline =
+            serde_json::from_str(&format!(r#"{{"id": "{}", "enabled": {}}}"#, id, enabled))
+                .unwrap();
+
+        pipeline.expression = expression.to_string();
+        pipeline.interval = Interval::new(expression).unwrap();
+
+        pipeline
+    }
+
+    #[test]
+    fn crontab_expression_from_interval() {
+        let pipeline = pipeline("loader", "30 0,4,8 * * 1,7", true);
+
+        assert_eq!(crontab_expression(&pipeline).unwrap(), "30 0,4,8 * * 1,7");
+    }
+
+    #[test]
+    fn crontab_expression_day_and_weekday() {
+        let pipeline = pipeline("loader", "0 0 1 * 1", true);
+
+        assert_eq!(crontab_expression(&pipeline), None);
+    }
+
+    #[test]
+    fn crontab_expression_vixie_compat() {
+        let mut pipeline = pipeline("loader", "0 0 13 * 5", true);
+
+        pipeline.interval = Interval::with_compat("0 0 13 * 5", Compat::Vixie).unwrap();
+
+        assert_eq!(crontab_expression(&pipeline).unwrap(), "0 0 13 * 5");
+
+        pipeline.interval = Interval::with_compat("@weekly", Compat::Vixie).unwrap();
+
+        assert_eq!(crontab_expression(&pipeline).unwrap(), "0 0 * * 7");
+    }
+
+    #[test]
+    fn crontab_expression_last_weekday() {
+        let pipeline = pipeline("loader", "0 0 LW * *", true);
+
+        assert_eq!(crontab_expression(&pipeline), None);
+    }
+
+    #[test]
+    fn ics_events() {
+        use chrono::TimeZone;
+
+        let pipelines = vec![
+            pipeline("loader", "0 8 * * *", true),
+            pipeline("cleanup", "0 0 * * *", false),
+        ];
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap();
+        let to = from + Duration::days(2);
+
+        let ics = ics(&pipelines, from, to, |_| 600.0);
+
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(ics.contains("DTSTART:20190701T080000Z\r\nDTEND:20190701T081000Z\r\n"));
+        assert!(ics.contains("UID:loader-20190702T080000Z@rusty-scheduler"));
+        assert!(!ics.contains("cleanup"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn ics_long_lines() {
+        let line = "x".repeat(100);
+
+        assert_eq!(
+            ics_fold(&line),
+            format!("{}\r\n {}", "x".repeat(75), "x".repeat(25))
+        );
+        assert_eq!(ics_escape("a, b; c"), "a\\, b\\; c");
+    }
+
+    #[test]
+    fn crontab_lines() {
+        let mut pipelines = vec![
+            pipeline("loader", "0 * * * *", true),
+            pipeline("cleanup", "0 0 * * *", false),
+            pipeline("report", "0 8 * * *", true),
+            pipeline("archive", "0 2 * * *", true),
+        ];
+
+        pipelines[2].interval.timezone = "America/Sao_Paulo".parse().unwrap();
+        pipelines[3].interval.timezone = Timezone::Local;
+
+        let crontab = crontab(&pipelines, "rusty-scheduler --pipelines /srv");
+
+        assert!(crontab.contains("\n0 * * * * rusty-scheduler --pipelines /srv run loader"));
+        assert!(crontab.contains("\n# 0 0 * * * rusty-scheduler --pipelines /srv run cleanup"));
+        assert!(crontab.contains(
+            "\nCRON_TZ=America/Sao_Paulo\n0 8 * * * rusty-scheduler --pipelines /srv run report"
+        ));
+        assert!(crontab.contains("\n# archive: local timezone cannot be converted: 0 2 * * *"));
+    }
+}
@@ -0,0 +1,178 @@
+use super::pipeline::Pipeline;
+use super::runtime::SharedRuntime;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+static FAIRNESS: Mutex<Fairness> = Mutex::new(Fairness::new());
+
+// Shares of the scheduled runs under a global limit of active runs. Each
+// pipeline, or each fair_share_tag shared by several pipelines, is charged
+// 1/weight per run, and the due pipelines charged the least start first
+#[derive(Debug, Default)]
+pub struct Fairness {
+    max_runs: Option<usize>,
+
+    // Weights of the fair_share_tag of pipelines
+    tag_weights: BTreeMap<String, u32>,
+
+    // Runs started over weight, by pipeline or tag
+    usage: BTreeMap<String, f64>,
+}
+
+impl Fairness {
+    pub const fn new() -> Fairness {
+        Fairness {
+            max_runs: None,
+            tag_weights: BTreeMap::new(),
+            usage: BTreeMap::new(),
+        }
+    }
+
+    // Pipelines in the order their due runs get the free slots, the ones
+    // with the same usage keep their order
+    pub fn sort(&mut self, pipelines: &mut [Pipeline]) {
+        if self.max_runs.is_none() {
+            return;
+        }
+
+        for pipeline in pipelines.iter() {
+            self.register(pipeline);
+        }
+
+        pipelines.sort_by(|a, b| {
+            self.usage_of(a)
+                .partial_cmp(&self.usage_of(b))
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+
+    pub fn is_full(&self, active: usize) -> bool {
+        self.max_runs.is_some_and(|max_runs| active >= max_runs)
+    }
+
+    pub fn record_start(&mut self, pipeline: &Pipeline) {
+        let weight = self.weight(pipeline);
+
+        *self.register(pipeline) += 1.0 / weight;
+    }
+
+    // Accounts seen for the first time start at the lowest usage, instead of
+    // taking every slot until they catch up with the others
+    fn register(&mut self, pipeline: &Pipeline) -> &mut f64 {
+        let lowest = self
+            .usage
+            .values()
+            .cloned()
+            .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .unwrap_or_default();
+
+        self.usage.entry(account(pipeline)).or_insert(lowest)
+    }
+
+    fn usage_of(&self, pipeline: &Pipeline) -> f64 {
+        self.usage
+            .get(&account(pipeline))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn weight(&self, pipeline: &Pipeline) -> f64 {
+        let weight = match &pipeline.fair_share_tag {
+            Some(tag) => self.tag_weights.get(tag).cloned().unwrap_or(1),
+            None => pipeline.weight,
+        };
+
+        weight.max(1) as f64
+    }
+}
+
+fn account(pipeline: &Pipeline) -> String {
+    match &pipeline.fair_share_tag {
+        Some(tag) => format!("tag:{}", tag),
+        None => format!("pipeline:{}", pipeline.id),
+    }
+}
+
+pub fn set_max_concurrent_runs(max_runs: Option<usize>) {
+    FAIRNESS.lock().unwrap().max_runs = max_runs;
+}
+
+pub fn set_tag_weights(tag_weights: BTreeMap<String, u32>) {
+    FAIRNESS.lock().unwrap().tag_weights = tag_weights;
+}
+
+pub fn sort(pipelines: &mut [Pipeline]) {
+    FAIRNESS.lock().unwrap().sort(pipelines);
+}
+
+// Runs started from triggers, watches and startups take slots too
+pub fn is_full(runtime: &SharedRuntime) -> bool {
+    let active = runtime.lock().unwrap().active.len();
+
+    FAIRNESS.lock().unwrap().is_full(active)
+}
+
+pub fn record_start(pipeline: &Pipeline) {
+    FAIRNESS.lock().unwrap().record_start(pipeline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(json: &str) -> Pipeline {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn share_slots_by_weight() {
+        let mut fairness = Fairness::new();
+
+        fairness.max_runs = Some(1);
+        fairness.tag_weights.insert(String::from("reports"), 2);
+
+        let mut pipelines = vec![
+            pipeline(r#"{"id": "frequent"}"#),
+            pipeline(r#"{"id": "loader", "weight": 2}"#),
+            pipeline(r#"{"id": "sales", "fair_share_tag": "reports"}"#),
+            pipeline(r#"{"id": "stock", "fair_share_tag": "reports"}"#),
+        ];
+
+        assert!(!fairness.is_full(0));
+        assert!(fairness.is_full(1));
+
+        // Every pipeline is due on every round, the first one takes the slot
+        let mut started: BTreeMap<String, u32> = BTreeMap::new();
+
+        for _ in 0..40 {
+            fairness.sort(&mut pipelines);
+            fairness.record_start(&pipelines[0]);
+
+            *started.entry(pipelines[0].id.to_string()).or_default() += 1;
+        }
+
+        assert_eq!(started["frequent"], 8);
+        assert_eq!(started["loader"], 16);
+        assert_eq!(
+            started.get("sales").unwrap_or(&0) + started.get("stock").unwrap_or(&0),
+            16
+        );
+
+        // A new pipeline doesn't get every slot until it catches up
+        pipelines.push(pipeline(r#"{"id": "new"}"#));
+
+        let mut new = 0;
+
+        for _ in 0..5 {
+            fairness.sort(&mut pipelines);
+            fairness.record_start(&pipelines[0]);
+
+            if pipelines[0].id == "new" {
+                new += 1;
+            }
+        }
+
+        assert_eq!(new, 1);
+    }
+}
@@ -0,0 +1,125 @@
+use super::runtime::{Cancellation, Output};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// Jobs with a "function" instead of a script run these, registered by the
+// program embedding the scheduler
+static FUNCTIONS: Mutex<BTreeMap<String, Function>> = Mutex::new(BTreeMap::new());
+
+pub type Function = Arc<dyn Fn(&Context) -> Result<(), String> + Send + Sync>;
+
+// What a function job gets instead of environment variables and a terminal
+pub struct Context {
+    pub job: String,
+
+    pub breadcrumb: String,
+
+    // Pipeline folder, relative paths of the job config start here
+    pub folder: String,
+
+    pub params: BTreeMap<String, String>,
+
+    // Interactive runs print the output instead of capturing it
+    pub stream: bool,
+
+    pub output: Output,
+
+    pub cancellation: Cancellation,
+}
+
+impl Context {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    // Same as a line printed by a script
+    pub fn print(&self, line: &str) {
+        if self.stream {
+            println!("{}", line);
+        } else {
+            self.output.push(format!("[{}] {}", self.breadcrumb, line));
+        }
+    }
+
+    // Same as a KEY=value line written by a script to its outputs file
+    pub fn set_output(&self, key: &str, value: &str) {
+        self.output.set_value(&self.job, key, value);
+    }
+
+    // Cancelling can't stop a thread, so long functions should check this
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+}
+
+// Replaces a function registered with the same name
+pub fn register<F>(name: &str, function: F)
+where
+    F: Fn(&Context) -> Result<(), String> + Send + Sync + 'static,
+{
+    FUNCTIONS
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), Arc::new(function));
+}
+
+pub fn find(name: &str) -> Option<Function> {
+    FUNCTIONS.lock().unwrap().get(name).cloned()
+}
+
+pub fn is_registered(name: &str) -> bool {
+    FUNCTIONS.lock().unwrap().contains_key(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::executor::{self, Options};
+    use super::super::pipeline::Pipeline;
+    use super::*;
+
+    fn pipeline(function: &str) -> Pipeline {
+        serde_json::from_str(&format!(
+            r#"{{"id": "embedded", "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "function": "{}"}}]}}"#,
+            function
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn run_registered_functions() {
+        register("test-greet", |context| {
+            context.print(&format!("hello {}", context.param("NAME").unwrap_or("?")));
+
+            Ok(())
+        });
+
+        let options = Options {
+            params: vec![(String::from("NAME"), String::from("world"))]
+                .into_iter()
+                .collect(),
+            ..Options::default()
+        };
+
+        let mut pipeline = pipeline("test-greet");
+        pipeline.jobs[0].breadcrumb = String::from("embedded/main/job");
+
+        assert!(executor::execute_stage(&pipeline, "main", &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[embedded/main/job] hello world")]
+        );
+    }
+
+    #[test]
+    fn fail_with_errors_panics_and_unknown_functions() {
+        register("test-fail", |_| Err(String::from("no data")));
+        register("test-panic", |_| panic!("boom"));
+
+        for function in &["test-fail", "test-panic", "test-unknown"] {
+            let options = Options::default();
+
+            assert!(executor::execute_stage(&pipeline(function), "main", &options).is_err());
+            assert_eq!(options.output.failed_jobs().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::pipeline::{Job, Pipeline};
+
+pub fn ascii(pipeline: &Pipeline) -> String {
+    let mut lines = vec![pipeline.id.to_string()];
+
+    for (index, stage) in pipeline.stages.iter().enumerate() {
+        let last_stage = index + 1 == pipeline.stages.len();
+
+        let (branch, indent) = if last_stage {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+
+        lines.push(format!("{}{}. {}", branch, index + 1, stage));
+
+        let jobs = stage_jobs(pipeline, stage);
+
+        for (index, job) in jobs.iter().enumerate() {
+            let branch = if index + 1 == jobs.len() {
+                "└── "
+            } else {
+                "├── "
+            };
+
+            let command = match (&job.function, &job.kind) {
+                (Some(function), _) => format!("{}()", function),
+                (None, Some(kind)) => format!("type {}", kind),
+                (None, None) => job.script.to_string(),
+            };
+
+            lines.push(format!("{}{}{} ({})", indent, branch, job.id, command));
+        }
+    }
+
+    lines.join("\n")
+}
+
+pub fn dot(pipeline: &Pipeline) -> String {
+    let mut lines = vec![
+        format!("digraph {:?} {{", pipeline.id),
+        String::from("    rankdir=LR;"),
+    ];
+
+    for (index, stage) in pipeline.stages.iter().enumerate() {
+        lines.push(format!("    subgraph \"cluster_{}\" {{", index));
+        lines.push(format!(
+            "        label={:?};",
+            format!("{}. {}", index + 1, stage)
+        ));
+
+        for job in stage_jobs(pipeline, stage) {
+            lines.push(format!(
+                "        {:?} [label={:?}];",
+                job.breadcrumb, job.id
+            ));
+        }
+
+        lines.push(String::from("    }"));
+    }
+
+    // Every job of a stage must complete before the next stage starts
+    for stages in pipeline.stages.windows(2) {
+        for from in stage_jobs(pipeline, &stages[0]) {
+            for to in stage_jobs(pipeline, &stages[1]) {
+                lines.push(format!("    {:?} -> {:?};", from.breadcrumb, to.breadcrumb));
+            }
+        }
+    }
+
+    lines.push(String::from("}"));
+
+    lines.join("\n")
+}
+
+fn stage_jobs<'a>(pipeline: &'a Pipeline, stage: &str) -> Vec<&'a Job> {
+    pipeline
+        .jobs
+        .iter()
+        .filter(|job| job.stage == stage)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline() -> Pipeline {
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{
+                "id": "loader",
+                "stages": ["download", "import"],
+                "jobs": [
+                    {"id": "catalog", "stage": "download", "script": "catalog.sh"},
+                    {"id": "prices", "stage": "download", "script": "prices.sh"},
+                    {"id": "import", "stage": "import", "script": "import.sh"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        for job in &mut pipeline.jobs {
+            job.breadcrumb = format!("{}/{}/{}", pipeline.id, job.stage, job.id);
+        }
+
+        pipeline
+    }
+
+    #[test]
+    fn ascii_tree() {
+        let expected = [
+            "loader",
+            "├── 1. download",
+            "│   ├── catalog (catalog.sh)",
+            "│   └── prices (prices.sh)",
+            "└── 2. import",
+            "    └── import (import.sh)",
+        ];
+
+        assert_eq!(ascii(&pipeline()), expected.join("\n"));
+    }
+
+    #[test]
+    fn dot_edges_between_stages() {
+        let dot = dot(&pipeline());
+
+        assert!(dot.starts_with("digraph \"loader\" {"));
+        assert!(dot.contains("\"loader/download/catalog\" -> \"loader/import/import\";"));
+        assert!(dot.contains("\"loader/download/prices\" -> \"loader/import/import\";"));
+        assert!(!dot.contains("\"loader/download/catalog\" -> \"loader/download/prices\";"));
+    }
+}
@@ -0,0 +1,465 @@
+use super::error::Error;
+use super::runtime::SharedRuntime;
+use super::security::Security;
+
+// Messages of the service, with the server and a client
+#[cfg(feature = "grpc")]
+pub mod proto {
+    tonic::include_proto!("rusty_scheduler");
+}
+
+// Status is the error type of every tonic handler
+#[cfg(feature = "grpc")]
+#[allow(clippy::result_large_err)]
+mod service {
+    use super::super::control::{self, Request, Response};
+    use super::super::error::{Error, ErrorKind};
+    use super::super::overview::Overview;
+    use super::super::pipeline::Pipeline;
+    use super::super::runtime::SharedRuntime;
+    use super::super::scheduler;
+    use super::super::security::{Role, Security};
+    use failure::ResultExt;
+    use log::{error, info};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+    use tonic::transport::{Identity, Server, ServerTlsConfig};
+    use tonic::{Code, Status};
+
+    use super::proto;
+    use proto::scheduler_server::{Scheduler, SchedulerServer};
+
+    // How often the output of an active run is checked for new lines
+    const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub struct Service {
+        pipelines_path: String,
+
+        runtime: SharedRuntime,
+    }
+
+    type Reply<T> = Result<tonic::Response<T>, Status>;
+
+    impl Service {
+        fn find_pipeline(&self, id: &str) -> Result<Pipeline, Status> {
+            scheduler::unwrap_pipelines(Pipeline::read_dir(&self.pipelines_path))
+                .into_iter()
+                .find(|pipeline| pipeline.id == id)
+                .ok_or_else(|| {
+                    Status::not_found(ErrorKind::PipelineNotFound(id.to_string()).to_string())
+                })
+        }
+
+        fn update(&self, request: Request, actor: &str) -> Reply<proto::UpdateResponse> {
+            match control::respond(request, &self.pipelines_path, &self.runtime, actor) {
+                Response::Updated { pipelines } => {
+                    Ok(tonic::Response::new(proto::UpdateResponse { pipelines }))
+                }
+                response => Err(status(response)),
+            }
+        }
+    }
+
+    // Role set by the interceptor
+    fn require<T>(request: &tonic::Request<T>, required: Role) -> Result<Role, Status> {
+        match request.extensions().get::<Role>() {
+            Some(role) if *role >= required => Ok(*role),
+            _ => Err(Status::permission_denied(format!(
+                "Forbidden: requires the {} role",
+                required
+            ))),
+        }
+    }
+
+    fn actor<T>(request: &tonic::Request<T>, role: Role) -> String {
+        match request.remote_addr() {
+            Some(address) => format!("grpc {} ({})", address, role),
+            None => String::from("grpc"),
+        }
+    }
+
+    fn authenticate(
+        mut request: tonic::Request<()>,
+        security: &Security,
+    ) -> Result<tonic::Request<()>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+
+        match security.authorize(authorization) {
+            Some(role) => {
+                request.extensions_mut().insert(role);
+
+                Ok(request)
+            }
+            None => Err(Status::unauthenticated("Unauthorized")),
+        }
+    }
+
+    // A run already active or not active
+    fn status(response: Response) -> Status {
+        match response {
+            Response::Error { message } => Status::new(Code::FailedPrecondition, message),
+            response => Status::internal(format!("Unexpected response: {:?}", response)),
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Scheduler for Service {
+        async fn list_pipelines(
+            &self,
+            request: tonic::Request<proto::ListPipelinesRequest>,
+        ) -> Reply<proto::ListPipelinesResponse> {
+            require(&request, Role::Viewer)?;
+
+            let pipelines = scheduler::unwrap_pipelines(Pipeline::read_dir(&self.pipelines_path));
+
+            let runtime = self.runtime.lock().unwrap();
+
+            let pipelines = pipelines
+                .iter()
+                .map(|pipeline| {
+                    let overview = Overview::from_pipeline(pipeline);
+
+                    proto::Pipeline {
+                        status: overview.status().to_string(),
+                        id: overview.id,
+                        expression: overview.expression,
+                        schedule: overview.schedule,
+                        last_run: overview
+                            .last_run
+                            .map(|last_run| last_run.to_rfc3339())
+                            .unwrap_or_default(),
+                        last_result: overview
+                            .last_result
+                            .map(|last_result| last_result.to_string())
+                            .unwrap_or_default(),
+                        next_run: overview.next_run.to_rfc3339(),
+                        active_run_id: runtime
+                            .find_run(&pipeline.id)
+                            .map(|run| run.run_id.to_string())
+                            .unwrap_or_default(),
+                    }
+                })
+                .collect();
+
+            Ok(tonic::Response::new(proto::ListPipelinesResponse {
+                pipelines,
+            }))
+        }
+
+        async fn trigger(
+            &self,
+            request: tonic::Request<proto::TriggerRequest>,
+        ) -> Reply<proto::TriggerResponse> {
+            let actor = actor(&request, require(&request, Role::Operator)?);
+            let request = request.into_inner();
+
+            self.find_pipeline(&request.pipeline)?;
+
+            let request = Request::Trigger {
+                pipeline: request.pipeline,
+                params: request.params.into_iter().collect(),
+                labels: request.labels.into_iter().collect(),
+                skip_stages: request.skip_stages,
+                skip_jobs: request.skip_jobs,
+            };
+
+            match control::respond(request, &self.pipelines_path, &self.runtime, &actor) {
+                Response::Triggered { run_id } => {
+                    Ok(tonic::Response::new(proto::TriggerResponse { run_id }))
+                }
+                response => Err(status(response)),
+            }
+        }
+
+        async fn cancel(
+            &self,
+            request: tonic::Request<proto::PipelineRequest>,
+        ) -> Reply<proto::UpdateResponse> {
+            let actor = actor(&request, require(&request, Role::Operator)?);
+            let pipeline = request.into_inner().pipeline;
+
+            self.find_pipeline(&pipeline)?;
+
+            self.update(Request::Cancel { pipeline }, &actor)
+        }
+
+        async fn pause(
+            &self,
+            request: tonic::Request<proto::PipelineRequest>,
+        ) -> Reply<proto::UpdateResponse> {
+            let actor = actor(&request, require(&request, Role::Admin)?);
+            let pipeline = request.into_inner().pipeline;
+
+            self.find_pipeline(&pipeline)?;
+
+            self.update(
+                Request::Pause {
+                    pipeline: Some(pipeline),
+                },
+                &actor,
+            )
+        }
+
+        async fn resume(
+            &self,
+            request: tonic::Request<proto::PipelineRequest>,
+        ) -> Reply<proto::UpdateResponse> {
+            let actor = actor(&request, require(&request, Role::Admin)?);
+            let pipeline = request.into_inner().pipeline;
+
+            self.find_pipeline(&pipeline)?;
+
+            self.update(
+                Request::Resume {
+                    pipeline: Some(pipeline),
+                },
+                &actor,
+            )
+        }
+
+        type StreamOutputStream = ReceiverStream<Result<proto::OutputLine, Status>>;
+
+        async fn stream_output(
+            &self,
+            request: tonic::Request<proto::PipelineRequest>,
+        ) -> Reply<Self::StreamOutputStream> {
+            require(&request, Role::Viewer)?;
+
+            let pipeline = request.into_inner().pipeline;
+
+            self.find_pipeline(&pipeline)?;
+
+            let run = self
+                .runtime
+                .lock()
+                .unwrap()
+                .find_run(&pipeline)
+                .cloned()
+                .ok_or_else(|| {
+                    Status::new(
+                        Code::FailedPrecondition,
+                        ErrorKind::PipelineNotRunning(pipeline.to_string()).to_string(),
+                    )
+                })?;
+
+            let runtime = self.runtime.clone();
+
+            let (sender, receiver) = mpsc::channel(64);
+
+            tokio::spawn(async move {
+                let mut cursor = 0;
+
+                loop {
+                    // Read the finished flag first, so lines pushed before the end are still sent
+                    let finished = !runtime.lock().unwrap().is_running(&run.run_id);
+
+                    let (lines, next_cursor) = run.output.lines_since(cursor);
+
+                    cursor = next_cursor;
+
+                    for line in lines {
+                        let line = proto::OutputLine {
+                            run_id: run.run_id.to_string(),
+                            line,
+                        };
+
+                        if sender.send(Ok(line)).await.is_err() {
+                            return;
+                        }
+                    }
+
+                    if finished {
+                        return;
+                    }
+
+                    tokio::time::sleep(OUTPUT_POLL_INTERVAL).await;
+                }
+            });
+
+            Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+        }
+    }
+
+    pub fn serve(
+        address: &str,
+        pipelines_path: &str,
+        runtime: SharedRuntime,
+        security: &Security,
+    ) -> Result<(), Error> {
+        // Bound here, so address errors are reported before the server thread starts
+        let listener =
+            TcpListener::bind(address).context(ErrorKind::GrpcListenFailed(address.to_string()))?;
+
+        listener
+            .set_nonblocking(true)
+            .context(ErrorKind::GrpcListenFailed(address.to_string()))?;
+
+        info!(
+            "gRPC API listening: {} ({})",
+            address,
+            if security.tls.is_some() {
+                "tls"
+            } else {
+                "plaintext"
+            }
+        );
+
+        let security = security.clone();
+
+        let service = Service {
+            pipelines_path: pipelines_path.to_string(),
+            runtime,
+        };
+
+        let address = address.to_string();
+
+        thread::spawn(move || {
+            let server = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .context(ErrorKind::GrpcListenFailed(address.to_string()))
+                .map_err(Error::from)
+                .and_then(|tokio_runtime| {
+                    tokio_runtime.block_on(async {
+                        let listener = tokio::net::TcpListener::from_std(listener)
+                            .context(ErrorKind::GrpcListenFailed(address.to_string()))?;
+
+                        let mut server = Server::builder();
+
+                        if let Some(tls) = &security.tls {
+                            server = server
+                                .tls_config(
+                                    ServerTlsConfig::new()
+                                        .identity(Identity::from_pem(&tls.cert, &tls.key)),
+                                )
+                                .map_err(|err| ErrorKind::InvalidTlsFile(err.to_string()))?;
+                        }
+
+                        server
+                            .add_service(SchedulerServer::with_interceptor(
+                                service,
+                                move |request| authenticate(request, &security),
+                            ))
+                            .serve_with_incoming(TcpListenerStream::new(listener))
+                            .await
+                            .context(ErrorKind::GrpcListenFailed(address.to_string()))?;
+
+                        Ok(())
+                    })
+                });
+
+            if let Err(err) = server {
+                error!("{}", err);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "grpc")]
+pub fn serve(
+    address: &str,
+    pipelines_path: &str,
+    runtime: SharedRuntime,
+    security: &Security,
+) -> Result<(), Error> {
+    service::serve(address, pipelines_path, runtime, security)
+}
+
+#[cfg(not(feature = "grpc"))]
+pub fn serve(
+    address: &str,
+    _pipelines_path: &str,
+    _runtime: SharedRuntime,
+    _security: &Security,
+) -> Result<(), Error> {
+    Err(super::error::ErrorKind::GrpcListenFailed(format!(
+        "{} (built without the grpc feature)",
+        address
+    )))?
+}
+
+#[cfg(all(test, feature = "grpc"))]
+mod tests {
+    use super::super::runtime::Runtime;
+    use super::super::security::Role;
+    use super::proto::scheduler_client::SchedulerClient;
+    use super::proto::ListPipelinesRequest;
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::net::TcpListener;
+    use tonic::transport::Endpoint;
+    use tonic::Code;
+
+    #[test]
+    fn list_pipelines_with_the_client() {
+        let dir = env::temp_dir().join("rusty-scheduler-grpc");
+
+        fs::create_dir_all(dir.join("nightly")).unwrap();
+        fs::write(
+            dir.join("nightly").join("pipeline.json"),
+            r#"{"id": "nightly", "expression": "0 0 * * *", "stages": ["main"], "jobs": []}"#,
+        )
+        .unwrap();
+
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        let security = Security {
+            tokens: vec![(Role::Viewer, String::from("secret"))],
+            ..Security::default()
+        };
+
+        serve(
+            &address,
+            &dir.to_string_lossy(),
+            Runtime::new_shared(60),
+            &security,
+        )
+        .unwrap();
+
+        let tokio_runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        tokio_runtime.block_on(async {
+            let channel = Endpoint::from_shared(format!("http://{}", address))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+
+            let mut client = SchedulerClient::new(channel);
+
+            let status = client
+                .list_pipelines(ListPipelinesRequest {})
+                .await
+                .unwrap_err();
+
+            assert_eq!(status.code(), Code::Unauthenticated);
+
+            let mut request = tonic::Request::new(ListPipelinesRequest {});
+
+            request
+                .metadata_mut()
+                .insert("authorization", "Bearer secret".parse().unwrap());
+
+            let pipelines = client.list_pipelines(request).await.unwrap().into_inner();
+
+            assert_eq!(pipelines.pipelines.len(), 1);
+            assert_eq!(pipelines.pipelines[0].id, "nightly");
+        });
+    }
+}
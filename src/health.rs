@@ -0,0 +1,165 @@
+use super::runtime::Health;
+use super::shutdown;
+use super::storage;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+// Extra time allowed for a refresh before the loop is considered stalled
+const TICK_GRACE_SECONDS: i64 = 60;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Report {
+    pub healthy: bool,
+
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Check {
+    pub name: String,
+
+    pub healthy: bool,
+
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: &str, healthy: bool, message: String) -> Check {
+        Check {
+            name: name.to_string(),
+            healthy,
+            message,
+        }
+    }
+}
+
+impl Report {
+    fn new(checks: Vec<Check>) -> Report {
+        Report {
+            healthy: checks.iter().all(|check| check.healthy),
+            checks,
+        }
+    }
+
+    pub fn status_code(&self) -> u16 {
+        if self.healthy {
+            200
+        } else {
+            503
+        }
+    }
+}
+
+// The scheduler process is alive and its loop is running
+pub fn liveness(health: &Health, now: DateTime<Utc>) -> Report {
+    Report::new(vec![check_tick(health, now)])
+}
+
+// The scheduler is able to load pipelines and save their state
+pub fn readiness(health: &Health, pipelines_path: &str, now: DateTime<Utc>) -> Report {
+    Report::new(vec![
+        check_tick(health, now),
+        check_reload(health),
+        check_storage(pipelines_path),
+        check_shutdown(),
+    ])
+}
+
+pub fn check_tick(health: &Health, now: DateTime<Utc>) -> Check {
+    let max_age = Duration::seconds(health.refresh as i64 * 2 + TICK_GRACE_SECONDS);
+
+    match health.last_tick {
+        None => Check::new("tick", false, String::from("Scheduler loop not started")),
+        Some(tick) if now - tick > max_age => Check::new(
+            "tick",
+            false,
+            format!("Last scheduler loop tick: {}", tick.to_rfc3339()),
+        ),
+        Some(tick) => Check::new("tick", true, tick.to_rfc3339()),
+    }
+}
+
+pub fn check_reload(health: &Health) -> Check {
+    if health.reload_errors.is_empty() {
+        Check::new("reload", true, String::from("Pipelines loaded"))
+    } else {
+        Check::new("reload", false, health.reload_errors.join("; "))
+    }
+}
+
+fn check_storage(pipelines_path: &str) -> Check {
+    if !storage::is_persistent() {
+        return Check::new("storage", true, String::from("Persistence disabled"));
+    }
+
+    let mut probe_path = PathBuf::from(pipelines_path);
+    probe_path.push(".healthz");
+
+    match fs::write(&probe_path, b"").and_then(|_| fs::remove_file(&probe_path)) {
+        Ok(_) => Check::new("storage", true, String::from("Writable")),
+        Err(err) => Check::new("storage", false, format!("Not writable: {}", err)),
+    }
+}
+
+fn check_shutdown() -> Check {
+    if shutdown::is_requested() {
+        Check::new("shutdown", false, String::from("Scheduler stopping"))
+    } else {
+        Check::new("shutdown", true, String::from("Running"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn health(last_tick: Option<DateTime<Utc>>) -> Health {
+        Health {
+            refresh: 60,
+            last_tick,
+            reload_errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn tick_recent() {
+        let tick = Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2019, 7, 1, 0, 3, 0).unwrap();
+
+        assert!(check_tick(&health(Some(tick)), now).healthy);
+        assert!(liveness(&health(Some(tick)), now).healthy);
+    }
+
+    #[test]
+    fn tick_stalled() {
+        let tick = Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2019, 7, 1, 0, 3, 1).unwrap();
+
+        let report = liveness(&health(Some(tick)), now);
+
+        assert!(!report.healthy);
+        assert_eq!(report.status_code(), 503);
+        assert!(!check_tick(&health(None), now).healthy);
+    }
+
+    #[test]
+    fn reload_errors() {
+        let mut health = health(None);
+
+        assert!(check_reload(&health).healthy);
+
+        health.reload_errors = vec![String::from("Invalid pipeline file: a/pipeline.json")];
+
+        assert_eq!(
+            check_reload(&health),
+            Check::new(
+                "reload",
+                false,
+                String::from("Invalid pipeline file: a/pipeline.json")
+            )
+        );
+    }
+}
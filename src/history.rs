@@ -0,0 +1,644 @@
+use super::crypto;
+use super::error::{Error, ErrorKind};
+use super::pipeline::Pipeline;
+use super::runtime::Output;
+use super::storage;
+use chrono::{DateTime, NaiveDate, Utc};
+use failure::ResultExt;
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct History {
+    #[serde(default)]
+    pub id: String,
+
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub path: String,
+
+    #[serde(skip_deserializing)]
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub encrypted: bool,
+
+    #[serde(default)]
+    pub summaries: Vec<Summary>,
+
+    #[serde(default)]
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Summary {
+    pub date: NaiveDate,
+
+    #[serde(default)]
+    pub succeeded: usize,
+
+    #[serde(default)]
+    pub failed: usize,
+
+    #[serde(default)]
+    pub skipped: usize,
+
+    #[serde(default)]
+    pub cancelled: usize,
+
+    // Seconds
+    #[serde(default)]
+    pub total_duration: f64,
+
+    // Seconds
+    #[serde(default)]
+    pub max_duration: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Run {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub id: String,
+
+    pub status: RunStatus,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<SkipReason>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduled: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_stages: Vec<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_jobs: Vec<String>,
+
+    // Triggers collapsed into a triggered run by the dedup_window
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapsed_triggers: Option<u32>,
+
+    // Run reproduced by this one
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerun_of: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<Report>,
+
+    // JSON printed by the jobs with an output format, by job
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub results: BTreeMap<String, Value>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Snapshot>,
+
+    pub start: DateTime<Utc>,
+
+    pub end: DateTime<Utc>,
+}
+
+// End-of-run report of the runs that started
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Report {
+    // Stages that ran, in execution order
+    #[serde(default)]
+    pub stages: Vec<StageReport>,
+
+    #[serde(default)]
+    pub passed: usize,
+
+    #[serde(default)]
+    pub failed: usize,
+
+    // Jobs that didn't run, after a failure or skipped by a trigger
+    #[serde(default)]
+    pub skipped: usize,
+
+    // Produced files and artifacts that exist after the run
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+}
+
+// What a run ran with, to reproduce it
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    // Version of the scheduler
+    pub version: String,
+
+    // Variables passed to every job, without the secrets
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    // Script jobs that started, by breadcrumb
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct JobSnapshot {
+    // Interpreter, script and arguments
+    pub command: Vec<String>,
+
+    // Variables of the job on top of the pipeline ones
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    // SHA-256 of the script file
+    #[serde(default)]
+    pub script_sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StageReport {
+    pub stage: String,
+
+    // Seconds
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+    Cancelled,
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            RunStatus::Succeeded => "succeeded",
+            RunStatus::Failed => "failed",
+            RunStatus::Skipped => "skipped",
+            RunStatus::Cancelled => "cancelled",
+        };
+
+        write!(formatter, "{}", status)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    // The previous run is still active
+    AlreadyRunning,
+    // The pipeline was paused
+    Paused,
+    // The only_if_changed files didn't change since the last success
+    Unchanged,
+    // Occurrences above the max_queue_depth, merged into one skip
+    QueueFull,
+    // Removed from the queue by an operator
+    Dequeued,
+    // Due in dry run mode, when it would have run
+    DryRun,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            SkipReason::AlreadyRunning => "already running",
+            SkipReason::Paused => "paused",
+            SkipReason::Unchanged => "unchanged",
+            SkipReason::QueueFull => "queue full",
+            SkipReason::Dequeued => "dequeued",
+            SkipReason::DryRun => "dry run",
+        };
+
+        write!(formatter, "{}", reason)
+    }
+}
+
+impl Run {
+    pub fn new(status: RunStatus, start: DateTime<Utc>, end: DateTime<Utc>) -> Run {
+        Run {
+            id: String::new(),
+            status,
+            reason: None,
+            scheduled: None,
+            params: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            skipped_stages: Vec::new(),
+            skipped_jobs: Vec::new(),
+            collapsed_triggers: None,
+            rerun_of: None,
+            report: None,
+            results: BTreeMap::new(),
+            snapshot: None,
+            start,
+            end,
+        }
+    }
+
+    pub fn skipped(
+        reason: SkipReason,
+        scheduled: Option<DateTime<Utc>>,
+        timestamp: DateTime<Utc>,
+    ) -> Run {
+        Run {
+            id: String::new(),
+            status: RunStatus::Skipped,
+            reason: Some(reason),
+            scheduled,
+            params: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            skipped_stages: Vec::new(),
+            skipped_jobs: Vec::new(),
+            collapsed_triggers: None,
+            rerun_of: None,
+            report: None,
+            results: BTreeMap::new(),
+            snapshot: None,
+            start: timestamp,
+            end: timestamp,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.end.signed_duration_since(self.start).num_milliseconds() as f64 / 1000.0
+    }
+}
+
+impl Report {
+    pub fn new(pipeline: &Pipeline, output: &Output, skipped_jobs: usize) -> Report {
+        let passed = output.passed_jobs();
+        let failed = output.failed_jobs();
+
+        let folder = Path::new(&pipeline.path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+
+        let produced = pipeline
+            .jobs
+            .iter()
+            .filter(|job| passed.contains(&job.breadcrumb))
+            .flat_map(|job| job.produces.iter());
+
+        let mut artifacts: Vec<String> = Vec::new();
+
+        for artifact in produced.chain(pipeline.artifacts.iter()) {
+            if folder.join(artifact).exists() && !artifacts.contains(artifact) {
+                artifacts.push(artifact.to_string());
+            }
+        }
+
+        let ran = pipeline
+            .jobs
+            .iter()
+            .filter(|job| passed.contains(&job.breadcrumb) || failed.contains(&job.breadcrumb))
+            .count();
+
+        Report {
+            stages: output
+                .stages()
+                .into_iter()
+                .map(|(stage, duration)| StageReport { stage, duration })
+                .collect(),
+            passed: passed.len(),
+            failed: failed.len(),
+            skipped: pipeline.jobs.len() - ran + skipped_jobs,
+            artifacts,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let stages: Vec<String> = self
+            .stages
+            .iter()
+            .map(|stage| format!("{} {:.1}s", stage.stage, stage.duration))
+            .collect();
+
+        write!(
+            formatter,
+            "stages [{}], jobs {} passed, {} failed, {} skipped",
+            stages.join(", "),
+            self.passed,
+            self.failed,
+            self.skipped
+        )?;
+
+        if !self.artifacts.is_empty() {
+            write!(formatter, ", artifacts [{}]", self.artifacts.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Summary {
+    pub fn new(date: NaiveDate) -> Summary {
+        Summary {
+            date,
+            succeeded: 0,
+            failed: 0,
+            skipped: 0,
+            cancelled: 0,
+            total_duration: 0.0,
+            max_duration: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, run: &Run) {
+        match run.status {
+            RunStatus::Succeeded => self.succeeded += 1,
+            RunStatus::Failed => self.failed += 1,
+            RunStatus::Skipped => {
+                self.skipped += 1;
+
+                return;
+            }
+            RunStatus::Cancelled => {
+                self.cancelled += 1;
+
+                return;
+            }
+        }
+
+        self.total_duration += run.duration();
+        self.max_duration = self.max_duration.max(run.duration());
+    }
+}
+
+impl History {
+    pub fn path_from_pipeline(pipeline: &Pipeline) -> String {
+        let mut history_path = PathBuf::from(&pipeline.path);
+        history_path.pop();
+        history_path.push("history.json");
+
+        history_path.to_string_lossy().to_string()
+    }
+
+    pub fn read_from_pipeline(pipeline: &Pipeline) -> History {
+        let history_path = History::path_from_pipeline(pipeline);
+
+        let history = if !storage::exists(&history_path) {
+            trace!("History created: {}", pipeline.id);
+
+            History::new(&pipeline.id, &history_path)
+        } else {
+            match History::read_file(&history_path) {
+                Ok(history) => {
+                    trace!("History loaded: {}", pipeline.id);
+
+                    history
+                }
+                Err(err) => {
+                    warn!("{}", err);
+                    warn!("History created: {}", pipeline.id);
+
+                    History::new(&pipeline.id, &history_path)
+                }
+            }
+        };
+
+        History {
+            encrypted: pipeline.encrypt_state,
+            ..history
+        }
+    }
+
+    pub fn new(id: &str, history_path: &str) -> History {
+        History {
+            id: id.to_string(),
+            path: history_path.to_string(),
+            encrypted: false,
+            summaries: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn is_skip_recorded(&self, run: &Run) -> bool {
+        // A skip is recorded once per scheduled occurrence and reason
+        self.runs.last().is_some_and(|last| {
+            last.status == RunStatus::Skipped
+                && last.reason == run.reason
+                && last.scheduled == run.scheduled
+        })
+    }
+
+    pub fn compact(&mut self, before: DateTime<Utc>, keep: usize) -> usize {
+        let keep_from = self.runs.len().saturating_sub(keep);
+
+        let count = self.runs[..keep_from]
+            .iter()
+            .take_while(|run| run.start < before)
+            .count();
+
+        let old: Vec<Run> = self.runs.drain(..count).collect();
+
+        for run in &old {
+            let date = run.start.date_naive();
+
+            match self.summaries.iter_mut().find(|summary| summary.date == date) {
+                Some(summary) => summary.add(run),
+                None => {
+                    let mut summary = Summary::new(date);
+                    summary.add(run);
+
+                    self.summaries.push(summary);
+                }
+            }
+        }
+
+        self.summaries.sort_by_key(|summary| summary.date);
+
+        old.len()
+    }
+
+    pub fn read_file(history_path: &str) -> Result<History, Error> {
+        let history_data = storage::read(history_path)
+            .context(ErrorKind::InvalidHistoryFile(history_path.to_string()))?;
+
+        let history_data = crypto::decrypt(&history_data, history_path)?;
+
+        let mut history: History = serde_json::from_slice(&history_data)
+            .context(ErrorKind::InvalidHistoryFile(history_path.to_string()))?;
+
+        history.path = history_path.to_string();
+
+        Ok(history)
+    }
+
+    pub fn write_file(&self) -> Result<(), Error> {
+        let history_data = serde_json::to_vec_pretty(&self)
+            .context(ErrorKind::InvalidHistoryFile(self.path.to_string()))?;
+
+        let history_data = if self.encrypted {
+            crypto::encrypt(&history_data, &self.path)?
+        } else {
+            history_data
+        };
+
+        storage::write(&self.path, history_data)
+            .context(ErrorKind::InvalidHistoryFile(self.path.to_string()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::builder::{JobBuilder, PipelineBuilder};
+    use super::super::executor::{self, Options};
+    use super::super::function;
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn run(status: RunStatus, day: u32, hour: u32, seconds: i64) -> Run {
+        let start = Utc.with_ymd_and_hms(2019, 7, day, hour, 0, 0).unwrap();
+        let end = start + Duration::seconds(seconds);
+
+        Run::new(status, start, end)
+    }
+
+    #[test]
+    fn report_finished_runs() {
+        function::register("test-report-ok", |_| Ok(()));
+        function::register("test-report-fail", |_| Err(String::from("failed")));
+
+        let pipeline = PipelineBuilder::new("report")
+            .expression("0 * * * *")
+            .stage("extract")
+            .stage("load")
+            .job(JobBuilder::new("a", "extract").function("test-report-ok"))
+            .job(JobBuilder::new("b", "extract").function("test-report-fail"))
+            .job(JobBuilder::new("c", "load").function("test-report-ok"))
+            .build()
+            .unwrap();
+
+        let options = Options::default();
+
+        assert!(executor::execute(&pipeline, &options).is_err());
+
+        let report = Report::new(&pipeline, &options.output, 1);
+
+        assert_eq!(report.stages.len(), 1);
+        assert_eq!(report.stages[0].stage, "extract");
+        assert_eq!((report.passed, report.failed, report.skipped), (1, 1, 2));
+        assert_eq!(
+            report.to_string(),
+            "stages [extract 0.0s], jobs 1 passed, 1 failed, 2 skipped"
+        );
+    }
+
+    #[test]
+    fn compact_old_runs_into_summaries() {
+        let mut history = History::new("test", "history.json");
+
+        history.runs.push(run(RunStatus::Succeeded, 1, 10, 10));
+        history.runs.push(run(RunStatus::Failed, 1, 11, 30));
+        history.runs.push(run(RunStatus::Succeeded, 2, 10, 20));
+        history.runs.push(run(RunStatus::Succeeded, 5, 10, 10));
+
+        let before = Utc.with_ymd_and_hms(2019, 7, 3, 0, 0, 0).unwrap();
+        let compacted = history.compact(before, 0);
+
+        assert_eq!(compacted, 3);
+        assert_eq!(history.runs.len(), 1);
+        assert_eq!(history.summaries.len(), 2);
+
+        let summary = &history.summaries[0];
+
+        assert_eq!(summary.date, NaiveDate::from_ymd_opt(2019, 7, 1).unwrap());
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.total_duration, 40.0);
+        assert_eq!(summary.max_duration, 30.0);
+    }
+
+    #[test]
+    fn compact_keeps_last_runs() {
+        let mut history = History::new("test", "history.json");
+
+        history.runs.push(run(RunStatus::Succeeded, 1, 10, 10));
+        history.runs.push(run(RunStatus::Succeeded, 1, 11, 10));
+        history.runs.push(run(RunStatus::Succeeded, 1, 12, 10));
+
+        let before = Utc.with_ymd_and_hms(2019, 7, 3, 0, 0, 0).unwrap();
+        let compacted = history.compact(before, 2);
+
+        assert_eq!(compacted, 1);
+        assert_eq!(history.runs.len(), 2);
+        assert_eq!(history.summaries[0].succeeded, 1);
+    }
+
+    #[test]
+    fn compact_counts_skipped_runs() {
+        let mut history = History::new("test", "history.json");
+
+        let timestamp = Utc.with_ymd_and_hms(2019, 7, 1, 10, 0, 0).unwrap();
+
+        history.runs.push(run(RunStatus::Succeeded, 1, 9, 10));
+        history
+            .runs
+            .push(Run::skipped(SkipReason::AlreadyRunning, None, timestamp));
+
+        history.compact(Utc.with_ymd_and_hms(2019, 7, 3, 0, 0, 0).unwrap(), 0);
+
+        assert_eq!(history.summaries[0].succeeded, 1);
+        assert_eq!(history.summaries[0].skipped, 1);
+        assert_eq!(history.summaries[0].total_duration, 10.0);
+    }
+
+    #[test]
+    fn skip_recorded_once_per_occurrence() {
+        let mut history = History::new("test", "history.json");
+
+        let scheduled = Some(Utc.with_ymd_and_hms(2019, 7, 1, 10, 0, 0).unwrap());
+        let timestamp = Utc.with_ymd_and_hms(2019, 7, 1, 10, 1, 0).unwrap();
+
+        let skip = Run::skipped(SkipReason::AlreadyRunning, scheduled, timestamp);
+
+        assert!(!history.is_skip_recorded(&skip));
+
+        history.runs.push(skip.clone());
+
+        assert!(history.is_skip_recorded(&skip));
+
+        let next_scheduled = Some(Utc.with_ymd_and_hms(2019, 7, 1, 11, 0, 0).unwrap());
+        let next_skip = Run::skipped(SkipReason::AlreadyRunning, next_scheduled, timestamp);
+
+        assert!(!history.is_skip_recorded(&next_skip));
+    }
+
+    #[test]
+    fn compact_merges_existing_summary() {
+        let mut history = History::new("test", "history.json");
+
+        history.runs.push(run(RunStatus::Succeeded, 1, 10, 10));
+        history.compact(Utc.with_ymd_and_hms(2019, 7, 3, 0, 0, 0).unwrap(), 0);
+
+        history.runs.push(run(RunStatus::Failed, 1, 11, 10));
+        history.compact(Utc.with_ymd_and_hms(2019, 7, 3, 0, 0, 0).unwrap(), 0);
+
+        assert_eq!(history.summaries.len(), 1);
+        assert_eq!(history.summaries[0].succeeded, 1);
+        assert_eq!(history.summaries[0].failed, 1);
+    }
+}
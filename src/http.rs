@@ -0,0 +1,214 @@
+use super::function::Context;
+use super::plugin::JobType;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::Duration;
+
+// Response bodies are printed to the run log, big downloads should use a script
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct HttpRequest {
+    pub url: String,
+
+    #[serde(default = "HttpRequest::default_method")]
+    pub method: String,
+
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    // Strings are sent as they are, anything else as JSON
+    #[serde(default)]
+    pub body: Option<Value>,
+
+    // Any 2xx status when empty
+    #[serde(default)]
+    pub expected_status: Vec<u16>,
+
+    // Seconds
+    #[serde(default = "HttpRequest::default_timeout")]
+    pub timeout: u64,
+}
+
+impl HttpRequest {
+    fn default_method() -> String {
+        String::from("GET")
+    }
+
+    fn default_timeout() -> u64 {
+        30
+    }
+
+    pub fn is_expected(&self, status: u16) -> bool {
+        if self.expected_status.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.expected_status.contains(&status)
+        }
+    }
+}
+
+pub fn job_type() -> JobType {
+    JobType::new(run)
+}
+
+fn run(job: &HttpRequest, context: &Context) -> Result<(), String> {
+    let method = job.method.to_uppercase();
+
+    let mut request = ureq::request(&method, &job.url).timeout(Duration::from_secs(job.timeout));
+
+    for (name, value) in &job.headers {
+        request = request.set(name, value);
+    }
+
+    let result = match &job.body {
+        None => request.call(),
+        Some(Value::String(body)) => request.send_string(body),
+        Some(body) => request.send_json(body),
+    };
+
+    // Unexpected statuses are checked below, they may be expected
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(ureq::Error::Transport(transport)) => {
+            return Err(match transport.message() {
+                Some(message) => format!("{}: {}", transport.kind(), message),
+                None => transport.kind().to_string(),
+            })
+        }
+    };
+
+    let status = response.status();
+
+    context.print(&format!("{} {} -> {}", method, job.url, status));
+
+    let mut body = String::new();
+
+    let _ = response
+        .into_reader()
+        .take(MAX_BODY_BYTES)
+        .read_to_string(&mut body);
+
+    for line in body.lines() {
+        context.print(line);
+    }
+
+    if job.is_expected(status) {
+        Ok(())
+    } else {
+        Err(format!("unexpected status code {}", status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::executor::{self, Options};
+    use super::super::pipeline::Pipeline;
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Answers one request with a status, and returns the request line and body
+    fn serve(status: u16) -> (String, thread::JoinHandle<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut content_length = 0;
+
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+
+                if header.trim().is_empty() {
+                    break;
+                }
+
+                if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {} Status\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                status
+            )
+            .unwrap();
+
+            (
+                request_line.trim().to_string(),
+                String::from_utf8(body).unwrap(),
+            )
+        });
+
+        (address, handle)
+    }
+
+    fn pipeline(job: &str) -> Pipeline {
+        serde_json::from_str(&format!(
+            r#"{{"id": "http", "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "type": "http_request", {}}}]}}"#,
+            job
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn expected_statuses() {
+        let request: HttpRequest = serde_json::from_str(r#"{"url": "http://a"}"#).unwrap();
+
+        assert!(request.is_expected(204));
+        assert!(!request.is_expected(301));
+
+        let request: HttpRequest =
+            serde_json::from_str(r#"{"url": "http://a", "expected_status": [404]}"#).unwrap();
+
+        assert!(request.is_expected(404));
+        assert!(!request.is_expected(200));
+    }
+
+    #[test]
+    fn send_requests() {
+        let (address, server) = serve(200);
+
+        let pipeline = pipeline(&format!(
+            r#""url": "http://{}/hook", "method": "post", "body": {{"event": "nightly"}}"#,
+            address
+        ));
+
+        let options = Options::default();
+
+        assert!(executor::execute_stage(&pipeline, "main", &options).is_ok());
+        assert_eq!(
+            server.join().unwrap(),
+            (
+                String::from("POST /hook HTTP/1.1"),
+                String::from(r#"{"event":"nightly"}"#)
+            )
+        );
+        assert_eq!(options.output.lines_since(0).0.len(), 2);
+    }
+
+    #[test]
+    fn fail_on_unexpected_statuses() {
+        let (address, server) = serve(503);
+
+        let pipeline = pipeline(&format!(r#""url": "http://{}/""#, address));
+
+        assert!(executor::execute_stage(&pipeline, "main", &Options::default()).is_err());
+
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use super::error::{Error, ErrorKind};
+use super::pipeline::Pipeline;
+use failure::ResultExt;
+use glob::Pattern;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+// Checksum of the files matched by the only_if_changed glob, relative to the
+// pipeline folder, a pipeline skips runs when it is the same as the last success
+pub fn checksum(pipeline: &Pipeline, glob: &str) -> Result<String, Error> {
+    let mut folder = PathBuf::from(&pipeline.path);
+    folder.pop();
+
+    let pattern = format!("{}/{}", Pattern::escape(&folder.to_string_lossy()), glob);
+
+    let mut files: Vec<PathBuf> = glob::glob(&pattern)
+        .context(ErrorKind::InvalidInputPattern(glob.to_string()))?
+        .filter_map(|path| path.ok())
+        .filter(|path| path.is_file())
+        .collect();
+
+    files.sort();
+
+    let mut hasher = Sha256::new();
+
+    for file in files {
+        let relative = file.strip_prefix(&folder).unwrap_or(&file);
+
+        // Renaming a file is a change too
+        hasher.update(relative.to_string_lossy().as_bytes());
+        hasher.update([0]);
+
+        let mut reader =
+            File::open(&file).context(ErrorKind::InvalidInputPattern(glob.to_string()))?;
+
+        io::copy(&mut reader, &mut hasher)
+            .context(ErrorKind::InvalidInputPattern(glob.to_string()))?;
+
+        hasher.update([0]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn change_with_the_matched_files() {
+        let folder = env::temp_dir().join("rusty-scheduler-inputs");
+
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join("data")).unwrap();
+
+        let pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "inputs", "path": "{}/pipeline.json"}}"#,
+            folder.to_string_lossy()
+        ))
+        .unwrap();
+
+        fs::write(folder.join("data/a.csv"), "1,2").unwrap();
+        fs::write(folder.join("data/notes.txt"), "draft").unwrap();
+
+        let first = checksum(&pipeline, "data/*.csv").unwrap();
+
+        fs::write(folder.join("data/notes.txt"), "final").unwrap();
+
+        assert_eq!(checksum(&pipeline, "data/*.csv").unwrap(), first);
+
+        fs::write(folder.join("data/b.csv"), "").unwrap();
+
+        let second = checksum(&pipeline, "data/*.csv").unwrap();
+
+        assert_ne!(second, first);
+
+        fs::write(folder.join("data/a.csv"), "1,3").unwrap();
+
+        assert_ne!(checksum(&pipeline, "data/*.csv").unwrap(), second);
+        assert!(checksum(&pipeline, "data/[").is_err());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -1,12 +1,58 @@
 use super::error::{Error, ErrorKind};
+use super::timezone::Timezone;
 use chrono::prelude::*;
-use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, Utc};
 use log::trace;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fmt;
 
-#[derive(Debug, Deserialize, Serialize)]
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// Names of the months and weekdays in cron fields, weekdays from Sunday (0)
+pub const CRON_MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+pub const CRON_WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// How the expression is read
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    // A day and a weekday together are ignored, missing days run on the last
+    // day of the month
+    #[default]
+    Native,
+    // Like Vixie cron, see Interval::new_vixie
+    Vixie,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Interval {
     #[serde(default)]
     pub expression: String,
@@ -23,6 +69,10 @@ pub struct Interval {
     #[serde(default)]
     pub days: Vec<u32>,
 
+    // "LW" day, the last Monday to Friday of the month
+    #[serde(default)]
+    pub last_weekday: bool,
+
     // 1 to 12
     #[serde(default)]
     pub months: Vec<u32>,
@@ -30,19 +80,17 @@ pub struct Interval {
     // 1 (monday) to 7 (sunday)
     #[serde(default)]
     pub weekdays: Vec<u32>,
-}
 
-impl Default for Interval {
-    fn default() -> Self {
-        Interval {
-            expression: String::from(""),
-            minutes: Vec::new(),
-            hours: Vec::new(),
-            days: Vec::new(),
-            months: Vec::new(),
-            weekdays: Vec::new(),
-        }
-    }
+    // Wall clock the expression is matched against
+    #[serde(skip)]
+    pub timezone: Timezone,
+
+    #[serde(skip)]
+    pub compat: Compat,
+
+    // With the vixie compat, a day matching the day or the weekday runs
+    #[serde(skip)]
+    pub day_or_weekday: bool,
 }
 
 impl fmt::Display for Interval {
@@ -57,26 +105,48 @@ impl fmt::Display for Interval {
 
 impl Interval {
     pub fn new(expression: &str) -> Result<Interval, Error> {
-        Interval::validate_expression(expression)?;
+        Interval::with_compat(expression, Compat::Native)
+    }
+
+    pub fn with_compat(expression: &str, compat: Compat) -> Result<Interval, Error> {
+        // Shorthands like "@daily" are kept in the expression
+        let expanded = match expression.trim() {
+            name if name.starts_with('@') => expand_macro(name)
+                .ok_or_else(|| ErrorKind::InvalidIntervalExpression(expression.to_string()))?,
+            _ => expression,
+        };
+
+        if compat == Compat::Vixie {
+            return Interval::new_vixie(expression, expanded);
+        }
 
-        let mut iter = expression.split_whitespace().map(|section| {
-            let mut numbers = section
-                .split(",")
-                .filter_map(|item| item.parse().ok())
-                .collect::<Vec<u32>>();
+        Interval::validate_expression(expanded)?;
 
-            numbers.sort();
+        let sections: Vec<&str> = expanded.split_whitespace().collect();
 
-            numbers
-        });
+        let last_weekday = sections[2] == "LW";
+
+        let field = |index: usize, min: u32, max: u32| {
+            if index == 2 && last_weekday {
+                return Ok(Vec::new());
+            }
+
+            expand_field(sections[index], min, max, &[], 0)
+                .map(Option::unwrap_or_default)
+                .map_err(|_| ErrorKind::InvalidIntervalExpression(expression.to_string()))
+        };
 
         let interval = Interval {
             expression: expression.to_string(),
-            minutes: iter.next().unwrap(),
-            hours: iter.next().unwrap(),
-            days: iter.next().unwrap(),
-            months: iter.next().unwrap(),
-            weekdays: iter.next().unwrap(),
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days: field(2, 1, 31)?,
+            last_weekday,
+            months: field(3, 1, 12)?,
+            weekdays: field(4, 1, 7)?,
+            timezone: Timezone::Utc,
+            compat: Compat::Native,
+            day_or_weekday: false,
         };
 
         Interval::validate_interval(&interval)?;
@@ -84,9 +154,103 @@ impl Interval {
         Ok(interval)
     }
 
+    // Vixie cron reads month and weekday names, Sunday as 0 or 7, and a step
+    // after a single number, like "5/15", as that number only. A day and a
+    // weekday together run when either matches, unless one of them starts
+    // with "*" (like "*/2"), and days missing in a month are skipped
+    fn new_vixie(expression: &str, expanded: &str) -> Result<Interval, Error> {
+        let invalid = || ErrorKind::InvalidIntervalExpression(expression.to_string());
+
+        let sections: Vec<&str> = expanded.split_whitespace().collect();
+
+        if sections.len() != 5 {
+            return Err(invalid().into());
+        }
+
+        let field = |index: usize, min: u32, max: u32, names: &[&str], offset: u32| {
+            let items: Vec<&str> = sections[index]
+                .split(',')
+                .map(|item| match item.split_once('/') {
+                    Some((start, step))
+                        if start != "*"
+                            && !start.contains('-')
+                            && step.parse::<u32>().is_ok_and(|step| step > 0) =>
+                    {
+                        start
+                    }
+                    _ => item,
+                })
+                .collect();
+
+            expand_field(&items.join(","), min, max, names, offset)
+                .map(Option::unwrap_or_default)
+                .map_err(|_| invalid())
+        };
+
+        let mut weekdays: Vec<u32> = field(4, 0, 7, &CRON_WEEKDAY_NAMES, 0)?
+            .into_iter()
+            .map(|weekday| if weekday == 0 { 7 } else { weekday })
+            .collect();
+
+        weekdays.sort();
+        weekdays.dedup();
+
+        if weekdays.len() == 7 {
+            weekdays.clear();
+        }
+
+        let interval = Interval {
+            expression: expression.to_string(),
+            minutes: field(0, 0, 59, &[], 0)?,
+            hours: field(1, 0, 23, &[], 0)?,
+            days: field(2, 1, 31, &[], 0)?,
+            last_weekday: false,
+            months: field(3, 1, 12, &CRON_MONTH_NAMES, 1)?,
+            weekdays,
+            timezone: Timezone::Utc,
+            compat: Compat::Vixie,
+            day_or_weekday: !sections[2].starts_with('*') && !sections[4].starts_with('*'),
+        };
+
+        // Like "30 2", which cron accepts and never runs
+        if !interval.day_or_weekday && interval.days_never_exist() {
+            return Err(invalid().into());
+        }
+
+        Ok(interval)
+    }
+
+    fn days_never_exist(&self) -> bool {
+        let months = self.months_or_all();
+
+        !self.days.is_empty()
+            && months
+                .iter()
+                .all(|&month| self.days.iter().all(|&day| day > Interval::max_day(month)))
+    }
+
+    fn months_or_all(&self) -> Vec<u32> {
+        if self.months.is_empty() {
+            (1..=12).collect()
+        } else {
+            self.months.clone()
+        }
+    }
+
+    // February 29 exists in leap years, cron runs on it
+    fn max_day(month: u32) -> u32 {
+        match month {
+            2 => 29,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
     fn validate_expression(expression: &str) -> Result<(), Error> {
-        let regex = r"(\*|(\d,?)+)\s(\*|(\d,?)+)\s(\*|(\d,?)+)\s(\*|(\d,?)+)\s(\*|(\d,?)+)";
-        let regex = Regex::new(regex).unwrap();
+        // Lists of numbers, ranges and steps, like "0-12/2,20" or "*/15"
+        let field = r"(\*|\d+)(-\d+)?(/\d+)?(,(\*|\d+)(-\d+)?(/\d+)?)*";
+        let regex = format!(r"{0}\s{0}\s({0}|LW)\s{0}\s{0}", field);
+        let regex = Regex::new(&regex).unwrap();
 
         if !regex.is_match(expression) {
             return Err(ErrorKind::InvalidIntervalExpression(expression.to_string()))?;
@@ -101,17 +265,21 @@ impl Interval {
         // 0 to 23
         let hour = interval.hours.iter().find(|&&h| h > 23);
         // 1 to 31
-        let day = interval.days.iter().find(|&&d| d < 1 || d > 31);
+        let day = interval.days.iter().find(|&&d| !(1..=31).contains(&d));
         // 1 to 12
-        let month = interval.months.iter().find(|&&m| m < 1 || m > 12);
+        let month = interval.months.iter().find(|&&m| !(1..=12).contains(&m));
         // 1 (monday) to 7 (sunday)
-        let weekday = interval.weekdays.iter().find(|&&w| w < 1 || w > 7);
+        let weekday = interval.weekdays.iter().find(|&&w| !(1..=7).contains(&w));
+
+        // The last weekday replaces the weekdays
+        let last_weekday = interval.last_weekday && !interval.weekdays.is_empty();
 
         if minute.is_some()
             || hour.is_some()
             || day.is_some()
             || month.is_some()
             || weekday.is_some()
+            || last_weekday
         {
             return Err(ErrorKind::InvalidIntervalExpression(
                 interval.expression.to_string(),
@@ -121,6 +289,205 @@ impl Interval {
         Ok(())
     }
 
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+
+        if self.minutes.len() == 1 && !self.hours.is_empty() {
+            let times: Vec<String> = self
+                .hours
+                .iter()
+                .map(|hour| format!("{:02}:{:02}", hour, self.minutes[0]))
+                .collect();
+
+            parts.push(format!("at {}", Interval::join(&times)));
+        } else {
+            if self.minutes.is_empty() {
+                parts.push(String::from("every minute"));
+            } else {
+                parts.push(format!("at minute {}", Interval::join_numbers(&self.minutes)));
+            }
+
+            if !self.hours.is_empty() {
+                parts.push(format!("past hour {}", Interval::join_numbers(&self.hours)));
+            }
+        }
+
+        // Every day matches either the day or the weekday when one of them
+        // has every value
+        let (days, weekdays) =
+            if self.day_or_weekday && (self.days.is_empty() || self.weekdays.is_empty()) {
+                (&[][..], &[][..])
+            } else {
+                (&self.days[..], &self.weekdays[..])
+            };
+
+        if !days.is_empty() {
+            parts.push(format!(
+                "on day {} of the month",
+                Interval::join_numbers(days)
+            ));
+        }
+
+        if self.last_weekday {
+            parts.push(String::from("on the last weekday of the month"));
+        }
+
+        if !weekdays.is_empty() {
+            let names: Vec<String> = weekdays
+                .iter()
+                .map(|&weekday| WEEKDAY_NAMES[weekday as usize - 1].to_string())
+                .collect();
+
+            if self.day_or_weekday {
+                parts.push(format!("or on {}", Interval::join(&names)));
+            } else {
+                parts.push(format!("on {}", Interval::join(&names)));
+            }
+        }
+
+        if !self.months.is_empty() {
+            parts.push(format!(
+                "in {}",
+                Interval::join(&Interval::month_names(&self.months))
+            ));
+        }
+
+        if self.timezone != Timezone::Utc {
+            parts.push(format!("({})", self.timezone));
+        }
+
+        let description = parts.join(" ");
+
+        let mut chars = description.chars();
+
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => description,
+        }
+    }
+
+    // Expressions this scheduler reads differently from cron, or that look
+    // like a mistake
+    pub fn lint(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        let expression = expand_macro(self.expression.trim()).unwrap_or(&self.expression);
+
+        let sections: Vec<&str> = expression.split_whitespace().collect();
+
+        if sections.len() > 5 {
+            warnings.push(format!(
+                "Only the first 5 fields are used, the rest is ignored: {}",
+                sections[5..].join(" ")
+            ));
+        }
+
+        let fields = [
+            ("minute", 0, 59),
+            ("hour", 0, 23),
+            ("day", 1, 31),
+            ("month", 1, 12),
+            ("weekday", 1, 7),
+        ];
+
+        // Steps past the end of the range, like */90 for minutes, match only
+        // the start
+        for ((name, min, max), section) in fields.iter().zip(&sections) {
+            for item in section.split(',') {
+                let (range, step) = match item.split_once('/') {
+                    Some((range, step)) => (range, step.parse::<u32>().unwrap_or_default()),
+                    None => continue,
+                };
+
+                if self.compat == Compat::Vixie && range != "*" && !range.contains('-') {
+                    warnings.push(format!(
+                        "Vixie cron reads {} as {} only, later crons as {}-{}/{}",
+                        item, range, range, max, step
+                    ));
+
+                    continue;
+                }
+
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => {
+                        (start.parse().unwrap_or(*min), end.parse().unwrap_or(*max))
+                    }
+                    None if range == "*" => (*min, *max),
+                    None => (range.parse().unwrap_or(*min), *max),
+                };
+
+                if step > end - start.min(end) {
+                    warnings.push(format!(
+                        "Step {} is larger than the {} range, {} only matches {}",
+                        step, name, item, start
+                    ));
+                }
+            }
+        }
+
+        // The rest is read like cron does
+        if self.compat == Compat::Vixie {
+            return warnings;
+        }
+
+        if !self.days.is_empty() && !self.weekdays.is_empty() {
+            warnings.push(String::from(
+                "Both the day and the weekday are restricted: cron runs when either matches, this scheduler ignores both and runs every day (\"compat\": \"vixie\" reads it like cron)",
+            ));
+        }
+
+        if !self.days.is_empty() && self.weekdays.is_empty() {
+            let months = self.months_or_all();
+
+            if self.days_never_exist() {
+                warnings.push(format!(
+                    "No day {} in {}: cron never runs it, this scheduler runs on the last day of the month instead",
+                    Interval::join_numbers(&self.days),
+                    Interval::join(&Interval::month_names(&months))
+                ));
+            } else {
+                for &day in &self.days {
+                    let missing: Vec<u32> = months
+                        .iter()
+                        .filter(|&&month| day > Interval::max_day(month))
+                        .cloned()
+                        .collect();
+
+                    if !missing.is_empty() {
+                        warnings.push(format!(
+                            "Day {} doesn't exist in {}: cron skips those months, this scheduler runs on their last day instead",
+                            day,
+                            Interval::join(&Interval::month_names(&missing))
+                        ));
+                    }
+                }
+            }
+        }
+
+        warnings
+    }
+
+    fn month_names(months: &[u32]) -> Vec<String> {
+        months
+            .iter()
+            .map(|&month| MONTH_NAMES[month as usize - 1].to_string())
+            .collect()
+    }
+
+    fn join_numbers(numbers: &[u32]) -> String {
+        let numbers: Vec<String> = numbers.iter().map(|number| number.to_string()).collect();
+
+        Interval::join(&numbers)
+    }
+
+    fn join(items: &[String]) -> String {
+        match items.split_last() {
+            None => String::new(),
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        }
+    }
+
     pub fn should_run(&self, previous: DateTime<Utc>, now: DateTime<Utc>) -> bool {
         let next = self.next_time(previous);
 
@@ -135,10 +502,130 @@ impl Interval {
         should
     }
 
+    pub fn preview(&self, from: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        let mut times = Vec::with_capacity(count);
+        let mut previous = from;
+
+        for _ in 0..count {
+            previous = self.next_time(previous);
+
+            times.push(previous);
+        }
+
+        times
+    }
+
+    pub fn between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: usize,
+    ) -> Vec<DateTime<Utc>> {
+        let mut times = Vec::new();
+        let mut next = self.next_time(from);
+
+        while next <= to && times.len() < limit {
+            times.push(next);
+            next = self.next_time(next);
+        }
+
+        times
+    }
+
+    // Occurrences in the period above the latest `keep` ones: their count and
+    // the last of them
+    pub fn overflow(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        keep: usize,
+    ) -> Option<(usize, DateTime<Utc>)> {
+        let mut latest = VecDeque::with_capacity(keep + 1);
+        let mut count = 0;
+        let mut next = self.next_time(from);
+
+        while next <= to {
+            if latest.len() > keep {
+                latest.pop_front();
+            }
+
+            latest.push_back(next);
+            count += 1;
+            next = self.next_time(next);
+        }
+
+        if count > keep {
+            Some((count - keep, latest[latest.len() - keep - 1]))
+        } else {
+            None
+        }
+    }
+
+    pub fn last_time(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut last = None;
+        let mut next = self.next_time(from);
+
+        while next <= to {
+            last = Some(next);
+            next = self.next_time(next);
+        }
+
+        last
+    }
+
     pub fn next_time(&self, previous: DateTime<Utc>) -> DateTime<Utc> {
+        match self.timezone {
+            Timezone::Utc => self.next_wall_time(previous),
+            Timezone::Local => self.next_zoned_time(previous, &Local),
+            Timezone::Named(tz) => self.next_zoned_time(previous, &tz),
+        }
+    }
+
+    // Wall clock times are computed as if they were UTC, then placed in the
+    // timezone. Times skipped by a DST change run when the clock jumps
+    // forward, and times repeated by one run once
+    fn next_zoned_time<Tz: TimeZone>(&self, previous: DateTime<Utc>, tz: &Tz) -> DateTime<Utc> {
+        let mut wall = Utc.from_utc_datetime(&previous.with_timezone(tz).naive_local());
+
+        loop {
+            wall = self.next_wall_time(wall);
+
+            let candidates = match tz.from_local_datetime(&wall.naive_utc()) {
+                LocalResult::Single(time) => vec![time],
+                LocalResult::Ambiguous(earliest, latest) => vec![earliest, latest],
+                LocalResult::None => (1..=180)
+                    .map(|minutes| wall.naive_utc() + Duration::minutes(minutes))
+                    .find_map(|time| tz.from_local_datetime(&time).earliest())
+                    .into_iter()
+                    .collect(),
+            };
+
+            let next = candidates
+                .into_iter()
+                .map(|time| time.with_timezone(&Utc))
+                .find(|time| *time > previous);
+
+            if let Some(next) = next {
+                return next;
+            }
+        }
+    }
+
+    fn next_wall_time(&self, previous: DateTime<Utc>) -> DateTime<Utc> {
+        if self.compat == Compat::Vixie {
+            return self.next_vixie_time(previous);
+        }
+
         let next = Utc
-            .ymd(previous.year(), previous.month(), previous.day())
-            .and_hms(previous.hour(), previous.minute(), 0)
+            .with_ymd_and_hms(
+                previous.year(),
+                previous.month(),
+                previous.day(),
+                previous.hour(),
+                previous.minute(),
+                0,
+            )
+            .unwrap()
             + Duration::minutes(1);
 
         let next = self.next_minute_or_carry_hour(next);
@@ -149,9 +636,61 @@ impl Interval {
 
         let next = self.next_day_or_carry_month(next);
 
-        let next = self.next_month_or_carry_year(next);
+        let next = self.next_last_weekday_or_carry_month(next);
 
-        next
+        self.next_month_or_carry_year(next)
+    }
+
+    // Skips the months, days, hours and minutes that don't match, like cron.
+    // Expressions without an existing day are rejected, so a leap day is at
+    // most 8 years away
+    fn next_vixie_time(&self, previous: DateTime<Utc>) -> DateTime<Utc> {
+        let mut next = Utc
+            .with_ymd_and_hms(
+                previous.year(),
+                previous.month(),
+                previous.day(),
+                previous.hour(),
+                previous.minute(),
+                0,
+            )
+            .unwrap()
+            + Duration::minutes(1);
+
+        loop {
+            if !self.months.is_empty() && !self.months.contains(&next.month()) {
+                let (year, month) = match next.month() {
+                    12 => (next.year() + 1, 1),
+                    month => (next.year(), month + 1),
+                };
+
+                next = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+            } else if !self.matches_day(next) {
+                next = Utc
+                    .with_ymd_and_hms(next.year(), next.month(), next.day(), 0, 0, 0)
+                    .unwrap()
+                    + Duration::days(1);
+            } else if !self.hours.is_empty() && !self.hours.contains(&next.hour()) {
+                next = next.with_minute(0).unwrap() + Duration::hours(1);
+            } else if !self.minutes.is_empty() && !self.minutes.contains(&next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return next;
+            }
+        }
+    }
+
+    fn matches_day(&self, date: DateTime<Utc>) -> bool {
+        let day = self.days.is_empty() || self.days.contains(&date.day());
+
+        let weekday = self.weekdays.is_empty()
+            || self.weekdays.contains(&date.weekday().number_from_monday());
+
+        if self.day_or_weekday {
+            day || weekday
+        } else {
+            day && weekday
+        }
     }
 
     fn next_minute_or_carry_hour(&self, date: DateTime<Utc>) -> DateTime<Utc> {
@@ -230,6 +769,27 @@ impl Interval {
         }
     }
 
+    fn next_last_weekday_or_carry_month(&self, date: DateTime<Utc>) -> DateTime<Utc> {
+        if !self.last_weekday {
+            return date;
+        }
+
+        let last_weekday = Interval::last_weekday_of_month(date.year(), date.month());
+
+        if last_weekday >= date.day() {
+            return date + Duration::days((last_weekday - date.day()) as i64);
+        }
+
+        let (year, month) = match date.month() {
+            12 => (date.year() + 1, 1),
+            month => (date.year(), month + 1),
+        };
+
+        let day = Interval::last_weekday_of_month(year, month);
+
+        date + Duration::days(Interval::days_to_safe_date(date, year, month, day))
+    }
+
     fn next_month_or_carry_year(&self, date: DateTime<Utc>) -> DateTime<Utc> {
         if self.months.is_empty() {
             return date;
@@ -241,18 +801,29 @@ impl Interval {
 
         match next {
             Some(&month) => {
-                let days = Interval::days_to_safe_date(date, date.year(), month, date.day());
+                let day = self.day_in_month(date, date.year(), month);
+                let days = Interval::days_to_safe_date(date, date.year(), month, day);
 
                 date + Duration::days(days)
             }
             None => {
-                let days = Interval::days_to_safe_date(date, date.year() + 1, first, date.day());
+                let day = self.day_in_month(date, date.year() + 1, first);
+                let days = Interval::days_to_safe_date(date, date.year() + 1, first, day);
 
                 date + Duration::days(days)
             }
         }
     }
 
+    // Day kept when moving to another month, the last weekday moves with it
+    fn day_in_month(&self, date: DateTime<Utc>, year: i32, month: u32) -> u32 {
+        if self.last_weekday && (year, month) != (date.year(), date.month()) {
+            Interval::last_weekday_of_month(year, month)
+        } else {
+            date.day()
+        }
+    }
+
     fn days_to_weekday(from: u32, to: u32) -> i64 {
         (((to + 7) - from) % 7) as i64
     }
@@ -279,14 +850,99 @@ impl Interval {
         safe_date.signed_duration_since(date).num_days()
     }
 
+    fn last_weekday_of_month(year: i32, month: u32) -> u32 {
+        let last_day = Interval::last_day_of_month(year, month);
+
+        match NaiveDate::from_ymd_opt(year, month, last_day)
+            .unwrap()
+            .weekday()
+        {
+            Weekday::Sat => last_day - 1,
+            Weekday::Sun => last_day - 2,
+            _ => last_day,
+        }
+    }
+
     fn last_day_of_month(year: i32, month: u32) -> u32 {
         NaiveDate::from_ymd_opt(year, month + 1, 1)
-            .unwrap_or(NaiveDate::from_ymd(year + 1, 1, 1))
-            .pred()
+            .unwrap_or_else(|| NaiveDate::from_ymd_opt(year + 1, 1, 1).unwrap())
+            .pred_opt()
+            .unwrap()
             .day()
     }
 }
 
+// Five fields expression of a shorthand like "@daily"
+pub fn expand_macro(name: &str) -> Option<&'static str> {
+    let expression = match name {
+        "@hourly" => "0 * * * *",
+        "@daily" | "@midnight" => "0 0 * * *",
+        "@weekly" => "0 0 * * 7",
+        "@monthly" => "0 0 1 * *",
+        "@yearly" | "@annually" => "0 0 1 1 *",
+        _ => return None,
+    };
+
+    Some(expression)
+}
+
+// Numbers of a field with lists, ranges and steps, like "0-12/2,20" or
+// "*/15", and names counted from `offset`. None means every value
+pub fn expand_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    offset: u32,
+) -> Result<Option<Vec<u32>>, String> {
+    let invalid = || format!("invalid schedule field: {}", field);
+
+    let parse = |value: &str| -> Result<u32, String> {
+        let value = value.to_lowercase();
+
+        match names.iter().position(|name| *name == value) {
+            Some(position) => Ok(position as u32 + offset),
+            None => value.parse().map_err(|_| invalid()),
+        }
+    };
+
+    let mut numbers = Vec::new();
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (item, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse(start)?, parse(end)?)
+        } else if item.contains('/') {
+            (parse(range)?, max)
+        } else {
+            let value = parse(range)?;
+
+            (value, value)
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+
+        numbers.extend((start..=end).step_by(step as usize));
+    }
+
+    numbers.sort();
+    numbers.dedup();
+
+    if numbers.len() as u32 == max - min + 1 {
+        Ok(None)
+    } else {
+        Ok(Some(numbers))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -301,113 +957,330 @@ mod tests {
         assert_eq!(interval.minutes[3], 45);
         assert_eq!(interval.hours[0], 10);
         assert_eq!(interval.hours[1], 20);
-        assert_eq!(interval.days.is_empty(), true);
-        assert_eq!(interval.months.is_empty(), true);
-        assert_eq!(interval.weekdays.is_empty(), true);
+        assert!(interval.days.is_empty());
+        assert!(interval.months.is_empty());
+        assert!(interval.weekdays.is_empty());
     }
 
     #[test]
     fn expression_invalid_chars() {
         let interval = Interval::new("0,45 a * * *");
 
-        assert_eq!(interval.is_err(), true);
+        assert!(interval.is_err());
+    }
+
+    #[test]
+    fn expression_steps_and_ranges() {
+        let interval = Interval::new("*/15 0-12/4,20 10-12 */3 1-5").expect("invalid expression");
+
+        assert_eq!(interval.minutes, vec![0, 15, 30, 45]);
+        assert_eq!(interval.hours, vec![0, 4, 8, 12, 20]);
+        assert_eq!(interval.days, vec![10, 11, 12]);
+        assert_eq!(interval.months, vec![1, 4, 7, 10]);
+        assert_eq!(interval.weekdays, vec![1, 2, 3, 4, 5]);
+
+        // Every value, like *
+        let interval = Interval::new("*/1 0-23 * * *").expect("invalid expression");
+
+        assert!(interval.minutes.is_empty());
+        assert!(interval.hours.is_empty());
+
+        for invalid in &["*/0 * * * *", "0 12-2 * * *", "0 0-24 * * *", "0 0 * * 0"] {
+            assert!(Interval::new(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn expression_macros() {
+        let interval = Interval::new("@daily").expect("invalid expression");
+
+        assert_eq!(interval.expression, "@daily");
+        assert_eq!(interval.minutes, vec![0]);
+        assert_eq!(interval.hours, vec![0]);
+        assert!(interval.days.is_empty());
+
+        let interval = Interval::new("@weekly").expect("invalid expression");
+
+        assert_eq!(interval.weekdays, vec![7]);
+
+        assert!(Interval::new("@yearly").is_ok());
+        assert!(Interval::new("@hourly").is_ok());
+        assert!(Interval::new("@monthly").is_ok());
+        assert!(Interval::new("@fortnightly").is_err());
+    }
+
+    #[test]
+    fn next_time_in_timezone() {
+        let mut interval = Interval::new("0 8 * * *").expect("invalid expression");
+
+        interval.timezone = "America/Sao_Paulo".parse().unwrap();
+
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 1, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            interval.next_time(current_date),
+            Utc.with_ymd_and_hms(2019, 7, 2, 11, 0, 0).unwrap()
+        );
+        assert_eq!(interval.describe(), "At 08:00 (America/Sao_Paulo)");
+
+        // 02:30 doesn't exist when New York moves to summer time, runs at 03:00
+        let mut interval = Interval::new("30 2 * * *").expect("invalid expression");
+
+        interval.timezone = "America/New_York".parse().unwrap();
+
+        let current_date = Utc.with_ymd_and_hms(2019, 3, 9, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            interval.next_time(current_date),
+            Utc.with_ymd_and_hms(2019, 3, 10, 7, 0, 0).unwrap()
+        );
+
+        // 01:30 happens twice when it moves back, runs the first time only
+        let mut interval = Interval::new("30 1 * * *").expect("invalid expression");
+
+        interval.timezone = "America/New_York".parse().unwrap();
+
+        let current_date = Utc.with_ymd_and_hms(2019, 11, 2, 12, 0, 0).unwrap();
+        let next_date = interval.next_time(current_date);
+
+        assert_eq!(
+            next_date,
+            Utc.with_ymd_and_hms(2019, 11, 3, 5, 30, 0).unwrap()
+        );
+        assert_eq!(
+            interval.next_time(next_date),
+            Utc.with_ymd_and_hms(2019, 11, 4, 6, 30, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn vixie_compat_known_behaviors() {
+        let time = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        // Expression, start and the next runs of Vixie cron
+        let cases = [
+            // Sunday is 0 or 7, names are read
+            (
+                "0 0 * * 0",
+                "2019-07-01T00:00:00Z",
+                vec!["2019-07-07T00:00:00Z"],
+            ),
+            (
+                "0 0 * * 7",
+                "2019-07-01T00:00:00Z",
+                vec!["2019-07-07T00:00:00Z"],
+            ),
+            (
+                "0 0 * * sun",
+                "2019-07-01T00:00:00Z",
+                vec!["2019-07-07T00:00:00Z"],
+            ),
+            (
+                "@weekly",
+                "2019-07-01T00:00:00Z",
+                vec!["2019-07-07T00:00:00Z"],
+            ),
+            (
+                "0 12 * jan,jul mon-fri",
+                "2019-07-31T13:00:00Z",
+                vec!["2020-01-01T12:00:00Z", "2020-01-02T12:00:00Z"],
+            ),
+            // A day and a weekday run when either matches
+            (
+                "0 0 13 * 5",
+                "2019-07-01T00:00:00Z",
+                vec![
+                    "2019-07-05T00:00:00Z",
+                    "2019-07-12T00:00:00Z",
+                    "2019-07-13T00:00:00Z",
+                ],
+            ),
+            // Unless one of them starts with "*", both must match
+            (
+                "0 0 */2 * 1",
+                "2019-06-30T12:00:00Z",
+                vec!["2019-07-01T00:00:00Z", "2019-07-15T00:00:00Z"],
+            ),
+            // Every day matches "1-31", which isn't "*"
+            (
+                "0 0 1-31 * 1",
+                "2019-07-01T00:00:00Z",
+                vec!["2019-07-02T00:00:00Z", "2019-07-03T00:00:00Z"],
+            ),
+            // Missing days are skipped, not moved to the end of the month
+            (
+                "0 0 31 * *",
+                "2019-04-01T00:00:00Z",
+                vec!["2019-05-31T00:00:00Z", "2019-07-31T00:00:00Z"],
+            ),
+            (
+                "0 0 29 2 *",
+                "2019-03-01T00:00:00Z",
+                vec!["2020-02-29T00:00:00Z", "2024-02-29T00:00:00Z"],
+            ),
+            // A step after a single number only matches the number
+            (
+                "5/15 * * * *",
+                "2019-07-01T12:00:00Z",
+                vec!["2019-07-01T12:05:00Z", "2019-07-01T13:05:00Z"],
+            ),
+        ];
+
+        for (expression, from, expected) in &cases {
+            let interval = Interval::with_compat(expression, Compat::Vixie).unwrap();
+
+            let expected: Vec<DateTime<Utc>> = expected.iter().map(|next| time(next)).collect();
+
+            assert_eq!(
+                interval.preview(time(from), expected.len()),
+                expected,
+                "{}",
+                expression
+            );
+        }
+
+        // Never runs in cron, or not cron at all
+        for invalid in &[
+            "0 0 30 2 *",
+            "0 0 31 4,6 *",
+            "0 18 LW * *",
+            "0 0 * * 8",
+            "* * * * * *",
+        ] {
+            assert!(Interval::with_compat(invalid, Compat::Vixie).is_err());
+        }
+
+        // Runs on the Mondays of February
+        assert!(Interval::with_compat("0 0 30 2 1", Compat::Vixie).is_ok());
+
+        let describe = |expression| {
+            Interval::with_compat(expression, Compat::Vixie)
+                .unwrap()
+                .describe()
+        };
+
+        assert_eq!(
+            describe("0 0 13 * 5"),
+            "At 00:00 on day 13 of the month or on Friday"
+        );
+        assert_eq!(describe("0 0 1-31 * 1"), "At 00:00");
+
+        let lint = |expression| {
+            Interval::with_compat(expression, Compat::Vixie)
+                .unwrap()
+                .lint()
+        };
+
+        assert!(lint("0 0 1 * 1").is_empty());
+        assert_eq!(
+            lint("5/15 * * * *"),
+            vec!["Vixie cron reads 5/15 as 5 only, later crons as 5-59/15"]
+        );
+
+        // The native compat moves missing days to the end of the month
+        let interval = Interval::new("0 0 31 * *").unwrap();
+
+        assert_eq!(
+            interval.next_time(time("2019-04-01T00:00:00Z")),
+            time("2019-04-30T00:00:00Z")
+        );
     }
 
     #[test]
     fn expression_invalid_length() {
         let interval = Interval::new("0,45 * *");
 
-        assert_eq!(interval.is_err(), true);
+        assert!(interval.is_err());
     }
 
     #[test]
     fn minute_found() {
         let interval = Interval::new("0,30 * * * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 1).and_hms(12, 15, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 1, 12, 15, 0).unwrap();
         let next_date = interval.next_minute_or_carry_hour(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 1).and_hms(12, 30, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 1, 12, 30, 0).unwrap());
     }
 
     #[test]
     fn minute_carry_hour() {
         let interval = Interval::new("0,30 * * * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 1).and_hms(12, 31, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 1, 12, 31, 0).unwrap();
         let next_date = interval.next_minute_or_carry_hour(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 1).and_hms(13, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 1, 13, 0, 0).unwrap());
     }
 
     #[test]
     fn minute_carry_hour_and_day() {
         let interval = Interval::new("0,30 * * * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 1).and_hms(23, 31, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 1, 23, 31, 0).unwrap();
         let next_date = interval.next_minute_or_carry_hour(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 2).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 2, 0, 0, 0).unwrap());
     }
 
     #[test]
     fn hour_found() {
         let interval = Interval::new("* 0,12 * * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 1).and_hms(6, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 1, 6, 0, 0).unwrap();
         let next_date = interval.next_hour_or_carry_day(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 1).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 1, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn hour_carry_day() {
         let interval = Interval::new("* 0,12 * * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 1).and_hms(18, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 1, 18, 0, 0).unwrap();
         let next_date = interval.next_hour_or_carry_day(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 2).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 2, 0, 0, 0).unwrap());
     }
 
     #[test]
     fn hour_carry_day_and_month() {
         let interval = Interval::new("* 0,12 * * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 31).and_hms(18, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 31, 18, 0, 0).unwrap();
         let next_date = interval.next_hour_or_carry_day(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 8, 1).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 8, 1, 0, 0, 0).unwrap());
     }
 
     #[test]
     fn day_found() {
         let interval = Interval::new("* * 1,20 * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 10).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 10, 12, 0, 0).unwrap();
         let next_date = interval.next_day_or_carry_month(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 20).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 20, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn day_carry_month() {
         let interval = Interval::new("* * 1,20 * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 25).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 25, 12, 0, 0).unwrap();
         let next_date = interval.next_day_or_carry_month(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 8, 1).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 8, 1, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn day_carry_month_and_year() {
         let interval = Interval::new("* * 1,20 * *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 12, 25).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 12, 25, 12, 0, 0).unwrap();
         let next_date = interval.next_day_or_carry_month(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2020, 1, 1).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap());
     }
 
     #[test]
@@ -434,122 +1307,300 @@ mod tests {
     fn weekday_found() {
         let interval = Interval::new("* * * * 1,4").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 2).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 2, 12, 0, 0).unwrap();
         let next_date = interval.next_weekday_or_carry_month(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 7, 4).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 4, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn weekday_carry_month() {
         let interval = Interval::new("* * * * 1,4").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 7, 30).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 7, 30, 12, 0, 0).unwrap();
         let next_date = interval.next_weekday_or_carry_month(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 8, 1).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 8, 1, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn weekday_carry_month_and_year() {
         let interval = Interval::new("* * * * 1,4").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 12, 31).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 12, 31, 12, 0, 0).unwrap();
         let next_date = interval.next_weekday_or_carry_month(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2020, 1, 2).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2020, 1, 2, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn month_found() {
         let interval = Interval::new("* * * 1,6 *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 3, 1).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 3, 1, 12, 0, 0).unwrap();
         let next_date = interval.next_month_or_carry_year(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2019, 6, 1).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 6, 1, 12, 0, 0).unwrap());
     }
 
     #[test]
     fn month_carry_year() {
         let interval = Interval::new("* * * 1,6 *").expect("invalid expression");
 
-        let current_date = Utc.ymd(2019, 8, 1).and_hms(12, 0, 0);
+        let current_date = Utc.with_ymd_and_hms(2019, 8, 1, 12, 0, 0).unwrap();
         let next_date = interval.next_month_or_carry_year(current_date);
 
-        assert_eq!(next_date, Utc.ymd(2020, 1, 1).and_hms(12, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2020, 1, 1, 12, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn between_times() {
+        let interval = Interval::new("0 8,20 * * *").unwrap();
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 8, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2019, 7, 2, 20, 0, 0).unwrap();
+
+        let times = interval.between(from, to, 10);
+
+        assert_eq!(times.len(), 3);
+        assert_eq!(
+            times[0],
+            Utc.with_ymd_and_hms(2019, 7, 1, 20, 0, 0).unwrap()
+        );
+        assert_eq!(times[2], to);
+
+        assert_eq!(interval.between(from, to, 2).len(), 2);
     }
 
     #[test]
     fn next_time_hour() {
         let interval = Interval::new("0 0,6,12,18 * * *").expect("invalid expression");
 
-        let mut next_date = Utc.ymd(2019, 7, 1).and_hms(12, 0, 0);
+        let mut next_date = Utc.with_ymd_and_hms(2019, 7, 1, 12, 0, 0).unwrap();
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 1).and_hms(18, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 1, 18, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 2).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 2, 0, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 2).and_hms(6, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 2, 6, 0, 0).unwrap());
     }
 
     #[test]
     fn next_time_weekday() {
         let interval = Interval::new("0 6,18 * * 1").expect("invalid expression");
 
-        let mut next_date = Utc.ymd(2019, 7, 1).and_hms(6, 0, 0);
+        let mut next_date = Utc.with_ymd_and_hms(2019, 7, 1, 6, 0, 0).unwrap();
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 1).and_hms(18, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 1, 18, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 8).and_hms(6, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 8, 6, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 8).and_hms(18, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 8, 18, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 7, 15).and_hms(6, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 7, 15, 6, 0, 0).unwrap());
     }
 
     #[test]
     fn next_time_day_end_month() {
         let interval = Interval::new("0 0 31 * *").expect("invalid expression");
 
-        let mut next_date = Utc.ymd(2019, 1, 30).and_hms(0, 0, 0);
+        let mut next_date = Utc.with_ymd_and_hms(2019, 1, 30, 0, 0, 0).unwrap();
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 1, 31).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 1, 31, 0, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 2, 28).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 2, 28, 0, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 3, 31).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 3, 31, 0, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 4, 30).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 4, 30, 0, 0, 0).unwrap());
     }
 
     #[test]
     fn next_time_month_end_month() {
         let interval = Interval::new("0 0 31 1,2,3,4 *").expect("invalid expression");
 
-        let mut next_date = Utc.ymd(2019, 1, 30).and_hms(0, 0, 0);
+        let mut next_date = Utc.with_ymd_and_hms(2019, 1, 30, 0, 0, 0).unwrap();
+
+        next_date = interval.next_time(next_date);
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 1, 31, 0, 0, 0).unwrap());
+
+        next_date = interval.next_time(next_date);
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 2, 28, 0, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 1, 31).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 3, 31, 0, 0, 0).unwrap());
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 2, 28).and_hms(0, 0, 0));
+        assert_eq!(next_date, Utc.with_ymd_and_hms(2019, 4, 30, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn next_time_last_weekday() {
+        let interval = Interval::new("0 18 LW * *").expect("invalid expression");
+
+        let mut next_date = Utc.with_ymd_and_hms(2019, 5, 30, 0, 0, 0).unwrap();
 
+        // Friday the 31st, Friday the 28th (30th is a Sunday), Wednesday the 31st
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 3, 31).and_hms(0, 0, 0));
+        assert_eq!(
+            next_date,
+            Utc.with_ymd_and_hms(2019, 5, 31, 18, 0, 0).unwrap()
+        );
 
         next_date = interval.next_time(next_date);
-        assert_eq!(next_date, Utc.ymd(2019, 4, 30).and_hms(0, 0, 0));
+        assert_eq!(
+            next_date,
+            Utc.with_ymd_and_hms(2019, 6, 28, 18, 0, 0).unwrap()
+        );
+
+        next_date = interval.next_time(next_date);
+        assert_eq!(
+            next_date,
+            Utc.with_ymd_and_hms(2019, 7, 31, 18, 0, 0).unwrap()
+        );
+
+        let interval = Interval::new("0 18 LW 3 *").expect("invalid expression");
+
+        next_date = interval.next_time(Utc.with_ymd_and_hms(2019, 4, 1, 0, 0, 0).unwrap());
+        assert_eq!(
+            next_date,
+            Utc.with_ymd_and_hms(2020, 3, 31, 18, 0, 0).unwrap()
+        );
+
+        assert_eq!(
+            interval.describe(),
+            "At 18:00 on the last weekday of the month in March"
+        );
+        assert!(Interval::new("0 18 LW * 5").is_err());
+    }
+
+    #[test]
+    fn describe_every_minute() {
+        let interval = Interval::new("* * * * *").expect("invalid expression");
+
+        assert_eq!(interval.describe(), "Every minute");
+    }
+
+    #[test]
+    fn describe_times() {
+        let interval = Interval::new("30 0,4,8 * * *").expect("invalid expression");
+
+        assert_eq!(interval.describe(), "At 00:30, 04:30 and 08:30");
+    }
+
+    #[test]
+    fn describe_minutes_and_hours() {
+        let interval = Interval::new("0,30 6 * * *").expect("invalid expression");
+
+        assert_eq!(interval.describe(), "At minute 0 and 30 past hour 6");
+    }
+
+    #[test]
+    fn describe_days_weekdays_and_months() {
+        let interval = Interval::new("0 12 1,15 * *").expect("invalid expression");
+
+        assert_eq!(
+            interval.describe(),
+            "At 12:00 on day 1 and 15 of the month"
+        );
+
+        let interval = Interval::new("0 12 * 1,6 1,5").expect("invalid expression");
+
+        assert_eq!(
+            interval.describe(),
+            "At 12:00 on Monday and Friday in January and June"
+        );
+    }
+
+    #[test]
+    fn preview_times() {
+        let interval = Interval::new("0 0,12 * * *").expect("invalid expression");
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 6, 0, 0).unwrap();
+        let times = interval.preview(from, 3);
+
+        assert_eq!(
+            times,
+            vec![
+                Utc.with_ymd_and_hms(2019, 7, 1, 12, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2019, 7, 2, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2019, 7, 2, 12, 0, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn overflow_times() {
+        let interval = Interval::new("0 * * * *").unwrap();
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 8, 30, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2019, 7, 1, 20, 0, 0).unwrap();
+
+        assert_eq!(
+            interval.overflow(from, to, 2),
+            Some((10, Utc.with_ymd_and_hms(2019, 7, 1, 18, 0, 0).unwrap()))
+        );
+        assert_eq!(interval.overflow(from, to, 12), None);
+    }
+
+    #[test]
+    fn last_time_found() {
+        let interval = Interval::new("0,30 * * * *").expect("invalid expression");
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 12, 10, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2019, 7, 1, 13, 15, 0).unwrap();
+
+        assert_eq!(
+            interval.last_time(from, to),
+            Some(Utc.with_ymd_and_hms(2019, 7, 1, 13, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn last_time_not_found() {
+        let interval = Interval::new("0,30 * * * *").expect("invalid expression");
+
+        let from = Utc.with_ymd_and_hms(2019, 7, 1, 12, 10, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2019, 7, 1, 12, 25, 0).unwrap();
+
+        assert_eq!(interval.last_time(from, to), None);
+    }
+
+    #[test]
+    fn lint_expressions() {
+        let lint = |expression| Interval::new(expression).unwrap().lint();
+
+        assert!(lint("0 0 * * *").is_empty());
+        assert!(lint("0 0 LW * *").is_empty());
+        assert!(lint("0 0 29 2 *").is_empty());
+
+        assert_eq!(
+            lint("0 0 30,31 2 *"),
+            vec!["No day 30 and 31 in February: cron never runs it, this scheduler runs on the last day of the month instead"]
+        );
+        assert_eq!(
+            lint("0 0 31 4,5 *"),
+            vec!["Day 31 doesn't exist in April: cron skips those months, this scheduler runs on their last day instead"]
+        );
+        assert_eq!(lint("0 0 1 * 1").len(), 1);
+        assert!(lint("*/5 0-12/2 * * *").is_empty());
+        assert_eq!(
+            lint("*/90 1-5,8 * * * *"),
+            vec![
+                "Only the first 5 fields are used, the rest is ignored: *",
+                "Step 90 is larger than the minute range, */90 only matches 0"
+            ]
+        );
     }
 }
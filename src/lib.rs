@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate derive_more;
+
+pub mod analyze;
+pub mod api;
+pub mod archive;
+pub mod audit;
+pub mod aws;
+pub mod builder;
+pub mod cache;
+pub mod commands;
+pub mod console;
+pub mod control;
+pub mod crontab;
+pub mod crypto;
+pub mod daemon;
+pub mod digest;
+pub mod doctor;
+pub mod error;
+pub mod executor;
+pub mod explain;
+pub mod export;
+pub mod fairness;
+pub mod function;
+pub mod graph;
+pub mod grpc;
+pub mod health;
+pub mod history;
+pub mod http;
+pub mod inputs;
+pub mod interval;
+pub mod lock;
+pub mod logging;
+pub mod logs;
+pub mod mqtt;
+pub mod notification;
+pub mod overview;
+pub mod pipeline;
+pub mod plugin;
+pub mod queue;
+pub mod runtime;
+pub mod scaffold;
+pub mod scheduler;
+pub mod secrets;
+pub mod security;
+pub mod services;
+pub mod settings;
+pub mod shutdown;
+pub mod sla;
+pub mod state;
+pub mod statistics;
+pub mod statsd;
+pub mod storage;
+pub mod stuck;
+pub mod systemd;
+pub mod timezone;
+pub mod upload;
+pub mod vault;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watch;
+pub mod websocket;
+pub mod window;
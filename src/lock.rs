@@ -0,0 +1,117 @@
+use super::error::{Error, ErrorKind};
+use super::pipeline::Pipeline;
+use failure::ResultExt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub struct Lock {
+    pub id: String,
+
+    pub path: String,
+
+    // The lock is held for as long as the file stays open
+    file: File,
+}
+
+impl Lock {
+    pub fn path_from_pipeline(pipeline: &Pipeline) -> String {
+        let mut lock_path = PathBuf::from(&pipeline.path);
+        lock_path.pop();
+        lock_path.push("pipeline.lock");
+
+        lock_path.to_string_lossy().to_string()
+    }
+
+    pub fn acquire_from_pipeline(pipeline: &Pipeline) -> Result<Option<Lock>, Error> {
+        Lock::acquire_file(&pipeline.id, &Lock::path_from_pipeline(pipeline))
+    }
+
+    // Probed with a shared lock on another descriptor, released right away.
+    // A run starting at the same moment may find it taken and be skipped
+    pub fn is_held_from_pipeline(pipeline: &Pipeline) -> Result<bool, Error> {
+        let lock_path = Lock::path_from_pipeline(pipeline);
+
+        // A pipeline that never ran has no lock file
+        let file = match File::open(&lock_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => Err(err).context(ErrorKind::InvalidLockFile(lock_path.to_string()))?,
+        };
+
+        match file.try_lock_shared() {
+            Ok(_) => {
+                file.unlock()
+                    .context(ErrorKind::InvalidLockFile(lock_path.to_string()))?;
+
+                Ok(false)
+            }
+            Err(TryLockError::WouldBlock) => Ok(true),
+            Err(TryLockError::Error(err)) => {
+                Err(err).context(ErrorKind::InvalidLockFile(lock_path.to_string()))?
+            }
+        }
+    }
+
+    pub fn acquire_file(id: &str, lock_path: &str) -> Result<Option<Lock>, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)
+            .context(ErrorKind::InvalidLockFile(lock_path.to_string()))?;
+
+        match file.try_lock() {
+            Ok(_) => Ok(Some(Lock {
+                id: id.to_string(),
+                path: lock_path.to_string(),
+                file,
+            })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => {
+                Err(err).context(ErrorKind::InvalidLockFile(lock_path.to_string()))?
+            }
+        }
+    }
+
+    pub fn release(self) -> Result<(), Error> {
+        self.file
+            .unlock()
+            .context(ErrorKind::InvalidLockFile(self.path.to_string()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn probe_held_locks() {
+        let dir = env::temp_dir().join("rusty-scheduler-lock");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut pipeline: Pipeline =
+            serde_json::from_str(r#"{"id": "locked", "stages": [], "jobs": []}"#).unwrap();
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+
+        let lock = Lock::acquire_from_pipeline(&pipeline).unwrap().unwrap();
+
+        assert!(Lock::is_held_from_pipeline(&pipeline).unwrap());
+
+        lock.release().unwrap();
+
+        assert!(!Lock::is_held_from_pipeline(&pipeline).unwrap());
+
+        // The probe doesn't keep the lock
+        let lock = Lock::acquire_from_pipeline(&pipeline).unwrap();
+
+        assert!(lock.is_some());
+    }
+}
@@ -0,0 +1,187 @@
+use super::pipeline::Pipeline;
+use super::timezone::{self, Timezone};
+use chrono::Utc;
+use env_logger::fmt::Formatter;
+use env_logger::Env;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+// Pipelines with a log_level, logging at another level than the scheduler
+static LEVELS: Mutex<BTreeMap<String, LevelFilter>> = Mutex::new(BTreeMap::new());
+
+// Level of the scheduler, from the log setting and RUST_LOG
+static DEFAULT_LEVEL: Mutex<LevelFilter> = Mutex::new(LevelFilter::Off);
+
+thread_local! {
+    // Pipeline being scheduled or run by the current thread
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+struct Logger {
+    default: env_logger::Logger,
+
+    // Used for the records of pipelines with a log_level
+    pipeline: env_logger::Logger,
+}
+
+// Sets the pipeline of the current thread until it is dropped
+pub struct Scope {
+    previous: Option<String>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match pipeline_level(metadata) {
+            Some(level) => metadata.level() <= level,
+            None => self.default.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        match pipeline_level(record.metadata()) {
+            Some(level) if record.level() <= level => self.pipeline.log(record),
+            Some(_) => {}
+            None => self.default.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.default.flush();
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+pub fn init(default_filter: &str) {
+    let mut default =
+        env_logger::Builder::from_env(Env::default().default_filter_or(default_filter));
+
+    let mut pipeline = env_logger::Builder::new();
+    pipeline.filter_level(LevelFilter::Trace);
+
+    // The default format of env_logger only shows UTC times
+    if timezone::display() != Timezone::Utc {
+        default.format(format);
+        pipeline.format(format);
+    }
+
+    let default = default.build();
+    let pipeline = pipeline.build();
+
+    *DEFAULT_LEVEL.lock().unwrap() = default.filter();
+
+    if log::set_boxed_logger(Box::new(Logger { default, pipeline })).is_ok() {
+        update_max_level();
+    }
+}
+
+// Replaces the levels with the log_level of the loaded pipelines
+pub fn set_levels(pipelines: &[Pipeline]) {
+    let levels = pipelines
+        .iter()
+        .filter_map(|pipeline| {
+            let level = pipeline.log_level.as_ref()?.parse().ok()?;
+
+            Some((pipeline.id.to_string(), level))
+        })
+        .collect();
+
+    *LEVELS.lock().unwrap() = levels;
+
+    update_max_level();
+}
+
+pub fn scope(pipeline_id: &str) -> Scope {
+    let previous = CURRENT.with(|current| current.replace(Some(pipeline_id.to_string())));
+
+    Scope { previous }
+}
+
+fn format(formatter: &mut Formatter, record: &Record) -> io::Result<()> {
+    writeln!(
+        formatter,
+        "[{} {:<5} {}] {}",
+        timezone::format(Utc::now(), "%Y-%m-%dT%H:%M:%S"),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+// Records below every level are dropped by the log macros, without locking
+fn update_max_level() {
+    let levels = LEVELS.lock().unwrap();
+
+    let max_level = levels
+        .values()
+        .copied()
+        .chain(Some(*DEFAULT_LEVEL.lock().unwrap()))
+        .max()
+        .unwrap_or(LevelFilter::Off);
+
+    log::set_max_level(max_level);
+}
+
+// Only records of the scheduler itself, libraries keep the default level
+fn pipeline_level(metadata: &Metadata) -> Option<LevelFilter> {
+    if !metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+        return None;
+    }
+
+    let id = CURRENT.with(|current| current.borrow().clone())?;
+
+    LEVELS.lock().unwrap().get(&id).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn metadata(level: Level, target: &str) -> Metadata<'_> {
+        Metadata::builder().level(level).target(target).build()
+    }
+
+    #[test]
+    fn use_the_level_of_the_current_pipeline() {
+        *LEVELS.lock().unwrap() = vec![
+            (String::from("test-noisy"), LevelFilter::Trace),
+            (String::from("test-quiet"), LevelFilter::Error),
+        ]
+        .into_iter()
+        .collect();
+
+        let trace = metadata(Level::Trace, "rusty_scheduler::scheduler");
+        let warn = metadata(Level::Warn, "rusty_scheduler::scheduler");
+
+        assert_eq!(pipeline_level(&trace), None);
+
+        {
+            let _scope = scope("test-noisy");
+
+            assert_eq!(pipeline_level(&trace), Some(LevelFilter::Trace));
+            assert_eq!(pipeline_level(&metadata(Level::Trace, "ureq::pool")), None);
+
+            {
+                let _scope = scope("test-quiet");
+
+                assert_eq!(pipeline_level(&warn), Some(LevelFilter::Error));
+            }
+
+            assert_eq!(pipeline_level(&warn), Some(LevelFilter::Trace));
+        }
+
+        assert_eq!(pipeline_level(&trace), None);
+
+        let _scope = scope("test-other");
+
+        assert_eq!(pipeline_level(&trace), None);
+    }
+}
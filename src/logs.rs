@@ -0,0 +1,277 @@
+use super::error::{Error, ErrorKind};
+use super::pipeline::Pipeline;
+use chrono::{DateTime, Duration, Utc};
+use failure::ResultExt;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::trace;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFile {
+    pub path: PathBuf,
+
+    // Bytes
+    pub size: u64,
+
+    pub modified: DateTime<Utc>,
+}
+
+pub fn dir_from_pipeline(pipeline: &Pipeline) -> PathBuf {
+    let mut logs_path = PathBuf::from(&pipeline.path);
+    logs_path.pop();
+    logs_path.push("logs");
+
+    logs_path
+}
+
+pub fn create(pipeline: &Pipeline, run_id: &str) -> Result<File, Error> {
+    let mut log_path = dir_from_pipeline(pipeline);
+
+    fs::create_dir_all(&log_path).context(ErrorKind::InvalidLogFile(
+        log_path.to_string_lossy().to_string(),
+    ))?;
+
+    log_path.push(format!("{}.log", run_id));
+
+    let file = File::create(&log_path).context(ErrorKind::InvalidLogFile(
+        log_path.to_string_lossy().to_string(),
+    ))?;
+
+    trace!("Log file created: {}", log_path.to_string_lossy());
+
+    Ok(file)
+}
+
+// Log of a run, compressed or not
+pub fn find(pipeline: &Pipeline, run_id: &str) -> Option<PathBuf> {
+    let logs_path = dir_from_pipeline(pipeline);
+
+    [format!("{}.log", run_id), format!("{}.log.gz", run_id)]
+        .iter()
+        .map(|name| logs_path.join(name))
+        .find(|log_path| log_path.is_file())
+}
+
+pub fn latest(pipeline: &Pipeline) -> Result<Option<PathBuf>, Error> {
+    let logs_path = dir_from_pipeline(pipeline);
+
+    if !logs_path.is_dir() {
+        return Ok(None);
+    }
+
+    let latest = list(&logs_path)?
+        .into_iter()
+        .max_by_key(|log| log.modified)
+        .map(|log| log.path);
+
+    Ok(latest)
+}
+
+pub fn read(log_path: &Path) -> Result<String, Error> {
+    let name = log_path.to_string_lossy().to_string();
+
+    let mut file = File::open(log_path).context(ErrorKind::InvalidLogFile(name.to_string()))?;
+
+    let mut content = String::new();
+
+    if name.ends_with(".gz") {
+        GzDecoder::new(file).read_to_string(&mut content)
+    } else {
+        file.read_to_string(&mut content)
+    }
+    .context(ErrorKind::InvalidLogFile(name))?;
+
+    Ok(content)
+}
+
+// Compresses finished logs and removes the ones outside the retention policy
+pub fn rotate(pipeline: &Pipeline) -> Result<usize, Error> {
+    let logs_path = dir_from_pipeline(pipeline);
+
+    if !logs_path.is_dir() {
+        return Ok(0);
+    }
+
+    if pipeline.compress_logs {
+        for log in list(&logs_path)? {
+            if log
+                .path
+                .extension()
+                .is_some_and(|extension| extension == "log")
+            {
+                compress(&log.path)?;
+            }
+        }
+    }
+
+    // Out of range retentions are rejected on load, no log expires by age
+    // if one gets here anyway
+    let before = Some(pipeline.log_days)
+        .filter(|days| *days >= 0)
+        .and_then(Duration::try_days)
+        .and_then(|days| Utc::now().checked_sub_signed(days))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let max_size = pipeline.log_max_size * 1024 * 1024;
+
+    let removed = expired(list(&logs_path)?, before, max_size);
+
+    for log in &removed {
+        trace!("Removing log file: {}", log.path.to_string_lossy());
+
+        fs::remove_file(&log.path).context(ErrorKind::InvalidLogFile(
+            log.path.to_string_lossy().to_string(),
+        ))?;
+    }
+
+    Ok(removed.len())
+}
+
+// Oldest logs go first, the most recent one is always kept
+pub fn expired(mut logs: Vec<LogFile>, before: DateTime<Utc>, max_size: u64) -> Vec<LogFile> {
+    logs.sort_by_key(|log| log.modified);
+
+    let mut total_size: u64 = logs.iter().map(|log| log.size).sum();
+
+    logs.pop();
+
+    logs.into_iter()
+        .filter(|log| {
+            let expired = log.modified < before || (max_size > 0 && total_size > max_size);
+
+            if expired {
+                total_size -= log.size;
+            }
+
+            expired
+        })
+        .collect()
+}
+
+fn list(logs_path: &Path) -> Result<Vec<LogFile>, Error> {
+    let mut logs = Vec::new();
+
+    let entries = fs::read_dir(logs_path).context(ErrorKind::InvalidLogFile(
+        logs_path.to_string_lossy().to_string(),
+    ))?;
+
+    for entry in entries {
+        let path = entry
+            .context(ErrorKind::InvalidLogFile(
+                logs_path.to_string_lossy().to_string(),
+            ))?
+            .path();
+
+        let name = path.to_string_lossy().to_string();
+
+        if !name.ends_with(".log") && !name.ends_with(".log.gz") {
+            continue;
+        }
+
+        let metadata = fs::metadata(&path).context(ErrorKind::InvalidLogFile(name.to_string()))?;
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let modified = metadata
+            .modified()
+            .context(ErrorKind::InvalidLogFile(name.to_string()))?;
+
+        logs.push(LogFile {
+            path,
+            size: metadata.len(),
+            modified: DateTime::from(modified),
+        });
+    }
+
+    Ok(logs)
+}
+
+fn compress(log_path: &Path) -> Result<(), Error> {
+    let name = log_path.to_string_lossy().to_string();
+
+    let compressed_path = format!("{}.gz", name);
+
+    trace!("Compressing log file: {}", name);
+
+    let mut input = File::open(log_path).context(ErrorKind::InvalidLogFile(name.to_string()))?;
+
+    let output = File::create(&compressed_path)
+        .context(ErrorKind::InvalidLogFile(compressed_path.to_string()))?;
+
+    let mut encoder = GzEncoder::new(output, Compression::default());
+
+    io::copy(&mut input, &mut encoder)
+        .and_then(|_| encoder.finish())
+        .context(ErrorKind::InvalidLogFile(compressed_path.to_string()))?;
+
+    fs::remove_file(log_path).context(ErrorKind::InvalidLogFile(name))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn log_file(name: &str, size: u64, day: u32) -> LogFile {
+        LogFile {
+            path: PathBuf::from(name),
+            size,
+            modified: Utc.with_ymd_and_hms(2019, 7, day, 0, 0, 0).unwrap(),
+        }
+    }
+
+    fn names(logs: Vec<LogFile>) -> Vec<String> {
+        logs.iter()
+            .map(|log| log.path.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn expired_by_age() {
+        let logs = vec![
+            log_file("c.log.gz", 10, 3),
+            log_file("a.log.gz", 10, 1),
+            log_file("b.log.gz", 10, 2),
+        ];
+
+        let before = Utc.with_ymd_and_hms(2019, 7, 3, 0, 0, 0).unwrap();
+
+        assert_eq!(
+            names(expired(logs, before, 0)),
+            vec!["a.log.gz", "b.log.gz"]
+        );
+    }
+
+    #[test]
+    fn expired_by_size() {
+        let logs = vec![
+            log_file("a.log.gz", 40, 1),
+            log_file("b.log.gz", 40, 2),
+            log_file("c.log.gz", 40, 3),
+            log_file("d.log.gz", 40, 4),
+        ];
+
+        let before = Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap();
+
+        assert_eq!(
+            names(expired(logs, before, 90)),
+            vec!["a.log.gz", "b.log.gz"]
+        );
+    }
+
+    #[test]
+    fn expired_keeps_most_recent() {
+        let logs = vec![log_file("a.log.gz", 100, 1)];
+
+        let before = Utc.with_ymd_and_hms(2019, 7, 2, 0, 0, 0).unwrap();
+
+        assert!(expired(logs, before, 10).is_empty());
+    }
+}
@@ -1,28 +1,278 @@
-#[macro_use]
-extern crate derive_more;
-
+use chrono::{DateTime, Utc};
 use clap::{load_yaml, value_t, App};
-use env_logger::Env;
-use std::time::Duration;
-
-mod error;
-mod executor;
-mod interval;
-mod pipeline;
-mod scheduler;
-mod state;
+use log::error;
+use rusty_scheduler::error::{Error, ErrorKind};
+use rusty_scheduler::settings::Settings;
+use rusty_scheduler::vault;
+use rusty_scheduler::{commands, console, daemon, digest, executor, fairness, logging, mqtt};
+use rusty_scheduler::{notification, scheduler, secrets, statsd, storage, timezone, upload};
+use std::process;
 
 fn main() {
     let cli_yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(cli_yaml).get_matches();
 
-    let log_level = matches.value_of("log").unwrap();
-    env_logger::from_env(Env::default().default_filter_or(log_level)).init();
+    let settings = Settings::from_matches(&matches);
+
+    let log_level = match &settings {
+        Ok(settings) => settings.log().to_string(),
+        Err(_) => String::from("error"),
+    };
+
+    // Log times use the display timezone too, an invalid one is reported below
+    if let Some(Ok(timezone)) = settings.as_ref().ok().map(Settings::display_timezone) {
+        timezone::set_display(timezone);
+    }
+
+    logging::init(&log_level);
+
+    let settings = settings.unwrap_or_else(|err| exit_with_error(err));
+
+    settings
+        .display_timezone()
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let refresh_interval = settings.refresh();
+
+    // Following a remote scheduler doesn't need a local pipelines directory
+    if let ("logs", Some(logs)) = matches.subcommand() {
+        if let (true, Some(address)) = (logs.is_present("follow"), logs.value_of("url")) {
+            let security = settings
+                .security()
+                .unwrap_or_else(|err| exit_with_error(err));
+
+            commands::follow_logs(address, logs.value_of("pipeline").unwrap(), &security)
+                .unwrap_or_else(|err| exit_with_error(err));
+
+            return;
+        }
+    }
+
+    let pipelines_path = settings
+        .pipelines()
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    console::init(settings.no_color());
+
+    if settings.no_persist() {
+        storage::disable_persistence();
+    }
+
+    if settings.dry_run() {
+        scheduler::enable_dry_run();
+    }
+
+    if let Some(interpreter) = &settings.interpreter {
+        executor::set_interpreter(interpreter).unwrap_or_else(|err| exit_with_error(err));
+    }
 
-    let refresh_interval = value_t!(matches, "refresh", u32).unwrap();
-    let refresh_interval = Duration::from_secs(refresh_interval.into());
+    scheduler::set_tags(&settings.tags());
+
+    fairness::set_max_concurrent_runs(
+        settings
+            .max_concurrent_runs()
+            .unwrap_or_else(|err| exit_with_error(err)),
+    );
+
+    fairness::set_tag_weights(
+        settings
+            .tag_weights()
+            .unwrap_or_else(|err| exit_with_error(err)),
+    );
+
+    if let Some(ssh_command) = &settings.ssh_command {
+        executor::set_ssh_command(ssh_command).unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if let Some(address) = &settings.statsd {
+        statsd::init(address, settings.statsd_prefix()).unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if let Some(dashboard_url) = settings.dashboard_url() {
+        notification::set_dashboard_url(&dashboard_url);
+    }
+
+    // Only the scheduler publishes events and uploads runs, so commands don't need them
+    if let (Some(address), None) = (&settings.mqtt, matches.subcommand_name()) {
+        mqtt::init(address, settings.mqtt_prefix()).unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if let Some(server) = &settings.digest_smtp {
+        settings
+            .digest_to
+            .as_deref()
+            .ok_or_else(|| ErrorKind::MissingSetting(String::from("digest_to")).into())
+            .and_then(|to| digest::init(server, to, settings.digest_schedule()))
+            .unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if let (Some(bucket), None) = (&settings.s3_bucket, matches.subcommand_name()) {
+        upload::init(
+            bucket,
+            settings.s3_endpoint.as_deref(),
+            settings.s3_region(),
+        )
+        .unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    // Interactive runs read secrets too
+    if let Some(address) = &settings.vault_addr {
+        settings
+            .vault_auth()
+            .and_then(|auth| vault::init(address, auth))
+            .unwrap_or_else(|err| exit_with_error(err));
+    }
+
+    if let Some(key_file) = &settings.sops_age_key_file {
+        secrets::set_sops_age_key_file(key_file);
+    }
+
+    let security = settings
+        .security()
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let pidfile_path = daemon::pidfile_path(pipelines_path, settings.pidfile.as_deref());
+
+    let result = match matches.subcommand() {
+        ("init", Some(matches)) => {
+            commands::init(pipelines_path, matches.value_of("pipeline").unwrap())
+        }
+        ("list", Some(matches)) => commands::list(pipelines_path, matches.is_present("json")),
+        ("run", Some(matches)) => commands::run(
+            pipelines_path,
+            matches.value_of("pipeline").unwrap(),
+            &matches
+                .values_of("stage")
+                .map(|values| values.collect::<Vec<_>>())
+                .unwrap_or_default(),
+            &matches
+                .values_of("job")
+                .map(|values| values.collect::<Vec<_>>())
+                .unwrap_or_default(),
+        ),
+        ("next", Some(matches)) => commands::next(
+            pipelines_path,
+            matches.value_of("pipeline"),
+            value_t!(matches, "count", usize).unwrap_or_else(|err| err.exit()),
+        ),
+        ("explain", Some(matches)) => {
+            commands::explain(pipelines_path, matches.value_of("pipeline").unwrap())
+        }
+        ("graph", Some(matches)) => commands::graph(
+            pipelines_path,
+            matches.value_of("pipeline").unwrap(),
+            matches.is_present("dot"),
+        ),
+        ("doctor", Some(_)) => commands::doctor(pipelines_path),
+        ("validate", Some(_)) => commands::validate(pipelines_path),
+        ("status", Some(_)) => commands::status(pipelines_path),
+        ("pause", Some(matches)) => {
+            commands::pause(pipelines_path, matches.value_of("pipeline"), true)
+        }
+        ("resume", Some(matches)) => {
+            commands::pause(pipelines_path, matches.value_of("pipeline"), false)
+        }
+        ("trigger", Some(matches)) => commands::trigger(
+            pipelines_path,
+            matches.value_of("pipeline").unwrap(),
+            matches
+                .values_of("param")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            matches
+                .values_of("skip-stage")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            matches
+                .values_of("skip-job")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            matches
+                .values_of("label")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+        ),
+        ("rerun", Some(matches)) => {
+            commands::rerun(pipelines_path, matches.value_of("run").unwrap())
+        }
+        ("stop", Some(_)) => daemon::stop(&pidfile_path).map(|pid| {
+            println!("Scheduler stopped: {}", pid);
+        }),
+        ("queue", Some(_)) => commands::queue(pipelines_path),
+        ("dequeue", Some(matches)) => commands::dequeue(
+            pipelines_path,
+            matches.value_of("pipeline").unwrap(),
+            matches.value_of("scheduled").map(|_| {
+                value_t!(matches, "scheduled", DateTime<Utc>).unwrap_or_else(|err| err.exit())
+            }),
+        ),
+        ("drain", Some(_)) => commands::drain(pipelines_path),
+        ("export", Some(matches)) => commands::export(
+            pipelines_path,
+            matches.value_of("format").unwrap(),
+            value_t!(matches, "days", i64).unwrap_or_else(|err| err.exit()),
+        ),
+        ("analyze", Some(matches)) => commands::analyze(
+            pipelines_path,
+            value_t!(matches, "days", i64).unwrap_or_else(|err| err.exit()),
+            value_t!(matches, "min-runs", usize).unwrap_or_else(|err| err.exit()),
+        ),
+        ("digest", Some(matches)) => commands::digest(
+            pipelines_path,
+            value_t!(matches, "days", i64).unwrap_or_else(|err| err.exit()),
+            matches.is_present("send"),
+        ),
+        ("import", Some(matches)) => commands::import(
+            pipelines_path,
+            matches.value_of("crontab").unwrap(),
+            matches.is_present("system"),
+        ),
+        ("logs", Some(matches)) if matches.is_present("follow") => match &settings.listen {
+            Some(address) => {
+                commands::follow_logs(address, matches.value_of("pipeline").unwrap(), &security)
+            }
+            None => Err(ErrorKind::MissingSetting(String::from("listen")).into()),
+        },
+        ("logs", Some(matches)) => {
+            commands::logs(pipelines_path, matches.value_of("pipeline").unwrap())
+        }
+        ("cancel", Some(matches)) => {
+            commands::cancel(pipelines_path, matches.value_of("pipeline").unwrap())
+        }
+        ("dump", Some(matches)) => commands::dump(pipelines_path, matches.value_of("file")),
+        ("restore", Some(matches)) => {
+            commands::restore(pipelines_path, matches.value_of("file").unwrap())
+        }
+        _ if settings.daemon() => daemon::daemonize(&pidfile_path).map(|_pidfile| {
+            scheduler::run(
+                pipelines_path,
+                refresh_interval,
+                settings.listen.as_deref(),
+                settings.grpc_listen.as_deref(),
+                &security,
+            );
+        }),
+        _ => {
+            scheduler::run(
+                pipelines_path,
+                refresh_interval,
+                settings.listen.as_deref(),
+                settings.grpc_listen.as_deref(),
+                &security,
+            );
+
+            Ok(())
+        }
+    };
+
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+}
 
-    let pipelines_path = matches.value_of("pipelines").unwrap();
+// Exit codes tell config errors, partial and pipeline failures apart
+fn exit_with_error(err: Error) -> ! {
+    error!("{} [{}]", err, err.code());
 
-    scheduler::run(pipelines_path, refresh_interval);
+    process::exit(err.exit_code());
 }
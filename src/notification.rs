@@ -0,0 +1,839 @@
+use super::error::{Error, ErrorKind};
+use super::history::{Run, RunStatus};
+use super::pipeline::Pipeline;
+use super::runtime::{ActiveRun, Output};
+use super::sla::Sla;
+use super::stuck::StuckAfter;
+use chrono::Utc;
+use handlebars::Handlebars;
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::ffi::CStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const TELEGRAM_URL: &str = "https://api.telegram.org";
+
+const PAGERDUTY_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+// Longest summary accepted by the PagerDuty Events API
+const PAGERDUTY_SUMMARY_LENGTH: usize = 1024;
+
+// Output lines sent as the body of the ping at the end of a run
+pub const PING_OUTPUT_LINES: usize = 100;
+
+// Output lines available to notification templates
+pub const NOTIFICATION_OUTPUT_LINES: usize = 20;
+
+// Public address of the dashboard, linked from notifications
+static DASHBOARD_URL: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Sink {
+    Discord { webhook_url: String },
+    Telegram { bot_token: String, chat_id: String },
+    PagerDuty { routing_key: String },
+}
+
+// Incidents are only opened for critical pipelines, and resolved on recovery
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alert {
+    Trigger,
+    Resolve,
+}
+
+// Runs that send notifications
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyOn {
+    // Failed runs, once notify_after_failures is reached
+    Failure,
+
+    // First successful run after notified failures
+    Recovery,
+
+    Success,
+
+    // Every run, including cancelled ones
+    Always,
+}
+
+// Dead man's switch pings around each run, see https://healthchecks.io/docs/http_api
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ping {
+    Start,
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub pipeline: String,
+
+    pub run_id: String,
+
+    pub status: RunStatus,
+
+    // Seconds
+    pub duration: f64,
+
+    // Failures in a row before and after the run
+    pub previous_failures: u32,
+
+    pub consecutive_failures: u32,
+
+    pub failed_jobs: Vec<String>,
+
+    // Last output lines of the run
+    pub output: Vec<String>,
+
+    pub dashboard_url: Option<String>,
+
+    // Missed SLA, the notification is about the deadline instead of a run
+    pub sla: Option<Sla>,
+
+    // Threshold a run that is still going exceeded, it is possibly stuck
+    pub stuck: Option<StuckAfter>,
+
+    // The run reached disable_after_failures, the pipeline is paused
+    pub paused: bool,
+}
+
+impl Notification {
+    // Failures in a row are set once the state is updated
+    pub fn from_run(pipeline: &Pipeline, run: &Run, output: &Output) -> Notification {
+        let (lines, _) = output.lines_since(0);
+
+        let dashboard_url = DASHBOARD_URL
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|url| format!("{}/#{}", url.trim_end_matches('/'), pipeline.id));
+
+        Notification {
+            pipeline: pipeline.id.to_string(),
+            run_id: run.id.to_string(),
+            status: run.status,
+            duration: run.duration(),
+            previous_failures: 0,
+            consecutive_failures: 0,
+            failed_jobs: output.failed_jobs(),
+            output: lines[lines.len().saturating_sub(NOTIFICATION_OUTPUT_LINES)..].to_vec(),
+            dashboard_url,
+            sla: None,
+            stuck: None,
+            paused: false,
+        }
+    }
+
+    // Missed SLA of a pipeline, with the run still going if any
+    pub fn from_sla(
+        pipeline: &Pipeline,
+        sla: Sla,
+        run: Option<&ActiveRun>,
+        consecutive_failures: u32,
+    ) -> Notification {
+        let now = Utc::now();
+
+        let notification = match run {
+            Some(active) => {
+                let run = Run {
+                    id: active.run_id.to_string(),
+                    ..Run::new(RunStatus::Failed, active.start, now)
+                };
+
+                Notification::from_run(pipeline, &run, &active.output)
+            }
+            None => {
+                let run = Run::new(RunStatus::Failed, now, now);
+
+                Notification::from_run(pipeline, &run, &Output::default())
+            }
+        };
+
+        Notification {
+            previous_failures: consecutive_failures,
+            consecutive_failures,
+            sla: Some(sla),
+            ..notification
+        }
+    }
+
+    // Warning about a run going longer than its stuck_after threshold
+    pub fn from_stuck(
+        pipeline: &Pipeline,
+        stuck_after: StuckAfter,
+        active: &ActiveRun,
+        consecutive_failures: u32,
+    ) -> Notification {
+        let run = Run {
+            id: active.run_id.to_string(),
+            ..Run::new(RunStatus::Failed, active.start, Utc::now())
+        };
+
+        Notification {
+            previous_failures: consecutive_failures,
+            consecutive_failures,
+            stuck: Some(stuck_after),
+            ..Notification::from_run(pipeline, &run, &active.output)
+        }
+    }
+
+    // The default message is used when a template fails to render
+    // Templates are written for runs, so missed SLAs and stuck runs always
+    // use the default
+    pub fn message(&self, template: Option<&str>) -> String {
+        let template = match template {
+            Some(template) if self.sla.is_none() && self.stuck.is_none() => template,
+            _ => return self.default_message(),
+        };
+
+        match self.render(template) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("{}", err);
+
+                self.default_message()
+            }
+        }
+    }
+
+    pub fn default_message(&self) -> String {
+        if let Some(stuck_after) = &self.stuck {
+            return format!(
+                "Pipeline {} possibly stuck ({}), still running after {:.1}s (run {})",
+                self.pipeline, stuck_after, self.duration, self.run_id
+            );
+        }
+
+        if let Some(sla) = &self.sla {
+            let message = format!("Pipeline {} missed its SLA ({})", self.pipeline, sla);
+
+            return if self.run_id.is_empty() {
+                format!("{}, no run in progress", message)
+            } else {
+                format!(
+                    "{}, still running after {:.1}s (run {})",
+                    message, self.duration, self.run_id
+                )
+            };
+        }
+
+        if self.is_recovery() {
+            return format!(
+                "Pipeline {} recovered after {} failure(s), succeeded after {:.1}s (run {})",
+                self.pipeline, self.previous_failures, self.duration, self.run_id
+            );
+        }
+
+        let message = format!(
+            "Pipeline {} {} after {:.1}s (run {})",
+            self.pipeline, self.status, self.duration, self.run_id
+        );
+
+        let message = if self.status == RunStatus::Failed && self.consecutive_failures > 1 {
+            format!(
+                "{}, {} failures in a row",
+                message, self.consecutive_failures
+            )
+        } else {
+            message
+        };
+
+        if self.paused {
+            format!("{}, pipeline paused", message)
+        } else {
+            message
+        }
+    }
+
+    pub fn is_recovery(&self) -> bool {
+        self.status == RunStatus::Succeeded && self.previous_failures > 0
+    }
+
+    // Incidents follow the failure threshold, whatever the notify_on rules.
+    // A stuck run is only a warning, it may still succeed
+    pub fn alert(&self, notify_after_failures: u32) -> Option<Alert> {
+        let threshold = notify_after_failures.max(1);
+
+        if self.stuck.is_some() {
+            return None;
+        }
+
+        let failed = self.status == RunStatus::Failed && self.consecutive_failures >= threshold;
+
+        if self.sla.is_some() || failed {
+            Some(Alert::Trigger)
+        } else if self.is_recovery() && self.previous_failures >= threshold {
+            Some(Alert::Resolve)
+        } else {
+            None
+        }
+    }
+
+    // Whether any notify_on rule of the pipeline matches the run, missed SLAs,
+    // stuck runs and runs pausing the pipeline are always notified
+    pub fn matches(&self, notify_on: &[NotifyOn], notify_after_failures: u32) -> bool {
+        let threshold = notify_after_failures.max(1);
+
+        if self.sla.is_some() || self.stuck.is_some() || self.paused {
+            return true;
+        }
+
+        notify_on.iter().any(|rule| match rule {
+            NotifyOn::Failure => {
+                self.status == RunStatus::Failed && self.consecutive_failures >= threshold
+            }
+            NotifyOn::Recovery => self.is_recovery() && self.previous_failures >= threshold,
+            NotifyOn::Success => self.status == RunStatus::Succeeded,
+            NotifyOn::Always => true,
+        })
+    }
+
+    pub fn render(&self, template: &str) -> Result<String, Error> {
+        let mut handlebars = Handlebars::new();
+
+        // Messages are plain text, not HTML
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        // Parse errors only keep the reason and position, the full error spans several lines
+        handlebars
+            .register_template_string("message", template)
+            .map_err(|err| {
+                let reason = match err.pos() {
+                    Some((line, column)) => {
+                        format!("{} (line {}, column {})", err.reason(), line, column)
+                    }
+                    None => err.reason().to_string(),
+                };
+
+                ErrorKind::InvalidNotificationTemplate(self.pipeline.to_string(), reason)
+            })?;
+
+        let message = handlebars
+            .render("message", &self.fields())
+            .map_err(|err| {
+                ErrorKind::InvalidNotificationTemplate(self.pipeline.to_string(), err.to_string())
+            })?;
+
+        Ok(message)
+    }
+
+    // Values available to templates
+    pub fn fields(&self) -> Value {
+        json!({
+            "pipeline": self.pipeline,
+            "run_id": self.run_id,
+            "status": self.status.to_string(),
+            "duration": format!("{:.1}", self.duration),
+            "consecutive_failures": self.consecutive_failures,
+            "recovered": self.is_recovery(),
+            "paused": self.paused,
+            "failed_job": self.failed_jobs.first(),
+            "failed_jobs": self.failed_jobs,
+            "output": self.output.join("\n"),
+            "dashboard_url": self.dashboard_url,
+        })
+    }
+}
+
+pub fn set_dashboard_url(url: &str) {
+    *DASHBOARD_URL.lock().unwrap() = Some(url.to_string());
+}
+
+impl Sink {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sink::Discord { .. } => "discord",
+            Sink::Telegram { .. } => "telegram",
+            Sink::PagerDuty { .. } => "pagerduty",
+        }
+    }
+
+    pub fn is_alert(&self) -> bool {
+        matches!(self, Sink::PagerDuty { .. })
+    }
+
+    // URL and JSON body of the sink request
+    pub fn request(
+        &self,
+        message: &str,
+        notification: &Notification,
+        alert: Alert,
+    ) -> (String, Value) {
+        match self {
+            Sink::Discord { webhook_url } => {
+                (webhook_url.to_string(), json!({ "content": message }))
+            }
+            Sink::Telegram { bot_token, chat_id } => (
+                format!("{}/bot{}/sendMessage", TELEGRAM_URL, bot_token),
+                json!({ "chat_id": chat_id, "text": message }),
+            ),
+            Sink::PagerDuty { routing_key } => (
+                PAGERDUTY_URL.to_string(),
+                pagerduty_event(routing_key, message, notification, alert),
+            ),
+        }
+    }
+
+    pub fn send(
+        &self,
+        message: &str,
+        notification: &Notification,
+        alert: Alert,
+    ) -> Result<(), Error> {
+        let (url, body) = self.request(message, notification, alert);
+
+        ureq::post(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send_json(body)
+            .map_err(|err| ErrorKind::NotificationFailed(self.name().to_string(), describe(err)))?;
+
+        Ok(())
+    }
+}
+
+impl Ping {
+    pub fn from_status(status: RunStatus) -> Ping {
+        match status {
+            RunStatus::Succeeded | RunStatus::Skipped => Ping::Success,
+            RunStatus::Failed | RunStatus::Cancelled => Ping::Failure,
+        }
+    }
+
+    pub fn url(self, ping_url: &str) -> String {
+        let ping_url = ping_url.trim_end_matches('/');
+
+        match self {
+            Ping::Start => format!("{}/start", ping_url),
+            Ping::Success => ping_url.to_string(),
+            Ping::Failure => format!("{}/fail", ping_url),
+        }
+    }
+
+    pub fn send(self, ping_url: &str, body: &str) {
+        trace!("Sending ping ({:?}): {}", self, ping_url);
+
+        let result = ureq::post(&self.url(ping_url))
+            .timeout(REQUEST_TIMEOUT)
+            .send_string(body);
+
+        if let Err(err) = result {
+            warn!(
+                "{}",
+                ErrorKind::NotificationFailed(String::from("ping"), describe(err))
+            );
+        }
+    }
+}
+
+// Request URLs may contain tokens, so they are left out of the error
+fn describe(err: ureq::Error) -> String {
+    match err {
+        ureq::Error::Status(code, _) => format!("status code {}", code),
+        ureq::Error::Transport(transport) => match transport.message() {
+            Some(message) => format!("{}: {}", transport.kind(), message),
+            None => transport.kind().to_string(),
+        },
+    }
+}
+
+// See https://developer.pagerduty.com/docs/events-api-v2/trigger-events
+fn pagerduty_event(
+    routing_key: &str,
+    message: &str,
+    notification: &Notification,
+    alert: Alert,
+) -> Value {
+    // One incident per pipeline, so a recovery resolves it. Missed SLAs have
+    // their own incident, a late success doesn't make the deadline
+    let dedup_key = match notification.sla {
+        Some(_) => format!("rusty-scheduler/{}/sla", notification.pipeline),
+        None => format!("rusty-scheduler/{}", notification.pipeline),
+    };
+
+    if alert == Alert::Resolve {
+        return json!({
+            "routing_key": routing_key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key,
+        });
+    }
+
+    let mut event = json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "dedup_key": dedup_key,
+        "payload": {
+            "summary": message.chars().take(PAGERDUTY_SUMMARY_LENGTH).collect::<String>(),
+            "source": hostname(),
+            "severity": "critical",
+            "component": notification.pipeline,
+            "custom_details": {
+                "run_id": notification.run_id,
+                "consecutive_failures": notification.consecutive_failures,
+                "failed_jobs": notification.failed_jobs,
+                "output": notification.output.join("\n"),
+            },
+        },
+    });
+
+    if let Some(dashboard_url) = &notification.dashboard_url {
+        event["links"] = json!([{ "href": dashboard_url, "text": "Dashboard" }]);
+    }
+
+    event
+}
+
+fn hostname() -> String {
+    let mut buffer = vec![0 as libc::c_char; 256];
+
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) };
+
+    if result != 0 {
+        return String::from("rusty-scheduler");
+    }
+
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn send(pipeline: &Pipeline, notification: &Notification) {
+    let notify = notification.matches(&pipeline.notify_on, pipeline.notify_after_failures);
+
+    let alert = if pipeline.critical {
+        notification.alert(pipeline.notify_after_failures)
+    } else {
+        None
+    };
+
+    let message = notification.message(pipeline.notification_template.as_deref());
+
+    for sink in &pipeline.notifications {
+        // Chat sinks follow the notify_on rules, alert sinks the incidents
+        let alert = match (sink.is_alert(), alert) {
+            (true, Some(alert)) => alert,
+            (false, _) if notify => Alert::Trigger,
+            _ => continue,
+        };
+
+        trace!("Sending notification ({}): {}", sink.name(), pipeline.id);
+
+        if let Err(err) = sink.send(&message, notification, alert) {
+            warn!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            pipeline: String::from("loader"),
+            run_id: String::from("loader-20190701T000000000"),
+            status: RunStatus::Failed,
+            duration: 12.34,
+            previous_failures: 0,
+            consecutive_failures: 1,
+            failed_jobs: vec![String::from("loader/extract/download")],
+            output: vec![
+                String::from("[loader/extract/download] Downloading"),
+                String::from("[loader/extract/download] Connection refused"),
+            ],
+            dashboard_url: Some(String::from("https://scheduler.example.com/#loader")),
+            sla: None,
+            stuck: None,
+            paused: false,
+        }
+    }
+
+    #[test]
+    fn message() {
+        assert_eq!(
+            notification().message(None),
+            "Pipeline loader failed after 12.3s (run loader-20190701T000000000)"
+        );
+    }
+
+    #[test]
+    fn template_message() {
+        let template = "**{{pipeline}}** {{status}} in {{failed_job}} after {{duration}}s\n```\n{{output}}\n```\n<{{dashboard_url}}>";
+
+        assert_eq!(
+            notification().message(Some(template)),
+            "**loader** failed in loader/extract/download after 12.3s\n```\n[loader/extract/download] Downloading\n[loader/extract/download] Connection refused\n```\n<https://scheduler.example.com/#loader>"
+        );
+
+        assert_eq!(
+            notification()
+                .render("{{#each failed_jobs}}{{this}} {{/each}}{{#unless missing}}-{{/unless}}")
+                .unwrap(),
+            "loader/extract/download -"
+        );
+    }
+
+    #[test]
+    fn invalid_template_message() {
+        assert!(notification().render("{{#if status}}").is_err());
+
+        assert_eq!(
+            notification().message(Some("{{#if status}}")),
+            notification().message(None)
+        );
+    }
+
+    #[test]
+    fn failure_and_recovery_messages() {
+        let failure = Notification {
+            previous_failures: 2,
+            consecutive_failures: 3,
+            ..notification()
+        };
+
+        assert_eq!(
+            failure.message(None),
+            "Pipeline loader failed after 12.3s (run loader-20190701T000000000), 3 failures in a row"
+        );
+
+        let recovery = Notification {
+            status: RunStatus::Succeeded,
+            previous_failures: 3,
+            consecutive_failures: 0,
+            ..notification()
+        };
+
+        assert_eq!(
+            recovery.message(None),
+            "Pipeline loader recovered after 3 failure(s), succeeded after 12.3s (run loader-20190701T000000000)"
+        );
+    }
+
+    #[test]
+    fn sla_messages() {
+        let sla = "by 06:00".parse().unwrap();
+
+        let running = Notification {
+            sla: Some(sla),
+            ..notification()
+        };
+
+        assert_eq!(
+            running.message(Some("{{pipeline}} {{status}}")),
+            "Pipeline loader missed its SLA (by 06:00), still running after 12.3s (run loader-20190701T000000000)"
+        );
+        assert!(running.matches(&[], 1));
+        assert_eq!(running.alert(1), Some(Alert::Trigger));
+
+        let idle = Notification {
+            run_id: String::new(),
+            ..running
+        };
+
+        assert_eq!(
+            idle.message(None),
+            "Pipeline loader missed its SLA (by 06:00), no run in progress"
+        );
+
+        let pagerduty = Sink::PagerDuty {
+            routing_key: String::from("R0UT1NG"),
+        };
+
+        let (_, trigger) = pagerduty.request(&idle.message(None), &idle, Alert::Trigger);
+
+        assert_eq!(trigger["dedup_key"], "rusty-scheduler/loader/sla");
+    }
+
+    #[test]
+    fn stuck_messages() {
+        let stuck = Notification {
+            stuck: Some(StuckAfter::Factor(3.0)),
+            consecutive_failures: 4,
+            ..notification()
+        };
+
+        assert_eq!(
+            stuck.message(Some("{{pipeline}} {{status}}")),
+            "Pipeline loader possibly stuck (3x), still running after 12.3s (run loader-20190701T000000000)"
+        );
+        assert!(stuck.matches(&[], 1));
+        assert_eq!(stuck.alert(1), None);
+    }
+
+    #[test]
+    fn paused_messages() {
+        let paused = Notification {
+            consecutive_failures: 3,
+            paused: true,
+            ..notification()
+        };
+
+        assert_eq!(
+            paused.message(None),
+            "Pipeline loader failed after 12.3s (run loader-20190701T000000000), 3 failures in a row, pipeline paused"
+        );
+        assert_eq!(
+            paused.message(Some("{{pipeline}} paused: {{paused}}")),
+            "loader paused: true"
+        );
+        assert!(paused.matches(&[], 5));
+    }
+
+    #[test]
+    fn notify_on_rules() {
+        let run = |status, previous_failures, consecutive_failures| Notification {
+            status,
+            previous_failures,
+            consecutive_failures,
+            ..notification()
+        };
+
+        let failure = [NotifyOn::Failure];
+        let recovery = [NotifyOn::Failure, NotifyOn::Recovery];
+
+        assert!(run(RunStatus::Failed, 0, 1).matches(&failure, 1));
+        assert!(!run(RunStatus::Succeeded, 1, 0).matches(&failure, 1));
+        assert!(!run(RunStatus::Cancelled, 0, 0).matches(&failure, 1));
+
+        // Escalation after 3 failures in a row
+        assert!(!run(RunStatus::Failed, 1, 2).matches(&failure, 3));
+        assert!(run(RunStatus::Failed, 2, 3).matches(&failure, 3));
+        assert!(run(RunStatus::Failed, 3, 4).matches(&failure, 3));
+
+        // Recovery only after failures that were notified
+        assert!(run(RunStatus::Succeeded, 3, 0).matches(&recovery, 3));
+        assert!(!run(RunStatus::Succeeded, 2, 0).matches(&recovery, 3));
+        assert!(!run(RunStatus::Succeeded, 0, 0).matches(&recovery, 1));
+
+        assert!(run(RunStatus::Succeeded, 0, 0).matches(&[NotifyOn::Success], 1));
+        assert!(run(RunStatus::Cancelled, 0, 0).matches(&[NotifyOn::Always], 1));
+        assert!(!run(RunStatus::Failed, 0, 1).matches(&[], 1));
+    }
+
+    #[test]
+    fn alerts() {
+        let run = |status, previous_failures, consecutive_failures| Notification {
+            status,
+            previous_failures,
+            consecutive_failures,
+            ..notification()
+        };
+
+        assert_eq!(run(RunStatus::Failed, 0, 1).alert(1), Some(Alert::Trigger));
+        assert_eq!(run(RunStatus::Failed, 0, 1).alert(2), None);
+        assert_eq!(
+            run(RunStatus::Succeeded, 2, 0).alert(2),
+            Some(Alert::Resolve)
+        );
+        assert_eq!(run(RunStatus::Succeeded, 1, 0).alert(2), None);
+        assert_eq!(run(RunStatus::Cancelled, 1, 1).alert(1), None);
+    }
+
+    #[test]
+    fn pagerduty_requests() {
+        let pagerduty = Sink::PagerDuty {
+            routing_key: String::from("R0UT1NG"),
+        };
+
+        let (url, trigger) =
+            pagerduty.request("Pipeline loader failed", &notification(), Alert::Trigger);
+
+        assert_eq!(url, "https://events.pagerduty.com/v2/enqueue");
+        assert_eq!(trigger["event_action"], "trigger");
+        assert_eq!(trigger["dedup_key"], "rusty-scheduler/loader");
+        assert_eq!(trigger["payload"]["summary"], "Pipeline loader failed");
+        assert_eq!(trigger["payload"]["severity"], "critical");
+        assert_eq!(
+            trigger["payload"]["custom_details"]["failed_jobs"],
+            json!(["loader/extract/download"])
+        );
+        assert_eq!(
+            trigger["links"][0]["href"],
+            "https://scheduler.example.com/#loader"
+        );
+
+        let (_, resolve) =
+            pagerduty.request("Pipeline loader recovered", &notification(), Alert::Resolve);
+
+        assert_eq!(
+            resolve,
+            json!({
+                "routing_key": "R0UT1NG",
+                "event_action": "resolve",
+                "dedup_key": "rusty-scheduler/loader",
+            })
+        );
+    }
+
+    #[test]
+    fn ping_urls() {
+        let ping_url = "https://hc-ping.com/5d1a0a2c";
+
+        assert_eq!(
+            Ping::Start.url(ping_url),
+            "https://hc-ping.com/5d1a0a2c/start"
+        );
+        assert_eq!(Ping::Success.url(ping_url), "https://hc-ping.com/5d1a0a2c");
+        assert_eq!(
+            Ping::Failure.url("https://hc-ping.com/5d1a0a2c/"),
+            "https://hc-ping.com/5d1a0a2c/fail"
+        );
+        assert_eq!(Ping::from_status(RunStatus::Cancelled), Ping::Failure);
+    }
+
+    #[test]
+    fn parse_sinks() {
+        let sinks: Vec<Sink> = serde_json::from_str(
+            r#"[
+                {"type": "discord", "webhook_url": "https://discord.com/api/webhooks/1/abc"},
+                {"type": "telegram", "bot_token": "123:abc", "chat_id": "-100"},
+                {"type": "pagerduty", "routing_key": "R0UT1NG"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(sinks[0].name(), "discord");
+        assert!(sinks[2].is_alert());
+        assert_eq!(
+            sinks[1],
+            Sink::Telegram {
+                bot_token: String::from("123:abc"),
+                chat_id: String::from("-100"),
+            }
+        );
+    }
+
+    #[test]
+    fn sink_requests() {
+        let discord = Sink::Discord {
+            webhook_url: String::from("https://discord.com/api/webhooks/1/abc"),
+        };
+
+        assert_eq!(
+            discord.request("hello", &notification(), Alert::Trigger),
+            (
+                String::from("https://discord.com/api/webhooks/1/abc"),
+                json!({ "content": "hello" })
+            )
+        );
+
+        let telegram = Sink::Telegram {
+            bot_token: String::from("123:abc"),
+            chat_id: String::from("-100"),
+        };
+
+        assert_eq!(
+            telegram.request("hello", &notification(), Alert::Trigger),
+            (
+                String::from("https://api.telegram.org/bot123:abc/sendMessage"),
+                json!({ "chat_id": "-100", "text": "hello" })
+            )
+        );
+    }
+}
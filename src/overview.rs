@@ -0,0 +1,64 @@
+use super::history::{History, RunStatus};
+use super::pipeline::Pipeline;
+use super::state::State;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Overview {
+    pub id: String,
+
+    pub expression: String,
+
+    pub schedule: String,
+
+    pub enabled: bool,
+
+    pub paused: bool,
+
+    pub last_run: Option<DateTime<Utc>>,
+
+    pub last_result: Option<RunStatus>,
+
+    pub next_run: DateTime<Utc>,
+}
+
+impl Overview {
+    pub fn status(&self) -> &'static str {
+        if !self.enabled {
+            "disabled"
+        } else if self.paused {
+            "paused"
+        } else {
+            "enabled"
+        }
+    }
+
+    pub fn from_pipeline(pipeline: &Pipeline) -> Overview {
+        let (timestamp, paused) = match State::read_file(&State::path_from_pipeline(pipeline)) {
+            Ok(state) => (state.timestamp, state.paused),
+            Err(_) => (State::first_timestamp(pipeline.first_run), false),
+        };
+
+        let history = History::read_file(&History::path_from_pipeline(pipeline)).ok();
+
+        let last = history.as_ref().and_then(|history| {
+            history
+                .runs
+                .iter()
+                .rev()
+                .find(|run| run.status != RunStatus::Skipped)
+        });
+
+        Overview {
+            id: pipeline.id.to_string(),
+            expression: pipeline.expression.to_string(),
+            schedule: pipeline.interval.describe(),
+            enabled: pipeline.enabled,
+            paused,
+            last_run: last.map(|run| run.start),
+            last_result: last.map(|run| run.status),
+            next_run: pipeline.interval.next_time(timestamp),
+        }
+    }
+}
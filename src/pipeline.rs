@@ -1,11 +1,31 @@
 use super::error::{Error, ErrorKind};
-use super::interval::Interval;
+use super::executor;
+use super::interval::{Compat, Interval};
+use super::notification::{NotifyOn, Sink};
+use super::secrets::Secret;
+use super::services::Service;
+use super::sla::Sla;
+use super::stuck::StuckAfter;
+use super::timezone::Timezone;
+use super::window::{OutsideWindow, Window};
 use failure::ResultExt;
+use glob::Pattern;
+use log::{warn, LevelFilter};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
+use std::thread;
 
-#[derive(Debug, Deserialize, Serialize)]
+// Fewer files are parsed faster than threads are started
+const MIN_FILES_PER_THREAD: usize = 32;
+
+// Upper bound of the retention settings, about a hundred years
+pub const MAX_RETENTION_DAYS: i64 = 36500;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Pipeline {
     #[serde(default)]
     pub id: String,
@@ -13,14 +33,168 @@ pub struct Pipeline {
     #[serde(default)]
     pub path: String,
 
+    #[serde(skip_deserializing)]
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub checksum: String,
+
     #[serde(default)]
     pub expression: String,
 
+    // Wall clock of the expression, UTC when not set
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Timezone>,
+
+    // Reads the expression like Vixie cron instead
+    #[serde(default)]
+    pub compat: Compat,
+
     #[serde(skip_deserializing)]
     #[serde(skip_serializing)]
     #[serde(default)]
     pub interval: Interval,
 
+    #[serde(default = "Pipeline::default_enabled")]
+    pub enabled: bool,
+
+    // Due runs are recorded but not started, to shadow another scheduler
+    #[serde(default)]
+    pub dry_run: bool,
+
+    // Runs again when its scripts change, while developing it
+    #[serde(default)]
+    pub watch: bool,
+
+    // Runs once when the scheduler starts, on top of its schedule
+    #[serde(default)]
+    pub run_on_startup: bool,
+
+    #[serde(default)]
+    pub first_run: FirstRun,
+
+    #[serde(default)]
+    pub concurrency: Concurrency,
+
+    // Pending occurrences of a queue pipeline, the oldest ones are dropped
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_queue_depth: Option<u32>,
+
+    // Share of the runs under --max-concurrent-runs
+    #[serde(default = "Pipeline::default_weight")]
+    pub weight: u32,
+
+    // Shares the weight of the tag set with --tag-weights with other pipelines
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fair_share_tag: Option<String>,
+
+    // Command running the scripts, like "bash -euo pipefail", instead of the
+    // global interpreter
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<String>,
+
+    #[serde(default)]
+    pub reset_on_change: bool,
+
+    #[serde(default = "Pipeline::default_history_days")]
+    pub history_days: i64,
+
+    #[serde(default = "Pipeline::default_log_days")]
+    pub log_days: i64,
+
+    // Megabytes, zero keeps logs of any size
+    #[serde(default = "Pipeline::default_log_max_size")]
+    pub log_max_size: u64,
+
+    #[serde(default = "Pipeline::default_compress_logs")]
+    pub compress_logs: bool,
+
+    // Level of the scheduler logs about this pipeline, instead of the log setting
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+
+    #[serde(default)]
+    pub encrypt_state: bool,
+
+    #[serde(default)]
+    pub notifications: Vec<Sink>,
+
+    // Failures open incidents in the alerting sinks
+    #[serde(default)]
+    pub critical: bool,
+
+    // Handlebars template of the notification message
+    #[serde(default)]
+    pub notification_template: Option<String>,
+
+    #[serde(default = "Pipeline::default_notify_on")]
+    pub notify_on: Vec<NotifyOn>,
+
+    // Failures in a row before failures are notified
+    #[serde(default = "Pipeline::default_notify_after_failures")]
+    pub notify_after_failures: u32,
+
+    // Failures in a row after which the pipeline is paused
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_after_failures: Option<u32>,
+
+    // Daily deadline of a successful run, notified when missed
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sla: Option<Sla>,
+
+    // Seconds after a triggered run starts during which other triggers are
+    // collapsed into it
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedup_window: Option<u64>,
+
+    // Tags a scheduler sharing the pipelines folder needs to run the pipeline,
+    // like "gpu", the others leave its runs to it
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub runs_on: Vec<String>,
+
+    // Runs going longer than this are notified once as possibly stuck
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stuck_after: Option<StuckAfter>,
+
+    // Services checked before a run starts, the run waits while they are down
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub depends_on_services: Vec<Service>,
+
+    // Seconds a run waits for its services before failing
+    #[serde(default = "Pipeline::default_services_timeout")]
+    pub services_timeout: u64,
+
+    #[serde(default)]
+    pub ping_url: Option<String>,
+
+    // Files or folders relative to the pipeline folder, uploaded after each run
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+
+    // Glob of input files relative to the pipeline folder, runs are skipped
+    // while they are the same as in the last successful run
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub only_if_changed: Option<String>,
+
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+
+    // Environment variables fetched from a secret provider when a run starts
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, Secret>,
+
     #[serde(default)]
     pub stages: Vec<String>,
 
@@ -28,7 +202,34 @@ pub struct Pipeline {
     pub jobs: Vec<Job>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirstRun {
+    // Run as soon as the pipeline is discovered
+    #[default]
+    Immediately,
+    // Wait for the next occurrence after the pipeline is discovered
+    Next,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    // Last line of the standard output, or all of it, stored in the history
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Concurrency {
+    // Occurrences due during a run are skipped, except the latest one
+    #[default]
+    Skip,
+    // Every occurrence runs, one after the other
+    Queue,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Job {
     #[serde(default)]
     pub id: String,
@@ -44,13 +245,150 @@ pub struct Job {
     #[serde(default)]
     pub script: String,
 
+    // Arguments of the script, may reference outputs of earlier jobs
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+
+    // Environment variables of the job, on top of the pipeline params
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    // Host variables the script inherits, like "AWS_*", all of them when not set
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pass_env: Option<Vec<String>>,
+
+    // Files relative to the pipeline folder written and read by the job. A job
+    // starts after the jobs of its stage producing the files it consumes
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub produces: Vec<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub consumes: Vec<String>,
+
+    // SSH hosts the script runs on in parallel, like "deploy@web1", the job
+    // passes when it passes on all of them
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+
+    // Daily period the job may start in, like "02:00-05:00"
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_window: Option<Window>,
+
+    #[serde(default)]
+    pub outside_window: OutsideWindow,
+
+    // Result printed by the script on its standard output
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+
+    // Registered function run instead of the script, for embedded schedulers
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+
+    // Job type added by a plugin, run instead of the script
+    #[serde(default)]
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+
+    // Other fields, the config of the job type
+    #[serde(flatten)]
+    pub config: Map<String, Value>,
+
     #[serde(default)]
     pub path: String,
+
+    #[serde(skip_deserializing)]
+    #[serde(skip_serializing)]
+    #[serde(default)]
+    pub folder: String,
 }
 
 impl Pipeline {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_history_days() -> i64 {
+        7
+    }
+
+    fn default_log_days() -> i64 {
+        30
+    }
+
+    fn default_log_max_size() -> u64 {
+        100
+    }
+
+    fn default_compress_logs() -> bool {
+        true
+    }
+
+    fn default_notify_on() -> Vec<NotifyOn> {
+        vec![NotifyOn::Failure]
+    }
+
+    fn default_notify_after_failures() -> u32 {
+        1
+    }
+
+    fn default_weight() -> u32 {
+        1
+    }
+
+    fn default_services_timeout() -> u64 {
+        600
+    }
+
     pub fn read_dir(pipelines_path: &str) -> Result<Vec<Result<Pipeline, Error>>, Error> {
-        let mut pipelines = Vec::new();
+        let files = Pipeline::find_files(pipelines_path)?;
+
+        Ok(Pipeline::read_files(&files))
+    }
+
+    // Files are split between threads, parsing thousands of pipelines is CPU bound
+    pub fn read_files(files: &[String]) -> Vec<Result<Pipeline, Error>> {
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+
+        let chunk_size = files.len().div_ceil(threads).max(MIN_FILES_PER_THREAD);
+
+        if files.len() <= chunk_size {
+            return files.iter().map(|file| Pipeline::read_file(file)).collect();
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = files
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|file| Pipeline::read_file(file))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    // The pipeline.json file of each sub-directory
+    pub fn find_files(pipelines_path: &str) -> Result<Vec<String>, Error> {
+        let mut files = Vec::new();
 
         let dirs = fs::read_dir(pipelines_path)
             .context(ErrorKind::InvalidPipelineFolder(pipelines_path.to_string()))?;
@@ -64,16 +402,12 @@ impl Pipeline {
                 entry.push("pipeline.json");
 
                 if entry.is_file() {
-                    let entry = entry.to_string_lossy().to_string();
-
-                    let pipeline = Pipeline::read_file(&entry);
-
-                    pipelines.push(pipeline);
+                    files.push(entry.to_string_lossy().to_string());
                 }
             }
         }
 
-        Ok(pipelines)
+        Ok(files)
     }
 
     pub fn read_file(pipeline_path: &str) -> Result<Pipeline, Error> {
@@ -84,19 +418,349 @@ impl Pipeline {
             .context(ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
 
         pipeline.path = pipeline_path.to_string();
+        pipeline.checksum = format!("{:x}", Sha256::digest(pipeline_data.as_bytes()));
 
-        pipeline.interval = Interval::new(&pipeline.expression)
+        pipeline.interval = Interval::with_compat(&pipeline.expression, pipeline.compat)
             .map_err(|_| ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+        pipeline.interval.timezone = pipeline.timezone.unwrap_or_default();
+
+        if let Some(glob) = &pipeline.only_if_changed {
+            Pattern::new(glob)
+                .map_err(|_| ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+        }
+
+        if pipeline.max_queue_depth == Some(0)
+            || pipeline.disable_after_failures == Some(0)
+            || pipeline.weight == 0
+            || !(0..=MAX_RETENTION_DAYS).contains(&pipeline.history_days)
+            || !(0..=MAX_RETENTION_DAYS).contains(&pipeline.log_days)
+        {
+            Err(ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+        }
+
+        for service in &pipeline.depends_on_services {
+            service
+                .validate()
+                .map_err(|_| ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+        }
+
+        for stage in &pipeline.stages {
+            pipeline
+                .job_groups(stage)
+                .map_err(|_| ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+        }
 
-        for job in &mut pipeline.jobs {
-            let mut script_file = PathBuf::from(pipeline_path);
+        for stage in pipeline.empty_stages() {
+            warn!("Stage has no jobs ({}): {}", pipeline_path, stage);
+        }
+
+        // A job of a stage that is not listed would never run
+        for job in &pipeline.jobs {
+            if !pipeline.stages.contains(&job.stage) {
+                let message = format!(
+                    "{}/{}/{}: stage is not listed: {} ({})",
+                    pipeline.id, job.stage, job.id, job.stage, pipeline_path
+                );
+
+                Err(ErrorKind::InvalidPipelineDefinition(message))?;
+            }
+
+            for glob in job.pass_env.iter().flatten() {
+                Pattern::new(glob)
+                    .map_err(|_| ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+            }
+
+            if !job.hosts.is_empty() && (job.function.is_some() || job.kind.is_some())
+                || !job.hosts.iter().all(|host| executor::is_host(host))
+                || job.output_format.is_some() && (job.script.is_empty() || !job.hosts.is_empty())
+            {
+                Err(ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+            }
+        }
+
+        if let Some(interpreter) = &pipeline.interpreter {
+            if interpreter.trim().is_empty() {
+                Err(ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+            }
+        }
+
+        if let Some(level) = &pipeline.log_level {
+            level
+                .parse::<LevelFilter>()
+                .map_err(|_| ErrorKind::InvalidPipelineFile(pipeline_path.to_string()))?;
+        }
+
+        pipeline.link_jobs();
+
+        Ok(pipeline)
+    }
+
+    // Listed stages without jobs, likely a typo in the stage of a job
+    pub fn empty_stages(&self) -> Vec<&String> {
+        self.stages
+            .iter()
+            .filter(|stage| !self.jobs.iter().any(|job| &job.stage == *stage))
+            .collect()
+    }
+
+    // Part of the pipeline for a manual run, all of it when nothing is selected.
+    // Jobs are selected by id, or by stage/id when ids repeat in stages
+    pub fn select(&self, stages: &[&str], jobs: &[&str]) -> Result<Pipeline, Error> {
+        for stage in stages {
+            if !self.stages.iter().any(|name| name == stage) {
+                Err(ErrorKind::StageNotFound(format!("{}/{}", self.id, stage)))?;
+            }
+        }
+
+        let matches = |job: &Job, selected: &str| {
+            selected == job.id || selected == format!("{}/{}", job.stage, job.id)
+        };
+
+        for selected in jobs {
+            if !self.jobs.iter().any(|job| matches(job, selected)) {
+                Err(ErrorKind::JobNotFound(format!("{}/{}", self.id, selected)))?;
+            }
+        }
+
+        let mut pipeline = self.clone();
+
+        if !jobs.is_empty() {
+            pipeline
+                .jobs
+                .retain(|job| jobs.iter().any(|selected| matches(job, selected)));
+        }
+
+        if !stages.is_empty() {
+            pipeline
+                .jobs
+                .retain(|job| stages.contains(&job.stage.as_str()));
+        }
+
+        // Stages keep their order, without the ones left with no jobs
+        let remaining = &pipeline.jobs;
+
+        pipeline.stages = self
+            .stages
+            .iter()
+            .filter(|stage| stages.is_empty() || stages.contains(&stage.as_str()))
+            .filter(|stage| jobs.is_empty() || remaining.iter().any(|job| &job.stage == *stage))
+            .cloned()
+            .collect();
+
+        Ok(pipeline)
+    }
+
+    // Pipeline without the skipped stages and jobs of a triggered run, stages
+    // left with no jobs are dropped too
+    pub fn skip(&self, stages: &[&str], jobs: &[&str]) -> Result<Pipeline, Error> {
+        // Validates the names like a selection
+        self.select(stages, &[])?;
+        self.select(&[], jobs)?;
+
+        let matches = |job: &Job, skipped: &str| {
+            skipped == job.id || skipped == format!("{}/{}", job.stage, job.id)
+        };
+
+        let mut pipeline = self.clone();
+
+        pipeline.jobs.retain(|job| {
+            !stages.contains(&job.stage.as_str())
+                && !jobs.iter().any(|skipped| matches(job, skipped))
+        });
+
+        let remaining = &pipeline.jobs;
+
+        pipeline.stages = self
+            .stages
+            .iter()
+            .filter(|stage| !stages.contains(&stage.as_str()))
+            .filter(|stage| jobs.is_empty() || remaining.iter().any(|job| &job.stage == *stage))
+            .cloned()
+            .collect();
+
+        Ok(pipeline)
+    }
+
+    // Script paths are relative to the folder of the pipeline file
+    // Jobs of a stage in groups that run one after the other, each job after
+    // the jobs producing the files it consumes
+    pub fn job_groups(&self, stage: &str) -> Result<Vec<Vec<&Job>>, String> {
+        let mut remaining: Vec<&Job> = self.jobs.iter().filter(|job| job.stage == stage).collect();
+
+        let mut groups = Vec::new();
+
+        while !remaining.is_empty() {
+            // Jobs consuming no file that a job still to run produces
+            let is_ready = |consumer: &&Job| {
+                !remaining.iter().any(|job| {
+                    job.breadcrumb != consumer.breadcrumb
+                        && job
+                            .produces
+                            .iter()
+                            .any(|file| consumer.consumes.contains(file))
+                })
+            };
+
+            let (ready, waiting): (Vec<&Job>, Vec<&Job>) =
+                remaining.iter().partition(|job| is_ready(job));
+
+            if ready.is_empty() {
+                let ids: Vec<&str> = waiting.iter().map(|job| job.id.as_str()).collect();
+
+                return Err(format!(
+                    "jobs consume each other's files: {}",
+                    ids.join(", ")
+                ));
+            }
+
+            groups.push(ready);
+            remaining = waiting;
+        }
+
+        Ok(groups)
+    }
+
+    pub fn link_jobs(&mut self) {
+        for job in &mut self.jobs {
+            let mut script_file = PathBuf::from(&self.path);
             script_file.pop();
+
+            job.folder = script_file.to_string_lossy().to_string();
+
             script_file.push(&job.script);
 
-            job.breadcrumb = format!("{}/{}/{}", &pipeline.id, &job.stage, &job.id);
+            job.breadcrumb = format!("{}/{}/{}", &self.id, &job.stage, &job.id);
             job.path = script_file.to_string_lossy().to_string();
         }
+    }
+}
 
-        Ok(pipeline)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline() -> Pipeline {
+        serde_json::from_str(
+            r#"{"id": "release", "stages": ["build", "test", "deploy"], "jobs": [
+                {"id": "compile", "stage": "build"},
+                {"id": "lint", "stage": "test"},
+                {"id": "unit", "stage": "test"},
+                {"id": "upload", "stage": "deploy"}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn names(pipeline: &Pipeline) -> (Vec<&str>, Vec<&str>) {
+        (
+            pipeline.stages.iter().map(String::as_str).collect(),
+            pipeline.jobs.iter().map(|job| job.id.as_str()).collect(),
+        )
+    }
+
+    #[test]
+    fn select_stages_and_jobs() {
+        let pipeline = pipeline();
+
+        let all = pipeline.select(&[], &[]).unwrap();
+        assert_eq!(names(&all).1.len(), 4);
+
+        let test = pipeline.select(&["test"], &[]).unwrap();
+        assert_eq!(names(&test), (vec!["test"], vec!["lint", "unit"]));
+
+        let jobs = pipeline.select(&[], &["upload", "test/unit"]).unwrap();
+        assert_eq!(
+            names(&jobs),
+            (vec!["test", "deploy"], vec!["unit", "upload"])
+        );
+
+        assert!(pipeline.select(&["package"], &[]).is_err());
+        assert!(pipeline.select(&[], &["build/unit"]).is_err());
+    }
+
+    #[test]
+    fn skip_stages_and_jobs() {
+        let pipeline = pipeline();
+
+        let none = pipeline.skip(&[], &[]).unwrap();
+        assert_eq!(names(&none).0.len(), 3);
+
+        let cleanup = pipeline.skip(&["deploy"], &["lint"]).unwrap();
+        assert_eq!(
+            names(&cleanup),
+            (vec!["build", "test"], vec!["compile", "unit"])
+        );
+
+        let jobs = pipeline.skip(&[], &["build/compile"]).unwrap();
+        assert_eq!(
+            names(&jobs),
+            (vec!["test", "deploy"], vec!["lint", "unit", "upload"])
+        );
+
+        assert!(pipeline.skip(&["package"], &[]).is_err());
+        assert!(pipeline.skip(&[], &["slow_report"]).is_err());
+    }
+
+    #[test]
+    fn group_jobs_by_data_dependencies() {
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "report", "stages": ["main"], "jobs": [
+                {"id": "render", "stage": "main", "consumes": ["report.csv"]},
+                {"id": "extract", "stage": "main", "produces": ["raw.json"]},
+                {"id": "notes", "stage": "main"},
+                {"id": "transform", "stage": "main", "consumes": ["raw.json"], "produces": ["report.csv"]}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.link_jobs();
+
+        let groups: Vec<Vec<&str>> = pipeline
+            .job_groups("main")
+            .unwrap()
+            .iter()
+            .map(|jobs| jobs.iter().map(|job| job.id.as_str()).collect())
+            .collect();
+
+        assert_eq!(
+            groups,
+            vec![vec!["extract", "notes"], vec!["transform"], vec!["render"]]
+        );
+
+        pipeline.jobs[1].consumes.push(String::from("report.csv"));
+
+        assert!(pipeline.job_groups("main").is_err());
+    }
+
+    #[test]
+    fn reject_jobs_of_unlisted_stages() {
+        let dir = std::env::temp_dir().join("rusty-scheduler-stages");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("pipeline.json");
+        let path = path.to_str().unwrap();
+
+        fs::write(
+            path,
+            r#"{"id": "stages", "expression": "0 0 * * *", "stages": ["build", "test"], "jobs": [
+                {"id": "compile", "stage": "build", "script": "compile.sh"},
+                {"id": "unit", "stage": "tests", "script": "unit.sh"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let err = Pipeline::read_file(path).unwrap_err();
+
+        assert!(matches!(err.kind(), ErrorKind::InvalidPipelineDefinition(_)));
+        assert!(err
+            .to_string()
+            .contains("stages/tests/unit: stage is not listed: tests"));
+
+        let mut pipeline = pipeline();
+
+        pipeline.stages.push(String::from("tests"));
+
+        assert_eq!(pipeline.empty_stages(), vec!["tests"]);
     }
 }
@@ -0,0 +1,148 @@
+use super::function::Context;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// Job types added by plugins, used by jobs with a "type"
+static TYPES: Mutex<BTreeMap<String, JobType>> = Mutex::new(BTreeMap::new());
+
+// Job types shipped with the scheduler, registered types with the same name win
+const BUILTINS: &[Builtin] = &[
+    ("http_request", super::http::job_type),
+    #[cfg(feature = "wasm")]
+    ("wasm", super::wasm::job_type),
+];
+
+type Validate = dyn Fn(&Value) -> Result<(), String> + Send + Sync;
+
+type Run = dyn Fn(&Value, &Context) -> Result<(), String> + Send + Sync;
+
+type Builtin = (&'static str, fn() -> JobType);
+
+// Executor of a job type, with the config being the other fields of the job
+#[derive(Clone)]
+pub struct JobType {
+    validate: Arc<Validate>,
+
+    run: Arc<Run>,
+}
+
+impl JobType {
+    pub fn new<C, F>(run: F) -> JobType
+    where
+        C: DeserializeOwned + 'static,
+        F: Fn(&C, &Context) -> Result<(), String> + Send + Sync + 'static,
+    {
+        JobType {
+            validate: Arc::new(|config| parse::<C>(config).map(|_| ())),
+            run: Arc::new(move |config, context| run(&parse::<C>(config)?, context)),
+        }
+    }
+
+    pub fn validate(&self, config: &Map<String, Value>) -> Result<(), String> {
+        (self.validate)(&Value::Object(config.clone()))
+    }
+
+    pub fn run(&self, config: &Map<String, Value>, context: &Context) -> Result<(), String> {
+        (self.run)(&Value::Object(config.clone()), context)
+    }
+}
+
+// Replaces a type registered with the same name
+pub fn register(name: &str, job_type: JobType) {
+    TYPES.lock().unwrap().insert(name.to_string(), job_type);
+}
+
+pub fn find(name: &str) -> Option<JobType> {
+    TYPES.lock().unwrap().get(name).cloned().or_else(|| {
+        BUILTINS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, job_type)| job_type())
+    })
+}
+
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = TYPES.lock().unwrap().keys().cloned().collect();
+
+    names.extend(BUILTINS.iter().map(|(name, _)| name.to_string()));
+    names.sort();
+    names.dedup();
+
+    names
+}
+
+fn parse<C: DeserializeOwned>(config: &Value) -> Result<C, String> {
+    serde_json::from_value(config.clone()).map_err(|err| format!("invalid config: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::executor::{self, Options};
+    use super::super::pipeline::Pipeline;
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Greeting {
+        name: String,
+
+        #[serde(default)]
+        times: usize,
+    }
+
+    fn pipeline(job: &str) -> Pipeline {
+        serde_json::from_str(&format!(
+            r#"{{"id": "plugins", "stages": ["main"], "jobs": [{}]}}"#,
+            job
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn run_job_types_with_their_config() {
+        register(
+            "test-greeting",
+            JobType::new(|config: &Greeting, context| {
+                for _ in 0..config.times.max(1) {
+                    context.print(&format!("hello {}", config.name));
+                }
+
+                Ok(())
+            }),
+        );
+
+        let pipeline = pipeline(
+            r#"{"id": "job", "stage": "main", "type": "test-greeting", "name": "world", "times": 2}"#,
+        );
+
+        let options = Options::default();
+
+        assert!(executor::execute_stage(&pipeline, "main", &options).is_ok());
+        assert_eq!(options.output.lines_since(0).0.len(), 2);
+    }
+
+    #[test]
+    fn validate_configs() {
+        register("test-validated", JobType::new(|_: &Greeting, _| Ok(())));
+
+        let job_type = find("test-validated").unwrap();
+
+        let valid =
+            pipeline(r#"{"id": "job", "stage": "main", "type": "test-validated", "name": "a"}"#);
+        let invalid = pipeline(r#"{"id": "job", "stage": "main", "type": "test-validated"}"#);
+
+        assert!(job_type.validate(&valid.jobs[0].config).is_ok());
+        assert!(job_type.validate(&invalid.jobs[0].config).is_err());
+
+        assert!(executor::execute_stage(&invalid, "main", &Options::default()).is_err());
+    }
+
+    #[test]
+    fn fail_unknown_types() {
+        let pipeline = pipeline(r#"{"id": "job", "stage": "main", "type": "test-unknown"}"#);
+
+        assert!(executor::execute_stage(&pipeline, "main", &Options::default()).is_err());
+    }
+}
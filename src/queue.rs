@@ -0,0 +1,252 @@
+use super::error::{Error, ErrorKind};
+use super::pipeline::{Concurrency, Pipeline};
+use super::runtime::ActiveRun;
+use super::state::State;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Occurrences listed per pipeline, a long outage may leave many more
+pub const QUEUE_LIMIT: usize = 100;
+
+// An occurrence that is due but didn't start yet. There is no queue file,
+// pending occurrences follow from the state, the schedule and the active run
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Pending {
+    pub pipeline: String,
+
+    pub scheduled: DateTime<Utc>,
+
+    pub reason: PendingReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingReason {
+    // Starts on the next refresh
+    Due,
+    // Starts once the active run completes
+    Waiting,
+    // Starts after the earlier occurrences of a queue pipeline
+    Queued,
+}
+
+impl fmt::Display for PendingReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            PendingReason::Due => "due",
+            PendingReason::Waiting => "waiting for active run",
+            PendingReason::Queued => "queued",
+        };
+
+        formatter.pad(reason)
+    }
+}
+
+// Queue pipelines run every occurrence in order, others only the latest one
+fn runs_all(pipeline: &Pipeline, state: &State) -> bool {
+    pipeline.concurrency == Concurrency::Queue && !state.is_first_run()
+}
+
+// Occurrence the scheduler starts next, when the pipeline is not running. A
+// first run has none, it starts when the pipeline is discovered
+pub fn next(pipeline: &Pipeline, state: &State, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if state.is_first_run() {
+        None
+    } else if runs_all(pipeline, state) {
+        state.next_timestamp.filter(|next| *next <= now)
+    } else {
+        pipeline.interval.last_time(state.timestamp, now)
+    }
+}
+
+pub fn pending(
+    pipeline: &Pipeline,
+    state: &State,
+    active: Option<&ActiveRun>,
+    now: DateTime<Utc>,
+) -> Vec<Pending> {
+    if !pipeline.enabled || state.paused || (state.is_first_run() && active.is_none()) {
+        return Vec::new();
+    }
+
+    let runs_all = runs_all(pipeline, state);
+
+    // Occurrences up to the active one are recorded when it completes
+    let from = match active {
+        Some(run) if runs_all => run.scheduled.unwrap_or(run.start).max(state.timestamp),
+        Some(run) => run.start,
+        None => state.timestamp,
+    };
+
+    let scheduled = if runs_all {
+        let from = match pipeline.max_queue_depth {
+            Some(max_depth) => pipeline
+                .interval
+                .overflow(from, now, max_depth as usize)
+                .map_or(from, |(_, last_dropped)| last_dropped),
+            None => from,
+        };
+
+        pipeline.interval.between(from, now, QUEUE_LIMIT)
+    } else {
+        pipeline.interval.last_time(from, now).into_iter().collect()
+    };
+
+    scheduled
+        .into_iter()
+        .filter(|scheduled| !state.dropped.contains(scheduled))
+        .enumerate()
+        .map(|(index, scheduled)| Pending {
+            pipeline: pipeline.id.to_string(),
+            scheduled,
+            reason: match (index, active) {
+                (0, None) => PendingReason::Due,
+                (0, Some(_)) => PendingReason::Waiting,
+                _ => PendingReason::Queued,
+            },
+        })
+        .collect()
+}
+
+// Removed occurrences are skipped when they would start, so a run that is
+// active meanwhile doesn't overwrite the change. Without a scheduled time
+// every pending occurrence is removed
+pub fn dequeue(
+    pipeline: &Pipeline,
+    active: Option<&ActiveRun>,
+    scheduled: Option<DateTime<Utc>>,
+) -> Result<Vec<Pending>, Error> {
+    let mut state = State::read_from_pipeline(pipeline);
+
+    let removed: Vec<Pending> = pending(pipeline, &state, active, Utc::now())
+        .into_iter()
+        .filter(|pending| scheduled.is_none_or(|scheduled| pending.scheduled == scheduled))
+        .collect();
+
+    if removed.is_empty() {
+        let run = match scheduled {
+            Some(scheduled) => format!("{} at {}", pipeline.id, scheduled.to_rfc3339()),
+            None => pipeline.id.to_string(),
+        };
+
+        return Err(ErrorKind::RunNotQueued(run).into());
+    }
+
+    let timestamp = state.timestamp;
+
+    state.dropped.retain(|dropped| *dropped > timestamp);
+    state
+        .dropped
+        .extend(removed.iter().map(|pending| pending.scheduled));
+
+    state.write_file()?;
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::builder::PipelineBuilder;
+    use super::*;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn state(timestamp: &str) -> State {
+        serde_json::from_value(serde_json::json!({ "timestamp": timestamp })).unwrap()
+    }
+
+    fn active(scheduled: &str, start: &str) -> ActiveRun {
+        ActiveRun {
+            id: String::from("hourly"),
+            run_id: String::new(),
+            scheduled: Some(time(scheduled)),
+            start: time(start),
+            stuck: false,
+            cancellation: Default::default(),
+            output: Default::default(),
+        }
+    }
+
+    fn times(pending: &[Pending]) -> Vec<(DateTime<Utc>, PendingReason)> {
+        pending
+            .iter()
+            .map(|pending| (pending.scheduled, pending.reason))
+            .collect()
+    }
+
+    #[test]
+    fn list_latest_occurrence() {
+        let pipeline = PipelineBuilder::new("hourly")
+            .expression("0 * * * *")
+            .build()
+            .unwrap();
+
+        let now = time("2019-07-02T03:30:00Z");
+
+        assert_eq!(
+            times(&pending(
+                &pipeline,
+                &state("2019-07-02T00:00:00Z"),
+                None,
+                now
+            )),
+            vec![(time("2019-07-02T03:00:00Z"), PendingReason::Due)]
+        );
+        assert_eq!(
+            times(&pending(
+                &pipeline,
+                &state("2019-07-02T00:00:00Z"),
+                Some(&active("2019-07-02T01:00:00Z", "2019-07-02T01:00:10Z")),
+                now
+            )),
+            vec![(time("2019-07-02T03:00:00Z"), PendingReason::Waiting)]
+        );
+
+        let mut paused = state("2019-07-02T00:00:00Z");
+        paused.paused = true;
+
+        assert!(pending(&pipeline, &paused, None, now).is_empty());
+        assert!(pending(&pipeline, &state("2019-07-02T03:00:00Z"), None, now).is_empty());
+    }
+
+    #[test]
+    fn list_queued_occurrences() {
+        let pipeline = PipelineBuilder::new("hourly")
+            .expression("0 * * * *")
+            .concurrency(Concurrency::Queue)
+            .max_queue_depth(2)
+            .build()
+            .unwrap();
+
+        let now = time("2019-07-02T03:30:00Z");
+
+        assert_eq!(
+            times(&pending(
+                &pipeline,
+                &state("2019-07-02T00:00:00Z"),
+                None,
+                now
+            )),
+            vec![
+                (time("2019-07-02T02:00:00Z"), PendingReason::Due),
+                (time("2019-07-02T03:00:00Z"), PendingReason::Queued)
+            ]
+        );
+
+        let mut state = state("2019-07-02T00:00:00Z");
+        state.dropped.push(time("2019-07-02T03:00:00Z"));
+
+        assert_eq!(
+            times(&pending(
+                &pipeline,
+                &state,
+                Some(&active("2019-07-02T01:00:00Z", "2019-07-02T01:00:10Z")),
+                now
+            )),
+            vec![(time("2019-07-02T02:00:00Z"), PendingReason::Waiting)]
+        );
+    }
+}
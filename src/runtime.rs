@@ -0,0 +1,483 @@
+use super::history::{JobSnapshot, Snapshot};
+use chrono::{DateTime, Duration, Utc};
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub type SharedRuntime = Arc<Mutex<Runtime>>;
+
+// Only the tail of the job output is kept in memory
+const MAX_OUTPUT_LINES: usize = 1000;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Runtime {
+    pub started: DateTime<Utc>,
+
+    #[serde(default)]
+    pub active: Vec<ActiveRun>,
+
+    #[serde(default)]
+    pub health: Health,
+
+    // Last triggered run of each pipeline with a dedup_window
+    #[serde(skip)]
+    pub triggers: Vec<Trigger>,
+}
+
+// Triggers in the dedup window of a triggered run are collapsed into it
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub pipeline: String,
+
+    pub run_id: String,
+
+    pub start: DateTime<Utc>,
+
+    pub collapsed: u32,
+
+    // The run is in the history, later triggers are counted there
+    pub recorded: bool,
+}
+
+// Updated by the scheduler loop on every refresh
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Health {
+    // Seconds
+    pub refresh: u64,
+
+    pub last_tick: Option<DateTime<Utc>>,
+
+    // Errors loading pipelines on the last refresh
+    #[serde(default)]
+    pub reload_errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ActiveRun {
+    pub id: String,
+
+    #[serde(default)]
+    pub run_id: String,
+
+    pub scheduled: Option<DateTime<Utc>>,
+
+    pub start: DateTime<Utc>,
+
+    // Notified as possibly stuck, the run goes on
+    #[serde(default)]
+    pub stuck: bool,
+
+    #[serde(skip)]
+    pub cancellation: Cancellation,
+
+    #[serde(skip)]
+    pub output: Output,
+}
+
+// Captured job output of a run, shared with the API for live viewing
+#[derive(Debug, Default, Clone)]
+pub struct Output {
+    tail: Arc<Mutex<Tail>>,
+
+    // Run log file, every line is also written there
+    log: Arc<Mutex<Option<File>>>,
+
+    // Breadcrumbs of the jobs that failed
+    failed_jobs: Arc<Mutex<Vec<String>>>,
+
+    // Breadcrumbs of the jobs that completed
+    passed_jobs: Arc<Mutex<Vec<String>>>,
+
+    // Stages that ran with their duration in seconds
+    stages: Arc<Mutex<Vec<(String, f64)>>>,
+
+    // KEY=value outputs written by the jobs
+    values: Arc<Mutex<BTreeMap<String, String>>>,
+
+    // Same outputs by job, referenced by the jobs of later stages
+    job_values: Arc<Mutex<BTreeMap<String, BTreeMap<String, String>>>>,
+
+    // JSON printed by the jobs with an output format, by job
+    results: Arc<Mutex<BTreeMap<String, Value>>>,
+
+    // What the run ran with, set when the jobs start
+    snapshot: Arc<Mutex<Option<Snapshot>>>,
+}
+
+#[derive(Debug, Default)]
+struct Tail {
+    lines: VecDeque<String>,
+
+    // Lines pushed since the run started, including the discarded ones
+    total: usize,
+}
+
+// Shared between a run and whoever wants to cancel it
+#[derive(Debug, Default, Clone)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+
+    // Job processes, each one leads its own process group
+    processes: Arc<Mutex<Vec<u32>>>,
+}
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn register(&self, pid: u32) {
+        self.processes.lock().unwrap().push(pid);
+    }
+
+    pub fn unregister(&self, pid: u32) {
+        self.processes
+            .lock()
+            .unwrap()
+            .retain(|&process| process != pid);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+
+        for &pid in self.processes.lock().unwrap().iter() {
+            trace!("Terminating job process: {}", pid);
+
+            let pid = pid as libc::pid_t;
+
+            // Interactive jobs stay in the terminal process group
+            unsafe {
+                if libc::kill(-pid, libc::SIGTERM) == -1 {
+                    libc::kill(pid, libc::SIGTERM);
+                }
+            }
+        }
+    }
+}
+
+impl Output {
+    pub fn set_log(&self, file: File) {
+        *self.log.lock().unwrap() = Some(file);
+    }
+
+    pub fn push(&self, line: String) {
+        if let Some(file) = self.log.lock().unwrap().as_mut() {
+            if let Err(err) = writeln!(file, "{}", line) {
+                warn!("Error writing log file: {}", err);
+            }
+        }
+
+        let mut tail = self.tail.lock().unwrap();
+
+        if tail.lines.len() == MAX_OUTPUT_LINES {
+            tail.lines.pop_front();
+        }
+
+        tail.lines.push_back(line);
+        tail.total += 1;
+    }
+
+    pub fn close_log(&self) {
+        self.log.lock().unwrap().take();
+    }
+
+    pub fn push_failed_job(&self, breadcrumb: &str) {
+        self.failed_jobs
+            .lock()
+            .unwrap()
+            .push(breadcrumb.to_string());
+    }
+
+    pub fn failed_jobs(&self) -> Vec<String> {
+        self.failed_jobs.lock().unwrap().clone()
+    }
+
+    pub fn push_passed_job(&self, breadcrumb: &str) {
+        self.passed_jobs
+            .lock()
+            .unwrap()
+            .push(breadcrumb.to_string());
+    }
+
+    pub fn passed_jobs(&self) -> Vec<String> {
+        self.passed_jobs.lock().unwrap().clone()
+    }
+
+    pub fn push_stage(&self, stage: &str, duration: f64) {
+        self.stages
+            .lock()
+            .unwrap()
+            .push((stage.to_string(), duration));
+    }
+
+    pub fn stages(&self) -> Vec<(String, f64)> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    pub fn set_value(&self, job: &str, key: &str, value: &str) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_string());
+
+        self.job_values
+            .lock()
+            .unwrap()
+            .entry(job.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    pub fn job_value(&self, job: &str, key: &str) -> Option<String> {
+        self.job_values
+            .lock()
+            .unwrap()
+            .get(job)
+            .and_then(|values| values.get(key).cloned())
+    }
+
+    pub fn values(&self) -> BTreeMap<String, String> {
+        self.values.lock().unwrap().clone()
+    }
+
+    pub fn set_result(&self, job: &str, result: Value) {
+        self.results.lock().unwrap().insert(job.to_string(), result);
+    }
+
+    pub fn results(&self) -> BTreeMap<String, Value> {
+        self.results.lock().unwrap().clone()
+    }
+
+    pub fn set_snapshot(&self, snapshot: Snapshot) {
+        *self.snapshot.lock().unwrap() = Some(snapshot);
+    }
+
+    pub fn push_job_snapshot(&self, breadcrumb: &str, job: JobSnapshot) {
+        if let Some(snapshot) = self.snapshot.lock().unwrap().as_mut() {
+            snapshot.jobs.insert(breadcrumb.to_string(), job);
+        }
+    }
+
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    // Lines after a cursor, with the cursor of the next call
+    pub fn lines_since(&self, cursor: usize) -> (Vec<String>, usize) {
+        let tail = self.tail.lock().unwrap();
+
+        let discarded = tail.total - tail.lines.len();
+        let skip = cursor.saturating_sub(discarded);
+
+        (tail.lines.iter().skip(skip).cloned().collect(), tail.total)
+    }
+}
+
+impl Runtime {
+    pub fn new_shared(refresh: u64) -> SharedRuntime {
+        Arc::new(Mutex::new(Runtime {
+            started: Utc::now(),
+            active: Vec::new(),
+            health: Health {
+                refresh,
+                ..Health::default()
+            },
+            triggers: Vec::new(),
+        }))
+    }
+
+    pub fn tick(&mut self, reload_errors: Vec<String>) {
+        self.health.last_tick = Some(Utc::now());
+        self.health.reload_errors = reload_errors;
+    }
+
+    pub fn start_run(&mut self, id: &str, scheduled: Option<DateTime<Utc>>) -> ActiveRun {
+        let start = Utc::now();
+
+        let run = ActiveRun {
+            id: id.to_string(),
+            run_id: Runtime::run_id(id, start),
+            scheduled,
+            start,
+            stuck: false,
+            cancellation: Cancellation::default(),
+            output: Output::default(),
+        };
+
+        self.active.push(run.clone());
+
+        run
+    }
+
+    pub fn run_id(id: &str, start: DateTime<Utc>) -> String {
+        format!("{}-{}", id, start.format("%Y%m%dT%H%M%S%3f"))
+    }
+
+    // The latest run of the pipeline, an earlier one may still be sending
+    // its notifications after releasing the lock
+    pub fn find_run(&self, id: &str) -> Option<&ActiveRun> {
+        self.active.iter().rev().find(|run| run.id == id)
+    }
+
+    pub fn is_running(&self, run_id: &str) -> bool {
+        self.active.iter().any(|run| run.run_id == run_id)
+    }
+
+    pub fn cancel_run(&self, id: &str) -> bool {
+        match self.find_run(id) {
+            Some(run) => {
+                run.cancellation.cancel();
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Whether the run was not marked yet, so it is notified once
+    pub fn mark_stuck(&mut self, id: &str) -> bool {
+        match self.active.iter_mut().rev().find(|run| run.id == id) {
+            Some(run) if !run.stuck => {
+                run.stuck = true;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn record_trigger(&mut self, pipeline: &str, run_id: &str, start: DateTime<Utc>) {
+        self.triggers.retain(|trigger| trigger.pipeline != pipeline);
+
+        self.triggers.push(Trigger {
+            pipeline: pipeline.to_string(),
+            run_id: run_id.to_string(),
+            start,
+            collapsed: 0,
+            recorded: false,
+        });
+    }
+
+    // Run a trigger is collapsed into, and whether that run is in the history
+    pub fn collapse_trigger(
+        &mut self,
+        pipeline: &str,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<(String, bool)> {
+        let trigger = self
+            .triggers
+            .iter_mut()
+            .find(|trigger| trigger.pipeline == pipeline && now < trigger.start + window)?;
+
+        trigger.collapsed += 1;
+
+        Some((trigger.run_id.to_string(), trigger.recorded))
+    }
+
+    // Triggers collapsed while the run was going, recorded in its history
+    pub fn take_collapsed(&mut self, run_id: &str) -> u32 {
+        match self
+            .triggers
+            .iter_mut()
+            .find(|trigger| trigger.run_id == run_id)
+        {
+            Some(trigger) => {
+                trigger.recorded = true;
+
+                trigger.collapsed
+            }
+            None => 0,
+        }
+    }
+
+    pub fn finish_run(&mut self, run_id: &str) {
+        self.active.retain(|run| run.run_id != run_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn output_lines_since_cursor() {
+        let output = Output::default();
+
+        for index in 0..MAX_OUTPUT_LINES + 5 {
+            output.push(format!("line {}", index));
+        }
+
+        let (lines, cursor) = output.lines_since(MAX_OUTPUT_LINES + 3);
+
+        assert_eq!(lines, vec!["line 1003", "line 1004"]);
+        assert_eq!(cursor, MAX_OUTPUT_LINES + 5);
+
+        // Discarded lines are skipped
+        let (lines, _) = output.lines_since(0);
+
+        assert_eq!(lines.len(), MAX_OUTPUT_LINES);
+        assert_eq!(lines[0], "line 5");
+    }
+
+    #[test]
+    fn finish_overlapping_runs() {
+        let mut runtime = Runtime {
+            started: Utc::now(),
+            active: Vec::new(),
+            health: Health::default(),
+            triggers: Vec::new(),
+        };
+
+        let finishing = runtime.start_run("loader", None);
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        let next = runtime.start_run("loader", None);
+
+        assert_eq!(runtime.find_run("loader").unwrap().run_id, next.run_id);
+
+        runtime.finish_run(&finishing.run_id);
+
+        assert_eq!(runtime.active.len(), 1);
+        assert!(runtime.is_running(&next.run_id));
+    }
+
+    #[test]
+    fn collapse_triggers_in_window() {
+        let mut runtime = Runtime {
+            started: Utc::now(),
+            active: Vec::new(),
+            health: Health::default(),
+            triggers: Vec::new(),
+        };
+
+        let start = Utc.with_ymd_and_hms(2019, 7, 1, 12, 0, 0).unwrap();
+        let window = Duration::seconds(30);
+
+        assert_eq!(runtime.collapse_trigger("loader", window, start), None);
+
+        runtime.record_trigger("loader", "loader-1", start);
+
+        assert_eq!(
+            runtime.collapse_trigger("loader", window, start + Duration::seconds(10)),
+            Some((String::from("loader-1"), false))
+        );
+        assert_eq!(runtime.collapse_trigger("report", window, start), None);
+        assert_eq!(runtime.take_collapsed("loader-1"), 1);
+        assert_eq!(
+            runtime.collapse_trigger("loader", window, start + Duration::seconds(20)),
+            Some((String::from("loader-1"), true))
+        );
+        assert_eq!(
+            runtime.collapse_trigger("loader", window, start + Duration::seconds(30)),
+            None
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use super::error::{Error, ErrorKind};
+use failure::ResultExt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+const PIPELINE_TEMPLATE: &str = r#"{
+  "id": "{id}",
+  "expression": "0 * * * *",
+  "enabled": true,
+  "first_run": "next",
+  "reset_on_change": false,
+  "history_days": 7,
+  "encrypt_state": false,
+  "params": {},
+  "stages": ["main"],
+  "jobs": [
+    {
+      "id": "hello",
+      "stage": "main",
+      "script": "hello.sh"
+    }
+  ]
+}
+"#;
+
+const SCRIPT_TEMPLATE: &str = r#"#!/bin/sh
+# Jobs are run with sh, a non-zero exit code fails the pipeline
+set -e
+
+echo "Hello from {id}"
+"#;
+
+// Files written by the scheduler next to the pipeline
+const GITIGNORE_TEMPLATE: &str = "state.json
+history.json
+pipeline.lock
+";
+
+pub fn create(pipelines_path: &str, id: &str) -> Result<String, Error> {
+    let pipeline_dir = create_dir(pipelines_path, id)?;
+
+    write(
+        &pipeline_dir,
+        "pipeline.json",
+        &PIPELINE_TEMPLATE.replace("{id}", id),
+        0o644,
+    )?;
+    write(
+        &pipeline_dir,
+        "hello.sh",
+        &SCRIPT_TEMPLATE.replace("{id}", id),
+        0o755,
+    )?;
+
+    Ok(pipeline_dir)
+}
+
+pub fn create_dir(pipelines_path: &str, id: &str) -> Result<String, Error> {
+    // The identifier is used as a folder name and inside the JSON template
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+
+    if id.is_empty() || !id.chars().all(valid) {
+        return Err(ErrorKind::InvalidPipelineId(id.to_string()).into());
+    }
+
+    let mut pipeline_dir = PathBuf::from(pipelines_path);
+    pipeline_dir.push(id);
+
+    let pipeline_dir = pipeline_dir.to_string_lossy().to_string();
+
+    if fs::metadata(&pipeline_dir).is_ok() {
+        return Err(ErrorKind::PipelineAlreadyExists(pipeline_dir).into());
+    }
+
+    fs::create_dir_all(&pipeline_dir)
+        .context(ErrorKind::InvalidPipelineFolder(pipeline_dir.to_string()))?;
+
+    write(&pipeline_dir, ".gitignore", GITIGNORE_TEMPLATE, 0o644)?;
+
+    Ok(pipeline_dir)
+}
+
+pub fn write(pipeline_dir: &str, name: &str, data: &str, mode: u32) -> Result<(), Error> {
+    let mut path = PathBuf::from(pipeline_dir);
+    path.push(name);
+
+    let path = path.to_string_lossy().to_string();
+
+    fs::write(&path, data).context(ErrorKind::InvalidPipelineFile(path.to_string()))?;
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+        .context(ErrorKind::InvalidPipelineFile(path.to_string()))?;
+
+    Ok(())
+}
@@ -1,23 +1,108 @@
+use super::api;
+use super::audit::{self, Action, Entry};
+use super::cache::{self, PipelineCache};
+use super::control;
+use super::digest;
 use super::error::Error;
 use super::executor;
-use super::pipeline::Pipeline;
+use super::fairness;
+use super::grpc;
+use super::history::{History, Report, Run, RunStatus, SkipReason};
+use super::inputs;
+use super::lock::Lock;
+use super::logging;
+use super::logs;
+use super::mqtt;
+use super::notification::{self, Notification, Ping, PING_OUTPUT_LINES};
+use super::pipeline::{Concurrency, Pipeline};
+use super::queue;
+use super::runtime::{Runtime, SharedRuntime};
+use super::security::Security;
+use super::shutdown;
 use super::state::State;
-use chrono::Utc;
-use log::{error, info, trace};
+use super::statistics::{Statistics, STATISTICS_RUNS};
+use super::statsd;
+use super::storage;
+use super::systemd;
+use super::upload;
+use super::watch;
+use chrono::{DateTime, Utc};
+use log::{error, info, trace, warn};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-pub fn run(pipelines_path: &str, refresh_interval: Duration) {
+// When set, due runs of every pipeline are recorded but not started
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+// Tags of this scheduler, matched against the runs_on tags of the pipelines
+static TAGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn run(
+    pipelines_path: &str,
+    refresh_interval: Duration,
+    listen: Option<&str>,
+    grpc_listen: Option<&str>,
+    security: &Security,
+) {
     info!("Scheduler started");
 
-    let mut ignore_active = true;
+    if !storage::is_persistent() {
+        info!("State persistence disabled");
+    }
+
+    let runtime = Runtime::new_shared(refresh_interval.as_secs());
+
+    if let Err(err) = control::serve(pipelines_path, runtime.clone()) {
+        error!("{}", err);
+    }
+
+    if let Err(err) = watch::serve(runtime.clone()) {
+        error!("{}", err);
+    }
+
+    if let Some(address) = listen {
+        if let Err(err) = api::serve(address, pipelines_path, runtime.clone(), security) {
+            error!("{}", err);
+        }
+    }
+
+    if let Some(address) = grpc_listen {
+        if let Err(err) = grpc::serve(address, pipelines_path, runtime.clone(), security) {
+            error!("{}", err);
+        }
+    }
+
+    shutdown::install();
+
+    systemd::notify("READY=1");
 
-    loop {
+    let watchdog = systemd::watchdog_interval();
+
+    let mut cache = PipelineCache::new();
+
+    let mut startup = true;
+
+    while !shutdown::is_requested() {
         trace!("Reloading pipelines");
 
-        let pipelines = Pipeline::read_dir(pipelines_path);
+        let pipelines = cache.read_dir(pipelines_path);
+
+        runtime.lock().unwrap().tick(reload_errors(&pipelines));
+
+        let mut pipelines = unwrap_pipelines(pipelines);
+
+        // Under --max-concurrent-runs the due pipelines with the smallest
+        // share of the runs so far take the free slots first
+        fairness::sort(&mut pipelines);
 
-        let pipelines = unwrap_pipelines(pipelines);
+        logging::set_levels(&pipelines);
+
+        digest::tick(&pipelines);
+
+        watch::update(&pipelines);
 
         if pipelines.is_empty() {
             trace!("No pipeline loaded");
@@ -27,64 +112,851 @@ pub fn run(pipelines_path: &str, refresh_interval: Duration) {
             }
 
             for pipeline in pipelines {
-                run_pipeline(pipeline, ignore_active);
+                // A drain or a signal during the tick stops new runs right away
+                if shutdown::is_requested() {
+                    break;
+                }
+
+                check_sla(&pipeline, &runtime);
+
+                check_stuck(&pipeline, &runtime);
+
+                if startup && pipeline.run_on_startup {
+                    run_on_startup(&pipeline, &runtime);
+                }
+
+                run_pipeline(pipeline, &runtime);
             }
         }
 
-        ignore_active = false;
+        startup = false;
+
+        wait(refresh_interval, watchdog);
+    }
+
+    drain(pipelines_path, &runtime, watchdog);
+}
+
+pub fn wait(duration: Duration, watchdog: Option<Duration>) {
+    let started = Instant::now();
+    let mut pinged = Instant::now();
+
+    systemd_watchdog(watchdog, &mut pinged, true);
 
-        thread::sleep(refresh_interval);
+    // Sleep in small steps to notice shutdown requests and keep the watchdog alive
+    while !shutdown::is_requested() && started.elapsed() < duration {
+        thread::sleep((duration - started.elapsed()).min(Duration::from_secs(1)));
+
+        systemd_watchdog(watchdog, &mut pinged, false);
+    }
+}
+
+pub fn drain(pipelines_path: &str, runtime: &SharedRuntime, watchdog: Option<Duration>) {
+    systemd::notify("STOPPING=1");
+
+    control::release(pipelines_path);
+
+    let mut pinged = Instant::now();
+
+    let active = runtime.lock().unwrap().active.len();
+
+    if active > 0 {
+        info!("Scheduler stopping, waiting for {} active run(s)", active);
+    }
+
+    while !runtime.lock().unwrap().active.is_empty() {
+        thread::sleep(Duration::from_secs(1));
+
+        systemd_watchdog(watchdog, &mut pinged, false);
     }
+
+    info!("Scheduler stopped");
 }
 
-pub fn run_pipeline(pipeline: Pipeline, ignore_active: bool) {
-    let mut state = match import_state(&pipeline, ignore_active) {
+fn systemd_watchdog(watchdog: Option<Duration>, pinged: &mut Instant, force: bool) {
+    if let Some(watchdog) = watchdog {
+        if force || pinged.elapsed() >= watchdog {
+            systemd::notify("WATCHDOG=1");
+
+            *pinged = Instant::now();
+        }
+    }
+}
+
+pub fn run_pipeline(pipeline: Pipeline, runtime: &SharedRuntime) {
+    let _scope = logging::scope(&pipeline.id);
+
+    if !pipeline.enabled {
+        trace!("Pipeline is disabled: {}", pipeline.id);
+
+        return;
+    }
+
+    // Left to the schedulers with the tags, its state is theirs to update
+    if !runs_here(&pipeline) {
+        trace!("Pipeline runs on other schedulers: {}", pipeline.id);
+
+        return;
+    }
+
+    let state_path = State::path_from_pipeline(&pipeline);
+
+    if let Some(next) = cache::next_time(&state_path, &pipeline.checksum) {
+        if next > Utc::now() {
+            trace!("Pipeline is not due: {}", pipeline.id);
+
+            return;
+        }
+    }
+
+    let mut state = match import_state(&pipeline) {
         None => return,
         Some(state) => state,
     };
 
-    thread::spawn(move || {
-        trace!("Running pipeline: {}", pipeline.id);
+    if pipeline.concurrency == Concurrency::Queue {
+        trim_queue(&pipeline, &mut state);
+    }
+
+    let scheduled = state.next_timestamp;
+
+    if state.paused {
+        trace!("Pipeline is paused: {}", pipeline.id);
+
+        record_skip(&pipeline, SkipReason::Paused, scheduled);
+
+        return;
+    }
+
+    // Still due on the next refresh, when a slot may be free
+    if fairness::is_full(runtime) {
+        trace!("Concurrency limit reached, run delayed: {}", pipeline.id);
+
+        return;
+    }
+
+    let lock = match acquire_lock(&pipeline, runtime) {
+        None => return,
+        Some(lock) => lock,
+    };
+
+    // Checked with the lock held, an active run would overwrite the skip
+    if skip_dequeued(&pipeline, &mut state) {
+        return;
+    }
+
+    // Also with the lock held, the skip is recorded in the state
+    if is_unchanged(&pipeline, &state) {
+        record_skip(&pipeline, SkipReason::Unchanged, scheduled);
+
+        state.record_skip(Utc::now());
+        state.update_next_timestamp(&pipeline.interval);
+
+        export_state(&state);
+
+        return;
+    }
+
+    // Skip pipelines catch up with the latest occurrence, not the first missed one
+    let logical_date = queue::next(&pipeline, &state, Utc::now());
+
+    if is_dry_run(&pipeline) {
+        info!("Pipeline would run, dry run: {}", pipeline.id);
+
+        record_skip(&pipeline, SkipReason::DryRun, logical_date);
+
+        state.record_skip(logical_date.unwrap_or_else(Utc::now));
+        state.update_next_timestamp(&pipeline.interval);
+
+        export_state(&state);
+
+        return;
+    }
+
+    spawn_run(pipeline, lock, runtime, logical_date, Overrides::default());
+}
+
+// Runs once when the scheduler starts, like @reboot in cron, whatever its
+// expression. The run is left to the schedule when the pipeline is busy
+fn run_on_startup(pipeline: &Pipeline, runtime: &SharedRuntime) {
+    let _scope = logging::scope(&pipeline.id);
+
+    if !pipeline.enabled || is_dry_run(pipeline) || !runs_here(pipeline) {
+        return;
+    }
+
+    if State::read_from_pipeline(pipeline).paused {
+        trace!("Pipeline is paused: {}", pipeline.id);
+
+        return;
+    }
+
+    if fairness::is_full(runtime) {
+        warn!(
+            "Concurrency limit reached, startup run skipped: {}",
+            pipeline.id
+        );
+
+        return;
+    }
+
+    match Lock::acquire_from_pipeline(pipeline) {
+        Ok(Some(lock)) => {
+            info!("Scheduler started, running: {}", pipeline.id);
+
+            let mut labels = BTreeMap::new();
+
+            labels.insert(String::from("reason"), String::from("startup"));
+
+            spawn_run(
+                pipeline.clone(),
+                lock,
+                runtime,
+                None,
+                Overrides {
+                    labels,
+                    startup: true,
+                    ..Overrides::default()
+                },
+            );
+        }
+        Ok(None) => warn!(
+            "Pipeline already running, startup run skipped: {}",
+            pipeline.id
+        ),
+        Err(err) => error!("{}", err),
+    }
+}
+
+// Skips the next occurrence when it was removed from the queue
+fn skip_dequeued(pipeline: &Pipeline, state: &mut State) -> bool {
+    let occurrence = match queue::next(pipeline, state, Utc::now()) {
+        Some(occurrence) if state.dropped.contains(&occurrence) => occurrence,
+        _ => return false,
+    };
+
+    record_skip(pipeline, SkipReason::Dequeued, Some(occurrence));
+
+    state.record_skip(occurrence);
+    state.update_next_timestamp(&pipeline.interval);
+    state.dropped.retain(|dropped| *dropped > occurrence);
+
+    export_state(state);
+
+    true
+}
+
+pub fn enable_dry_run() {
+    DRY_RUN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_dry_run(pipeline: &Pipeline) -> bool {
+    pipeline.dry_run || DRY_RUN.load(Ordering::SeqCst)
+}
+
+pub fn set_tags(tags: &[String]) {
+    *TAGS.lock().unwrap() = tags.to_vec();
+}
+
+// The scheduler has every runs_on tag of the pipeline
+pub fn runs_here(pipeline: &Pipeline) -> bool {
+    let tags = TAGS.lock().unwrap();
+
+    pipeline.runs_on.iter().all(|tag| tags.contains(tag))
+}
+
+// Notifies once per deadline when the pipeline didn't succeed by its SLA,
+// even if a run is still going
+fn check_sla(pipeline: &Pipeline, runtime: &SharedRuntime) {
+    let sla = match pipeline.sla {
+        Some(sla) if pipeline.enabled && !is_dry_run(pipeline) => sla,
+        _ => return,
+    };
+
+    let deadline = sla.deadline(Utc::now());
+
+    let is_checked =
+        |checked: Option<DateTime<Utc>>| checked.is_some_and(|checked| checked >= deadline);
+
+    if is_checked(State::read_from_pipeline(pipeline).sla_deadline) {
+        return;
+    }
+
+    // Checked again in the update, a run may finish or another scheduler may
+    // check the deadline in between
+    let mut checked = None;
+
+    let state = match update_state(pipeline, |state| {
+        checked = state.sla_deadline;
+
+        if !is_checked(checked) {
+            state.sla_deadline = Some(deadline);
+        }
+    }) {
+        Some(state) if !is_checked(checked) => state,
+        _ => return,
+    };
+
+    // Deadlines before the SLA was set are not checked
+    if checked.is_none() || state.paused {
+        return;
+    }
+
+    let history = History::read_from_pipeline(pipeline);
+
+    if sla.is_met(&history.runs, deadline) {
+        return;
+    }
+
+    warn!("Pipeline missed its SLA ({}): {}", sla, pipeline.id);
+
+    let notification = Notification::from_sla(
+        pipeline,
+        sla,
+        runtime.lock().unwrap().find_run(&pipeline.id),
+        state.consecutive_failures,
+    );
+
+    notification::send(pipeline, &notification);
+}
+
+// Notifies once per run when it goes longer than the stuck_after threshold,
+// the run is not cancelled
+fn check_stuck(pipeline: &Pipeline, runtime: &SharedRuntime) {
+    let stuck_after = match pipeline.stuck_after {
+        Some(stuck_after) => stuck_after,
+        None => return,
+    };
+
+    let active = match runtime.lock().unwrap().find_run(&pipeline.id) {
+        Some(active) if !active.stuck => active.clone(),
+        _ => return,
+    };
+
+    let state = State::read_from_pipeline(pipeline);
+
+    let threshold = match stuck_after.threshold(&state.statistics) {
+        Some(threshold) => threshold,
+        None => return,
+    };
+
+    let running = Utc::now()
+        .signed_duration_since(active.start)
+        .num_milliseconds() as f64
+        / 1000.0;
+
+    if running <= threshold || !runtime.lock().unwrap().mark_stuck(&pipeline.id) {
+        return;
+    }
+
+    warn!(
+        "Pipeline possibly stuck, running for {:.1}s ({}): {}",
+        running, stuck_after, pipeline.id
+    );
+
+    let notification =
+        Notification::from_stuck(pipeline, stuck_after, &active, state.consecutive_failures);
+
+    notification::send(pipeline, &notification);
+}
+
+// Drops the oldest occurrences of a queue pipeline above its max_queue_depth,
+// so an outage or a slow run doesn't cause a burst of runs
+fn trim_queue(pipeline: &Pipeline, state: &mut State) {
+    let max_depth = match pipeline.max_queue_depth {
+        None => return,
+        Some(max_depth) => max_depth as usize,
+    };
+
+    // A first run has no backlog
+    if state.is_first_run() {
+        return;
+    }
+
+    let overflow = pipeline
+        .interval
+        .overflow(state.timestamp, Utc::now(), max_depth);
+
+    let (dropped, last_dropped) = match overflow {
+        None => return,
+        Some(overflow) => overflow,
+    };
+
+    warn!(
+        "Queued runs dropped ({}, max_queue_depth {}): {}",
+        dropped, max_depth, pipeline.id
+    );
+
+    record_skip(pipeline, SkipReason::QueueFull, Some(last_dropped));
+
+    state.record_skip(last_dropped);
+    state.update_next_timestamp(&pipeline.interval);
+
+    export_state(state);
+}
+
+// Unreadable inputs don't skip the run, the jobs may report a better error
+fn is_unchanged(pipeline: &Pipeline, state: &State) -> bool {
+    let glob = match &pipeline.only_if_changed {
+        None => return false,
+        Some(glob) => glob,
+    };
+
+    match inputs::checksum(pipeline, glob) {
+        Ok(checksum) => state.input_checksum.as_ref() == Some(&checksum),
+        Err(err) => {
+            warn!("{}", err);
+
+            false
+        }
+    }
+}
+
+// Changes of a triggered run, recorded in its history
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub params: BTreeMap<String, String>,
+
+    // Why the run happened, like reason=hotfix
+    pub labels: BTreeMap<String, String>,
+
+    // Already removed from the pipeline
+    pub skip_stages: Vec<String>,
+
+    pub skip_jobs: Vec<String>,
 
-        let timestamp = Utc::now();
+    // Later triggers in the dedup_window of the pipeline are collapsed into the run
+    pub dedup: bool,
 
-        let status = executor::execute(&pipeline);
+    // Run reproduced by a rerun
+    pub rerun_of: Option<String>,
 
-        match status {
-            Ok(_) => {
-                trace!("Pipeline completed: {}", pipeline.id);
+    // Variables of the reproduced run, the parameters override them
+    pub env: BTreeMap<String, String>,
 
-                state.timestamp = timestamp;
+    // Logical date of the reproduced run
+    pub logical_date: Option<DateTime<Utc>>,
+
+    // Runs on top of the schedule, its occurrences are left alone
+    pub startup: bool,
+}
+
+pub fn spawn_run(
+    pipeline: Pipeline,
+    lock: Lock,
+    runtime: &SharedRuntime,
+    scheduled: Option<DateTime<Utc>>,
+    overrides: Overrides,
+) -> String {
+    fairness::record_start(&pipeline);
+
+    let active = {
+        let mut runtime = runtime.lock().unwrap();
+
+        let active = runtime.start_run(&pipeline.id, scheduled);
+
+        if overrides.dedup {
+            runtime.record_trigger(&pipeline.id, &active.run_id, active.start);
+        }
+
+        active
+    };
+
+    let run_id = active.run_id.to_string();
+
+    let runtime = runtime.clone();
+
+    thread::spawn(move || {
+        let _scope = logging::scope(&pipeline.id);
+
+        trace!("Running pipeline: {} ({})", pipeline.id, active.run_id);
+
+        if storage::is_persistent() {
+            match logs::create(&pipeline, &active.run_id) {
+                Ok(file) => active.output.set_log(file),
+                Err(err) => warn!("{}", err),
             }
-            Err(err) => {
-                error!("{}", err);
+        }
+
+        if let Some(ping_url) = &pipeline.ping_url {
+            Ping::Start.send(ping_url, "");
+        }
+
+        mqtt::publish(&mqtt::Event::started(&active));
+
+        let timestamp = active.start;
+
+        // Inputs are read before the jobs, which may change them
+        let input_checksum = pipeline
+            .only_if_changed
+            .as_ref()
+            .and_then(|glob| inputs::checksum(&pipeline, glob).ok());
+
+        let mut params = overrides.env.clone();
+
+        params.extend(overrides.params.clone());
+
+        // The variables of a rerun already have the outputs of its time
+        let last_outputs = match overrides.rerun_of {
+            Some(_) => BTreeMap::new(),
+            None => State::read_from_pipeline(&pipeline).outputs,
+        };
+
+        let options = executor::Options {
+            params,
+            labels: overrides.labels.clone(),
+            last_outputs,
+            logical_date: Some(overrides.logical_date.or(scheduled).unwrap_or(timestamp)),
+            cancellation: active.cancellation.clone(),
+            output: active.output.clone(),
+            ..executor::Options::default()
+        };
+
+        let status = executor::execute(&pipeline, &options);
+
+        let run_status = match status {
+            Ok(_) => RunStatus::Succeeded,
+            Err(_) if options.cancellation.is_cancelled() => RunStatus::Cancelled,
+            Err(_) => RunStatus::Failed,
+        };
+
+        let report = Report::new(&pipeline, &active.output, overrides.skip_jobs.len());
+
+        let collapsed = runtime.lock().unwrap().take_collapsed(&active.run_id);
+
+        let run = Run {
+            id: active.run_id.to_string(),
+            scheduled,
+            params: overrides.params,
+            labels: overrides.labels,
+            skipped_stages: overrides.skip_stages,
+            skipped_jobs: overrides.skip_jobs,
+            collapsed_triggers: Some(collapsed).filter(|collapsed| *collapsed > 0),
+            rerun_of: overrides.rerun_of,
+            report: Some(report.clone()),
+            results: active.output.results(),
+            snapshot: active.output.snapshot(),
+            ..Run::new(run_status, timestamp, Utc::now())
+        };
+
+        // One line with the whole run, the history keeps the same report
+        info!(
+            "Run summary: {} {} in {:.1}s, {}",
+            pipeline.id,
+            run_status,
+            run.duration(),
+            report
+        );
+
+        let notification = Notification::from_run(&pipeline, &run, &active.output);
+
+        let event = mqtt::Event::finished(&pipeline.id, &run);
+
+        let statistics = record_run(&pipeline, run);
+
+        let mut previous_failures = 0;
+
+        let mut paused = false;
+
+        let startup = overrides.startup;
+
+        // Updated as one change, so a concurrent change like an SLA check is
+        // neither lost nor overwrites the run
+        let state = update_state(&pipeline, |state| {
+            // Queued occurrences are recorded as run, so the next one is due
+            // right away. A first run has no earlier occurrences to catch up
+            let occurrence = match (pipeline.concurrency, scheduled) {
+                _ if startup => state.timestamp,
+                (Concurrency::Queue, Some(scheduled)) if !state.is_first_run() => scheduled,
+                _ => timestamp,
+            };
+
+            previous_failures = state.consecutive_failures;
+
+            state.statistics = statistics;
+
+            match &status {
+                Ok(_) => {
+                    trace!("Pipeline completed: {}", pipeline.id);
+
+                    state.record_success(occurrence);
+                    state.update_next_timestamp(&pipeline.interval);
+
+                    if input_checksum.is_some() {
+                        state.input_checksum = input_checksum;
+                    }
+
+                    state.record_outputs(options.output.values());
+                }
+                Err(_) if run_status == RunStatus::Cancelled => {
+                    info!("Pipeline cancelled: {}", pipeline.id);
+
+                    state.record_cancel(occurrence);
+                    state.update_next_timestamp(&pipeline.interval);
+                }
+                Err(err) => {
+                    error!("{}", err);
+
+                    state.record_failure();
+
+                    warn!(
+                        "Pipeline failed {} time(s) in a row: {}",
+                        state.consecutive_failures, pipeline.id
+                    );
+
+                    // Stops a broken pipeline from running again until it is resumed
+                    if let Some(failures) = pipeline.disable_after_failures {
+                        if state.consecutive_failures >= failures && !state.paused {
+                            warn!(
+                                "Pipeline paused after {} failures in a row: {}",
+                                state.consecutive_failures, pipeline.id
+                            );
+
+                            state.paused = true;
+                            paused = true;
+                        }
+                    }
+                }
             }
+        })
+        .unwrap_or_else(|| State::read_from_pipeline(&pipeline));
+
+        if paused {
+            let entry = Entry::new("scheduler", Action::Pause, &pipeline.id).detail(
+                "consecutive_failures",
+                &state.consecutive_failures.to_string(),
+            );
+
+            audit::record(&audit::path_from_pipeline(&pipeline), &entry);
         }
 
-        state.active = false;
+        // The run is finished, so its log is closed before rotating
+        active.output.close_log();
 
-        export_state(&state);
+        if let Err(err) = logs::rotate(&pipeline) {
+            warn!("{}", err);
+        }
+
+        let uploads = upload::stage_run(&pipeline, &active.run_id);
+
+        // The occurrence is recorded and the files of the run are set aside,
+        // the next run can start while this one uploads them and sends its
+        // notifications
+        release_lock(lock);
+
+        let notification = Notification {
+            previous_failures,
+            consecutive_failures: state.consecutive_failures,
+            paused,
+            ..notification
+        };
+
+        statsd::record_consecutive_failures(&pipeline.id, state.consecutive_failures);
+
+        upload::upload_run(&pipeline, &active.run_id, uploads);
+
+        if let Some(ping_url) = &pipeline.ping_url {
+            let (lines, _) = active.output.lines_since(0);
+            let lines = &lines[lines.len().saturating_sub(PING_OUTPUT_LINES)..];
+
+            Ping::from_status(run_status).send(ping_url, &lines.join("\n"));
+        }
+
+        notification::send(&pipeline, &notification);
+
+        if let Some(event) = event {
+            mqtt::publish(&mqtt::Event {
+                consecutive_failures: Some(state.consecutive_failures),
+                ..event
+            });
+        }
+
+        // Last, a drain waits for the active runs, so it doesn't stop the
+        // scheduler before the notifications of the run are sent
+        runtime.lock().unwrap().finish_run(&active.run_id);
     });
+
+    run_id
+}
+
+pub fn record_skip(pipeline: &Pipeline, reason: SkipReason, scheduled: Option<DateTime<Utc>>) {
+    let mut history = History::read_from_pipeline(pipeline);
+
+    let run = Run::skipped(reason, scheduled, Utc::now());
+
+    if history.is_skip_recorded(&run) {
+        return;
+    }
+
+    info!("Pipeline skipped ({}): {}", reason, pipeline.id);
+
+    statsd::record_run(&pipeline.id, &run);
+
+    history.runs.push(run);
+
+    export_history(&history);
+}
+
+pub fn record_run(pipeline: &Pipeline, run: Run) -> Statistics {
+    let mut history = History::read_from_pipeline(pipeline);
+
+    let statistics = Statistics::from_runs(&history.runs, STATISTICS_RUNS);
+
+    if statistics.is_slow(&run) {
+        warn!(
+            "Pipeline took {:.1}s, average is {:.1}s: {}",
+            run.duration(),
+            statistics.average_duration,
+            pipeline.id
+        );
+    }
+
+    statsd::record_run(&pipeline.id, &run);
+
+    history.runs.push(run);
+
+    // Out of range retentions are rejected on load, every run is kept if
+    // one gets here anyway
+    let before = Some(pipeline.history_days)
+        .filter(|days| *days >= 0)
+        .and_then(chrono::Duration::try_days)
+        .and_then(|days| Utc::now().checked_sub_signed(days));
+
+    if let Some(before) = before {
+        let compacted = history.compact(before, STATISTICS_RUNS);
+
+        if compacted > 0 {
+            trace!("History compacted: {} ({} runs)", pipeline.id, compacted);
+        }
+    }
+
+    export_history(&history);
+
+    Statistics::from_runs(&history.runs, STATISTICS_RUNS)
+}
+
+// A trigger collapsed into a run that already finished
+pub fn record_collapsed_trigger(pipeline: &Pipeline, run_id: &str) {
+    let mut history = History::read_from_pipeline(pipeline);
+
+    match history.runs.iter_mut().find(|run| run.id == run_id) {
+        Some(run) => {
+            run.collapsed_triggers = Some(run.collapsed_triggers.unwrap_or_default() + 1);
+
+            export_history(&history);
+        }
+        None => warn!("Run of a collapsed trigger not found: {}", run_id),
+    }
 }
 
-pub fn import_state(pipeline: &Pipeline, ignore_active: bool) -> Option<State> {
-    let mut state = State::read_from_pipeline(&pipeline);
+pub fn import_state(pipeline: &Pipeline) -> Option<State> {
+    let mut state = State::read_from_pipeline(pipeline);
+
+    let mut changed = state.update_next_timestamp(&pipeline.interval);
+
+    let previous_checksum = state.checksum.to_string();
+
+    if state.update_checksum(pipeline) {
+        changed = true;
+
+        if state.definition_changed {
+            info!("Pipeline definition changed: {}", pipeline.id);
+
+            let entry = Entry::new("scheduler", Action::DefinitionChanged, &pipeline.id)
+                .detail("previous_checksum", &previous_checksum)
+                .detail("checksum", &pipeline.checksum);
+
+            audit::record(&audit::path_from_pipeline(pipeline), &entry);
+        }
+    }
+
+    if changed {
+        export_state(&state);
+    }
 
     if !pipeline.interval.should_run(state.timestamp, Utc::now()) {
+        if let Some(next) = state.next_timestamp {
+            cache::set_next_time(&state.path, &pipeline.checksum, next);
+        }
+
         return None;
     }
 
-    if state.active && !ignore_active {
-        trace!("Pipeline is already running: {}", pipeline.id);
+    Some(state)
+}
+
+pub fn acquire_lock(pipeline: &Pipeline, runtime: &SharedRuntime) -> Option<Lock> {
+    match Lock::acquire_from_pipeline(pipeline) {
+        Ok(Some(lock)) => {
+            trace!("Lock acquired: {}", lock.id);
 
-        return None;
+            Some(lock)
+        }
+        Ok(None) => {
+            trace!("Pipeline is already running: {}", pipeline.id);
+
+            let start = runtime
+                .lock()
+                .unwrap()
+                .find_run(&pipeline.id)
+                .map(|run| run.start);
+
+            // The latest occurrence due during the active run is run after
+            // it completes, any earlier one is skipped. Queue pipelines run
+            // all of them
+            let missed = start
+                .filter(|_| pipeline.concurrency == Concurrency::Skip)
+                .and_then(|start| {
+                    pipeline
+                        .interval
+                        .last_time(start, Utc::now())
+                        .and_then(|latest| {
+                            pipeline
+                                .interval
+                                .last_time(start, latest - chrono::Duration::seconds(1))
+                        })
+                });
+
+            if missed.is_some() {
+                record_skip(pipeline, SkipReason::AlreadyRunning, missed);
+            }
+
+            None
+        }
+        Err(err) => {
+            error!("{}", err);
+
+            None
+        }
     }
+}
+
+pub fn release_lock(lock: Lock) {
+    let id = lock.id.to_string();
+
+    match lock.release() {
+        Ok(_) => {
+            trace!("Lock released: {}", id);
+        }
+        Err(err) => {
+            error!("{}", err);
+        }
+    };
+}
 
-    state.active = true;
+pub fn set_paused(pipeline: &Pipeline, paused: bool) {
+    if State::read_from_pipeline(pipeline).paused == paused {
+        return;
+    }
 
-    export_state(&state);
+    // The end of an active run writes the state too
+    update_state(pipeline, |state| state.paused = paused);
 
-    Some(state)
+    if paused {
+        info!("Pipeline paused: {}", pipeline.id);
+    } else {
+        info!("Pipeline resumed: {}", pipeline.id);
+    }
 }
 
 pub fn export_state(state: &State) {
@@ -98,6 +970,46 @@ pub fn export_state(state: &State) {
     };
 }
 
+pub fn update_state<F>(pipeline: &Pipeline, update: F) -> Option<State>
+where
+    F: FnOnce(&mut State),
+{
+    match State::update(pipeline, update) {
+        Ok(state) => {
+            trace!("State updated: {}", state.id);
+
+            Some(state)
+        }
+        Err(err) => {
+            error!("{}", err);
+
+            None
+        }
+    }
+}
+
+pub fn export_history(history: &History) {
+    match history.write_file() {
+        Ok(_) => {
+            trace!("History exported: {}", history.id);
+        }
+        Err(err) => {
+            error!("{}", err);
+        }
+    };
+}
+
+fn reload_errors(pipelines: &Result<Vec<Result<Pipeline, Error>>, Error>) -> Vec<String> {
+    match pipelines {
+        Err(err) => vec![err.to_string()],
+        Ok(pipelines) => pipelines
+            .iter()
+            .filter_map(|pipeline| pipeline.as_ref().err())
+            .map(|err| err.to_string())
+            .collect(),
+    }
+}
+
 pub fn unwrap_pipelines(pipelines: Result<Vec<Result<Pipeline, Error>>, Error>) -> Vec<Pipeline> {
     match pipelines {
         Err(err) => {
@@ -118,3 +1030,112 @@ pub fn unwrap_pipelines(pipelines: Result<Vec<Result<Pipeline, Error>>, Error>)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::function;
+    use super::*;
+    use chrono::TimeZone;
+    use std::env;
+    use std::fs;
+
+    fn wait_for_runs(runtime: &SharedRuntime) {
+        let started = Instant::now();
+
+        while !runtime.lock().unwrap().active.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn count_consecutive_failures() {
+        static FAIL: AtomicBool = AtomicBool::new(true);
+
+        function::register("test-failures", |_| match FAIL.load(Ordering::SeqCst) {
+            true => Err(String::from("failed")),
+            false => Ok(()),
+        });
+
+        let dir = env::temp_dir().join("rusty-scheduler-failures");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("pipeline.json"),
+            r#"{"id": "failures", "expression": "@yearly", "disable_after_failures": 2, "stages": ["main"],
+                "jobs": [{"id": "job", "stage": "main", "function": "test-failures"}]}"#,
+        )
+        .unwrap();
+
+        let pipeline = Pipeline::read_file(&dir.join("pipeline.json").to_string_lossy()).unwrap();
+        let runtime = Runtime::new_shared(60);
+
+        let run = |fail: bool| {
+            FAIL.store(fail, Ordering::SeqCst);
+
+            let lock = Lock::acquire_from_pipeline(&pipeline).unwrap().unwrap();
+
+            spawn_run(pipeline.clone(), lock, &runtime, None, Overrides::default());
+
+            wait_for_runs(&runtime);
+
+            State::read_from_pipeline(&pipeline)
+        };
+
+        let state = run(true);
+
+        assert_eq!(state.consecutive_failures, 1);
+        assert!(!state.paused);
+
+        let state = run(true);
+
+        assert_eq!(state.consecutive_failures, 2);
+        assert!(state.paused);
+
+        let state = run(false);
+
+        assert_eq!(state.consecutive_failures, 0);
+        assert!(state.paused);
+    }
+
+    #[test]
+    fn keep_the_schedule_after_startup_runs() {
+        function::register("test-startup", |_| Ok(()));
+
+        let dir = env::temp_dir().join("rusty-scheduler-startup");
+        let timestamp = Utc.with_ymd_and_hms(2019, 7, 1, 0, 0, 0).unwrap();
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("pipeline.json"),
+            r#"{"id": "startup", "expression": "@yearly", "run_on_startup": true, "stages": ["main"],
+                "jobs": [{"id": "job", "stage": "main", "function": "test-startup"}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("state.json"),
+            r#"{"id": "startup", "timestamp": "2019-07-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+
+        let pipeline = Pipeline::read_file(&dir.join("pipeline.json").to_string_lossy()).unwrap();
+        let runtime = Runtime::new_shared(60);
+
+        run_on_startup(&pipeline, &runtime);
+
+        wait_for_runs(&runtime);
+
+        let history = History::read_from_pipeline(&pipeline);
+        let state = State::read_from_pipeline(&pipeline);
+
+        assert_eq!(history.runs.last().unwrap().status, RunStatus::Succeeded);
+        assert_eq!(state.timestamp, timestamp);
+        assert_eq!(
+            state.next_timestamp,
+            Some(pipeline.interval.next_time(timestamp))
+        );
+    }
+}
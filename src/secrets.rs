@@ -0,0 +1,191 @@
+use super::aws;
+use super::error::{Error, ErrorKind};
+use super::pipeline::Pipeline;
+use super::vault;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+// SOPS encrypted dotenv file, in the pipelines directory for every pipeline
+// and in a pipeline folder for that pipeline
+pub const SOPS_FILE: &str = "secrets.sops.env";
+
+// Key given to sops, otherwise it finds keys on its own (default age key
+// file, PGP keyring, cloud KMS credentials)
+static SOPS_AGE_KEY_FILE: Mutex<Option<String>> = Mutex::new(None);
+
+// Where the value of a pipeline secret is fetched from
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum Secret {
+    // Key of a KV secret, like {"path": "secret/data/db", "key": "password"}
+    Vault {
+        path: String,
+        key: String,
+    },
+
+    // Secrets Manager secret by name or ARN, or a key of its JSON value
+    AwsSecretsManager {
+        id: String,
+        #[serde(default)]
+        key: Option<String>,
+        #[serde(default)]
+        region: Option<String>,
+    },
+
+    // Parameter Store parameter by name or ARN
+    AwsParameterStore {
+        name: String,
+        #[serde(default)]
+        region: Option<String>,
+    },
+}
+
+impl Secret {
+    fn fetch(&self) -> Result<String, String> {
+        match self {
+            Secret::Vault { path, key } => vault::read(path, key),
+            Secret::AwsSecretsManager { id, key, region } => {
+                aws::secret_value(id, key.as_deref(), region.as_deref())
+            }
+            Secret::AwsParameterStore { name, region } => aws::parameter(name, region.as_deref()),
+        }
+    }
+}
+
+pub fn set_sops_age_key_file(path: &str) {
+    *SOPS_AGE_KEY_FILE.lock().unwrap() = Some(path.to_string());
+}
+
+// Values of the pipeline secrets by environment variable, fetched when a run
+// starts so rotated secrets are picked up without a restart. Entries of the
+// pipeline file override the SOPS files, and the pipeline folder file the
+// global one
+pub fn resolve(pipeline: &Pipeline) -> Result<BTreeMap<String, String>, Error> {
+    let mut values = BTreeMap::new();
+
+    for path in sops_files(pipeline) {
+        let decrypted = decrypt(&path).map_err(|reason| {
+            let file = path.file_name().unwrap_or_default().to_string_lossy();
+
+            ErrorKind::SecretFetchFailed(format!("{}/{}", pipeline.id, file), reason)
+        })?;
+
+        values.extend(parse_env(&decrypted));
+    }
+
+    for (name, secret) in &pipeline.secrets {
+        let value = secret.fetch().map_err(|reason| {
+            ErrorKind::SecretFetchFailed(format!("{}/{}", pipeline.id, name), reason)
+        })?;
+
+        values.insert(name.to_string(), value);
+    }
+
+    Ok(values)
+}
+
+// The global file first, so the pipeline one overrides it
+fn sops_files(pipeline: &Pipeline) -> Vec<PathBuf> {
+    let folder = match Path::new(&pipeline.path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => return Vec::new(),
+    };
+
+    folder
+        .parent()
+        .into_iter()
+        .chain(Some(folder))
+        .map(|dir| dir.join(SOPS_FILE))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn decrypt(path: &Path) -> Result<String, String> {
+    let mut command = Command::new("sops");
+
+    if let Some(key_file) = SOPS_AGE_KEY_FILE.lock().unwrap().as_ref() {
+        command.env("SOPS_AGE_KEY_FILE", key_file);
+    }
+
+    let output = command
+        .args([
+            "--decrypt",
+            "--input-type",
+            "dotenv",
+            "--output-type",
+            "dotenv",
+        ])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("error running sops: {}", err))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        return Err(stderr.lines().last().unwrap_or("sops failed").to_string());
+    }
+
+    String::from_utf8(output.stdout).map_err(|err| err.to_string())
+}
+
+// KEY=value lines, comments and blank lines are ignored
+pub fn parse_env(data: &str) -> BTreeMap<String, String> {
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), value.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::builder::PipelineBuilder;
+    use super::*;
+
+    #[test]
+    fn parse_env_files() {
+        let values = parse_env("# database\nDB_PASSWORD=s3cr=t\n\nAPI_KEY=abc\ninvalid\n");
+
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["DB_PASSWORD"], "s3cr=t");
+        assert_eq!(values["API_KEY"], "abc");
+    }
+
+    #[test]
+    fn find_sops_files() {
+        let dir = std::env::temp_dir().join(format!("sops-{}", std::process::id()));
+        let folder = dir.join("loader");
+
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(dir.join(SOPS_FILE), "").unwrap();
+
+        let pipeline = PipelineBuilder::new("loader")
+            .expression("0 * * * *")
+            .folder(&folder.to_string_lossy())
+            .build()
+            .unwrap();
+
+        assert_eq!(sops_files(&pipeline), vec![dir.join(SOPS_FILE)]);
+
+        std::fs::write(folder.join(SOPS_FILE), "").unwrap();
+
+        assert_eq!(
+            sops_files(&pipeline),
+            vec![dir.join(SOPS_FILE), folder.join(SOPS_FILE)]
+        );
+
+        let in_code = PipelineBuilder::new("loader")
+            .expression("0 * * * *")
+            .build()
+            .unwrap();
+
+        assert!(sops_files(&in_code).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+use super::error::{Error, ErrorKind};
+use failure::ResultExt;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use rustls::{StreamOwned, ALL_VERSIONS};
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Protection of the HTTP and gRPC APIs
+#[derive(Clone, Default)]
+pub struct Security {
+    // Bearer tokens required by every endpoint except the health checks
+    pub tokens: Vec<(Role, String)>,
+
+    pub tls: Option<Tls>,
+}
+
+// Each role can do everything the previous ones can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Dashboard, status, history and output
+    Viewer,
+
+    // Trigger and cancel runs
+    Operator,
+
+    // Pause and resume pipelines
+    Admin,
+}
+
+// PEM contents of the certificate chain and private key
+#[derive(Clone)]
+pub struct Tls {
+    pub cert: Vec<u8>,
+
+    pub key: Vec<u8>,
+}
+
+// A plain or TLS connection of the API or of its clients
+pub enum Stream {
+    Plain(TcpStream),
+    Server(Box<StreamOwned<ServerConnection, TcpStream>>),
+    Client(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Security {
+    pub fn new(
+        tokens: &[(Role, Option<&str>)],
+        cert_path: Option<&str>,
+        key_path: Option<&str>,
+    ) -> Result<Security, Error> {
+        let read = |path: &str| -> Result<Vec<u8>, Error> {
+            Ok(fs::read(path).context(ErrorKind::InvalidTlsFile(path.to_string()))?)
+        };
+
+        let tls = match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => Some(Tls {
+                cert: read(cert_path)?,
+                key: read(key_path)?,
+            }),
+            (None, None) => None,
+            (Some(_), None) => {
+                return Err(ErrorKind::MissingSetting(String::from("tls_key")).into())
+            }
+            (None, Some(_)) => {
+                return Err(ErrorKind::MissingSetting(String::from("tls_cert")).into())
+            }
+        };
+
+        let security = Security {
+            tokens: tokens
+                .iter()
+                .filter_map(|(role, token)| Some((*role, token.filter(|token| !token.is_empty())?)))
+                .map(|(role, token)| (role, token.to_string()))
+                .collect(),
+            tls,
+        };
+
+        // Invalid certificates are reported on start, not on the first request
+        security.server_config()?;
+
+        Ok(security)
+    }
+
+    // Role of the value of an Authorization header, like "Bearer <token>",
+    // everyone is an admin without tokens
+    pub fn authorize(&self, authorization: Option<&str>) -> Option<Role> {
+        if self.tokens.is_empty() {
+            return Some(Role::Admin);
+        }
+
+        let given = authorization
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .map(str::trim)
+            .unwrap_or_default();
+
+        // Every token is compared, so the time doesn't tell which one matched
+        self.tokens
+            .iter()
+            .filter(|(_, token)| constant_time_eq(given.as_bytes(), token.as_bytes()))
+            .map(|(role, _)| *role)
+            .fold(None, |found, role| found.max(Some(role)))
+    }
+
+    // Token sent by the commands talking to the API, the most powerful one configured
+    pub fn client_token(&self) -> Option<&str> {
+        self.tokens
+            .iter()
+            .max_by_key(|(role, _)| *role)
+            .map(|(_, token)| token.as_str())
+    }
+
+    pub fn server_config(&self) -> Result<Option<Arc<ServerConfig>>, Error> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => return Ok(None),
+        };
+
+        let certs = CertificateDer::pem_slice_iter(&tls.cert)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ErrorKind::InvalidTlsFile(format!("certificate: {}", err)))?;
+
+        let key = PrivateKeyDer::from_pem_slice(&tls.key)
+            .map_err(|err| ErrorKind::InvalidTlsFile(format!("private key: {}", err)))?;
+
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_protocol_versions(ALL_VERSIONS)
+                .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+                .map_err(|err| ErrorKind::InvalidTlsFile(err.to_string()))?;
+
+        Ok(Some(Arc::new(config)))
+    }
+
+    // Public roots, plus the scheduler certificate so self-signed ones work
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>, Error> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        if let Some(tls) = &self.tls {
+            for cert in CertificateDer::pem_slice_iter(&tls.cert) {
+                let cert =
+                    cert.map_err(|err| ErrorKind::InvalidTlsFile(format!("certificate: {}", err)))?;
+
+                roots
+                    .add(cert)
+                    .map_err(|err| ErrorKind::InvalidTlsFile(err.to_string()))?;
+            }
+        }
+
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_protocol_versions(ALL_VERSIONS)
+                .map_err(|err| ErrorKind::InvalidTlsFile(err.to_string()))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+        Ok(Arc::new(config))
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Operator => write!(f, "operator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl Stream {
+    pub fn accept(stream: TcpStream, config: Option<&Arc<ServerConfig>>) -> io::Result<Stream> {
+        match config {
+            Some(config) => {
+                let connection = ServerConnection::new(config.clone())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+                Ok(Stream::Server(Box::new(StreamOwned::new(
+                    connection, stream,
+                ))))
+            }
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+
+    pub fn connect(
+        stream: TcpStream,
+        host: &str,
+        config: Option<Arc<ClientConfig>>,
+    ) -> io::Result<Stream> {
+        match config {
+            Some(config) => {
+                let name = ServerName::try_from(host.to_string())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+                let connection = ClientConnection::new(config, name)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+                Ok(Stream::Client(Box::new(StreamOwned::new(
+                    connection, stream,
+                ))))
+            }
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Server(stream) => &stream.sock,
+            Stream::Client(stream) => &stream.sock,
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+
+    // TLS clients expect a close_notify before the connection ends
+    pub fn close(&mut self) {
+        match self {
+            Stream::Plain(_) => {}
+            Stream::Server(stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+            Stream::Client(stream) => {
+                stream.conn.send_close_notify();
+                let _ = stream.flush();
+            }
+        }
+
+        let _ = self.tcp().shutdown(Shutdown::Write);
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buffer),
+            Stream::Server(stream) => stream.read(buffer),
+            Stream::Client(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buffer),
+            Stream::Server(stream) => stream.write(buffer),
+            Stream::Client(stream) => stream.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Server(stream) => stream.flush(),
+            Stream::Client(stream) => stream.flush(),
+        }
+    }
+}
+
+// Comparison time doesn't depend on where the tokens differ
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+
+    left.iter()
+        .zip(right)
+        .fold(0, |difference, (left, right)| difference | (left ^ right))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn security(tokens: &[(Role, Option<&str>)]) -> Security {
+        Security::new(tokens, None, None).unwrap()
+    }
+
+    #[test]
+    fn authorize_tokens() {
+        let admin = security(&[(Role::Admin, Some("s3cret"))]);
+
+        assert_eq!(security(&[]).authorize(None), Some(Role::Admin));
+        assert_eq!(admin.authorize(Some("Bearer s3cret")), Some(Role::Admin));
+        assert_eq!(admin.authorize(Some("Bearer s3crex")), None);
+        assert_eq!(admin.authorize(Some("Basic s3cret")), None);
+        assert_eq!(admin.authorize(Some("Bearer ")), None);
+        assert_eq!(admin.authorize(None), None);
+    }
+
+    #[test]
+    fn authorize_roles() {
+        let security = security(&[
+            (Role::Admin, Some("a")),
+            (Role::Operator, Some("o")),
+            (Role::Viewer, Some("v")),
+        ]);
+
+        assert_eq!(security.authorize(Some("Bearer v")), Some(Role::Viewer));
+        assert_eq!(security.authorize(Some("Bearer o")), Some(Role::Operator));
+        assert_eq!(security.authorize(Some("Bearer a")), Some(Role::Admin));
+        assert_eq!(security.client_token(), Some("a"));
+
+        assert!(Role::Viewer < Role::Operator && Role::Operator < Role::Admin);
+    }
+
+    #[test]
+    fn viewer_token_alone_protects_everything() {
+        let security = security(&[(Role::Admin, None), (Role::Viewer, Some("v"))]);
+
+        assert_eq!(security.authorize(None), None);
+        assert_eq!(security.authorize(Some("Bearer v")), Some(Role::Viewer));
+    }
+
+    #[test]
+    fn empty_token_disables_authentication() {
+        let security = security(&[(Role::Admin, Some(""))]);
+
+        assert_eq!(security.authorize(None), Some(Role::Admin));
+    }
+
+    #[test]
+    fn tls_needs_cert_and_key() {
+        assert!(Security::new(&[], Some("cert.pem"), None).is_err());
+        assert!(Security::new(&[], None, Some("key.pem")).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_certificates() {
+        let security = Security {
+            tokens: Vec::new(),
+            tls: Some(Tls {
+                cert: b"not a certificate".to_vec(),
+                key: b"not a key".to_vec(),
+            }),
+        };
+
+        assert!(security.server_config().is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use super::error::{Error, ErrorKind};
+use super::pipeline::Pipeline;
+use super::runtime::{Cancellation, Output};
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Delay before checking the services again, doubled after each check
+const FIRST_BACKOFF: Duration = Duration::from_secs(5);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// A cancelled run stops waiting within this delay
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// External dependency of a pipeline, like {"tcp": "db:5432"} or
+// {"http": "https://api/health"}
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    // Accepts connections on the host and port
+    Tcp(String),
+
+    // Answers the URL with a status code below 400
+    Http(String),
+}
+
+impl Service {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Service::Tcp(address) => match address.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+                _ => Err(format!("invalid tcp address: {}", address)),
+            },
+            Service::Http(url) => {
+                if url.starts_with("http://") || url.starts_with("https://") {
+                    Ok(())
+                } else {
+                    Err(format!("invalid http URL: {}", url))
+                }
+            }
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Service::Tcp(address) => {
+                let addresses = address.to_socket_addrs().map_err(|err| err.to_string())?;
+
+                let mut reason = String::from("no address found");
+
+                for address in addresses {
+                    match TcpStream::connect_timeout(&address, CHECK_TIMEOUT) {
+                        Ok(_) => return Ok(()),
+                        Err(err) => reason = err.to_string(),
+                    }
+                }
+
+                Err(reason)
+            }
+            Service::Http(url) => match ureq::get(url).timeout(CHECK_TIMEOUT).call() {
+                Ok(_) => Ok(()),
+                Err(err) => Err(describe(err)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Service::Tcp(address) => write!(formatter, "tcp {}", address),
+            Service::Http(url) => write!(formatter, "http {}", url),
+        }
+    }
+}
+
+// Waits until the services of the pipeline are up, checking them again with
+// a growing delay, and fails once they are down for longer than its
+// services_timeout
+pub fn wait(
+    pipeline: &Pipeline,
+    cancellation: &Cancellation,
+    output: &Output,
+) -> Result<(), Error> {
+    let timeout = Duration::from_secs(pipeline.services_timeout);
+    let started = Instant::now();
+    let mut backoff = FIRST_BACKOFF;
+
+    loop {
+        let down: Vec<String> = pipeline
+            .depends_on_services
+            .iter()
+            .filter_map(|service| {
+                service
+                    .check()
+                    .err()
+                    .map(|reason| format!("{} ({})", service, reason))
+            })
+            .collect();
+
+        if down.is_empty() {
+            trace!("Services are up: {}", pipeline.id);
+
+            return Ok(());
+        }
+
+        let waited = started.elapsed();
+
+        if waited >= timeout {
+            return Err(
+                ErrorKind::ServicesUnavailable(pipeline.id.to_string(), down.join(", ")).into(),
+            );
+        }
+
+        let delay = backoff.min(timeout - waited);
+
+        warn!(
+            "Services down, checking again in {}s ({}): {}",
+            delay.as_secs(),
+            pipeline.id,
+            down.join(", ")
+        );
+
+        output.push(format!(
+            "Waiting {}s for services: {}",
+            delay.as_secs(),
+            down.join(", ")
+        ));
+
+        let slept = Instant::now();
+
+        while slept.elapsed() < delay {
+            if cancellation.is_cancelled() {
+                return Err(ErrorKind::PipelineCancelled(pipeline.id.to_string()).into());
+            }
+
+            thread::sleep(CANCEL_CHECK_INTERVAL.min(delay - slept.elapsed()));
+        }
+
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn describe(err: ureq::Error) -> String {
+    match err {
+        ureq::Error::Status(code, _) => format!("status code {}", code),
+        ureq::Error::Transport(transport) => match transport.message() {
+            Some(message) => format!("{}: {}", transport.kind(), message),
+            None => transport.kind().to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn wait_for_services() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "services", "services_timeout": 0, "depends_on_services": [{{"tcp": "{}"}}]}}"#,
+            address
+        ))
+        .unwrap();
+
+        let cancellation = Cancellation::default();
+        let output = Output::default();
+
+        assert!(wait(&pipeline, &cancellation, &output).is_ok());
+
+        // Nothing listens on the port anymore
+        drop(listener);
+
+        let err = wait(&pipeline, &cancellation, &output).unwrap_err();
+
+        assert_eq!(err.code(), "services_unavailable");
+
+        assert!(Service::Tcp(String::from("db")).validate().is_err());
+        assert!(Service::Http(String::from("api/health"))
+            .validate()
+            .is_err());
+        assert!(Service::Http(String::from("https://api/health"))
+            .validate()
+            .is_ok());
+    }
+}
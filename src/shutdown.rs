@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Requested with the drain command, so a signal afterwards still waits for the runs
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+pub fn install() {
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst) || DRAINING.load(Ordering::SeqCst)
+}
+
+// Stops starting runs and exits once the active ones complete, like a signal
+pub fn drain() {
+    DRAINING.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn handle(_signal: libc::c_int) {
+    // A second signal skips waiting for the active runs
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(1) };
+    }
+}
@@ -0,0 +1,188 @@
+use super::history::{Run, RunStatus};
+use super::timezone::Timezone;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// Wall-clock time a pipeline is expected to succeed by every day, like
+// "by 06:00" (UTC) or "by 06:00 Europe/Lisbon"
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Sla {
+    pub time: NaiveTime,
+
+    pub timezone: Timezone,
+}
+
+impl Sla {
+    // Latest deadline up to a time
+    pub fn deadline(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.timezone {
+            Timezone::Utc => last_deadline(&Utc, self.time, now),
+            Timezone::Local => last_deadline(&Local, self.time, now),
+            Timezone::Named(tz) => last_deadline(&tz, self.time, now),
+        }
+    }
+
+    // Whether a run succeeded between the previous deadline and this one
+    pub fn is_met(&self, runs: &[Run], deadline: DateTime<Utc>) -> bool {
+        let previous = self.deadline(deadline - Duration::seconds(1));
+
+        runs.iter().any(|run| {
+            run.status == RunStatus::Succeeded && run.end > previous && run.end <= deadline
+        })
+    }
+}
+
+fn last_deadline<T: TimeZone>(tz: &T, time: NaiveTime, now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.with_timezone(tz).date_naive();
+
+    let deadline = deadline_on(tz, today, time);
+
+    if deadline <= now {
+        deadline
+    } else {
+        deadline_on(tz, today.pred_opt().unwrap_or(today), time)
+    }
+}
+
+fn deadline_on<T: TimeZone>(tz: &T, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = date.and_time(time);
+
+    // Times skipped by a daylight saving change are an hour later
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|deadline| deadline.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+impl FromStr for Sla {
+    type Err = String;
+
+    fn from_str(sla: &str) -> Result<Sla, String> {
+        let parts: Vec<&str> = sla.split_whitespace().collect();
+
+        let (time, timezone) = match parts.as_slice() {
+            ["by", time] => (time, Timezone::Utc),
+            ["by", time, timezone] => (time, timezone.parse()?),
+            _ => return Err(sla.to_string()),
+        };
+
+        let time = NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| sla.to_string())?;
+
+        Ok(Sla { time, timezone })
+    }
+}
+
+impl TryFrom<String> for Sla {
+    type Error = String;
+
+    fn try_from(sla: String) -> Result<Sla, String> {
+        sla.parse().map_err(|_| format!("invalid SLA: {}", sla))
+    }
+}
+
+impl From<Sla> for String {
+    fn from(sla: Sla) -> String {
+        sla.to_string()
+    }
+}
+
+impl fmt::Display for Sla {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let time = self.time.format("%H:%M");
+
+        match self.timezone {
+            Timezone::Utc => write!(formatter, "by {}", time),
+            Timezone::Local => write!(formatter, "by {} local", time),
+            Timezone::Named(tz) => write!(formatter, "by {} {}", time, tz.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_slas() {
+        let sla: Sla = "by 06:00".parse().unwrap();
+
+        assert_eq!(sla.time, NaiveTime::from_hms_opt(6, 0, 0).unwrap());
+        assert_eq!(sla.timezone, Timezone::Utc);
+        assert_eq!(sla.to_string(), "by 06:00");
+
+        let sla: Sla = "by 7:30 Europe/Lisbon".parse().unwrap();
+
+        assert_eq!(sla.to_string(), "by 07:30 Europe/Lisbon");
+
+        assert!("06:00".parse::<Sla>().is_err());
+        assert!("by 25:00".parse::<Sla>().is_err());
+        assert!("by 06:00 Mars/Olympus".parse::<Sla>().is_err());
+        assert!(serde_json::from_str::<Sla>(r#""before 06:00""#).is_err());
+    }
+
+    #[test]
+    fn find_deadlines() {
+        let sla: Sla = "by 06:00".parse().unwrap();
+
+        assert_eq!(
+            sla.deadline(time("2019-07-02T05:59:59Z")),
+            time("2019-07-01T06:00:00Z")
+        );
+        assert_eq!(
+            sla.deadline(time("2019-07-02T06:00:00Z")),
+            time("2019-07-02T06:00:00Z")
+        );
+
+        // Lisbon is an hour ahead of UTC in the summer
+        let sla: Sla = "by 06:00 Europe/Lisbon".parse().unwrap();
+
+        assert_eq!(
+            sla.deadline(time("2019-07-02T05:30:00Z")),
+            time("2019-07-02T05:00:00Z")
+        );
+        assert_eq!(
+            sla.deadline(time("2019-07-02T04:30:00Z")),
+            time("2019-07-01T05:00:00Z")
+        );
+    }
+
+    #[test]
+    fn check_runs() {
+        let sla: Sla = "by 06:00".parse().unwrap();
+        let deadline = time("2019-07-02T06:00:00Z");
+
+        let run = |status, end| Run::new(status, time("2019-07-01T00:00:00Z"), time(end));
+
+        assert!(sla.is_met(
+            &[run(RunStatus::Succeeded, "2019-07-02T05:30:00Z")],
+            deadline
+        ));
+        assert!(sla.is_met(
+            &[run(RunStatus::Succeeded, "2019-07-01T06:30:00Z")],
+            deadline
+        ));
+
+        // Too late, for the previous deadline, or failed
+        assert!(!sla.is_met(
+            &[run(RunStatus::Succeeded, "2019-07-02T06:00:01Z")],
+            deadline
+        ));
+        assert!(!sla.is_met(
+            &[run(RunStatus::Succeeded, "2019-07-01T05:30:00Z")],
+            deadline
+        ));
+        assert!(!sla.is_met(&[run(RunStatus::Failed, "2019-07-02T05:30:00Z")], deadline));
+    }
+}
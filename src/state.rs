@@ -1,10 +1,16 @@
+use super::cache;
+use super::crypto;
 use super::error::{Error, ErrorKind};
-use super::pipeline::Pipeline;
+use super::interval::Interval;
+use super::pipeline::{FirstRun, Pipeline};
+use super::statistics::Statistics;
+use super::storage;
 use chrono::{DateTime, TimeZone, Utc};
 use failure::ResultExt;
 use log::{trace, warn};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::collections::BTreeMap;
+use std::io;
 use std::path::PathBuf;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -16,48 +22,249 @@ pub struct State {
     #[serde(default)]
     pub path: String,
 
+    #[serde(skip_deserializing)]
+    #[serde(skip_serializing)]
     #[serde(default)]
-    pub active: bool,
+    pub encrypted: bool,
 
-    #[serde(default = Utc::now())]
+    #[serde(default = "Utc::now")]
     pub timestamp: DateTime<Utc>,
+
+    #[serde(default)]
+    pub next_timestamp: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub paused: bool,
+
+    #[serde(default)]
+    pub consecutive_failures: u32,
+
+    #[serde(default)]
+    pub checksum: String,
+
+    #[serde(default)]
+    pub definition_changed: bool,
+
+    // Checksum of the only_if_changed files in the last successful run
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_checksum: Option<String>,
+
+    // Job outputs of the successful runs, passed to the next runs
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, String>,
+
+    // Latest SLA deadline checked, so each miss is only notified once
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sla_deadline: Option<DateTime<Utc>>,
+
+    // Occurrences removed from the queue, skipped instead of run
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub statistics: Statistics,
 }
 
 impl State {
-    pub fn read_from_pipeline(pipeline: &Pipeline) -> State {
+    pub fn path_from_pipeline(pipeline: &Pipeline) -> String {
         let mut state_path = PathBuf::from(&pipeline.path);
         state_path.pop();
         state_path.push("state.json");
 
-        let state_path = state_path.to_string_lossy().to_string();
+        state_path.to_string_lossy().to_string()
+    }
+
+    pub fn read_from_pipeline(pipeline: &Pipeline) -> State {
+        let state_path = State::path_from_pipeline(pipeline);
 
         let state = State::read_file(&state_path);
 
         match state {
-            Ok(state) => {
+            Ok(mut state) => {
                 trace!("State loaded: {}", pipeline.id);
 
+                state.encrypted = pipeline.encrypt_state;
+
                 state
             }
             Err(err) => {
                 warn!("{}", err);
                 warn!("State created: {}", pipeline.id);
 
-                State {
-                    id: pipeline.id.to_string(),
-                    path: state_path.to_string(),
-                    active: false,
-                    timestamp: Utc.timestamp(0, 0),
+                State::new(pipeline)
+            }
+        }
+    }
+
+    fn new(pipeline: &Pipeline) -> State {
+        State {
+            id: pipeline.id.to_string(),
+            path: State::path_from_pipeline(pipeline),
+            encrypted: pipeline.encrypt_state,
+            timestamp: State::first_timestamp(pipeline.first_run),
+            next_timestamp: None,
+            paused: false,
+            consecutive_failures: 0,
+            checksum: String::new(),
+            definition_changed: false,
+            input_checksum: None,
+            outputs: BTreeMap::new(),
+            sla_deadline: None,
+            dropped: Vec::new(),
+            statistics: Statistics::default(),
+        }
+    }
+
+    // Reads, changes and writes the state while holding the file, so changes
+    // made at the same time, like the end of a run, are not overwritten
+    pub fn update<F>(pipeline: &Pipeline, update: F) -> Result<State, Error>
+    where
+        F: FnOnce(&mut State),
+    {
+        let state_path = State::path_from_pipeline(pipeline);
+
+        let mut updated = None;
+        let mut failed = None;
+
+        let result = storage::update(&state_path, |data| {
+            let mut state = match State::from_data(&data, &state_path) {
+                Ok(state) => state,
+                Err(err) => {
+                    if !data.is_empty() {
+                        warn!("{}", err);
+                    }
+
+                    warn!("State created: {}", pipeline.id);
+
+                    State::new(pipeline)
                 }
+            };
+
+            state.encrypted = pipeline.encrypt_state;
+
+            update(&mut state);
+
+            let data = state.to_data();
+
+            updated = Some(state);
+
+            data.map_err(|err| {
+                let message = err.to_string();
+
+                failed = Some(err);
+
+                io::Error::other(message)
+            })
+        });
+
+        if let Some(err) = failed {
+            return Err(err);
+        }
+
+        result.context(ErrorKind::InvalidStateFile(state_path.to_string()))?;
+
+        cache::invalidate_next_time(&state_path);
+
+        Ok(updated.unwrap_or_else(|| State::new(pipeline)))
+    }
+
+    pub fn first_timestamp(first_run: FirstRun) -> DateTime<Utc> {
+        match first_run {
+            FirstRun::Immediately => Utc.timestamp_opt(0, 0).unwrap(),
+            FirstRun::Next => Utc::now(),
+        }
+    }
+
+    // Never ran, and runs as soon as it is discovered
+    pub fn is_first_run(&self) -> bool {
+        self.timestamp == State::first_timestamp(FirstRun::Immediately)
+    }
+
+    pub fn record_success(&mut self, timestamp: DateTime<Utc>) {
+        self.timestamp = timestamp;
+        self.consecutive_failures = 0;
+        self.definition_changed = false;
+    }
+
+    pub fn record_cancel(&mut self, timestamp: DateTime<Utc>) {
+        // The cancelled occurrence is not retried
+        self.timestamp = timestamp;
+        self.definition_changed = false;
+    }
+
+    pub fn record_skip(&mut self, timestamp: DateTime<Utc>) {
+        // The skipped occurrence is not retried
+        self.timestamp = timestamp;
+    }
+
+    // Outputs keep their last value, an empty value removes them
+    pub fn record_outputs(&mut self, outputs: BTreeMap<String, String>) {
+        for (key, value) in outputs {
+            if value.is_empty() {
+                self.outputs.remove(&key);
+            } else {
+                self.outputs.insert(key, value);
             }
         }
     }
 
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        self.definition_changed = false;
+    }
+
+    pub fn update_checksum(&mut self, pipeline: &Pipeline) -> bool {
+        if self.checksum == pipeline.checksum {
+            return false;
+        }
+
+        // The first checksum seen for a pipeline is not a change
+        self.definition_changed = !self.checksum.is_empty();
+        self.checksum = pipeline.checksum.to_string();
+
+        if self.definition_changed && pipeline.reset_on_change {
+            self.consecutive_failures = 0;
+        }
+
+        true
+    }
+
+    pub fn update_next_timestamp(&mut self, interval: &Interval) -> bool {
+        let next = Some(interval.next_time(self.timestamp));
+
+        let changed = self.next_timestamp != next;
+
+        self.next_timestamp = next;
+
+        changed
+    }
+
     pub fn read_file(state_path: &str) -> Result<State, Error> {
-        let state_data = fs::read_to_string(state_path)
+        let state_data = storage::read(state_path)
             .context(ErrorKind::InvalidStateFile(state_path.to_string()))?;
 
-        let mut state: State = serde_json::from_str(&state_data)
+        State::from_data(&state_data, state_path)
+    }
+
+    pub fn write_file(&self) -> Result<(), Error> {
+        let state_data = self.to_data()?;
+
+        storage::write(&self.path, state_data)
+            .context(ErrorKind::InvalidStateFile(self.path.to_string()))?;
+
+        cache::invalidate_next_time(&self.path);
+
+        Ok(())
+    }
+
+    fn from_data(state_data: &[u8], state_path: &str) -> Result<State, Error> {
+        let state_data = crypto::decrypt(state_data, state_path)?;
+
+        let mut state: State = serde_json::from_slice(&state_data)
             .context(ErrorKind::InvalidStateFile(state_path.to_string()))?;
 
         state.path = state_path.to_string();
@@ -65,13 +272,127 @@ impl State {
         Ok(state)
     }
 
-    pub fn write_file(&self) -> Result<(), Error> {
-        let state_data = serde_json::to_string_pretty(&self)
+    fn to_data(&self) -> Result<Vec<u8>, Error> {
+        let state_data = serde_json::to_vec_pretty(&self)
             .context(ErrorKind::InvalidStateFile(self.path.to_string()))?;
 
-        fs::write(self.path.to_string(), state_data)
-            .context(ErrorKind::InvalidStateFile(self.path.to_string()))?;
+        if self.encrypted {
+            crypto::encrypt(&state_data, &self.path)
+        } else {
+            Ok(state_data)
+        }
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::thread;
+
+    fn pipeline(first_run: &str) -> Pipeline {
+        let mut pipeline: Pipeline = serde_json::from_str(&format!(
+            r#"{{"id": "first", "first_run": "{}", "stages": ["main"], "jobs": []}}"#,
+            first_run
+        ))
+        .unwrap();
+
+        pipeline.path = env::temp_dir()
+            .join("rusty-scheduler-first-run")
+            .join(first_run)
+            .join("pipeline.json")
+            .to_string_lossy()
+            .to_string();
+
+        pipeline
+    }
+
+    #[test]
+    fn start_new_pipelines_by_first_run_policy() {
+        let state = State::read_from_pipeline(&pipeline("immediately"));
+
+        assert!(state.is_first_run());
+        assert_eq!(state.timestamp, Utc.timestamp_opt(0, 0).unwrap());
+
+        let before = Utc::now();
+        let state = State::read_from_pipeline(&pipeline("next"));
+
+        assert!(!state.is_first_run());
+        assert!(state.timestamp >= before && state.timestamp <= Utc::now());
+    }
+
+    #[test]
+    fn reset_failures_on_definition_changes() {
+        let mut pipeline = pipeline("immediately");
+
+        pipeline.checksum = String::from("first");
+        pipeline.reset_on_change = true;
+
+        let mut state = State::read_from_pipeline(&pipeline);
+
+        state.consecutive_failures = 3;
+
+        assert!(state.update_checksum(&pipeline));
+        assert!(!state.definition_changed);
+        assert_eq!(state.consecutive_failures, 3);
+
+        assert!(!state.update_checksum(&pipeline));
+        assert_eq!(state.consecutive_failures, 3);
+
+        pipeline.checksum = String::from("second");
+
+        assert!(state.update_checksum(&pipeline));
+        assert!(state.definition_changed);
+        assert_eq!(state.consecutive_failures, 0);
+
+        pipeline.checksum = String::from("third");
+        pipeline.reset_on_change = false;
+        state.consecutive_failures = 3;
+
+        assert!(state.update_checksum(&pipeline));
+        assert!(state.definition_changed);
+        assert_eq!(state.consecutive_failures, 3);
+    }
+
+    #[test]
+    fn update_states_as_one_change() {
+        let mut pipeline = pipeline("immediately");
+        let dir = env::temp_dir().join("rusty-scheduler-updates");
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("state.json"));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let pipeline = pipeline.clone();
+
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        State::update(&pipeline, |state| state.consecutive_failures += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(
+            State::read_from_pipeline(&pipeline).consecutive_failures,
+            80
+        );
+    }
+
+    #[test]
+    fn read_states_without_timestamp() {
+        let before = Utc::now();
+        let state: State = serde_json::from_str(r#"{"id": "first"}"#).unwrap();
+
+        assert!(!state.is_first_run());
+        assert!(state.timestamp >= before);
     }
 }
@@ -0,0 +1,143 @@
+use super::history::{Run, RunStatus};
+use serde::{Deserialize, Serialize};
+
+// Number of recent runs used to compute statistics
+pub const STATISTICS_RUNS: usize = 20;
+
+// A run slower than this factor times the average is flagged
+pub const SLOW_RUN_FACTOR: f64 = 2.0;
+
+// Minimum number of runs before flagging slow runs
+pub const SLOW_RUN_MIN_RUNS: usize = 3;
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Statistics {
+    #[serde(default)]
+    pub runs: usize,
+
+    // 0 to 1
+    #[serde(default)]
+    pub success_rate: f64,
+
+    // Seconds
+    #[serde(default)]
+    pub average_duration: f64,
+
+    // Seconds
+    #[serde(default)]
+    pub max_duration: f64,
+}
+
+impl Statistics {
+    pub fn from_runs(runs: &[Run], count: usize) -> Statistics {
+        let runs: Vec<&Run> = runs
+            .iter()
+            .filter(|run| run.status != RunStatus::Skipped && run.status != RunStatus::Cancelled)
+            .collect();
+
+        let recent = &runs[runs.len().saturating_sub(count)..];
+
+        if recent.is_empty() {
+            return Statistics::default();
+        }
+
+        let successful = recent
+            .iter()
+            .filter(|run| run.status == RunStatus::Succeeded)
+            .count();
+
+        let durations: Vec<f64> = recent.iter().map(|run| run.duration()).collect();
+
+        let total_duration: f64 = durations.iter().sum();
+        let max_duration = durations.iter().cloned().fold(0.0, f64::max);
+
+        Statistics {
+            runs: recent.len(),
+            success_rate: successful as f64 / recent.len() as f64,
+            average_duration: total_duration / recent.len() as f64,
+            max_duration,
+        }
+    }
+
+    pub fn is_slow(&self, run: &Run) -> bool {
+        self.runs >= SLOW_RUN_MIN_RUNS
+            && self.average_duration > 0.0
+            && run.duration() > self.average_duration * SLOW_RUN_FACTOR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn run(status: RunStatus, seconds: i64) -> Run {
+        let start = Utc.with_ymd_and_hms(2019, 7, 1, 12, 0, 0).unwrap();
+        let end = start + chrono::Duration::seconds(seconds);
+
+        Run::new(status, start, end)
+    }
+
+    #[test]
+    fn statistics_empty() {
+        let statistics = Statistics::from_runs(&[], STATISTICS_RUNS);
+
+        assert_eq!(statistics, Statistics::default());
+    }
+
+    #[test]
+    fn statistics_success_rate_and_durations() {
+        let runs = vec![
+            run(RunStatus::Succeeded, 10),
+            run(RunStatus::Failed, 20),
+            run(RunStatus::Succeeded, 30),
+            run(RunStatus::Succeeded, 40),
+        ];
+
+        let statistics = Statistics::from_runs(&runs, STATISTICS_RUNS);
+
+        assert_eq!(statistics.runs, 4);
+        assert_eq!(statistics.success_rate, 0.75);
+        assert_eq!(statistics.average_duration, 25.0);
+        assert_eq!(statistics.max_duration, 40.0);
+    }
+
+    #[test]
+    fn statistics_last_runs_only() {
+        let runs = vec![
+            run(RunStatus::Failed, 100),
+            run(RunStatus::Succeeded, 10),
+            run(RunStatus::Succeeded, 20),
+        ];
+
+        let statistics = Statistics::from_runs(&runs, 2);
+
+        assert_eq!(statistics.runs, 2);
+        assert_eq!(statistics.success_rate, 1.0);
+        assert_eq!(statistics.average_duration, 15.0);
+        assert_eq!(statistics.max_duration, 20.0);
+    }
+
+    #[test]
+    fn slow_run_flagged() {
+        let runs = vec![
+            run(RunStatus::Succeeded, 10),
+            run(RunStatus::Succeeded, 10),
+            run(RunStatus::Succeeded, 10),
+        ];
+
+        let statistics = Statistics::from_runs(&runs, STATISTICS_RUNS);
+
+        assert!(statistics.is_slow(&run(RunStatus::Succeeded, 25)));
+        assert!(!statistics.is_slow(&run(RunStatus::Succeeded, 15)));
+    }
+
+    #[test]
+    fn slow_run_needs_history() {
+        let runs = vec![run(RunStatus::Succeeded, 10)];
+
+        let statistics = Statistics::from_runs(&runs, STATISTICS_RUNS);
+
+        assert!(!statistics.is_slow(&run(RunStatus::Succeeded, 100)));
+    }
+}
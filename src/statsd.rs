@@ -0,0 +1,160 @@
+use super::error::{Error, ErrorKind};
+use super::history::{Run, RunStatus};
+use failure::ResultExt;
+use log::{info, trace};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+
+// Metrics are only sent when a StatsD address is configured
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+#[derive(Debug)]
+struct Client {
+    socket: UdpSocket,
+
+    prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Count,
+    Gauge,
+    Timing,
+}
+
+pub fn init(address: &str, prefix: &str) -> Result<(), Error> {
+    let target = address
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .ok_or_else(|| ErrorKind::InvalidStatsdAddress(address.to_string()))?;
+
+    let local = match target {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+
+    let socket = UdpSocket::bind(local)
+        .and_then(|socket| socket.connect(target).map(|_| socket))
+        .context(ErrorKind::InvalidStatsdAddress(address.to_string()))?;
+
+    // Metrics are best effort and never hold up a run
+    socket
+        .set_nonblocking(true)
+        .context(ErrorKind::InvalidStatsdAddress(address.to_string()))?;
+
+    info!("Sending metrics to StatsD: {}", address);
+
+    *CLIENT.lock().unwrap() = Some(Client {
+        socket,
+        prefix: prefix.to_string(),
+    });
+
+    Ok(())
+}
+
+pub fn record_run(pipeline_id: &str, run: &Run) {
+    let status = run.status.to_string();
+
+    let mut tags = vec![("pipeline", pipeline_id), ("status", status.as_str())];
+
+    let reason = run.reason.map(|reason| reason.to_string());
+
+    if let Some(reason) = &reason {
+        tags.push(("reason", reason.as_str()));
+    }
+
+    send("runs", "1", Kind::Count, &tags);
+
+    if run.status != RunStatus::Skipped {
+        let duration = (run.duration() * 1000.0).round().to_string();
+
+        send("run.duration", &duration, Kind::Timing, &tags);
+    }
+
+    if run.status == RunStatus::Failed {
+        send("failures", "1", Kind::Count, &[("pipeline", pipeline_id)]);
+    }
+}
+
+pub fn record_consecutive_failures(pipeline_id: &str, failures: u32) {
+    send(
+        "consecutive_failures",
+        &failures.to_string(),
+        Kind::Gauge,
+        &[("pipeline", pipeline_id)],
+    );
+}
+
+fn send(name: &str, value: &str, kind: Kind, tags: &[(&str, &str)]) {
+    if let Some(client) = CLIENT.lock().unwrap().as_ref() {
+        let metric = metric(&client.prefix, name, value, kind, tags);
+
+        trace!("StatsD metric: {}", metric);
+
+        if let Err(err) = client.socket.send(metric.as_bytes()) {
+            trace!("Error sending StatsD metric: {}", err);
+        }
+    }
+}
+
+// Tags use the DogStatsD format, which plain StatsD servers ignore
+pub fn metric(prefix: &str, name: &str, value: &str, kind: Kind, tags: &[(&str, &str)]) -> String {
+    let kind = match kind {
+        Kind::Count => "c",
+        Kind::Gauge => "g",
+        Kind::Timing => "ms",
+    };
+
+    let name = if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    };
+
+    let mut metric = format!("{}:{}|{}", name, value, kind);
+
+    if !tags.is_empty() {
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|(key, value)| format!("{}:{}", key, value.replace(['|', ',', '#'], "_")))
+            .collect();
+
+        metric.push_str("|#");
+        metric.push_str(&tags.join(","));
+    }
+
+    metric
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_metrics() {
+        assert_eq!(
+            metric(
+                "rusty_scheduler",
+                "runs",
+                "1",
+                Kind::Count,
+                &[("pipeline", "loader"), ("status", "failed")]
+            ),
+            "rusty_scheduler.runs:1|c|#pipeline:loader,status:failed"
+        );
+
+        assert_eq!(
+            metric("", "run.duration", "1500", Kind::Timing, &[]),
+            "run.duration:1500|ms"
+        );
+    }
+
+    #[test]
+    fn format_metric_tags() {
+        assert_eq!(
+            metric("", "failures", "2", Kind::Gauge, &[("pipeline", "a|b,c#d")]),
+            "failures:2|g|#pipeline:a_b_c_d"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// When set, files are kept in memory instead of being written to disk
+static MEMORY: Mutex<Option<HashMap<String, Vec<u8>>>> = Mutex::new(None);
+
+pub fn disable_persistence() {
+    *MEMORY.lock().unwrap() = Some(HashMap::new());
+}
+
+pub fn is_persistent() -> bool {
+    MEMORY.lock().unwrap().is_none()
+}
+
+pub fn exists(path: &str) -> bool {
+    match MEMORY.lock().unwrap().as_ref() {
+        Some(memory) => memory.contains_key(path),
+        None => Path::new(path).is_file(),
+    }
+}
+
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    match MEMORY.lock().unwrap().as_ref() {
+        Some(memory) => memory
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_string())),
+        None => read_locked(path),
+    }
+}
+
+pub fn write(path: &str, data: Vec<u8>) -> io::Result<()> {
+    match MEMORY.lock().unwrap().as_mut() {
+        Some(memory) => {
+            memory.insert(path.to_string(), data);
+
+            Ok(())
+        }
+        None => write_locked(path, &data),
+    }
+}
+
+// Reads and rewrites a file as one change, a missing file reads as empty, so
+// other writers can't change it in between
+pub fn update<F>(path: &str, update: F) -> io::Result<()>
+where
+    F: FnOnce(Vec<u8>) -> io::Result<Vec<u8>>,
+{
+    match MEMORY.lock().unwrap().as_mut() {
+        Some(memory) => {
+            let data = update(memory.get(path).cloned().unwrap_or_default())?;
+
+            memory.insert(path.to_string(), data);
+
+            Ok(())
+        }
+        None => update_locked(path, update),
+    }
+}
+
+// Existing content is never rewritten, for append-only files
+pub fn append(path: &str, data: &[u8]) -> io::Result<()> {
+    match MEMORY.lock().unwrap().as_mut() {
+        Some(memory) => {
+            memory
+                .entry(path.to_string())
+                .or_default()
+                .extend_from_slice(data);
+
+            Ok(())
+        }
+        None => append_locked(path, data),
+    }
+}
+
+// Readers and writers hold advisory locks, so other processes using
+// flock never see a partially written file
+fn read_locked(path: &str) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+
+    file.lock_shared()?;
+
+    let mut data = Vec::new();
+    let result = file.read_to_end(&mut data);
+
+    file.unlock()?;
+
+    result.map(|_| data)
+}
+
+fn write_locked(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+
+    file.lock()?;
+
+    let result = file
+        .set_len(0)
+        .and_then(|_| file.write_all(data))
+        .and_then(|_| file.flush());
+
+    file.unlock()?;
+
+    result
+}
+
+fn update_locked<F>(path: &str, update: F) -> io::Result<()>
+where
+    F: FnOnce(Vec<u8>) -> io::Result<Vec<u8>>,
+{
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    file.lock()?;
+
+    let mut data = Vec::new();
+
+    let result = file
+        .read_to_end(&mut data)
+        .and_then(|_| update(data))
+        .and_then(|data| {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&data)?;
+            file.flush()
+        });
+
+    file.unlock()?;
+
+    result
+}
+
+fn append_locked(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    file.lock()?;
+
+    let result = file.write_all(data).and_then(|_| file.flush());
+
+    file.unlock()?;
+
+    result
+}
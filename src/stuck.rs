@@ -0,0 +1,141 @@
+use super::statistics::{Statistics, SLOW_RUN_MIN_RUNS};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// How long a run goes before it is possibly stuck, like "3x" (times the
+// average duration) or "2h" (also "90m" or "600s")
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum StuckAfter {
+    Factor(f64),
+
+    // Seconds
+    Duration(u64),
+}
+
+impl StuckAfter {
+    // Seconds, none until there are enough runs for an average
+    pub fn threshold(&self, statistics: &Statistics) -> Option<f64> {
+        match self {
+            StuckAfter::Factor(factor) => {
+                if statistics.runs >= SLOW_RUN_MIN_RUNS && statistics.average_duration > 0.0 {
+                    Some(statistics.average_duration * factor)
+                } else {
+                    None
+                }
+            }
+            StuckAfter::Duration(seconds) => Some(*seconds as f64),
+        }
+    }
+}
+
+impl FromStr for StuckAfter {
+    type Err = String;
+
+    fn from_str(stuck_after: &str) -> Result<StuckAfter, String> {
+        let invalid = || stuck_after.to_string();
+
+        let unit = stuck_after.chars().last().ok_or_else(invalid)?;
+        let value = &stuck_after[..stuck_after.len() - unit.len_utf8()];
+
+        if unit == 'x' {
+            let factor: f64 = value.parse().map_err(|_| invalid())?;
+
+            return if factor > 0.0 && factor.is_finite() {
+                Ok(StuckAfter::Factor(factor))
+            } else {
+                Err(invalid())
+            };
+        }
+
+        let multiplier = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            _ => return Err(invalid()),
+        };
+
+        match value.parse::<u64>() {
+            Ok(value) if value > 0 => Ok(StuckAfter::Duration(value * multiplier)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for StuckAfter {
+    type Error = String;
+
+    fn try_from(stuck_after: String) -> Result<StuckAfter, String> {
+        stuck_after
+            .parse()
+            .map_err(|_| format!("invalid stuck_after: {}", stuck_after))
+    }
+}
+
+impl From<StuckAfter> for String {
+    fn from(stuck_after: StuckAfter) -> String {
+        stuck_after.to_string()
+    }
+}
+
+impl fmt::Display for StuckAfter {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StuckAfter::Factor(factor) => write!(formatter, "{}x", factor),
+            StuckAfter::Duration(seconds) if seconds % 3600 == 0 => {
+                write!(formatter, "{}h", seconds / 3600)
+            }
+            StuckAfter::Duration(seconds) if seconds % 60 == 0 => {
+                write!(formatter, "{}m", seconds / 60)
+            }
+            StuckAfter::Duration(seconds) => write!(formatter, "{}s", seconds),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_thresholds() {
+        assert_eq!("3x".parse(), Ok(StuckAfter::Factor(3.0)));
+        assert_eq!("1.5x".parse(), Ok(StuckAfter::Factor(1.5)));
+        assert_eq!("90m".parse(), Ok(StuckAfter::Duration(5400)));
+        assert_eq!("2h".parse(), Ok(StuckAfter::Duration(7200)));
+
+        for invalid in &["", "x", "0x", "-2x", "0s", "10", "10d", "2 h"] {
+            assert!(invalid.parse::<StuckAfter>().is_err());
+        }
+
+        assert_eq!(StuckAfter::Duration(5400).to_string(), "90m");
+        assert_eq!(StuckAfter::Duration(7200).to_string(), "2h");
+        assert_eq!(StuckAfter::Duration(100).to_string(), "100s");
+    }
+
+    #[test]
+    fn compute_thresholds() {
+        let statistics = Statistics {
+            runs: 5,
+            success_rate: 1.0,
+            average_duration: 60.0,
+            max_duration: 90.0,
+        };
+
+        assert_eq!(StuckAfter::Factor(3.0).threshold(&statistics), Some(180.0));
+        assert_eq!(
+            StuckAfter::Duration(600).threshold(&statistics),
+            Some(600.0)
+        );
+
+        let new = Statistics {
+            runs: 1,
+            ..statistics
+        };
+
+        assert_eq!(StuckAfter::Factor(3.0).threshold(&new), None);
+        assert_eq!(StuckAfter::Duration(600).threshold(&new), Some(600.0));
+    }
+}
@@ -0,0 +1,43 @@
+use log::{trace, warn};
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+pub fn notify(state: &str) {
+    let socket_path = match env::var("NOTIFY_SOCKET") {
+        Ok(socket_path) => socket_path,
+        Err(_) => return,
+    };
+
+    // Abstract sockets are given with a leading '@'
+    let address = match socket_path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&socket_path),
+    };
+
+    let result = address.and_then(|address| {
+        let socket = UnixDatagram::unbound()?;
+
+        socket.send_to_addr(state.as_bytes(), &address)
+    });
+
+    match result {
+        Ok(_) => trace!("Systemd notified: {}", state),
+        Err(err) => warn!("Error notifying systemd: {}", err),
+    }
+}
+
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    // Only the main process should ping the watchdog
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid != std::process::id().to_string() {
+            return None;
+        }
+    }
+
+    // Pinging at half the interval leaves room for a slow refresh
+    Some(Duration::from_micros(usec / 2))
+}
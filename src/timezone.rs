@@ -0,0 +1,121 @@
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+// Times are stored and scheduled in UTC, this is only how they are shown
+static DISPLAY: Mutex<Timezone> = Mutex::new(Timezone::Utc);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timezone {
+    #[default]
+    Utc,
+    // Timezone of the host running the scheduler
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Timezone, String> {
+        match name {
+            "UTC" | "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            _ => name
+                .parse()
+                .map(Timezone::Named)
+                .map_err(|_| name.to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Timezone, String> {
+        name.parse()
+            .map_err(|_| format!("invalid timezone: {}", name))
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(timezone: Timezone) -> String {
+        timezone.to_string()
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timezone::Utc => write!(formatter, "UTC"),
+            Timezone::Local => write!(formatter, "local"),
+            Timezone::Named(tz) => write!(formatter, "{}", tz.name()),
+        }
+    }
+}
+
+impl Timezone {
+    // Appends the timezone to a date format without one
+    pub fn format(self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            Timezone::Utc => format!("{} UTC", time.format(format)),
+            Timezone::Local => time
+                .with_timezone(&Local)
+                .format(&format!("{} %:z", format))
+                .to_string(),
+            Timezone::Named(tz) => time
+                .with_timezone(&tz)
+                .format(&format!("{} %Z", format))
+                .to_string(),
+        }
+    }
+}
+
+pub fn set_display(timezone: Timezone) {
+    *DISPLAY.lock().unwrap() = timezone;
+}
+
+pub fn display() -> Timezone {
+    *DISPLAY.lock().unwrap()
+}
+
+// A time of the command output or logs, in the display timezone
+pub fn format(time: DateTime<Utc>, format: &str) -> String {
+    display().format(time, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn format_times_in_timezones() {
+        let time = Utc.with_ymd_and_hms(2019, 7, 13, 1, 0, 0).unwrap();
+
+        let berlin: Timezone = "Europe/Berlin".parse().unwrap();
+        let tokyo: Timezone = "Asia/Tokyo".parse().unwrap();
+
+        assert_eq!(
+            Timezone::Utc.format(time, "%Y-%m-%d %H:%M"),
+            "2019-07-13 01:00 UTC"
+        );
+        assert_eq!(
+            berlin.format(time, "%Y-%m-%d %H:%M"),
+            "2019-07-13 03:00 CEST"
+        );
+        assert_eq!(tokyo.format(time, "%H:%M"), "10:00 JST");
+    }
+
+    #[test]
+    fn parse_timezones() {
+        assert_eq!("utc".parse(), Ok(Timezone::Utc));
+        assert_eq!("local".parse(), Ok(Timezone::Local));
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+    }
+}
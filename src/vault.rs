@@ -0,0 +1,439 @@
+use super::error::{Error, ErrorKind};
+use log::{info, trace, warn};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Secrets are only fetched when an address is configured. Requests hold the
+// lock, so concurrent runs share one login and one read of each path
+static VAULT: Mutex<Option<Vault>> = Mutex::new(None);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Values are fetched again after this, or sooner when their lease is shorter
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+// Tokens closer to their expiration are renewed before being used
+const RENEW_BEFORE: Duration = Duration::from_secs(60);
+
+pub struct Vault {
+    // Like "https://vault.example.com:8200"
+    address: String,
+
+    auth: Auth,
+
+    session: Option<Session>,
+
+    cache: HashMap<String, Cached>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Auth {
+    Token(String),
+    AppRole { role_id: String, secret_id: String },
+}
+
+struct Session {
+    token: String,
+
+    // Tokens without a TTL never expire
+    expires: Option<Instant>,
+
+    renewable: bool,
+}
+
+struct Cached {
+    data: Map<String, Value>,
+
+    expires: Instant,
+}
+
+impl Vault {
+    pub fn new(address: &str, auth: Auth) -> Vault {
+        Vault {
+            address: address.trim_end_matches('/').to_string(),
+            auth,
+            session: None,
+            cache: HashMap::new(),
+        }
+    }
+
+    // Key of a KV secret, version 1 or 2 (like "secret/data/db" for version 2)
+    pub fn read(&mut self, path: &str, key: &str) -> Result<String, String> {
+        let data = self.secret(path)?;
+
+        match data.get(key) {
+            Some(Value::String(value)) => Ok(value.to_string()),
+            Some(value) => Ok(value.to_string()),
+            None => Err(format!("no key {} in {}", key, path)),
+        }
+    }
+
+    fn secret(&mut self, path: &str) -> Result<Map<String, Value>, String> {
+        let path = path.trim_matches('/');
+
+        if let Some(cached) = self.cache.get(path) {
+            if cached.expires > Instant::now() {
+                return Ok(cached.data.clone());
+            }
+        }
+
+        trace!("Reading Vault secret: {}", path);
+
+        let token = self.token()?;
+
+        let response = match self.get(path, &token)? {
+            Some(response) => response,
+            // The token may have been revoked, logging in again may fix it
+            None => {
+                self.session = None;
+
+                let token = self.token()?;
+
+                self.get(path, &token)?
+                    .ok_or_else(|| String::from("status code 403 (permission denied)"))?
+            }
+        };
+
+        let lease = response["lease_duration"].as_u64().unwrap_or(0);
+
+        let data = match response["data"].as_object() {
+            // KV version 2 nests the values with their metadata
+            Some(data) if data.contains_key("metadata") => data.get("data"),
+            _ => response.get("data"),
+        }
+        .and_then(Value::as_object)
+        .cloned()
+        .ok_or_else(|| format!("no data in {}", path))?;
+
+        let ttl = match Duration::from_secs(lease) {
+            lease if lease.is_zero() => CACHE_TTL,
+            lease => lease.min(CACHE_TTL),
+        };
+
+        self.cache.insert(
+            path.to_string(),
+            Cached {
+                data: data.clone(),
+                expires: Instant::now() + ttl,
+            },
+        );
+
+        Ok(data)
+    }
+
+    // None when the token is denied
+    fn get(&self, path: &str, token: &str) -> Result<Option<Value>, String> {
+        let response = ureq::get(&format!("{}/v1/{}", self.address, path))
+            .timeout(REQUEST_TIMEOUT)
+            .set("X-Vault-Token", token)
+            .call();
+
+        match response {
+            Ok(response) => response
+                .into_json()
+                .map(Some)
+                .map_err(|err| err.to_string()),
+            Err(ureq::Error::Status(403, _)) => Ok(None),
+            Err(err) => Err(describe(err)),
+        }
+    }
+
+    fn token(&mut self) -> Result<String, String> {
+        let now = Instant::now();
+
+        if let Some(session) = &self.session {
+            match session.expires {
+                None => return Ok(session.token.to_string()),
+                Some(expires) if expires > now + RENEW_BEFORE => {
+                    return Ok(session.token.to_string())
+                }
+                Some(expires) if session.renewable && expires > now => {
+                    match self.renew(&session.token) {
+                        Ok(session) => return Ok(self.start(session)),
+                        Err(err) => warn!("Error renewing Vault token: {}", err),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let session = self.login()?;
+
+        Ok(self.start(session))
+    }
+
+    fn start(&mut self, session: Session) -> String {
+        let token = session.token.to_string();
+
+        self.session = Some(session);
+
+        token
+    }
+
+    fn login(&self) -> Result<Session, String> {
+        match &self.auth {
+            // A token is only looked up, for its expiration
+            Auth::Token(token) => {
+                let response: Value =
+                    self.request("GET", "auth/token/lookup-self", Some(token), None)?;
+
+                let data = &response["data"];
+
+                Ok(Session {
+                    token: token.to_string(),
+                    expires: expires(data["ttl"].as_u64()),
+                    renewable: data["renewable"].as_bool().unwrap_or(false),
+                })
+            }
+            Auth::AppRole { role_id, secret_id } => {
+                trace!("Logging in to Vault with AppRole: {}", role_id);
+
+                let body = json!({"role_id": role_id, "secret_id": secret_id});
+
+                let response = self.request("POST", "auth/approle/login", None, Some(body))?;
+
+                session(&response)
+            }
+        }
+    }
+
+    fn renew(&self, token: &str) -> Result<Session, String> {
+        trace!("Renewing Vault token");
+
+        let response = self.request(
+            "POST",
+            "auth/token/renew-self",
+            Some(token),
+            Some(json!({})),
+        )?;
+
+        session(&response)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Result<Value, String> {
+        let mut request = ureq::request(method, &format!("{}/v1/{}", self.address, path))
+            .timeout(REQUEST_TIMEOUT);
+
+        if let Some(token) = token {
+            request = request.set("X-Vault-Token", token);
+        }
+
+        let response = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        }
+        .map_err(describe)?;
+
+        response.into_json().map_err(|err| err.to_string())
+    }
+}
+
+pub fn init(address: &str, auth: Auth) -> Result<(), Error> {
+    if !address.starts_with("http://") && !address.starts_with("https://") {
+        return Err(ErrorKind::InvalidSetting(format!("vault_addr={}", address)).into());
+    }
+
+    info!("Reading secrets from Vault: {}", address);
+
+    *VAULT.lock().unwrap() = Some(Vault::new(address, auth));
+
+    Ok(())
+}
+
+pub fn read(path: &str, key: &str) -> Result<String, String> {
+    match VAULT.lock().unwrap().as_mut() {
+        Some(vault) => vault.read(path, key),
+        None => Err(String::from("Vault is not configured (vault_addr)")),
+    }
+}
+
+// Token of a login or renewal response
+fn session(response: &Value) -> Result<Session, String> {
+    let auth = &response["auth"];
+
+    let token = auth["client_token"]
+        .as_str()
+        .ok_or_else(|| String::from("no token in the response"))?;
+
+    Ok(Session {
+        token: token.to_string(),
+        expires: expires(auth["lease_duration"].as_u64()),
+        renewable: auth["renewable"].as_bool().unwrap_or(false),
+    })
+}
+
+fn expires(ttl: Option<u64>) -> Option<Instant> {
+    match ttl {
+        Some(ttl) if ttl > 0 => Some(Instant::now() + Duration::from_secs(ttl)),
+        _ => None,
+    }
+}
+
+fn describe(err: ureq::Error) -> String {
+    match err {
+        ureq::Error::Status(code, response) => {
+            // Vault explains failures in an errors list
+            let errors = response
+                .into_json::<Value>()
+                .ok()
+                .and_then(|body| body["errors"].as_array().cloned())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect::<Vec<String>>();
+
+            if errors.is_empty() {
+                format!("status code {}", code)
+            } else {
+                format!("status code {} ({})", code, errors.join(", "))
+            }
+        }
+        ureq::Error::Transport(transport) => match transport.message() {
+            Some(message) => format!("{}: {}", transport.kind(), message),
+            None => transport.kind().to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Answers a request with each body in order, and returns the request
+    // lines with their tokens
+    fn serve(bodies: Vec<Value>) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for body in bodies {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let mut token = String::new();
+                let mut content_length = 0;
+
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+
+                    if header.trim().is_empty() {
+                        break;
+                    }
+
+                    let header = header.to_lowercase();
+
+                    if let Some(value) = header.strip_prefix("x-vault-token:") {
+                        token = value.trim().to_string();
+                    }
+
+                    if let Some(value) = header.strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+
+                let mut request_body = vec![0; content_length];
+                reader.read_exact(&mut request_body).unwrap();
+
+                let body = body.to_string();
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+
+                requests.push((request_line.trim().to_string(), token));
+            }
+
+            requests
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn read_cached_secrets_with_approle() {
+        let (address, server) = serve(vec![
+            json!({"auth": {"client_token": "s.login", "lease_duration": 3600, "renewable": true}}),
+            json!({"lease_duration": 0, "data": {
+                "data": {"password": "hunter2", "port": 5432},
+                "metadata": {"version": 3}
+            }}),
+        ]);
+
+        let auth = Auth::AppRole {
+            role_id: String::from("scheduler"),
+            secret_id: String::from("secret"),
+        };
+
+        let mut vault = Vault::new(&address, auth);
+
+        assert_eq!(vault.read("secret/data/db", "password").unwrap(), "hunter2");
+        assert_eq!(vault.read("/secret/data/db", "port").unwrap(), "5432");
+        assert!(vault.read("secret/data/db", "user").is_err());
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                (
+                    String::from("POST /v1/auth/approle/login HTTP/1.1"),
+                    String::new()
+                ),
+                (
+                    String::from("GET /v1/secret/data/db HTTP/1.1"),
+                    String::from("s.login")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn renew_expiring_tokens() {
+        let (address, server) = serve(vec![
+            json!({"data": {"ttl": 30, "renewable": true}}),
+            json!({"lease_duration": 600, "data": {"user": "loader"}}),
+            json!({"auth": {"client_token": "s.renewed", "lease_duration": 3600, "renewable": true}}),
+            json!({"lease_duration": 600, "data": {"key": "abc"}}),
+        ]);
+
+        let mut vault = Vault::new(&address, Auth::Token(String::from("s.token")));
+
+        assert_eq!(vault.read("kv/db", "user").unwrap(), "loader");
+        assert_eq!(vault.read("kv/api", "key").unwrap(), "abc");
+
+        let tokens: Vec<(String, String)> = server.join().unwrap();
+
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|(request, token)| (request.split(' ').nth(1).unwrap(), token.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("/v1/auth/token/lookup-self", "s.token"),
+                ("/v1/kv/db", "s.token"),
+                ("/v1/auth/token/renew-self", "s.token"),
+                ("/v1/kv/api", "s.renewed"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use super::function::Context;
+use super::plugin::JobType;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use wasmtime::{Config, Engine, Linker, Module, Store, UpdateDeadline};
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
+
+// Output kept in memory until the module exits
+const MAX_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+
+// How often a running module checks if the run was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Deserialize)]
+pub struct WasmJob {
+    // Module file relative to the pipeline folder, .wasm or .wat
+    pub module: String,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    // Folders the module can access, "host" or "host:guest", relative to the pipeline folder
+    #[serde(default)]
+    pub dirs: Vec<String>,
+
+    #[serde(default)]
+    pub read_only: bool,
+
+    // Parameters and scheduler variables passed to the module, nothing else is
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+pub fn job_type() -> JobType {
+    JobType::new(run)
+}
+
+fn run(job: &WasmJob, context: &Context) -> Result<(), String> {
+    let folder = Path::new(&context.folder);
+
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+
+    let engine = Engine::new(&config).map_err(|err| err.to_string())?;
+
+    let module_path = folder.join(&job.module);
+
+    let module = Module::from_file(&engine, &module_path)
+        .map_err(|err| format!("{}: {}", module_path.to_string_lossy(), err))?;
+
+    let mut linker: Linker<WasiP1Ctx> = Linker::new(&engine);
+    preview1::add_to_linker_sync(&mut linker, |wasi| wasi).map_err(|err| err.to_string())?;
+
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT_BYTES);
+    let stderr = MemoryOutputPipe::new(MAX_OUTPUT_BYTES);
+
+    let mut wasi = WasiCtxBuilder::new();
+
+    wasi.stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .arg(&job.module)
+        .args(&job.args);
+
+    for (name, value) in environment(&job.env, &context.params) {
+        wasi.env(name, value);
+    }
+
+    let (dir_perms, file_perms) = if job.read_only {
+        (DirPerms::READ, FilePerms::READ)
+    } else {
+        (DirPerms::all(), FilePerms::all())
+    };
+
+    for dir in &job.dirs {
+        let (host, guest) = preopen(folder, dir);
+
+        wasi.preopened_dir(&host, &guest, dir_perms, file_perms)
+            .map_err(|err| format!("{}: {}", host.to_string_lossy(), err))?;
+    }
+
+    let mut store = Store::new(&engine, wasi.build_p1());
+
+    let cancellation = context.cancellation.clone();
+
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if cancellation.is_cancelled() {
+            Err(wasmtime::Error::msg("cancelled"))
+        } else {
+            Ok(UpdateDeadline::Continue(1))
+        }
+    });
+
+    // The engine epoch only moves when something increments it
+    let finished = Arc::new(AtomicBool::new(false));
+
+    let ticker = {
+        let engine = engine.clone();
+        let finished = finished.clone();
+
+        thread::spawn(move || {
+            while !finished.load(Ordering::SeqCst) {
+                thread::sleep(CANCEL_POLL_INTERVAL);
+                engine.increment_epoch();
+            }
+        })
+    };
+
+    let result = linker
+        .instantiate(&mut store, &module)
+        .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, "_start"))
+        .and_then(|start| start.call(&mut store, ()));
+
+    finished.store(true, Ordering::SeqCst);
+    let _ = ticker.join();
+
+    for line in String::from_utf8_lossy(&stdout.contents()).lines() {
+        context.print(line);
+    }
+
+    let errors = String::from_utf8_lossy(&stderr.contents()).to_string();
+
+    for line in errors.lines() {
+        context.print(line);
+    }
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => match err.downcast_ref::<I32Exit>() {
+            Some(I32Exit(0)) => Ok(()),
+            Some(I32Exit(code)) => Err(format!("exit code {}\n{}", code, errors)),
+            None => Err(format!("{}\n{}", err, errors)),
+        },
+    }
+}
+
+// Parameters win over the environment of the scheduler
+fn environment(names: &[String], params: &BTreeMap<String, String>) -> Vec<(String, String)> {
+    names
+        .iter()
+        .filter_map(|name| {
+            params
+                .get(name)
+                .cloned()
+                .or_else(|| env::var(name).ok())
+                .map(|value| (name.to_string(), value))
+        })
+        .collect()
+}
+
+fn preopen(folder: &Path, dir: &str) -> (PathBuf, String) {
+    match dir.split_once(':') {
+        Some((host, guest)) => (folder.join(host), guest.to_string()),
+        None => (folder.join(dir), dir.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::executor::{self, Options};
+    use super::super::pipeline::Pipeline;
+    use super::*;
+    use std::fs;
+
+    fn module(name: &str, code: &str) -> String {
+        let path = env::temp_dir().join(format!("rusty-scheduler-{}.wat", name));
+
+        fs::write(
+            &path,
+            format!(
+                r#"(module
+                    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 16) "hello wasm\n")
+                    (func (export "_start") {}))"#,
+                code
+            ),
+        )
+        .unwrap();
+
+        path.to_string_lossy().to_string()
+    }
+
+    fn pipeline(module: &str) -> Pipeline {
+        serde_json::from_str(&format!(
+            r#"{{"id": "wasm", "stages": ["main"], "jobs": [{{"id": "job", "stage": "main", "type": "wasm", "module": "{}"}}]}}"#,
+            module
+        ))
+        .unwrap()
+    }
+
+    const HELLO: &str = "(i32.store (i32.const 0) (i32.const 16)) (i32.store (i32.const 4) (i32.const 11)) (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))";
+
+    #[test]
+    fn run_modules() {
+        let options = Options::default();
+
+        let pipeline = pipeline(&module("hello", HELLO));
+
+        assert!(executor::execute_stage(&pipeline, "main", &options).is_ok());
+        assert_eq!(
+            options.output.lines_since(0).0,
+            vec![String::from("[] hello wasm")]
+        );
+    }
+
+    #[test]
+    fn fail_on_exit_codes() {
+        let code = format!("{} (call $proc_exit (i32.const 3))", HELLO);
+
+        let pipeline = pipeline(&module("exit", &code));
+
+        assert!(executor::execute_stage(&pipeline, "main", &Options::default()).is_err());
+    }
+
+    #[test]
+    fn stop_cancelled_modules() {
+        let options = Options::default();
+        options.cancellation.cancel();
+
+        let pipeline = pipeline(&module("loop", "(loop (br 0))"));
+
+        assert!(executor::execute_stage(&pipeline, "main", &options).is_err());
+    }
+
+    #[test]
+    fn pass_allowed_variables() {
+        let params = vec![(String::from("TARGET"), String::from("staging"))]
+            .into_iter()
+            .collect();
+
+        let names = vec![
+            String::from("TARGET"),
+            String::from("RUSTY_SCHEDULER_TEST_UNSET"),
+        ];
+
+        assert_eq!(
+            environment(&names, &params),
+            vec![(String::from("TARGET"), String::from("staging"))]
+        );
+    }
+
+    #[test]
+    fn map_preopened_dirs() {
+        let folder = Path::new("/srv/pipelines/loader");
+
+        assert_eq!(
+            preopen(folder, "data"),
+            (
+                PathBuf::from("/srv/pipelines/loader/data"),
+                String::from("data")
+            )
+        );
+        assert_eq!(
+            preopen(folder, "/var/cache:/cache"),
+            (PathBuf::from("/var/cache"), String::from("/cache"))
+        );
+    }
+}
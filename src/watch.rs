@@ -0,0 +1,343 @@
+use super::error::{Error, ErrorKind};
+use super::fairness;
+use super::lock::Lock;
+use super::pipeline::Pipeline;
+use super::runtime::SharedRuntime;
+use super::scheduler::{self, Overrides};
+use super::shutdown;
+use super::state::State;
+use failure::ResultExt;
+use log::{error, info, trace, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Runs start once the scripts stop changing for this long, editors save
+// files in several steps
+pub const DEBOUNCE: Duration = Duration::from_secs(2);
+
+// Written and renamed files, editors often save to a temporary file first
+const EVENTS: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+
+static WATCHER: Mutex<Option<Watcher>> = Mutex::new(None);
+
+// Folders of the scripts of the pipelines with "watch", with an inotify watch each
+pub struct Watcher {
+    fd: libc::c_int,
+
+    dirs: BTreeMap<libc::c_int, PathBuf>,
+
+    pipelines: Vec<Pipeline>,
+
+    // Last change of the scripts of a pipeline not run yet
+    changed: BTreeMap<String, Instant>,
+}
+
+impl Watcher {
+    pub fn new() -> Result<Watcher, Error> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+
+        if fd == -1 {
+            Err(io::Error::last_os_error()).context(ErrorKind::WatchFailed)?;
+        }
+
+        Ok(Watcher {
+            fd,
+            dirs: BTreeMap::new(),
+            pipelines: Vec::new(),
+            changed: BTreeMap::new(),
+        })
+    }
+
+    // Watches the folders of the scripts, and stops watching the others
+    pub fn update(&mut self, pipelines: &[Pipeline]) {
+        self.pipelines = pipelines
+            .iter()
+            .filter(|pipeline| pipeline.watch && pipeline.enabled)
+            .cloned()
+            .collect();
+
+        let folders: BTreeSet<PathBuf> = self
+            .pipelines
+            .iter()
+            .flat_map(scripts)
+            .filter_map(|script| script.parent().map(Path::to_path_buf))
+            .collect();
+
+        let removed: Vec<libc::c_int> = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| !folders.contains(*dir))
+            .map(|(wd, _)| *wd)
+            .collect();
+
+        for wd in removed {
+            unsafe { libc::inotify_rm_watch(self.fd, wd) };
+
+            self.dirs.remove(&wd);
+        }
+
+        for folder in folders {
+            if self.dirs.values().any(|dir| *dir == folder) {
+                continue;
+            }
+
+            let path = match CString::new(folder.as_os_str().as_bytes()) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+
+            match unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), EVENTS) } {
+                -1 => warn!(
+                    "Error watching scripts ({}): {}",
+                    folder.to_string_lossy(),
+                    io::Error::last_os_error()
+                ),
+                wd => {
+                    trace!("Watching scripts: {}", folder.to_string_lossy());
+
+                    self.dirs.insert(wd, folder);
+                }
+            }
+        }
+
+        let ids: Vec<&String> = self.pipelines.iter().map(|pipeline| &pipeline.id).collect();
+
+        self.changed.retain(|id, _| ids.contains(&id));
+    }
+
+    // Marks the pipelines whose scripts changed, from the pending events
+    pub fn read(&mut self) {
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+
+            if read <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+
+            while offset + mem::size_of::<libc::inotify_event>() <= read as usize {
+                let event: libc::inotify_event =
+                    unsafe { ptr::read_unaligned(buffer.as_ptr().add(offset) as *const _) };
+
+                let start = offset + mem::size_of::<libc::inotify_event>();
+                let name = &buffer[start..start + event.len as usize];
+                let name = OsStr::from_bytes(name.split(|byte| *byte == 0).next().unwrap_or(&[]));
+
+                if let Some(dir) = self.dirs.get(&event.wd) {
+                    self.mark(&dir.join(name));
+                }
+
+                offset = start + event.len as usize;
+            }
+        }
+    }
+
+    fn mark(&mut self, file: &Path) {
+        for pipeline in &self.pipelines {
+            if scripts(pipeline).iter().any(|script| script == file) {
+                trace!(
+                    "Script changed ({}): {}",
+                    pipeline.id,
+                    file.to_string_lossy()
+                );
+
+                self.changed.insert(pipeline.id.to_string(), Instant::now());
+            }
+        }
+    }
+
+    // Pipelines whose scripts stopped changing, no longer marked
+    pub fn due(&mut self) -> Vec<Pipeline> {
+        let due: Vec<String> = self
+            .changed
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= DEBOUNCE)
+            .map(|(id, _)| id.to_string())
+            .collect();
+
+        self.changed.retain(|id, _| !due.contains(id));
+
+        self.pipelines
+            .iter()
+            .filter(|pipeline| due.contains(&pipeline.id))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// Watches the scripts of the pipelines with "watch" and runs them once
+// their scripts change
+pub fn serve(runtime: SharedRuntime) -> Result<(), Error> {
+    let watcher = Watcher::new()?;
+
+    let fd = watcher.fd;
+
+    *WATCHER.lock().unwrap() = Some(watcher);
+
+    thread::spawn(move || {
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        while !shutdown::is_requested() {
+            // Wakes up every second for the debounced runs
+            unsafe { libc::poll(&mut poll, 1, 1000) };
+
+            let due = match WATCHER.lock().unwrap().as_mut() {
+                Some(watcher) => {
+                    watcher.read();
+                    watcher.due()
+                }
+                None => break,
+            };
+
+            for pipeline in due {
+                run(pipeline, &runtime);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// Watched pipelines, as loaded by the scheduler
+pub fn update(pipelines: &[Pipeline]) {
+    if let Some(watcher) = WATCHER.lock().unwrap().as_mut() {
+        watcher.update(pipelines);
+    }
+}
+
+// A pipeline already running, or waiting for a free slot, runs again after
+// the active run. Paused pipelines don't run until they are resumed
+fn run(pipeline: Pipeline, runtime: &SharedRuntime) {
+    if scheduler::is_dry_run(&pipeline) || !scheduler::runs_here(&pipeline) {
+        return;
+    }
+
+    if State::read_from_pipeline(&pipeline).paused {
+        trace!("Pipeline is paused, change ignored: {}", pipeline.id);
+
+        return;
+    }
+
+    if fairness::is_full(runtime) {
+        trace!("Concurrency limit reached, run delayed: {}", pipeline.id);
+
+        retry(pipeline);
+
+        return;
+    }
+
+    match Lock::acquire_from_pipeline(&pipeline) {
+        Ok(Some(lock)) => {
+            info!("Pipeline scripts changed, running: {}", pipeline.id);
+
+            let mut labels = BTreeMap::new();
+
+            labels.insert(String::from("reason"), String::from("watch"));
+
+            scheduler::spawn_run(
+                pipeline,
+                lock,
+                runtime,
+                None,
+                Overrides {
+                    labels,
+                    ..Overrides::default()
+                },
+            );
+        }
+        Ok(None) => retry(pipeline),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn retry(pipeline: Pipeline) {
+    if let Some(watcher) = WATCHER.lock().unwrap().as_mut() {
+        watcher.changed.insert(pipeline.id, Instant::now());
+    }
+}
+
+fn scripts(pipeline: &Pipeline) -> Vec<PathBuf> {
+    pipeline
+        .jobs
+        .iter()
+        .filter(|job| !job.script.is_empty())
+        .map(|job| PathBuf::from(&job.path))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn mark_changed_scripts() {
+        let dir = env::temp_dir().join("rusty-scheduler-watch");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("job.sh"), "echo 1\n").unwrap();
+
+        let mut pipeline: Pipeline = serde_json::from_str(
+            r#"{"id": "watched", "watch": true, "stages": ["main"], "jobs": [
+                {"id": "job", "stage": "main", "script": "job.sh"}
+            ]}"#,
+        )
+        .unwrap();
+
+        pipeline.path = dir.join("pipeline.json").to_string_lossy().to_string();
+        pipeline.link_jobs();
+
+        let mut watcher = Watcher::new().unwrap();
+
+        watcher.update(&[pipeline]);
+
+        fs::write(dir.join("other.txt"), "").unwrap();
+
+        watcher.read();
+
+        assert!(watcher.changed.is_empty());
+
+        fs::write(dir.join("job.sh"), "echo 2\n").unwrap();
+
+        watcher.read();
+
+        assert!(watcher.changed.contains_key("watched"));
+        assert!(watcher.due().is_empty());
+
+        // Debounced, the change is still pending
+        watcher
+            .changed
+            .insert(String::from("watched"), Instant::now() - DEBOUNCE);
+
+        assert_eq!(watcher.due().len(), 1);
+        assert!(watcher.changed.is_empty());
+    }
+}
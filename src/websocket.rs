@@ -0,0 +1,316 @@
+use super::error::{Error, ErrorKind};
+use super::security::{Security, Stream};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::ResultExt;
+use sha1::{Digest, Sha1};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// See https://tools.ietf.org/html/rfc6455
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Frames carry output lines, anything bigger is not from this scheduler
+const MAX_FRAME_LENGTH: u64 = 1024 * 1024;
+
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(GUID.as_bytes());
+
+    STANDARD.encode(hasher.finalize())
+}
+
+// Client frames are masked, server frames are not
+pub fn encode_frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+
+    match payload.len() {
+        length if length < 126 => frame.push(mask_bit | length as u8),
+        length if length <= 0xFFFF => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(
+                payload
+                    .iter()
+                    .enumerate()
+                    .map(|(index, byte)| byte ^ mask[index % 4]),
+            );
+        }
+        None => frame.extend_from_slice(payload),
+    }
+
+    frame
+}
+
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+
+            u64::from(u16::from_be_bytes(length))
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+
+            u64::from_be_bytes(length)
+        }
+        length => u64::from(length),
+    };
+
+    if length > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame too large: {} bytes", length),
+        ));
+    }
+
+    let mut mask = [0; 4];
+
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+
+    if masked {
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+    }
+
+    Ok((opcode, payload))
+}
+
+// The key and masks only need to differ between connections
+fn nonce() -> [u8; 16] {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    let mut hasher = Sha1::new();
+    hasher.update(nanos.to_be_bytes());
+    hasher.update(process::id().to_be_bytes());
+
+    let mut nonce = [0; 16];
+    nonce.copy_from_slice(&hasher.finalize()[..16]);
+
+    nonce
+}
+
+pub struct Client {
+    reader: BufReader<Stream>,
+
+    mask: [u8; 4],
+}
+
+impl Client {
+    // Address like "127.0.0.1:8080", "http://127.0.0.1:8080" or "https://host:8443",
+    // without a scheme TLS is used when the scheduler has a certificate
+    pub fn connect(address: &str, path: &str, security: &Security) -> Result<Client, Error> {
+        let tls = if address.starts_with("https://") {
+            true
+        } else {
+            !address.starts_with("http://") && security.tls.is_some()
+        };
+
+        let address = address
+            .trim_start_matches("http://")
+            .trim_start_matches("https://")
+            .trim_end_matches('/')
+            .to_string();
+
+        let tcp = TcpStream::connect(&address)
+            .context(ErrorKind::WebSocketFailed(address.to_string()))?;
+
+        let host = match address.rsplit_once(':') {
+            Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => &address,
+        };
+
+        let config = if tls {
+            Some(security.client_config()?)
+        } else {
+            None
+        };
+
+        let mut stream = Stream::connect(tcp, host, config)
+            .context(ErrorKind::WebSocketFailed(address.to_string()))?;
+
+        let authorization = match security.client_token() {
+            Some(token) => format!("Authorization: Bearer {}\r\n", token),
+            None => String::new(),
+        };
+
+        let nonce = nonce();
+        let key = STANDARD.encode(nonce);
+
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+            path, address, key, authorization
+        );
+
+        stream
+            .write_all(request.as_bytes())
+            .and_then(|_| stream.flush())
+            // The TLS handshake happens here, its reason is worth showing
+            .map_err(|err| ErrorKind::WebSocketFailed(format!("{}: {}", address, err)))?;
+
+        let mut reader = BufReader::new(stream);
+
+        let mut status = String::new();
+        let mut accept = None;
+        let mut content_length = 0;
+
+        reader
+            .read_line(&mut status)
+            .context(ErrorKind::WebSocketFailed(address.to_string()))?;
+
+        loop {
+            let mut header = String::new();
+
+            reader
+                .read_line(&mut header)
+                .context(ErrorKind::WebSocketFailed(address.to_string()))?;
+
+            let header = header.trim();
+
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = header.split_once(':') {
+                let name = name.trim().to_lowercase();
+
+                if name == "sec-websocket-accept" {
+                    accept = Some(value.trim().to_string());
+                } else if name == "content-length" {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        if status.split_whitespace().nth(1) != Some("101") {
+            // Errors come back as a regular JSON response
+            let mut body = vec![0; content_length];
+            let _ = reader.read_exact(&mut body);
+
+            let message = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| status.trim().to_string());
+
+            return Err(ErrorKind::WebSocketFailed(message).into());
+        }
+
+        if accept.as_deref() != Some(accept_key(&key).as_str()) {
+            return Err(ErrorKind::WebSocketFailed(String::from("invalid handshake")).into());
+        }
+
+        Ok(Client {
+            reader,
+            mask: [nonce[0], nonce[1], nonce[2], nonce[3]],
+        })
+    }
+
+    // Next text message, None when the server closes the connection
+    pub fn read_text(&mut self) -> Result<Option<String>, Error> {
+        loop {
+            let (opcode, payload) = read_frame(&mut self.reader)
+                .context(ErrorKind::WebSocketFailed(String::from("read")))?;
+
+            match opcode {
+                OPCODE_TEXT => return Ok(Some(String::from_utf8_lossy(&payload).to_string())),
+                OPCODE_CLOSE => {
+                    let frame = encode_frame(OPCODE_CLOSE, &[], Some(self.mask));
+                    let _ = self.write(&frame);
+
+                    return Ok(None);
+                }
+                OPCODE_PING => {
+                    let frame = encode_frame(OPCODE_PONG, &payload, Some(self.mask));
+
+                    self.write(&frame)
+                        .context(ErrorKind::WebSocketFailed(String::from("pong")))?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn write(&mut self, frame: &[u8]) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+
+        stream.write_all(frame)?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn encode_frames() {
+        assert_eq!(
+            encode_frame(OPCODE_TEXT, b"Hello", None),
+            vec![0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]
+        );
+
+        // Masked example of the RFC
+        assert_eq!(
+            encode_frame(OPCODE_TEXT, b"Hello", Some([0x37, 0xfa, 0x21, 0x3d])),
+            vec![0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+    }
+
+    #[test]
+    fn read_frames() {
+        let payload = vec![b'x'; 300];
+
+        let mut frames = encode_frame(OPCODE_TEXT, &payload, Some([1, 2, 3, 4]));
+        frames.extend(encode_frame(OPCODE_CLOSE, &[], None));
+
+        let mut reader = frames.as_slice();
+
+        assert_eq!(read_frame(&mut reader).unwrap(), (OPCODE_TEXT, payload));
+        assert_eq!(read_frame(&mut reader).unwrap(), (OPCODE_CLOSE, Vec::new()));
+    }
+}
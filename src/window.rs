@@ -0,0 +1,156 @@
+use super::timezone::Timezone;
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// Daily wall-clock period a job may start in, like "02:00-05:00" (UTC) or
+// "22:00-02:00 Europe/Lisbon" across midnight
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Window {
+    pub start: NaiveTime,
+
+    pub end: NaiveTime,
+
+    pub timezone: Timezone,
+}
+
+// What a job reaching its allowed window while it is closed does
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutsideWindow {
+    // Starts once the window opens, the rest of the stage keeps running
+    #[default]
+    Wait,
+    // Doesn't run, the next stages still run
+    Skip,
+}
+
+impl Window {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = match self.timezone {
+            Timezone::Utc => now.time(),
+            Timezone::Local => now.with_timezone(&Local).time(),
+            Timezone::Named(tz) => now.with_timezone(&tz).time(),
+        };
+
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(window: &str) -> Result<Window, String> {
+        let parts: Vec<&str> = window.split_whitespace().collect();
+
+        let (period, timezone) = match parts.as_slice() {
+            [period] => (period, Timezone::Utc),
+            [period, timezone] => (period, timezone.parse()?),
+            _ => return Err(window.to_string()),
+        };
+
+        let (start, end) = period.split_once('-').ok_or_else(|| window.to_string())?;
+
+        let time = |time| NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| window.to_string());
+
+        let (start, end) = (time(start)?, time(end)?);
+
+        if start == end {
+            return Err(window.to_string());
+        }
+
+        Ok(Window {
+            start,
+            end,
+            timezone,
+        })
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = String;
+
+    fn try_from(window: String) -> Result<Window, String> {
+        window
+            .parse()
+            .map_err(|_| format!("invalid allowed_window: {}", window))
+    }
+}
+
+impl From<Window> for String {
+    fn from(window: Window) -> String {
+        window.to_string()
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let period = format!(
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        );
+
+        match self.timezone {
+            Timezone::Utc => write!(formatter, "{}", period),
+            Timezone::Local => write!(formatter, "{} local", period),
+            Timezone::Named(tz) => write!(formatter, "{} {}", period, tz.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_windows() {
+        let window: Window = "2:00-05:00".parse().unwrap();
+
+        assert_eq!(window.start, NaiveTime::from_hms_opt(2, 0, 0).unwrap());
+        assert_eq!(window.to_string(), "02:00-05:00");
+
+        let window: Window = "22:00-02:30 Europe/Lisbon".parse().unwrap();
+
+        assert_eq!(window.to_string(), "22:00-02:30 Europe/Lisbon");
+
+        for invalid in &[
+            "02:00",
+            "02:00-02:00",
+            "02:00-25:00",
+            "02:00 - 05:00",
+            "02:00-05:00 Mars/Olympus",
+        ] {
+            assert!(invalid.parse::<Window>().is_err());
+        }
+    }
+
+    #[test]
+    fn check_open_windows() {
+        let window: Window = "02:00-05:00".parse().unwrap();
+
+        assert!(window.contains(time("2019-07-02T02:00:00Z")));
+        assert!(window.contains(time("2019-07-02T04:59:59Z")));
+        assert!(!window.contains(time("2019-07-02T05:00:00Z")));
+        assert!(!window.contains(time("2019-07-02T01:59:59Z")));
+
+        // Across midnight, Lisbon is an hour ahead of UTC in the summer
+        let window: Window = "22:00-02:00 Europe/Lisbon".parse().unwrap();
+
+        assert!(window.contains(time("2019-07-01T21:00:00Z")));
+        assert!(window.contains(time("2019-07-02T00:59:00Z")));
+        assert!(!window.contains(time("2019-07-02T01:00:00Z")));
+        assert!(!window.contains(time("2019-07-01T20:59:00Z")));
+    }
+}